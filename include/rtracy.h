@@ -0,0 +1,61 @@
+/* C API of rtracy snapshot reader, build library with:
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib
+ */
+#ifndef RTRACY_H
+#define RTRACY_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct RtracyReader RtracyReader;
+
+typedef struct {
+    double multiplier;
+    uint64_t init_begin;
+    uint64_t init_end;
+    uint64_t epoch;
+    uint64_t process_id;
+    uint32_t location_count;
+} RtracyHeader;
+
+/* Strings are owned by reader and valid until rtracy_close, color is 0xRRGGBB */
+typedef struct {
+    const char* name;
+    const char* function;
+    const char* file;
+    uint32_t line;
+    uint32_t color;
+} RtracyLocation;
+
+enum {
+    RTRACY_ZONE_BEGIN = 15,
+    RTRACY_ZONE_END = 17,
+    RTRACY_ZONE_COLOR = 62,
+    RTRACY_FRAME_MARK = 64,
+};
+
+typedef struct {
+    uint8_t kind;
+    uint32_t thread_id;
+    uint32_t location;  /* RTRACY_ZONE_BEGIN only */
+    uint64_t timestamp; /* snapshot ticks, nanoseconds = ticks * multiplier */
+    uint32_t color;     /* RTRACY_ZONE_COLOR only */
+} RtracyEvent;
+
+/* Functions returning int return -1 on error, message is available with rtracy_last_error */
+const char* rtracy_last_error(void);
+RtracyReader* rtracy_open(const char* path);
+void rtracy_close(RtracyReader* reader);
+int rtracy_get_header(const RtracyReader* reader, RtracyHeader* header);
+int rtracy_get_location(const RtracyReader* reader, uint32_t index, RtracyLocation* location);
+/* Returns 1 when event was read, 0 at the end of snapshot, -1 when events are truncated or corrupted */
+int rtracy_next_event(RtracyReader* reader, RtracyEvent* event);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,142 @@
+use std::net::IpAddr;
+use crate::failure::usage;
+
+// Comma separated addresses and CIDR ranges, e.g. "10.0.0.0/8,192.168.1.5,::1"
+pub struct AllowList {
+    rules: Vec<(IpAddr, u8)>,
+}
+
+fn prefix_bits(address: IpAddr) -> u8 {
+    return if address.is_ipv4() { 32 } else { 128 };
+}
+
+fn to_bits(address: IpAddr) -> u128 {
+    return match address {
+        IpAddr::V4(address) => u32::from(address) as u128,
+        IpAddr::V6(address) => u128::from(address),
+    };
+}
+
+impl AllowList {
+    pub fn parse(text: &str) -> Result<AllowList, String> {
+        let mut rules = Vec::new();
+        for rule in text.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let (address, prefix) = rule.split_once('/').unwrap_or((rule, ""));
+            let address: IpAddr = address.parse().map_err(|_| usage(format!("Wrong input: --allow {rule}, expected address or address/prefix")))?;
+            let prefix = if prefix.is_empty() {
+                prefix_bits(address)
+            } else {
+                prefix.parse::<u8>().ok().filter(|prefix| *prefix <= prefix_bits(address))
+                    .ok_or_else(|| usage(format!("Wrong input: --allow {rule}, prefix is out of range")))?
+            };
+            rules.push((address, prefix));
+        }
+        if rules.is_empty() {
+            return Err(usage("Wrong input: --allow needs at least one address"));
+        }
+        return Ok(AllowList { rules });
+    }
+
+    pub fn allows(&self, peer: IpAddr) -> bool {
+        // IPv4 peers may show up as mapped IPv6 addresses
+        let peer = peer.to_canonical();
+        return self.rules.iter().any(|(address, prefix)| {
+            if address.is_ipv4() != peer.is_ipv4() {
+                return false;
+            }
+            let shift = prefix_bits(*address) - prefix;
+            // Prefix 0 matches everything and would shift all 128 bits out
+            return shift == 128 || to_bits(*address) >> shift == to_bits(peer) >> shift;
+        });
+    }
+}
+
+// Sent before Tracy greeting by clients knowing the shared secret: magic, u16 token length, token.
+// Tracy UI never sends it, so plain connections still work when server has no --auth-token
+pub const AUTH_MAGIC: &[u8; 8] = b"RTrcAuth";
+
+pub fn auth_prefix(token: &str) -> Vec<u8> {
+    let mut prefix = AUTH_MAGIC.to_vec();
+    prefix.extend_from_slice(&(token.len() as u16).to_le_bytes());
+    prefix.extend_from_slice(token.as_bytes());
+    return prefix;
+}
+
+pub fn parse_token(token: &str) -> Result<String, String> {
+    if token.is_empty() || token.len() > u16::MAX as usize {
+        return Err(usage("Wrong input: auth token must be 1..=65535 bytes"));
+    }
+    return Ok(token.to_string());
+}
+
+// Compares every byte, so response time doesn't tell how much of token was right
+pub fn token_matches(expected: &str, got: &[u8]) -> bool {
+    let expected = expected.as_bytes();
+    if expected.len() != got.len() {
+        return false;
+    }
+    return expected.iter().zip(got).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use super::{parse_token, token_matches, AllowList};
+
+    fn allows(rules: &str, peer: &str) -> bool {
+        return AllowList::parse(rules).unwrap().allows(peer.parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn prefix_zero_allows_whole_family() {
+        assert!(allows("0.0.0.0/0", "203.0.113.9"));
+        assert!(allows("0.0.0.0/0", "::ffff:203.0.113.9"));
+        assert!(!allows("0.0.0.0/0", "2001:db8::1"));
+        assert!(allows("::/0", "2001:db8::1"));
+        assert!(!allows("::/0", "203.0.113.9"));
+    }
+
+    #[test]
+    fn full_prefix_allows_one_address() {
+        assert!(allows("192.168.1.5/32", "192.168.1.5"));
+        assert!(!allows("192.168.1.5/32", "192.168.1.6"));
+        assert!(allows("192.168.1.5", "192.168.1.5"));
+        assert!(allows("2001:db8::1/128", "2001:db8::1"));
+        assert!(!allows("2001:db8::1/128", "2001:db8::2"));
+    }
+
+    #[test]
+    fn range_allows_its_addresses_only() {
+        assert!(allows("10.0.0.0/8", "10.255.0.1"));
+        assert!(!allows("10.0.0.0/8", "11.0.0.1"));
+        assert!(allows("172.16.0.0/12,10.0.0.0/8", "172.31.255.255"));
+        assert!(!allows("172.16.0.0/12", "172.32.0.0"));
+    }
+
+    #[test]
+    fn mapped_ipv6_peer_matches_ipv4_range() {
+        assert!(allows("10.0.0.0/8", "::ffff:10.0.0.1"));
+        assert!(!allows("10.0.0.0/8", "::ffff:11.0.0.1"));
+    }
+
+    #[test]
+    fn wrong_rules_are_refused() {
+        assert!(AllowList::parse("10.0.0.0/33").is_err());
+        assert!(AllowList::parse("::/129").is_err());
+        assert!(AllowList::parse("10.0.0.0/-1").is_err());
+        assert!(AllowList::parse("10.0.0/8").is_err());
+        assert!(AllowList::parse(" , ").is_err());
+    }
+
+    #[test]
+    fn token_must_match_exactly() {
+        assert!(token_matches("secret", b"secret"));
+        assert!(!token_matches("secret", b"secreT"));
+        assert!(!token_matches("secret", b"secret2"));
+        assert!(!token_matches("secret", b"secre"));
+        assert!(!token_matches("secret", b""));
+        assert!(parse_token("").is_err());
+        assert!(parse_token(&"x".repeat(65536)).is_err());
+        assert!(parse_token(&"x".repeat(65535)).is_ok());
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use crate::analysis::{walk_snapshot, Walk};
+use crate::intern::SourceTable;
+use crate::snapshot::Snapshot;
+
+// Frames of the other capture searched for match of the next frame, while in sync
+const ALIGN_WINDOW: usize = 200;
+// Weighted Jaccard similarity of zone counts needed to pair two frames
+const MIN_SIMILARITY: f64 = 0.8;
+// Similarity given up per frame skipped in the other capture, so among similar frames the nearest one wins
+const SKIP_PENALTY: f64 = 0.001;
+
+// Zone counts of every frame by zone name id, sorted by id. Frames are numbered like in zone stats,
+// zones are counted in the frame they end in
+fn fingerprints(snapshot: &Snapshot, table: &SourceTable, names: &mut HashMap<String, u32>) -> Result<Vec<Vec<(u32, u32)>>, String> {
+    let mut ids = HashMap::<u64, u32>::new();
+    let mut frames = Vec::<HashMap<u32, u32>>::new();
+    walk_snapshot(snapshot, |walk| {
+        let Walk::Zone(zone) = walk else {
+            return;
+        };
+        let name = table.locations[zone.location as usize].name;
+        let id = *ids.entry(name).or_insert_with(|| {
+            let next = names.len() as u32;
+            return *names.entry(table.strings.get(&name).cloned().unwrap_or_default()).or_insert(next);
+        });
+        if frames.len() <= zone.frame as usize {
+            frames.resize_with(zone.frame as usize + 1, HashMap::new);
+        }
+        *frames[zone.frame as usize].entry(id).or_default() += 1;
+    })?;
+    return Ok(frames.into_iter().map(|counts| {
+        let mut counts: Vec<(u32, u32)> = counts.into_iter().collect();
+        counts.sort_unstable();
+        return counts;
+    }).collect());
+}
+
+// Sum of smaller counts over sum of larger counts, 1 for frames running the same zones the same number of times
+fn similarity(a: &[(u32, u32)], b: &[(u32, u32)]) -> f64 {
+    let total = |counts: &[(u32, u32)]| counts.iter().map(|(_, count)| *count as u64).sum::<u64>();
+    let (mut i, mut j, mut common) = (0, 0, 0u64);
+    while i < a.len() && j < b.len() {
+        if a[i].0 == b[j].0 {
+            common += a[i].1.min(b[j].1) as u64;
+            i += 1;
+            j += 1;
+        } else if a[i].0 < b[j].0 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    return common as f64 / (total(a) + total(b) - common).max(1) as f64;
+}
+
+// Pairs of (current frame, baseline frame) doing the same work, matched by zone composition instead of index, so rounds
+// that diverge early (longer setup, lag spike, restart) still compare like with like. Pairs keep frame order, each frame is
+// matched to the most similar following unpaired frame in a window. After a window of unmatched frames the whole rest is searched once
+pub fn align_frames(current: &Snapshot, current_table: &SourceTable, baseline: &Snapshot, baseline_table: &SourceTable) -> Result<Vec<(u32, u32)>, String> {
+    let mut names = HashMap::new();
+    let current = fingerprints(current, current_table, &mut names)?;
+    let baseline = fingerprints(baseline, baseline_table, &mut names)?;
+    let mut pairs = Vec::new();
+    let (mut next, mut unmatched) = (0, 0);
+    for (index, counts) in current.iter().enumerate().filter(|(_, counts)| !counts.is_empty()) {
+        let resync = unmatched >= ALIGN_WINDOW;
+        let end = if resync { baseline.len() } else { (next + ALIGN_WINDOW).min(baseline.len()) };
+        let best = (next..end).map(|other| (other, similarity(counts, &baseline[other])))
+            .filter(|(_, similarity)| *similarity >= MIN_SIMILARITY)
+            .map(|(other, similarity)| (other, similarity - (other - next) as f64 * SKIP_PENALTY))
+            .fold(None, |best: Option<(usize, f64)>, candidate| if best.is_some_and(|best| best.1 >= candidate.1) { best } else { Some(candidate) });
+        match best {
+            Some((other, _)) => {
+                pairs.push((index as u32, other as u32));
+                next = other + 1;
+                unmatched = 0;
+            }
+            None if resync => unmatched = 0,
+            None => unmatched += 1,
+        }
+    }
+    return Ok(pairs);
+}
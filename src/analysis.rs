@@ -0,0 +1,384 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use bincode::error::DecodeError;
+use serde::{Deserialize, Serialize};
+use crate::args::Arguments;
+use crate::failure::{fail, usage, ErrorKind};
+use crate::groups::ZoneGroups;
+use crate::intern::SourceTable;
+use crate::snapshot::Snapshot;
+use crate::structs::{BINCODE_CONFIG, EventType, UTracyEvent};
+
+// Zone occurrence resolved from begin/end pair, timestamps are in snapshot ticks
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Zone {
+    pub thread_id: u32,
+    // Index in SourceTable::locations
+    pub location: u32,
+    pub start: u64,
+    pub end: u64,
+    // Time spent in direct children, in ticks
+    pub children: u64,
+    pub depth: u32,
+    pub frame: u32,
+}
+
+pub enum Walk {
+    Begin { thread_id: u32, location: u32, timestamp: u64 },
+    Zone(Zone),
+    Frame { index: u32, timestamp: u64 },
+}
+
+// Decodes snapshot events and reports every opened zone, closed zone and frame mark in file order,
+// End events without matching Begin are dropped
+pub fn walk_snapshot<F: FnMut(Walk)>(snapshot: &Snapshot, mut callback: F) -> Result<(), String> {
+    return walk_snapshot_while(snapshot, |walk| {
+        callback(walk);
+        true
+    });
+}
+
+// Same as walk_snapshot, stops as soon as callback returns false
+pub fn walk_snapshot_while<F: FnMut(Walk) -> bool>(snapshot: &Snapshot, callback: F) -> Result<(), String> {
+    let mut events_data = snapshot.open_events()?;
+    return walk_events(&mut events_data, snapshot.location_offset, snapshot.location_count, callback);
+}
+
+// Event walk over any reader positioned at the first event, location_offset is added to reported locations.
+// Fails on Begin outside the location_count locations of snapshot, so callbacks can index SourceTable directly
+pub fn walk_events<R: Read, F: FnMut(Walk) -> bool>(events_data: &mut R, location_offset: u32, location_count: u32, mut callback: F) -> Result<(), String> {
+    let mut stacks = HashMap::<u32, Vec<(u32, u64, u64)>>::new();
+    let mut frame = 0u32;
+    loop {
+        let e1: Result<UTracyEvent, DecodeError> = bincode::decode_from_std_read(events_data, BINCODE_CONFIG);
+        let Ok(event) = e1 else {
+            break;
+        };
+        unsafe {
+            match event.event_type {
+                EventType::Begin => {
+                    let begin = event.event.begin;
+                    let location = location_offset.checked_add(begin.source_location).filter(|_| begin.source_location < location_count)
+                        .ok_or_else(|| bad_location(begin.source_location, location_count))?;
+                    stacks.entry(begin.thread_id).or_default().push((location, begin.timestamp, 0));
+                    if !callback(Walk::Begin { thread_id: begin.thread_id, location, timestamp: begin.timestamp }) {
+                        break;
+                    }
+                }
+                EventType::End => {
+                    let end = event.event.end;
+                    let stack = stacks.entry(end.thread_id).or_default();
+                    if let Some((location, start, children)) = stack.pop() {
+                        if let Some(parent) = stack.last_mut() {
+                            parent.2 += end.timestamp.saturating_sub(start);
+                        }
+                        let zone = Zone {
+                            thread_id: end.thread_id,
+                            location,
+                            start,
+                            end: end.timestamp,
+                            children,
+                            depth: stack.len() as u32,
+                            frame,
+                        };
+                        if !callback(Walk::Zone(zone)) {
+                            break;
+                        }
+                    }
+                }
+                EventType::Color => {}
+                EventType::Mark => {
+                    if !callback(Walk::Frame { index: frame, timestamp: event.event.mark.timestamp }) {
+                        break;
+                    }
+                    frame += 1;
+                }
+            }
+        }
+    }
+    return Ok(());
+}
+
+pub fn bad_location(location: u32, location_count: u32) -> String {
+    return fail(ErrorKind::Corrupted, format!("Event references source location {location} but capture has only {location_count}, it is corrupted"));
+}
+
+pub struct ZoneStats {
+    pub name: String,
+    // Durations in nanoseconds, sorted after collection
+    pub durations: Vec<u64>,
+    pub total: u64,
+    // Total minus time spent in child zones
+    pub self_total: u64,
+}
+
+impl Zone {
+    pub fn duration(&self) -> u64 {
+        return self.end.saturating_sub(self.start);
+    }
+
+    pub fn self_time(&self) -> u64 {
+        return self.duration().saturating_sub(self.children);
+    }
+}
+
+impl ZoneStats {
+    pub fn count(&self) -> usize {
+        return self.durations.len();
+    }
+
+    pub fn mean(&self) -> u64 {
+        if self.durations.is_empty() {
+            return 0;
+        }
+        return self.total / self.durations.len() as u64;
+    }
+
+    // Nearest-rank percentile, p in 0..=100
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.durations.is_empty() {
+            return 0;
+        }
+        let rank = ((p / 100f64) * self.durations.len() as f64).ceil() as usize;
+        return self.durations[rank.clamp(1, self.durations.len()) - 1];
+    }
+}
+
+pub fn ticks_to_ns(snapshot: &Snapshot, ticks: u64) -> u64 {
+    return (ticks as f64 * snapshot.header.multiplier) as u64;
+}
+
+// Phase selection and outlier handling of zone stats, percentages are taken from each end of every zone's durations
+#[derive(Clone, Default)]
+pub struct StatsOptions {
+    // Frame range first..end, end excluded, zones are counted in the frame they end in
+    pub frames: Option<(u32, u32)>,
+    // Drop slowest and fastest occurrences
+    pub trim: f64,
+    // Clamp slowest and fastest occurrences to the nearest kept duration
+    pub winsorize: f64,
+    // Zones matching grouping rules are reported under their group name
+    pub groups: Option<ZoneGroups>,
+    // Only these frames are counted, e.g. frames aligned with other capture
+    pub frame_set: Option<HashSet<u32>>,
+}
+
+impl StatsOptions {
+    pub fn from_arguments(arguments: &Arguments) -> Result<StatsOptions, String> {
+        let frames = arguments.get("--frames").map(parse_frame_range).transpose()?;
+        let trim: f64 = arguments.get_parsed("--trim")?.unwrap_or(0f64);
+        let winsorize: f64 = arguments.get_parsed("--winsorize")?.unwrap_or(0f64);
+        if !(0f64..50f64).contains(&trim) || !(0f64..50f64).contains(&winsorize) {
+            return Err(usage("Wrong input: --trim and --winsorize take percent of each end, 0 to 50"));
+        }
+        let groups = arguments.get("--groups").map(ZoneGroups::load).transpose()?;
+        return Ok(StatsOptions { frames, trim, winsorize, groups, frame_set: None });
+    }
+
+    fn contains_frame(&self, frame: u32) -> bool {
+        return self.frames.is_none_or(|(first, end)| frame >= first && frame < end) && self.frame_set.as_ref().is_none_or(|set| set.contains(&frame));
+    }
+
+    // Occurrences sorted by duration, each one is (duration, self time)
+    fn apply(&self, occurrences: &mut Vec<(u64, u64)>) {
+        let trimmed = (occurrences.len() as f64 * self.trim / 100f64) as usize;
+        if trimmed > 0 {
+            occurrences.truncate(occurrences.len() - trimmed);
+            occurrences.drain(..trimmed);
+        }
+        let clamped = (occurrences.len() as f64 * self.winsorize / 100f64) as usize;
+        if clamped > 0 {
+            let (low, high) = (occurrences[clamped].0, occurrences[occurrences.len() - 1 - clamped].0);
+            for (duration, self_time) in occurrences.iter_mut() {
+                let value = (*duration).clamp(low, high);
+                *self_time = (*self_time).min(value);
+                *duration = value;
+            }
+        }
+    }
+}
+
+// "500..2000", "..500" or "500..", frame numbers as counted by frame marks
+fn parse_frame_range(text: &str) -> Result<(u32, u32), String> {
+    let wrong = || usage(format!("Wrong input: --frames {text}, expected first..end"));
+    let (first, end) = text.split_once("..").ok_or_else(wrong)?;
+    let first = if first.is_empty() { 0 } else { first.parse().map_err(|_| wrong())? };
+    let end = if end.is_empty() { u32::MAX } else { end.parse().map_err(|_| wrong())? };
+    if first >= end {
+        return Err(wrong());
+    }
+    return Ok((first, end));
+}
+
+// Zone durations grouped by zone name, locations sharing a name are merged
+pub fn collect_zone_stats(snapshot: &Snapshot, table: &SourceTable) -> Result<Vec<ZoneStats>, String> {
+    return collect_zone_stats_with(snapshot, table, &StatsOptions::default());
+}
+
+pub fn collect_zone_stats_with(snapshot: &Snapshot, table: &SourceTable, options: &StatsOptions) -> Result<Vec<ZoneStats>, String> {
+    let mut events_data = snapshot.open_events()?;
+    return collect_zone_stats_from(&mut events_data, snapshot.location_offset, snapshot.location_count, snapshot.header.multiplier, table, options);
+}
+
+// Stats key of every zone name, names matched by grouping rules share one key
+struct StatsKeys<'a> {
+    table: &'a SourceTable,
+    groups: Option<&'a ZoneGroups>,
+    by_name: HashMap<u64, usize>,
+    by_label: HashMap<String, usize>,
+    // Label and whether it is a group
+    labels: Vec<(String, bool)>,
+}
+
+impl StatsKeys<'_> {
+    fn key(&mut self, location: u32) -> usize {
+        let name = self.table.locations[location as usize].name;
+        if let Some(key) = self.by_name.get(&name) {
+            return *key;
+        }
+        let zone = self.table.strings.get(&name).cloned().unwrap_or_default();
+        let (label, grouped) = match self.groups.and_then(|groups| groups.group_of(&zone)) {
+            Some(group) => (group, true),
+            None => (zone, false),
+        };
+        let next = self.labels.len();
+        let key = *self.by_label.entry(label.clone()).or_insert(next);
+        if key == next {
+            self.labels.push((label, grouped));
+        }
+        self.by_name.insert(name, key);
+        return key;
+    }
+}
+
+pub fn collect_zone_stats_from<R: Read>(events_data: &mut R, location_offset: u32, location_count: u32, multiplier: f64, table: &SourceTable, options: &StatsOptions) -> Result<Vec<ZoneStats>, String> {
+    let ticks_to_ns = |ticks: u64| (ticks as f64 * multiplier) as u64;
+    let mut keys = StatsKeys { table, groups: options.groups.as_ref(), by_name: HashMap::new(), by_label: HashMap::new(), labels: Vec::new() };
+    let mut occurrences = Vec::<Vec<(u64, u64)>>::new();
+    // Open zones with self time of nested zones of the same group, only tracked when grouping
+    let mut stacks = HashMap::<u32, Vec<(usize, u64)>>::new();
+    walk_events(events_data, location_offset, location_count, |walk| {
+        match walk {
+            Walk::Begin { thread_id, location, .. } if options.groups.is_some() => {
+                let key = keys.key(location);
+                stacks.entry(thread_id).or_default().push((key, 0));
+            }
+            Walk::Zone(zone) => {
+                let key = keys.key(zone.location);
+                if occurrences.len() <= key {
+                    occurrences.resize_with(key + 1, Vec::new);
+                }
+                let mut self_time = ticks_to_ns(zone.self_time());
+                if options.groups.is_some() {
+                    let stack = stacks.entry(zone.thread_id).or_default();
+                    self_time += stack.pop().map_or(0, |(_, nested)| nested);
+                    // Group zone inside another zone of the same group adds only its self time, so group total is not counted twice
+                    if keys.labels[key].1 {
+                        if let Some(outer) = stack.iter_mut().rev().find(|(outer, _)| *outer == key) {
+                            outer.1 += self_time;
+                            return true;
+                        }
+                    }
+                }
+                if options.contains_frame(zone.frame) {
+                    occurrences[key].push((ticks_to_ns(zone.duration()), self_time));
+                }
+            }
+            _ => {}
+        }
+        true
+    })?;
+    let mut result: Vec<ZoneStats> = occurrences.into_iter().enumerate().filter_map(|(key, mut occurrences)| {
+        occurrences.sort_unstable();
+        options.apply(&mut occurrences);
+        if occurrences.is_empty() {
+            return None;
+        }
+        return Some(ZoneStats {
+            name: keys.labels[key].0.clone(),
+            durations: occurrences.iter().map(|(duration, _)| *duration).collect(),
+            total: occurrences.iter().map(|(duration, _)| duration).sum(),
+            self_total: occurrences.iter().map(|(_, self_time)| self_time).sum(),
+        });
+    }).collect();
+    result.sort_by_key(|s| Reverse(s.total));
+    return Ok(result);
+}
+
+pub struct ZoneDelta {
+    pub name: String,
+    pub mean: u64,
+    pub baseline_mean: u64,
+    // Change of total time if baseline calls took current mean, ranks changes by impact instead of by percent
+    pub impact: i64,
+}
+
+impl ZoneDelta {
+    pub fn percent(&self) -> f64 {
+        return (self.mean as f64 / self.baseline_mean.max(1) as f64 - 1f64) * 100f64;
+    }
+}
+
+// Zones present in both captures whose mean changed by at least min_percent, biggest impact first
+pub fn compare_zone_stats(current: &[ZoneStats], baseline: &[ZoneStats], min_percent: f64) -> Vec<ZoneDelta> {
+    let baseline: HashMap<&str, &ZoneStats> = baseline.iter().map(|stats| (stats.name.as_str(), stats)).collect();
+    let mut deltas: Vec<ZoneDelta> = current.iter().filter_map(|stats| {
+        let old = baseline.get(stats.name.as_str())?;
+        return Some(ZoneDelta {
+            name: stats.name.clone(),
+            mean: stats.mean(),
+            baseline_mean: old.mean(),
+            impact: (stats.mean() as i64 - old.mean() as i64) * stats.count() as i64,
+        });
+    }).filter(|delta| delta.percent().abs() >= min_percent).collect();
+    deltas.sort_by_key(|delta| Reverse(delta.impact.abs()));
+    return deltas;
+}
+
+// Periods longer than min_gap ticks without any event (server hibernation, round end), in file order
+pub fn find_idle_gaps(snapshot: &Snapshot, min_gap: u64) -> Result<Vec<(u64, u64)>, String> {
+    let mut gaps = Vec::new();
+    let mut last: Option<u64> = None;
+    walk_snapshot(snapshot, |walk| {
+        let timestamp = match walk {
+            Walk::Begin { timestamp, .. } => timestamp,
+            Walk::Zone(zone) => zone.end,
+            Walk::Frame { timestamp, .. } => timestamp,
+        };
+        if let Some(last) = last {
+            if timestamp > last && timestamp - last > min_gap {
+                gaps.push((last, timestamp));
+            }
+        }
+        last = Some(last.map_or(timestamp, |last| last.max(timestamp)));
+    })?;
+    return Ok(gaps);
+}
+
+// Timestamps of all frame marks, frame N spans marks[N - 1]..marks[N]
+pub fn collect_frame_marks(snapshot: &Snapshot) -> Result<Vec<u64>, String> {
+    let mut marks = Vec::new();
+    walk_snapshot(snapshot, |walk| {
+        if let Walk::Frame { timestamp, .. } = walk {
+            marks.push(timestamp);
+        }
+    })?;
+    return Ok(marks);
+}
+
+pub fn median(sorted: &[u64]) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    return sorted[sorted.len() / 2];
+}
+
+pub fn standard_deviation(values: &[u64]) -> f64 {
+    if values.is_empty() {
+        return 0f64;
+    }
+    let mean = values.iter().map(|v| *v as f64).sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (*v as f64 - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    return variance.sqrt();
+}
@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use crate::args::Arguments;
+use crate::failure::{open_failed, usage};
+use crate::seal::sealed_length;
+use crate::snapshot::FILE_SIGNATURE;
+use crate::structs::{fnv1a, BINCODE_CONFIG, FNV_OFFSET, U32SizeString, UTracyHeader, UTracySourceLocation};
+use crate::writer::create_snapshot;
+
+fn hash_text(text: &str) -> String {
+    return format!("{:016x}", fnv1a(FNV_OFFSET, text.as_bytes()));
+}
+
+fn fixed_text<const N: usize>(text: &str) -> [u8; N] {
+    let mut result = [0u8; N];
+    let len = text.len().min(N - 1);
+    result[..len].copy_from_slice(&text.as_bytes()[..len]);
+    return result;
+}
+
+// Hashing keeps equal paths equal, so per-file grouping still works on anonymized captures
+fn anonymize_path(path: &str, hash: bool) -> String {
+    if path.is_empty() {
+        return String::new();
+    }
+    if hash {
+        let extension = path.rsplit_once('.').map(|(_, e)| e).filter(|e| !e.contains(['/', '\\'])).unwrap_or("");
+        return format!("{}.{extension}", hash_text(path));
+    }
+    return String::new();
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &["--hash"])?;
+    arguments.check_known(&["-o", "--hash"])?;
+    let (Some(path), Some(output)) = (arguments.positional.first(), arguments.get("-o")) else {
+        return Err(usage("Use: anonymize <file> -o out.utracy [--hash]"));
+    };
+    let hash = arguments.has("--hash");
+
+    let mut reader = BufReader::new(File::open(path).map_err(|e| open_failed(path, e))?);
+    let mut header: UTracyHeader = bincode::decode_from_reader(&mut reader, BINCODE_CONFIG).map_err(|e| format!("{path}: {e}"))?;
+    if header.signature != FILE_SIGNATURE || header.version != 2 {
+        return Err(format!("{path} is not utracy v2 snapshot"));
+    }
+    let program_name = String::from_utf8_lossy(&header.program_name).trim_end_matches('\0').to_string();
+    header.program_name = fixed_text(&if hash { hash_text(&program_name) } else { "program".into() });
+    header.host_info = [0u8; 1024];
+    header.process_id = 0;
+    let sealed_length = sealed_length(path, &header)?;
+    header.seal_marker = 0;
+
+    let location_count: u32 = bincode::decode_from_reader(&mut reader, BINCODE_CONFIG).map_err(|e| format!("{path}: {e}"))?;
+    let mut locations = Vec::with_capacity(location_count as usize);
+    for _ in 0..location_count {
+        let mut location: UTracySourceLocation = bincode::decode_from_reader(&mut reader, BINCODE_CONFIG).map_err(|e| format!("{path}: {e}"))?;
+        location.file = U32SizeString(anonymize_path(&location.file.0, hash));
+        locations.push(location);
+    }
+    let mut writer = create_snapshot(output, &header, locations.iter())?;
+
+    // Events reference locations by index and carry no strings, copied as is without seal trailer
+    let position = reader.stream_position().map_err(|e| format!("{path}: {e}"))?;
+    let events_length = sealed_length.map(|length| length - position).unwrap_or(u64::MAX);
+    let events = writer.copy_events(&mut (&mut reader).take(events_length)).map_err(|e| format!("{output}: {e}"))?;
+    writer.finish().map_err(|e| format!("{output}: {e}"))?;
+    println!("Anonymized {location_count} source locations, copied {events} events to {output}");
+    return Ok(());
+}
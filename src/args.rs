@@ -0,0 +1,62 @@
+use std::str::FromStr;
+use crate::failure::usage;
+
+// Command line split into positional values and "-x value" / "--flag" options
+pub struct Arguments {
+    pub positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Arguments {
+    // Options listed in flags take no value, every other option consumes the next argument
+    pub fn parse(args: &[String], flags: &[&str]) -> Result<Arguments, String> {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        let mut i = 0;
+        while i < args.len() {
+            let arg = &args[i];
+            if arg.starts_with('-') && arg.len() > 1 {
+                if flags.contains(&arg.as_str()) {
+                    options.push((arg.clone(), None));
+                } else {
+                    let value = args.get(i + 1).ok_or_else(|| usage(format!("Missing value for option {arg}")))?;
+                    options.push((arg.clone(), Some(value.clone())));
+                    i += 1;
+                }
+            } else {
+                positional.push(arg.clone());
+            }
+            i += 1;
+        }
+        return Ok(Arguments { positional, options });
+    }
+
+    // Fails on options not listed in known, so typos are not silently ignored
+    pub fn check_known(&self, known: &[&str]) -> Result<(), String> {
+        for (name, _) in &self.options {
+            if !known.contains(&name.as_str()) {
+                return Err(usage(format!("Wrong option {name}")));
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        return self.options.iter().any(|(n, _)| n == name);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        return self.options.iter().rev().find(|(n, _)| n == name).and_then(|(_, v)| v.as_deref());
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        return self.options.iter().filter(|(n, _)| n == name).filter_map(|(_, v)| v.as_deref()).collect();
+    }
+
+    pub fn get_parsed<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        return match self.get(name) {
+            None => Ok(None),
+            Some(value) => value.parse().map(Some).map_err(|_| usage(format!("Wrong input: {name} {value}"))),
+        };
+    }
+}
@@ -0,0 +1,34 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+use serde::Serialize;
+
+// One JSON line per client connection in --audit-log file, refused handshakes included
+#[derive(Serialize)]
+pub struct SessionAudit {
+    pub peer: String,
+    // Unix seconds when client connected
+    pub start: u64,
+    pub duration_ms: u64,
+    pub captures: Vec<String>,
+    pub profile: Option<String>,
+    // Unnamed frame marks streamed to client
+    pub frames: u32,
+    pub queries: u64,
+    // Compressed event and answer blocks with their size prefixes
+    pub bytes_sent: u64,
+    // Refusal or disconnect reason, null when session ended normally
+    pub error: Option<String>,
+}
+
+// Connections finish on their own threads, lines must not interleave
+static AUDIT_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn append_audit(path: &str, record: &SessionAudit) -> Result<(), String> {
+    let mut line = serde_json::to_string(record).map_err(|e| format!("{path}: {e}"))?;
+    line.push('\n');
+    let _guard = AUDIT_LOCK.lock().unwrap();
+    let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| format!("Error opening audit log {path}: {e}"))?;
+    file.write_all(line.as_bytes()).map_err(|e| format!("Error writing audit log {path}: {e}"))?;
+    return Ok(());
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use bincode::error::DecodeError;
+use crate::args::Arguments;
+use crate::failure::{open_failed, usage};
+use crate::intern::SourceTable;
+use crate::pool::{WorkerPool, DEFAULT_WORKERS};
+use crate::snapshot::{load_snapshot, read_header, read_locations, Snapshot};
+use crate::server::{stream_to_memory, MemoryStream, Profile, ServerOptions, DEFAULT_BATCH_EVENTS, MAX_BATCH_BYTES};
+use crate::structs::{BINCODE_CONFIG, UTracyEvent};
+
+// Counts bytes pulled through decoder
+struct CountingReader<R: Read> {
+    inner: R,
+    bytes: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes += read as u64;
+        return Ok(read);
+    }
+}
+
+fn print_stage(stage: &str, time: Duration, events: u64, bytes: u64) {
+    let seconds = time.as_secs_f64().max(1e-9);
+    println!("{stage:<10} {:>10.3} s {:>14.0} {:>12.1}", seconds, events as f64 / seconds, bytes as f64 / seconds / 1_000_000f64);
+}
+
+// Only decodes events, the part of serving done by read ahead thread
+fn decode_events(snapshot: &Snapshot) -> Result<(u64, u64), String> {
+    let mut reader = CountingReader { inner: snapshot.open_events()?, bytes: 0 };
+    let mut events = 0u64;
+    loop {
+        let event: Result<UTracyEvent, DecodeError> = bincode::decode_from_std_read(&mut reader, BINCODE_CONFIG);
+        if event.is_err() {
+            break;
+        }
+        events += 1;
+    }
+    return Ok((events, reader.bytes));
+}
+
+// Session defaults of serve, nothing filtered, gaps kept and every frame sent
+fn bench_options(batch_bytes: usize) -> ServerOptions {
+    return ServerOptions {
+        snapshot_names: vec![0],
+        batch_events: DEFAULT_BATCH_EVENTS,
+        batch_bytes,
+        gaps: vec![Vec::new()],
+        collapse: vec![Vec::new()],
+        ..ServerOptions::default()
+    };
+}
+
+fn bench_profile() -> Profile {
+    return Profile { name: None, skip_frames: 0, limit_frames: u32::MAX, filter_script: None, frame_stride: 1, within: None, max_depth: None, thread_names: HashMap::new() };
+}
+
+// Every client streams whole snapshot with its own reader like server sessions do, on the same bounded pool
+fn run_parallel(snapshot: Snapshot, table: SourceTable, events: u64, batch_bytes: usize, clients: usize, workers: usize) -> Result<(), String> {
+    let served: &'static (Vec<Snapshot>, SourceTable, ServerOptions) = Box::leak(Box::new((vec![snapshot], table, bench_options(batch_bytes))));
+    let pool = WorkerPool::start(workers);
+    let (sender, receiver) = mpsc::channel();
+    let start = Instant::now();
+    let mut peak_queued = 0;
+    for _ in 0..clients {
+        let sender = sender.clone();
+        let waiting = pool.submit(Box::new(move || {
+            let session_start = Instant::now();
+            let (snapshots, table, options) = served;
+            let _ = sender.send(stream_to_memory(snapshots, table, options, &bench_profile()).map(|stream| (stream, session_start.elapsed())));
+        }));
+        peak_queued = peak_queued.max(waiting);
+    }
+    drop(sender);
+    let mut sent_bytes = 0u64;
+    let mut slowest = Duration::ZERO;
+    for result in receiver {
+        let (stream, time) = result?;
+        sent_bytes += stream.sent_bytes;
+        slowest = slowest.max(time);
+    }
+    let total_time = start.elapsed();
+    let saturated = pool.status().saturated;
+    println!("{clients} clients on {workers} workers, {saturated} waited for worker, at most {peak_queued} queued");
+    println!("{:<10} {:>12} {:>14} {:>12}", "", "Time", "Events/s", "MB/s sent");
+    print_stage("total", total_time, events * clients as u64, sent_bytes);
+    print_stage("slowest", slowest, events, sent_bytes / clients as u64);
+    return Ok(());
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--batch-bytes", "--clients", "--workers"])?;
+    let Some(path) = arguments.positional.first() else {
+        return Err(usage("Use: bench <file> [--batch-bytes n] [--clients n] [--workers n]"));
+    };
+    let batch_bytes: usize = arguments.get_parsed("--batch-bytes")?.unwrap_or(MAX_BATCH_BYTES);
+    if batch_bytes == 0 || batch_bytes > MAX_BATCH_BYTES {
+        return Err(usage(format!("Wrong input: --batch-bytes {batch_bytes}, expected 1..={MAX_BATCH_BYTES}")));
+    }
+    let clients: usize = arguments.get_parsed("--clients")?.unwrap_or(1);
+    let workers: usize = arguments.get_parsed("--workers")?.unwrap_or(DEFAULT_WORKERS);
+    if clients == 0 || workers == 0 {
+        return Err(usage("Wrong input: --clients and --workers need at least one"));
+    }
+    // Locations are read past the cache, so load time shows actual string processing
+    let load_start = Instant::now();
+    let mut location_reader = BufReader::new(File::open(path).map_err(|e| open_failed(path, e))?);
+    read_header(&mut location_reader).map_err(|e| format!("{path}: {e}"))?;
+    let (_, location_count) = read_locations(&mut location_reader, &mut SourceTable::new(), None).map_err(|e| format!("{path}: {e}"))?;
+    let load_time = load_start.elapsed();
+    drop(location_reader);
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+
+    let start = Instant::now();
+    let (events, file_bytes) = decode_events(&snapshot)?;
+    let decode_time = start.elapsed();
+    if clients > 1 {
+        return run_parallel(snapshot, table, events, batch_bytes, clients, workers);
+    }
+    // Read ahead, encoding and compression overlap in sessions, so they are timed together
+    let snapshots = [snapshot];
+    let options = bench_options(batch_bytes);
+    let stream_start = Instant::now();
+    let MemoryStream { frames, encoded_bytes, sent_bytes } = stream_to_memory(&snapshots, &table, &options, &bench_profile())?;
+    let stream_time = stream_start.elapsed();
+    let total_time = start.elapsed();
+
+    println!("{events} events, {frames} frames, {:.1} MB snapshot, {:.1} MB encoded, {:.1} MB compressed ({:.1}x)", file_bytes as f64 / 1_000_000f64,
+             encoded_bytes as f64 / 1_000_000f64, sent_bytes as f64 / 1_000_000f64, encoded_bytes as f64 / sent_bytes.max(1) as f64);
+    println!("{location_count} source locations loaded in {:.3} s ({:.0} locations/s)", load_time.as_secs_f64(),
+             location_count as f64 / load_time.as_secs_f64().max(1e-9));
+    println!("{:<10} {:>12} {:>14} {:>12}", "Stage", "Time", "Events/s", "MB/s");
+    print_stage("decode", decode_time, events, file_bytes);
+    print_stage("stream", stream_time, events, encoded_bytes);
+    print_stage("total", total_time, events, file_bytes);
+    return Ok(());
+}
@@ -0,0 +1,57 @@
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+use crate::server::SharedServed;
+use crate::structs::{BINCODE_CONFIG, NetworkBroadcast, PROTOCOL_VERSION};
+
+// Tracy UI listens on this UDP port and lists senders as clients it can connect to
+pub const BROADCAST_PORT: u16 = 8086;
+const BROADCAST_VERSION: u16 = 3;
+const BROADCAST_INTERVAL: Duration = Duration::from_secs(3);
+// Program name with its NUL fills at most WelcomeMessageProgramNameSize of Tracy
+const PROGRAM_NAME_SIZE: usize = 64;
+
+fn broadcast_message(port: u16, process_id: u64, active_time: i32, program_name: &str) -> Result<Vec<u8>, String> {
+    let mut message = bincode::encode_to_vec(NetworkBroadcast {
+        broadcast_version: BROADCAST_VERSION,
+        listen_port: port,
+        protocol_version: PROTOCOL_VERSION,
+        process_id,
+        active_time,
+    }, BINCODE_CONFIG).map_err(|e| format!("{e}"))?;
+    let name = program_name.as_bytes();
+    message.extend_from_slice(&name[..name.len().min(PROGRAM_NAME_SIZE - 1)]);
+    message.push(0);
+    return Ok(message);
+}
+
+// Announces listener every 3 seconds like Tracy clients do, with program and process of the first served capture.
+// Nothing is sent while no capture is uploaded
+pub fn spawn_broadcast(port: u16, served: &'static SharedServed) -> Result<(), String> {
+    let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).map_err(|e| format!("Error opening broadcast socket: {e}"))?;
+    socket.set_broadcast(true).map_err(|e| format!("Error enabling broadcast: {e}"))?;
+    let start = Instant::now();
+    thread::spawn(move || {
+        let mut failing = false;
+        loop {
+            let current = served.read().unwrap().clone();
+            if let Some(current) = current {
+                let snapshot = &current.snapshots[0];
+                let active_time = start.elapsed().as_secs().min(i32::MAX as u64) as i32;
+                let result = broadcast_message(port, snapshot.header.process_id, active_time, snapshot.program_name())
+                    .and_then(|message| socket.send_to(&message, (Ipv4Addr::BROADCAST, BROADCAST_PORT)).map_err(|e| format!("{e}")));
+                // Reported once until sending works again, network may come up later
+                match result {
+                    Ok(_) => failing = false,
+                    Err(msg) if !failing => {
+                        println!("Discovery broadcast failed: {msg}");
+                        failing = true;
+                    }
+                    Err(_) => {}
+                }
+            }
+            sleep(BROADCAST_INTERVAL);
+        }
+    });
+    return Ok(());
+}
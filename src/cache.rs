@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use bincode::{Decode, Encode};
+use crate::intern::SourceTable;
+use crate::structs::{BINCODE_CONFIG, SourceLocation, U32SizeString, UTracyHeader};
+
+const CACHE_VERSION: u32 = 3;
+// Smaller snapshots load fast enough without leaving cache files around
+const MIN_CACHED_LOCATIONS: u32 = 10_000;
+
+// Processed locations of one snapshot with their strings, valid while snapshot file is unchanged.
+// File starts with version and key, so caches of other versions are rejected before their layout is decoded
+#[derive(Encode, Decode)]
+struct LocationCache {
+    events_position: u64,
+    locations: Vec<SourceLocation>,
+    ids: Vec<u64>,
+    strings: Vec<(u64, String)>,
+}
+
+// Per user cache directory, captures may be in directories which are read-only or shared with other users
+fn cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| Path::new(&home).join("Library").join("Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    return base.map(|base| base.join("rtracy"));
+}
+
+// Named after capture and checksum of its absolute path, so captures with the same name in different directories don't share cache
+pub fn cache_path(path: &str) -> Option<PathBuf> {
+    let absolute = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let name = Path::new(path).file_name()?.to_string_lossy();
+    let checksum = crc32fast::hash(absolute.to_string_lossy().as_bytes());
+    return Some(cache_dir()?.join(format!("{name}-{checksum:08x}.rtcache")));
+}
+
+// File length, modification time and header checksum, cheap to compute compared to hashing whole capture
+fn file_key(path: &str, header: &UTracyHeader) -> Option<u64> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64;
+    let header_bytes = bincode::encode_to_vec(header, BINCODE_CONFIG).ok()?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header_bytes);
+    hasher.update(&metadata.len().to_le_bytes());
+    hasher.update(&modified.to_le_bytes());
+    return Some((hasher.finalize() as u64) << 32 ^ modified);
+}
+
+// Appends cached locations to the table, returns index of the first one, their count and position of events
+pub fn load(path: &str, header: &UTracyHeader, table: &mut SourceTable) -> Option<(u32, u32, u64)> {
+    let key = file_key(path, header)?;
+    let mut reader = BufReader::new(File::open(cache_path(path)?).ok()?);
+    let (version, cache_key): (u32, u64) = bincode::decode_from_std_read(&mut reader, BINCODE_CONFIG).ok()?;
+    if version != CACHE_VERSION || cache_key != key {
+        return None;
+    }
+    let cache: LocationCache = bincode::decode_from_std_read(&mut reader, BINCODE_CONFIG).ok()?;
+
+    // Pointers were interned against table of the run which wrote cache, strings colliding with already loaded ones are interned again
+    let mut moved = HashMap::<u64, u64>::new();
+    for (pointer, string) in cache.strings {
+        match table.strings.get(&pointer) {
+            Some(existing) if existing == &string => {}
+            None => {
+                table.strings.insert(pointer, string);
+            }
+            Some(_) => {
+                moved.insert(pointer, table.intern(U32SizeString(string)));
+            }
+        }
+    }
+    let location_offset = table.locations.len() as u32;
+    let remap = |pointer: u64| *moved.get(&pointer).unwrap_or(&pointer);
+    for (location, id) in cache.locations.iter().zip(cache.ids) {
+        table.push_location_with_id(id, SourceLocation {
+            name: remap(location.name),
+            function: remap(location.function),
+            file: remap(location.file),
+            ..*location
+        });
+    }
+    return Some((location_offset, cache.locations.len() as u32, cache.events_position));
+}
+
+// Failing to write cache only costs next start time. Written to temporary file first, so concurrent runs never read half written cache
+pub fn store(path: &str, header: &UTracyHeader, table: &SourceTable, location_offset: u32, location_count: u32, events_position: u64) {
+    if location_count < MIN_CACHED_LOCATIONS {
+        return;
+    }
+    let Some(key) = file_key(path, header) else {
+        return;
+    };
+    let range = location_offset as usize..(location_offset + location_count) as usize;
+    let locations = table.locations[range.clone()].to_vec();
+    let ids = table.ids[range].to_vec();
+    let mut pointers: Vec<u64> = locations.iter().flat_map(|location| [location.name, location.function, location.file]).collect();
+    pointers.sort_unstable();
+    pointers.dedup();
+    let strings = pointers.into_iter().map(|pointer| (pointer, table.strings[&pointer].clone())).collect();
+    let cache = LocationCache { events_position, locations, ids, strings };
+    let Some(cache_path) = cache_path(path) else {
+        return;
+    };
+    let Some(dir) = cache_path.parent().filter(|dir| fs::create_dir_all(dir).is_ok()) else {
+        return;
+    };
+    let temporary = dir.join(format!(".{}.{}", cache_path.file_name().unwrap_or_default().to_string_lossy(), std::process::id()));
+    let Ok(file) = File::create(&temporary) else {
+        return;
+    };
+    let mut writer = BufWriter::new(file);
+    let written = bincode::encode_into_std_write((CACHE_VERSION, key), &mut writer, BINCODE_CONFIG).map_err(|e| format!("{e}"))
+        .and_then(|_| bincode::encode_into_std_write(&cache, &mut writer, BINCODE_CONFIG).map_err(|e| format!("{e}")))
+        .and_then(|_| writer.flush().map_err(|e| format!("{e}")))
+        .and_then(|_| fs::rename(&temporary, &cache_path).map_err(|e| format!("{e}")));
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+}
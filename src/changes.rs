@@ -0,0 +1,99 @@
+use std::fs;
+use std::process::Command;
+use crate::args::Arguments;
+use crate::meta::load_meta;
+
+// Commits listed between two captures, the rest is only counted
+const MAX_LISTED: usize = 20;
+
+// Where commits between captures come from. Without source only the hash range is shown
+pub enum Commits {
+    None,
+    // Local clone of game repository
+    Git(String),
+    // Lines of `git log --oneline` output, newest first
+    Changelog(Vec<(String, String)>),
+}
+
+// Game commit from round metadata of capture
+pub fn capture_commit(capture: &str) -> Result<Option<String>, String> {
+    return Ok(load_meta(capture)?.and_then(|meta| meta.commit().map(str::to_string)));
+}
+
+// Hashes come from metadata of uploaded captures, anything else is never passed to git
+fn check_hash(hash: &str) -> Result<(), String> {
+    if hash.len() < 4 || hash.len() > 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("\"{hash}\" is not a commit hash"));
+    }
+    return Ok(());
+}
+
+// Short and full hashes of one commit match
+fn same_commit(a: &str, b: &str) -> bool {
+    let (a, b) = (a.to_ascii_lowercase(), b.to_ascii_lowercase());
+    return a.starts_with(&b) || b.starts_with(&a);
+}
+
+impl Commits {
+    pub fn from_arguments(arguments: &Arguments) -> Result<Commits, String> {
+        return match (arguments.get("--git-repo"), arguments.get("--changelog")) {
+            (Some(_), Some(_)) => Err("Use either --git-repo or --changelog".into()),
+            (Some(dir), None) => Ok(Commits::Git(dir.to_string())),
+            (None, Some(path)) => {
+                let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+                let lines = text.lines().map(str::trim).filter(|line| !line.is_empty()).map(|line| {
+                    let (hash, subject) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                    return (hash.to_string(), subject.trim().to_string());
+                }).collect();
+                Ok(Commits::Changelog(lines))
+            }
+            (None, None) => Ok(Commits::None),
+        };
+    }
+
+    // Commits reachable from new but not from old as "hash subject", newest first
+    fn between(&self, old: &str, new: &str) -> Result<Vec<String>, String> {
+        match self {
+            Commits::None => return Ok(Vec::new()),
+            Commits::Git(dir) => {
+                let output = Command::new("git").args(["-C", dir, "log", "--oneline", "--no-decorate", &format!("{old}..{new}"), "--"]).output()
+                    .map_err(|e| format!("git: {e}"))?;
+                if !output.status.success() {
+                    let error = String::from_utf8_lossy(&output.stderr);
+                    return Err(format!("git: {}", error.lines().next().unwrap_or("failed")));
+                }
+                return Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect());
+            }
+            Commits::Changelog(lines) => {
+                let position = |hash: &str| lines.iter().position(|(line_hash, _)| same_commit(line_hash, hash)).ok_or(format!("{hash} is not in changelog"));
+                let (old, new) = (position(old)?, position(new)?);
+                // Newer capture built from older commit lists nothing, same as git
+                return Ok(lines[new.min(old)..old].iter().map(|(hash, subject)| format!("{hash} {subject}")).collect());
+            }
+        }
+    }
+
+    // "Commits 4f2a9c1..9e8d7c6: 3" followed by indented commits, empty when both captures are built from the same commit
+    pub fn describe(&self, old: &str, new: &str) -> Vec<String> {
+        if same_commit(old, new) {
+            return Vec::new();
+        }
+        let range = format!("Commits {old}..{new}");
+        if let Err(msg) = check_hash(old).and(check_hash(new)) {
+            return vec![format!("{range}: {msg}")];
+        }
+        if let Commits::None = self {
+            return vec![range];
+        }
+        let commits = match self.between(old, new) {
+            Ok(commits) => commits,
+            Err(msg) => return vec![format!("{range}: {msg}")],
+        };
+        let mut lines = vec![format!("{range}: {}", commits.len())];
+        lines.extend(commits.iter().take(MAX_LISTED).map(|commit| format!("  {commit}")));
+        if commits.len() > MAX_LISTED {
+            lines.push(format!("  ... {} more", commits.len() - MAX_LISTED));
+        }
+        return lines;
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs;
+use regex::Regex;
+use serde::Deserialize;
+use crate::analysis::{collect_zone_stats_with, StatsOptions};
+use crate::args::Arguments;
+use crate::clock::{format_duration, parse_duration};
+use crate::color::{green, red, yellow};
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::snapshot::load_snapshot;
+
+// budgets.toml:
+//   [[budget]]
+//   zone = "^SSair\\.fire$"
+//   mean = "5ms"
+//   p99 = "20ms"
+#[derive(Deserialize)]
+struct BudgetFile {
+    #[serde(default)]
+    budget: Vec<BudgetEntry>,
+}
+
+// Durations are strings with unit ("5ms") or plain milliseconds
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum BudgetDuration {
+    Text(String),
+    Milliseconds(f64),
+}
+
+impl BudgetDuration {
+    pub fn to_ns(&self) -> Result<u64, String> {
+        return match self {
+            BudgetDuration::Text(text) => parse_duration(text),
+            BudgetDuration::Milliseconds(ms) => Ok((ms * 1_000_000f64) as u64),
+        };
+    }
+}
+
+#[derive(Deserialize)]
+struct BudgetEntry {
+    zone: String,
+    mean: Option<BudgetDuration>,
+    p99: Option<BudgetDuration>,
+}
+
+struct Budget {
+    pattern: Regex,
+    mean: Option<u64>,
+    p99: Option<u64>,
+}
+
+fn load_budgets(path: &str) -> Result<Vec<Budget>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let file: BudgetFile = toml::from_str(&text).map_err(|e| format!("{path}: {e}"))?;
+    let mut budgets = Vec::new();
+    for entry in file.budget {
+        budgets.push(Budget {
+            pattern: Regex::new(&entry.zone).map_err(|e| format!("{path}: {e}"))?,
+            mean: entry.mean.map(|d| d.to_ns()).transpose()?,
+            p99: entry.p99.map(|d| d.to_ns()).transpose()?,
+        });
+    }
+    return Ok(budgets);
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--budget", "--frames", "--trim", "--winsorize", "--groups"])?;
+    let options = StatsOptions::from_arguments(&arguments)?;
+    let (Some(path), Some(budget_path)) = (arguments.positional.first(), arguments.get("--budget")) else {
+        return Err(usage("Use: check <file> --budget budgets.toml [stats options]"));
+    };
+    let budgets = load_budgets(budget_path)?;
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    let stats = collect_zone_stats_with(&snapshot, &table, &options)?;
+
+    let mut violations = 0;
+    for budget in &budgets {
+        let mut matched = 0;
+        for zone in stats.iter().filter(|s| budget.pattern.is_match(&s.name)) {
+            matched += 1;
+            let checks = [("mean", zone.mean(), budget.mean), ("p99", zone.percentile(99f64), budget.p99)];
+            for (metric, value, limit) in checks {
+                let Some(limit) = limit else {
+                    continue;
+                };
+                if value > limit {
+                    violations += 1;
+                    println!("{} {} {metric} {} > {} ({} calls)", red("FAIL"), zone.name, format_duration(value), format_duration(limit), zone.count());
+                } else {
+                    println!("{}   {} {metric} {} <= {} ({} calls)", green("OK"), zone.name, format_duration(value), format_duration(limit), zone.count());
+                }
+            }
+        }
+        if matched == 0 {
+            println!("{} {} matched no zones", yellow("SKIP"), budget.pattern);
+        }
+    }
+
+    if violations > 0 {
+        return Err(format!("{violations} budget violations"));
+    }
+    println!("All budgets passed");
+    return Ok(());
+}
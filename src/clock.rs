@@ -0,0 +1,139 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Civil date from days since unix epoch, see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    return era * 146097 + doe - 719468;
+}
+
+// RFC 3339 in UTC, e.g. 2024-05-01T20:31:05Z
+pub fn format_utc(unix_seconds: u64) -> String {
+    let days = (unix_seconds / 86400) as i64;
+    let seconds = unix_seconds % 86400;
+    let (year, month, day) = civil_from_days(days);
+    return format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z", seconds / 3600, seconds / 60 % 60, seconds % 60);
+}
+
+// Basic ISO 8601 as used in AWS request signing, e.g. 20240501T203105Z
+pub fn format_amz_date(unix_seconds: u64) -> String {
+    let (year, month, day) = civil_from_days((unix_seconds / 86400) as i64);
+    let seconds = unix_seconds % 86400;
+    return format!("{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z", seconds / 3600, seconds / 60 % 60, seconds % 60);
+}
+
+// Same with milliseconds, e.g. 2024-05-01T20:31:05.120Z
+pub fn format_utc_ns(unix_ns: u64) -> String {
+    let seconds = unix_ns / 1_000_000_000;
+    let days = (seconds / 86400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let seconds = seconds % 86400;
+    let millis = unix_ns / 1_000_000 % 1000;
+    return format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{millis:03}Z", seconds / 3600, seconds / 60 % 60, seconds % 60);
+}
+
+// Accepts "2024-05-01T20:31:05Z", "2024-05-01 20:31:05" and optional fraction of second, always UTC
+pub fn parse_utc(text: &str) -> Result<u64, String> {
+    let error = || format!("Invalid time \"{text}\", expected YYYY-MM-DDTHH:MM:SSZ");
+    let trimmed = text.trim().trim_end_matches('Z').trim_end_matches(" UTC");
+    let (date, time) = trimmed.split_once(['T', ' ']).ok_or_else(error)?;
+    let date: Vec<&str> = date.split('-').collect();
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let time: Vec<&str> = time.split(':').collect();
+    if date.len() != 3 || time.len() != 3 {
+        return Err(error());
+    }
+    let year: i64 = date[0].parse().map_err(|_| error())?;
+    let month: u32 = date[1].parse().map_err(|_| error())?;
+    let day: u32 = date[2].parse().map_err(|_| error())?;
+    let hour: u64 = time[0].parse().map_err(|_| error())?;
+    let minute: u64 = time[1].parse().map_err(|_| error())?;
+    let second: u64 = time[2].parse().map_err(|_| error())?;
+    let mut nanos = 0u64;
+    if !fraction.is_empty() {
+        let digits = fraction.len().min(9);
+        nanos = fraction[..digits].parse::<u64>().map_err(|_| error())? * 10u64.pow(9 - digits as u32);
+    }
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return Err(error());
+    }
+    return Ok(((days as u64 * 86400) + hour * 3600 + minute * 60 + second) * 1_000_000_000 + nanos);
+}
+
+// Accepts "250us", "5ms", "1.5s", "100ns", plain numbers are milliseconds
+pub fn parse_duration(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(text.len());
+    let (value, unit) = text.split_at(split);
+    let value: f64 = value.parse().map_err(|_| format!("Invalid duration \"{text}\""))?;
+    let scale = match unit.trim() {
+        "ns" => 1f64,
+        "us" | "µs" => 1_000f64,
+        "" | "ms" => 1_000_000f64,
+        "s" => 1_000_000_000f64,
+        _ => return Err(format!("Invalid duration unit in \"{text}\"")),
+    };
+    return Ok((value * scale) as u64);
+}
+
+// Set by --raw, durations and counts are printed as plain integers (nanoseconds) for scripts
+static RAW_NUMBERS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_raw_numbers(raw: bool) {
+    RAW_NUMBERS.store(raw, Ordering::Relaxed);
+}
+
+pub fn format_duration(ns: u64) -> String {
+    if RAW_NUMBERS.load(Ordering::Relaxed) {
+        return ns.to_string();
+    }
+    return if ns < 1_000 {
+        format!("{ns} ns")
+    } else if ns < 1_000_000 {
+        format!("{:.2} µs", ns as f64 / 1_000f64)
+    } else if ns < 1_000_000_000 {
+        format!("{:.2} ms", ns as f64 / 1_000_000f64)
+    } else {
+        format!("{} s", group_thousands(&format!("{:.2}", ns as f64 / 1_000_000_000f64)))
+    };
+}
+
+// 1234567 -> "1,234,567"
+pub fn format_count(count: u64) -> String {
+    if RAW_NUMBERS.load(Ordering::Relaxed) {
+        return count.to_string();
+    }
+    return group_thousands(&count.to_string());
+}
+
+// Separates thousands in integer part of number
+fn group_thousands(number: &str) -> String {
+    let (integer, fraction) = number.split_at(number.find('.').unwrap_or(number.len()));
+    let mut grouped = String::new();
+    for (index, digit) in integer.chars().enumerate() {
+        if index > 0 && (integer.len() - index) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped.push_str(fraction);
+    return grouped;
+}
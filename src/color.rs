@@ -0,0 +1,55 @@
+use std::env;
+use std::io::{stdout, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+// Colors only reach a terminal, pipes and files get plain text. NO_COLOR is honored when set to anything but empty, see no-color.org
+pub fn init_color(no_color: bool) {
+    let disabled = no_color || env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    COLOR.store(!disabled && stdout().is_terminal(), Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    return COLOR.load(Ordering::Relaxed);
+}
+
+fn paint(code: &str, text: &str) -> String {
+    if !enabled() {
+        return text.to_string();
+    }
+    return format!("\x1b[{code}m{text}\x1b[0m");
+}
+
+// Failures and regressions
+pub fn red(text: &str) -> String {
+    return paint("31", text);
+}
+
+// Warnings, spikes and skipped checks
+pub fn yellow(text: &str) -> String {
+    return paint("33", text);
+}
+
+// Passed checks and improvements
+pub fn green(text: &str) -> String {
+    return paint("32", text);
+}
+
+pub fn warning(msg: &str) {
+    println!("{}", yellow(&format!("Warning: {msg}")));
+}
+
+// Drawn on stderr and hidden when it is not a terminal. Lines printed while bar is shown go through ProgressBar::suspend
+pub fn progress_bar(len: usize, action: &str) -> ProgressBar {
+    let template = if enabled() {
+        "{prefix} [{bar:30.cyan/blue}] {pos}/{len} {wide_msg}"
+    } else {
+        "{prefix} [{bar:30}] {pos}/{len} {wide_msg}"
+    };
+    // Cleared when command stops early with an error, so the error is printed on its own line
+    let bar = ProgressBar::new(len as u64).with_prefix(action.to_string()).with_finish(ProgressFinish::AndClear);
+    bar.set_style(ProgressStyle::with_template(template).unwrap_or_else(|_| ProgressStyle::default_bar()).progress_chars("=> "));
+    return bar;
+}
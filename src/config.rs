@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::fs;
+use serde::Deserialize;
+use crate::filter::FilterScript;
+use crate::server::Profile;
+
+// rtracy.toml, every listener serves the loaded snapshots on its own port, unset fields come from command line:
+//   [thread_names]
+//   1 = "Main"
+//
+//   [[listener]]
+//   port = 8087
+//   name = "main thread"
+//   filter_script = "main_thread.rhai"
+//   skip_frames = 100
+//   limit_frames = 1000
+//   every_nth_frame = 2
+//   within = "SSair.fire"
+//   max_depth = 3
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    thread_names: HashMap<String, String>,
+    #[serde(default)]
+    listener: Vec<ListenerEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListenerEntry {
+    port: u16,
+    name: Option<String>,
+    skip_frames: Option<u32>,
+    limit_frames: Option<u32>,
+    filter_script: Option<String>,
+    every_nth_frame: Option<u32>,
+    within: Option<String>,
+    max_depth: Option<u32>,
+}
+
+pub struct Config {
+    // Command line profile with thread names of config file
+    pub main: Profile,
+    pub listeners: Vec<(u16, Profile)>,
+}
+
+pub fn load_config(path: &str, base: &Profile) -> Result<Config, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let file: ConfigFile = toml::from_str(&text).map_err(|e| format!("{path}: {e}"))?;
+    let mut thread_names = HashMap::new();
+    for (thread, name) in file.thread_names {
+        let thread: u32 = thread.parse().map_err(|_| format!("{path}: thread_names key \"{thread}\" is not thread id"))?;
+        thread_names.insert(thread, name);
+    }
+    let main = Profile { thread_names: thread_names.clone(), ..base.clone() };
+    let mut listeners: Vec<(u16, Profile)> = Vec::new();
+    for entry in file.listener {
+        if listeners.iter().any(|(port, _)| *port == entry.port) {
+            return Err(format!("{path}: port {} is used by several listeners", entry.port));
+        }
+        let profile = Profile {
+            name: Some(entry.name.unwrap_or_else(|| format!("port {}", entry.port))),
+            skip_frames: entry.skip_frames.unwrap_or(base.skip_frames),
+            limit_frames: entry.limit_frames.unwrap_or(base.limit_frames),
+            filter_script: entry.filter_script.or_else(|| base.filter_script.clone()),
+            frame_stride: entry.every_nth_frame.unwrap_or(base.frame_stride).max(1),
+            within: entry.within.or_else(|| base.within.clone()),
+            max_depth: entry.max_depth.or(base.max_depth).filter(|depth| *depth > 0),
+            thread_names: thread_names.clone(),
+        };
+        // Compile once to report script errors before any client connects
+        if let Some(script) = &profile.filter_script {
+            FilterScript::load(script)?;
+        }
+        listeners.push((entry.port, profile));
+    }
+    return Ok(Config { main, listeners });
+}
@@ -0,0 +1,72 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use crate::analysis::{collect_frame_marks, ticks_to_ns, walk_snapshot, Walk};
+use crate::args::Arguments;
+use crate::clock::{format_count, format_duration};
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::snapshot::load_snapshot;
+
+struct Correlation {
+    name: u64,
+    mean: f64,
+    deviation: f64,
+    // Pearson correlation of zone time per frame with frame time
+    correlation: f64,
+    // Covariance with frame time divided by frame time variance, self times of all zones add up to at most 1
+    variance_share: f64,
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["-n"])?;
+    let Some(path) = arguments.positional.first() else {
+        return Err(usage("Use: correlate <file> [-n count]"));
+    };
+    let count: usize = arguments.get_parsed("-n")?.unwrap_or(20);
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    let marks = collect_frame_marks(&snapshot)?;
+    if marks.len() < 3 {
+        return Err(format!("{path}: {} frame marks, at least 3 are needed", marks.len()));
+    }
+    // Frame N ends with mark N, frames[N - 1] is its length
+    let frames: Vec<f64> = marks.windows(2).map(|w| ticks_to_ns(&snapshot, w[1].saturating_sub(w[0])) as f64).collect();
+
+    // Self time so wrapping zones do not correlate just because they contain the whole frame
+    let mut times = HashMap::<u64, Vec<f64>>::new();
+    walk_snapshot(&snapshot, |walk| {
+        if let Walk::Zone(zone) = walk {
+            if zone.frame == 0 || zone.frame as usize > frames.len() {
+                return;
+            }
+            let name = table.locations[zone.location as usize].name;
+            let per_frame = times.entry(name).or_insert_with(|| vec![0f64; frames.len()]);
+            per_frame[zone.frame as usize - 1] += ticks_to_ns(&snapshot, zone.self_time()) as f64;
+        }
+    })?;
+
+    let frame_count = frames.len() as f64;
+    let frame_mean = frames.iter().sum::<f64>() / frame_count;
+    let frame_variance = frames.iter().map(|frame| (frame - frame_mean).powi(2)).sum::<f64>() / frame_count;
+    if frame_variance == 0f64 {
+        return Err(format!("{path}: all frames have the same length, nothing to correlate"));
+    }
+    let mut results: Vec<Correlation> = times.into_iter().map(|(name, per_frame)| {
+        let mean = per_frame.iter().sum::<f64>() / frame_count;
+        let variance = per_frame.iter().map(|time| (time - mean).powi(2)).sum::<f64>() / frame_count;
+        let covariance = per_frame.iter().zip(&frames).map(|(time, frame)| (time - mean) * (frame - frame_mean)).sum::<f64>() / frame_count;
+        let correlation = if variance > 0f64 { covariance / (variance * frame_variance).sqrt() } else { 0f64 };
+        return Correlation { name, mean, deviation: variance.sqrt(), correlation, variance_share: covariance / frame_variance };
+    }).collect();
+    results.sort_by(|a, b| b.variance_share.partial_cmp(&a.variance_share).unwrap_or(Ordering::Equal));
+
+    println!("{} frames, mean {}, deviation {}", format_count(frames.len() as u64), format_duration(frame_mean as u64), format_duration(frame_variance.sqrt() as u64));
+    println!("{:>9} {:>12} {:>12} {:>12}  Zone", "Variance", "Correlation", "Mean/frame", "Deviation");
+    for result in results.iter().take(count) {
+        println!("{:>8.1}% {:>12.3} {:>12} {:>12}  {}", result.variance_share * 100f64, result.correlation, format_duration(result.mean as u64),
+                 format_duration(result.deviation as u64), table.strings.get(&result.name).map(String::as_str).unwrap_or("Unkn"));
+    }
+    return Ok(());
+}
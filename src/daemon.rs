@@ -0,0 +1,120 @@
+use std::env;
+use std::net::TcpListener;
+use crate::color::warning;
+
+// First descriptor passed by systemd socket activation, see sd_listen_fds(3)
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+// Listening socket inherited from systemd when started by .socket unit
+#[cfg(unix)]
+pub fn inherited_listener() -> Result<Option<TcpListener>, String> {
+    use std::os::fd::FromRawFd;
+    let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    let fds = env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse::<i32>().ok()).unwrap_or(0);
+    if pid != Some(std::process::id()) || fds < 1 {
+        return Ok(None);
+    }
+    // Not passed further to child processes
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    if fds > 1 {
+        warning(&format!("systemd passed {fds} sockets, only first one is used"));
+    }
+    let listener = unsafe { TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    listener.local_addr().map_err(|e| format!("Inherited socket is not TCP listener: {e}"))?;
+    return Ok(Some(listener));
+}
+
+#[cfg(not(unix))]
+pub fn inherited_listener() -> Result<Option<TcpListener>, String> {
+    if env::var("LISTEN_FDS").is_ok() {
+        warning("socket activation is not supported on this platform");
+    }
+    return Ok(None);
+}
+
+// Background process left by daemonize, foreground process waits until it reports being ready
+#[cfg(unix)]
+pub struct Daemon {
+    // Write end of pipe foreground process reads
+    ready: i32,
+}
+
+#[cfg(not(unix))]
+pub struct Daemon;
+
+// Double fork so process is not session leader. Foreground process exits once daemon is ready, or with error
+// when daemon fails to start, so errors of loading captures and binding ports still reach the terminal.
+// Must be called before any thread is spawned, rayon threads of location decoding included, they don't survive fork
+#[cfg(unix)]
+pub fn daemonize() -> Result<Daemon, String> {
+    let mut pipe = [0i32; 2];
+    unsafe {
+        if libc::pipe(pipe.as_mut_ptr()) == -1 {
+            return Err(format!("Error creating pipe: {}", std::io::Error::last_os_error()));
+        }
+        match libc::fork() {
+            -1 => return Err(format!("Error forking: {}", std::io::Error::last_os_error())),
+            0 => {}
+            _ => {
+                // Pipe is closed without a byte when daemon exits before it is ready
+                libc::close(pipe[1]);
+                let mut status = 0u8;
+                let read = libc::read(pipe[0], &mut status as *mut u8 as *mut libc::c_void, 1);
+                libc::_exit(if read == 1 { 0 } else { 1 });
+            }
+        }
+        libc::close(pipe[0]);
+        if libc::setsid() == -1 {
+            return Err(format!("Error creating session: {}", std::io::Error::last_os_error()));
+        }
+        match libc::fork() {
+            -1 => return Err(format!("Error forking: {}", std::io::Error::last_os_error())),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+    }
+    return Ok(Daemon { ready: pipe[1] });
+}
+
+#[cfg(unix)]
+impl Daemon {
+    // Output goes to /dev/null after pid is reported, then foreground process is released
+    pub fn ready(self, pidfile: Option<&str>) -> Result<(), String> {
+        let pid = std::process::id();
+        if let Some(path) = pidfile {
+            std::fs::write(path, format!("{pid}\n")).map_err(|e| format!("Error writing pidfile {path}: {e}"))?;
+        }
+        println!("Running in background, pid {pid}");
+        unsafe {
+            let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+            if null == -1 {
+                return Err(format!("Error opening /dev/null: {}", std::io::Error::last_os_error()));
+            }
+            for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+                libc::dup2(null, fd);
+            }
+            if null > libc::STDERR_FILENO {
+                libc::close(null);
+            }
+            let status = 1u8;
+            libc::write(self.ready, &status as *const u8 as *const libc::c_void, 1);
+            libc::close(self.ready);
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(not(unix))]
+pub fn daemonize() -> Result<Daemon, String> {
+    return Err("--daemon is not supported on this platform".into());
+}
+
+#[cfg(not(unix))]
+impl Daemon {
+    pub fn ready(self, _pidfile: Option<&str>) -> Result<(), String> {
+        return Ok(());
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, ErrorKind, Read, Seek};
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use crate::analysis::collect_frame_marks;
+use crate::args::Arguments;
+use crate::color::{green, red, yellow};
+use crate::failure::{error_kind, ErrorKind as Kind};
+use crate::intern::SourceTable;
+use crate::meta::load_meta;
+use crate::seal::TRAILER_TAG;
+use crate::snapshot::{load_snapshot, read_header, read_locations, Snapshot, FILE_SIGNATURE};
+use crate::structs::{tracy_release, EventType, NetworkHeader, UTracyEvent, BINCODE_CONFIG, HEADER_SIZE, PROTOCOL_VERSION, TRACY_DOWNLOAD, TRACY_RELEASES};
+use crate::writer::EVENT_SIZE;
+
+// Captures suggested when requested one is missing
+const SIMILAR_FILES: usize = 5;
+
+// Problems found so far, every check prints its own line and hints how to fix it
+#[derive(Default)]
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&self, text: &str) {
+        println!("{}   {text}", green("OK"));
+    }
+
+    fn warn(&mut self, text: &str, hint: &str) {
+        self.warnings += 1;
+        println!("{} {text}", yellow("WARN"));
+        println!("     {hint}");
+    }
+
+    fn fail(&mut self, text: &str, hint: &str) {
+        self.failures += 1;
+        println!("{} {text}", red("FAIL"));
+        println!("     {hint}");
+    }
+}
+
+fn captures_in(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<String> = entries.filter_map(Result::ok).map(|entry| entry.path())
+        .filter(|file| file.extension().is_some_and(|extension| extension == "utracy"))
+        .map(|file| file.display().to_string()).collect();
+    files.sort();
+    return files;
+}
+
+// Captures in directory of missing file, most often the name was mistyped or relative to other directory
+fn similar_files(path: &str) -> Vec<String> {
+    let dir = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut files = captures_in(dir);
+    files.truncate(SIMILAR_FILES);
+    return files;
+}
+
+fn check_file(report: &mut Report, path: &str) {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let cwd = env::current_dir().map(|dir| dir.display().to_string()).unwrap_or_default();
+            let similar = similar_files(path);
+            let hint = if similar.is_empty() {
+                format!("Relative paths start at working directory {cwd}, no .utracy files found next to it")
+            } else {
+                format!("Relative paths start at working directory {cwd}, found there: {}", similar.join(", "))
+            };
+            return report.fail(&format!("{path} not found"), &hint);
+        }
+        Err(e) => return report.fail(&format!("{path}: {e}"), "Check that user running rtracy can read the file"),
+    };
+    if metadata.is_dir() {
+        let files = captures_in(Path::new(path));
+        for file in &files {
+            check_file(report, file);
+        }
+        if files.is_empty() {
+            report.warn(&format!("{path} is a directory without .utracy files"), "Uploaded captures are served with --upload-dir, other files are passed one by one");
+        }
+        return;
+    }
+    if metadata.len() == 0 {
+        return report.fail(&format!("{path} is empty"), "Capture was not finished, wait until the game writes it on round end");
+    }
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return report.fail(&format!("{path}: {e}"), "Check that user running rtracy can read the file"),
+    };
+    let mut reader = BufReader::new(file);
+    let mut signature = [0u8; 8];
+    if reader.read_exact(&mut signature).is_err() || u64::from_le_bytes(signature) != FILE_SIGNATURE {
+        let hint = "Only utracy captures written by the game are served, .tracy files saved by Tracy UI are opened in Tracy itself";
+        return report.fail(&format!("{path} is not a utracy capture"), hint);
+    }
+    let _ = reader.rewind();
+    if let Err(msg) = read_header(&mut reader) {
+        if error_kind(&msg) == Some(Kind::UnsupportedVersion) {
+            return report.fail(&format!("{path}: {msg}"), "Capture was written by incompatible game library, only version 2 is served");
+        }
+        return report.fail(&format!("{path}: {msg}"), "File is truncated or damaged, copy it again after the game finished writing it");
+    }
+    // Decoded on its own first, so broken table is reported as such and not as a failed load
+    let available = metadata.len().saturating_sub(reader.stream_position().unwrap_or(0));
+    if let Err(msg) = read_locations(&mut reader, &mut SourceTable::new(), Some(available)) {
+        return report.fail(&format!("{path}: source location table can't be decoded: {msg}"), "File is truncated or was damaged while copying, copy it again");
+    }
+    // Sealed captures are verified on load
+    let mut table = SourceTable::new();
+    let snapshot = match load_snapshot(path, &mut table) {
+        Ok(snapshot) => snapshot,
+        Err(msg) => return report.fail(&msg, "File is truncated or was damaged while copying, copy it again"),
+    };
+    let scan = match scan_events(&snapshot) {
+        Ok(scan) => scan,
+        Err(msg) => return report.fail(&format!("{path}: {msg}"), "Check that user running rtracy can read the file"),
+    };
+    if let Some((index, location)) = scan.bad_location {
+        let hint = "Capture is corrupted and can't be served, copy it again or record the round again";
+        return report.fail(&format!("{path}: event {index} references source location {location}, capture has only {}", snapshot.location_count), hint);
+    }
+    if let Some(index) = scan.undecodable {
+        let hint = "Events after it are not served, copy the capture again or record the round again";
+        return report.fail(&format!("{path}: event {index} has unknown type"), hint);
+    }
+    if scan.cut_off > 0 {
+        report.warn(&format!("{path} ends with {} bytes of cut off event", scan.cut_off), "Game stopped while writing the capture, the event is dropped when served");
+    }
+    if scan.unpaired_ends > 0 {
+        let hint = "They are dropped when served, capture was started inside zones or its events are damaged";
+        report.warn(&format!("{path} has {} zone ends without matching begin", scan.unpaired_ends), hint);
+    }
+    match collect_frame_marks(&snapshot) {
+        Ok(marks) if marks.len() < 2 => {
+            report.warn(&format!("{path} has no complete frames"), "Tracy shows whole capture as one frame, game probably didn't run long enough");
+        }
+        Ok(marks) => report.ok(&format!("{path}: {}, {} source locations, {} frames", snapshot.program_name(), snapshot.location_count, marks.len() - 1)),
+        Err(msg) => report.fail(&format!("{path}: {msg}"), "Events are cut off, file is truncated"),
+    }
+    if let Err(msg) = load_meta(path) {
+        report.warn(&msg, "Round metadata is skipped until the sidecar is valid JSON object");
+    }
+}
+
+// Event problems loading does not see, serving drops the event or fails on it
+#[derive(Default)]
+struct EventScan {
+    // Index of the first Begin outside location table and its location
+    bad_location: Option<(u64, u32)>,
+    // Index of the first event of unknown type, nothing after it is read
+    undecodable: Option<u64>,
+    unpaired_ends: u64,
+    // Bytes of the last event when file ends inside it
+    cut_off: usize,
+}
+
+// Reads raw events like the C API does, so cut off tail is told apart from end of file and seal trailer
+fn scan_events(snapshot: &Snapshot) -> Result<EventScan, String> {
+    let mut reader = snapshot.open_events()?;
+    let mut scan = EventScan::default();
+    let mut depths = HashMap::<u32, u64>::new();
+    let mut bytes = [0u8; EVENT_SIZE as usize];
+    for index in 0.. {
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match reader.read(&mut bytes[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(format!("{e}")),
+            }
+        }
+        if filled == 0 || bytes[..TRAILER_TAG.len()] == TRAILER_TAG {
+            break;
+        }
+        if filled < bytes.len() {
+            scan.cut_off = filled;
+            break;
+        }
+        let Ok((event, _)) = bincode::decode_from_slice::<UTracyEvent, _>(&bytes, BINCODE_CONFIG) else {
+            scan.undecodable = Some(index);
+            break;
+        };
+        unsafe {
+            match event.event_type {
+                EventType::Begin => {
+                    let begin = event.event.begin;
+                    if begin.source_location >= snapshot.location_count {
+                        scan.bad_location = Some((index, begin.source_location));
+                        break;
+                    }
+                    *depths.entry(begin.thread_id).or_default() += 1;
+                }
+                EventType::End => match depths.get_mut(&event.event.end.thread_id) {
+                    Some(depth) if *depth > 0 => *depth -= 1,
+                    _ => scan.unpaired_ends += 1,
+                },
+                _ => {}
+            }
+        }
+    }
+    return Ok(scan);
+}
+
+// Header layout and version are checked against table of Tracy releases, a mismatch here means rtracy itself is built wrong
+fn check_protocol(report: &mut Report) {
+    let header = NetworkHeader {
+        multiplier: 1f64,
+        init_begin: 0,
+        init_end: 0,
+        delay: 0,
+        resolution: 0,
+        epoch: 0,
+        exec_time: 0,
+        process_id: 0,
+        sampling_period: 0,
+        flags: 0,
+        cpu_arch: 0,
+        cpu_manufacturer: [0; 12],
+        cpu_id: 0,
+        program_name: [0; 64],
+        host_info: [0; 1024],
+    };
+    let size = bincode::encode_to_vec(&header, BINCODE_CONFIG).map(|bytes| bytes.len()).unwrap_or(0);
+    if size != HEADER_SIZE {
+        report.fail(&format!("Welcome message is {size} bytes, protocol {PROTOCOL_VERSION} expects {HEADER_SIZE}"), "Tracy will refuse the stream, report this build as broken");
+    }
+    let Some(release) = tracy_release(PROTOCOL_VERSION) else {
+        return report.fail(&format!("Protocol {PROTOCOL_VERSION} is not in Tracy release table"), "Report this build as broken");
+    };
+    let newer: Vec<String> = TRACY_RELEASES.iter().filter(|(version, _)| *version > PROTOCOL_VERSION)
+        .map(|(version, release)| format!("{release} (protocol {version})")).collect();
+    report.ok(&format!("Protocol {PROTOCOL_VERSION}, connect with Tracy {release} from {TRACY_DOWNLOAD}{release}"));
+    if !newer.is_empty() {
+        println!("     Newer Tracy releases refuse connection: {}", newer.join(", "));
+    }
+}
+
+fn check_port(report: &mut Report, port: u16) {
+    match TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))) {
+        Ok(_) => report.ok(&format!("Port {port} is free")),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            report.warn(&format!("Port {port} is taken"), "Another rtracy or a profiled game listens there, serve moves to next free port or pick one with -p");
+        }
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            report.fail(&format!("Port {port}: {e}"), "Ports below 1024 need administrator rights, pick another one with -p");
+        }
+        Err(e) => report.fail(&format!("Port {port}: {e}"), "Pick another port with -p"),
+    }
+    #[cfg(windows)]
+    report.warn("Windows Firewall may block Tracy on other machines",
+                &format!("Allow the port once from administrator prompt: netsh advfirewall firewall add rule name=rtracy dir=in action=allow protocol=TCP localport={port}"));
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["-p"])?;
+    let port: u16 = arguments.get_parsed("-p")?.unwrap_or(8086);
+    let mut report = Report::default();
+    for path in &arguments.positional {
+        check_file(&mut report, path);
+    }
+    check_protocol(&mut report);
+    check_port(&mut report, port);
+
+    if report.failures > 0 {
+        return Err(format!("{} problems found, {} warnings", report.failures, report.warnings));
+    }
+    println!("No problems found, {} warnings", report.warnings);
+    return Ok(());
+}
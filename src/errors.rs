@@ -0,0 +1,39 @@
+use serde_json::json;
+use crate::color::red;
+use crate::failure::{error_kind, usage, ErrorKind};
+
+pub fn error_code(msg: &str) -> &'static str {
+    return match error_kind(msg) {
+        Some(ErrorKind::PortUnavailable) => "port_unavailable",
+        Some(ErrorKind::BadSignature) => "bad_signature",
+        Some(ErrorKind::UnsupportedVersion) => "unsupported_version",
+        Some(ErrorKind::Corrupted) => "corrupted",
+        Some(ErrorKind::Truncated) => "truncated",
+        Some(ErrorKind::FileNotFound) => "file_not_found",
+        Some(ErrorKind::FileUnreadable) => "file_unreadable",
+        Some(ErrorKind::Usage) => "usage",
+        None => "error",
+    };
+}
+
+// Removes --error-format from arguments, json makes fatal errors one JSON line on stderr for launchers
+pub fn take_error_format(args: &mut Vec<String>) -> Result<bool, String> {
+    let Some(index) = args.iter().position(|arg| arg == "--error-format") else {
+        return Ok(false);
+    };
+    let json = match args.get(index + 1).map(String::as_str) {
+        Some("json") => true,
+        Some("text") => false,
+        value => return Err(usage(format!("Wrong input: --error-format {}, expected text or json", value.unwrap_or("")))),
+    };
+    args.drain(index..index + 2);
+    return Ok(json);
+}
+
+pub fn print_error(msg: &str, json: bool) {
+    if json {
+        eprintln!("{}", json!({ "error": error_code(msg), "message": msg }));
+    } else {
+        println!("{}", red(msg));
+    }
+}
@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{stdout, BufWriter, Write};
+use std::sync::Arc;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, Int32Type, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+use serde::Serialize;
+use crate::analysis::{collect_zone_stats, ticks_to_ns, walk_snapshot, Walk};
+use crate::args::Arguments;
+use crate::clock::format_utc_ns;
+use crate::failure::usage;
+use crate::groups::ZoneGroups;
+use crate::hist::{zone_histogram, HISTOGRAM_OPTIONS};
+use crate::intern::SourceTable;
+use crate::model::SnapshotModel;
+use crate::report::{markdown_report, REPORT_OPTIONS};
+use crate::snapshot::{load_snapshot, Snapshot};
+use crate::tree::CallTree;
+
+// Output format over decoded snapshot, new formats only need to be added to EXPORTERS.
+// Formats of other commands are registered as "<command>-<format>" and looked up by them, e.g. `hist --format csv` uses hist-csv
+pub trait Exporter: Sync {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    // Options read from arguments by export, accepted by `export` next to --format
+    fn options(&self) -> &'static [&'static str] {
+        return &[];
+    }
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, arguments: &Arguments, output: &mut dyn Write) -> Result<(), String>;
+}
+
+pub static EXPORTERS: &[&dyn Exporter] = &[&ZonesCsv, &Parquet, &StatsJson, &TreeJson, &ChromeTrace, &Folded, &ModelJson, &HistCsv, &HistJson, &ReportMarkdown];
+
+pub fn find_exporter(name: &str) -> Result<&'static dyn Exporter, String> {
+    return EXPORTERS.iter().find(|exporter| exporter.name() == name).copied().ok_or_else(|| {
+        let names: Vec<&str> = EXPORTERS.iter().map(|exporter| exporter.name()).collect();
+        format!("Unknown export format {name}, available: {}", names.join(", "))
+    });
+}
+
+// Quoted as RFC 4180 field when it holds separator, quote or line break, quotes inside are doubled
+pub fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", text.replace('"', "\"\""));
+    }
+    return text.to_string();
+}
+
+fn location_string(table: &SourceTable, pointer: u64) -> &str {
+    return table.strings.get(&pointer).map(String::as_str).unwrap_or("");
+}
+
+// Every zone occurrence as CSV row, in order zones were closed
+struct ZonesCsv;
+
+impl Exporter for ZonesCsv {
+    fn name(&self) -> &'static str {
+        return "zones-csv";
+    }
+
+    fn description(&self) -> &'static str {
+        return "every zone with thread, frame, start, UTC start, duration and self time";
+    }
+
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, _arguments: &Arguments, output: &mut dyn Write) -> Result<(), String> {
+        writeln!(output, "thread,frame,depth,start_ns,start_utc,duration_ns,self_ns,name,file,line").map_err(|e| format!("{e}"))?;
+        let mut result = Ok(());
+        walk_snapshot(snapshot, |walk| {
+            let Walk::Zone(zone) = walk else {
+                return;
+            };
+            if result.is_err() {
+                return;
+            }
+            let location = &table.locations[zone.location as usize];
+            let name = csv_field(location_string(table, location.name));
+            let file = csv_field(location_string(table, location.file));
+            result = writeln!(output, "{},{},{},{},{},{},{},{name},{file},{}", zone.thread_id, zone.frame, zone.depth,
+                              ticks_to_ns(snapshot, zone.start.saturating_sub(snapshot.header.init_begin)), format_utc_ns(snapshot.wall_clock_ns(zone.start)),
+                              ticks_to_ns(snapshot, zone.duration()), ticks_to_ns(snapshot, zone.self_time()), location.line);
+        })?;
+        return result.map_err(|e| format!("{e}"));
+    }
+}
+
+const PARQUET_SCHEMA: &str = "
+message zones {
+    REQUIRED INT32 thread (INTEGER(32, false));
+    REQUIRED INT32 frame (INTEGER(32, false));
+    REQUIRED INT32 depth (INTEGER(32, false));
+    REQUIRED INT64 start_ns;
+    REQUIRED INT64 start_utc (TIMESTAMP(NANOS, true));
+    REQUIRED INT64 end_ns;
+    REQUIRED INT64 duration_ns;
+    REQUIRED INT64 self_ns;
+    REQUIRED BYTE_ARRAY name (UTF8);
+    REQUIRED BYTE_ARRAY file (UTF8);
+    REQUIRED INT32 line (INTEGER(32, false));
+}";
+// Zones buffered before they are written as one row group
+const PARQUET_ROW_GROUP: usize = 1 << 20;
+
+#[derive(Default)]
+struct ZoneColumns {
+    thread: Vec<i32>,
+    frame: Vec<i32>,
+    depth: Vec<i32>,
+    start: Vec<i64>,
+    start_utc: Vec<i64>,
+    end: Vec<i64>,
+    duration: Vec<i64>,
+    self_time: Vec<i64>,
+    name: Vec<ByteArray>,
+    file: Vec<ByteArray>,
+    line: Vec<i32>,
+}
+
+fn write_column<T: DataType>(row_group: &mut SerializedRowGroupWriter<Vec<u8>>, values: &[T::T]) -> Result<(), ParquetError> {
+    let Some(mut column) = row_group.next_column()? else {
+        return Err(ParquetError::General("Column count does not match schema".into()));
+    };
+    column.typed::<T>().write_batch(values, None, None)?;
+    return column.close();
+}
+
+fn write_row_group(writer: &mut SerializedFileWriter<Vec<u8>>, columns: &mut ZoneColumns) -> Result<(), ParquetError> {
+    let mut row_group = writer.next_row_group()?;
+    write_column::<Int32Type>(&mut row_group, &columns.thread)?;
+    write_column::<Int32Type>(&mut row_group, &columns.frame)?;
+    write_column::<Int32Type>(&mut row_group, &columns.depth)?;
+    write_column::<Int64Type>(&mut row_group, &columns.start)?;
+    write_column::<Int64Type>(&mut row_group, &columns.start_utc)?;
+    write_column::<Int64Type>(&mut row_group, &columns.end)?;
+    write_column::<Int64Type>(&mut row_group, &columns.duration)?;
+    write_column::<Int64Type>(&mut row_group, &columns.self_time)?;
+    write_column::<ByteArrayType>(&mut row_group, &columns.name)?;
+    write_column::<ByteArrayType>(&mut row_group, &columns.file)?;
+    write_column::<Int32Type>(&mut row_group, &columns.line)?;
+    row_group.close()?;
+    *columns = ZoneColumns::default();
+    return Ok(());
+}
+
+// Same zones as zones-csv in columnar file for DuckDB, Polars or pandas, plus end_ns.
+// Parquet writer needs owned output, file is built in memory and copied out at the end
+struct Parquet;
+
+impl Exporter for Parquet {
+    fn name(&self) -> &'static str {
+        return "parquet";
+    }
+
+    fn description(&self) -> &'static str {
+        return "zones table with thread, frame, depth, start, UTC start, end, duration, self time, name, file and line, snappy compressed";
+    }
+
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, _arguments: &Arguments, output: &mut dyn Write) -> Result<(), String> {
+        let schema = Arc::new(parse_message_type(PARQUET_SCHEMA).map_err(|e| format!("{e}"))?);
+        let properties = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+        let mut writer = SerializedFileWriter::new(Vec::new(), schema, properties).map_err(|e| format!("{e}"))?;
+        // Location strings are converted once, ByteArray clones share their buffer
+        let strings: Vec<(ByteArray, ByteArray)> = table.locations.iter()
+            .map(|location| (ByteArray::from(location_string(table, location.name)), ByteArray::from(location_string(table, location.file))))
+            .collect();
+        let mut columns = ZoneColumns::default();
+        let mut result = Ok(());
+        walk_snapshot(snapshot, |walk| {
+            let Walk::Zone(zone) = walk else {
+                return;
+            };
+            if result.is_err() {
+                return;
+            }
+            let (name, file) = &strings[zone.location as usize];
+            columns.thread.push(zone.thread_id as i32);
+            columns.frame.push(zone.frame as i32);
+            columns.depth.push(zone.depth as i32);
+            columns.start.push(ticks_to_ns(snapshot, zone.start.saturating_sub(snapshot.header.init_begin)) as i64);
+            columns.start_utc.push(snapshot.wall_clock_ns(zone.start) as i64);
+            columns.end.push(ticks_to_ns(snapshot, zone.end.saturating_sub(snapshot.header.init_begin)) as i64);
+            columns.duration.push(ticks_to_ns(snapshot, zone.duration()) as i64);
+            columns.self_time.push(ticks_to_ns(snapshot, zone.self_time()) as i64);
+            columns.name.push(name.clone());
+            columns.file.push(file.clone());
+            columns.line.push(table.locations[zone.location as usize].line as i32);
+            if columns.thread.len() >= PARQUET_ROW_GROUP {
+                result = write_row_group(&mut writer, &mut columns);
+            }
+        })?;
+        result.map_err(|e| format!("{e}"))?;
+        if !columns.thread.is_empty() {
+            write_row_group(&mut writer, &mut columns).map_err(|e| format!("{e}"))?;
+        }
+        let file = writer.into_inner().map_err(|e| format!("{e}"))?;
+        return output.write_all(&file).map_err(|e| format!("{e}"));
+    }
+}
+
+#[derive(Serialize)]
+struct ZoneStatsJson<'l> {
+    name: &'l str,
+    count: usize,
+    total_ns: u64,
+    self_ns: u64,
+    child_ns: u64,
+    mean_ns: u64,
+    p50_ns: u64,
+    p99_ns: u64,
+    max_ns: u64,
+}
+
+// Per zone name statistics, same numbers as `top` prints
+struct StatsJson;
+
+impl Exporter for StatsJson {
+    fn name(&self) -> &'static str {
+        return "stats-json";
+    }
+
+    fn description(&self) -> &'static str {
+        return "per zone count, total, self, child, mean, p50, p99 and max";
+    }
+
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, _arguments: &Arguments, output: &mut dyn Write) -> Result<(), String> {
+        let stats = collect_zone_stats(snapshot, table)?;
+        let json: Vec<ZoneStatsJson> = stats.iter().map(|zone| ZoneStatsJson {
+            name: &zone.name,
+            count: zone.count(),
+            total_ns: zone.total,
+            self_ns: zone.self_total,
+            child_ns: zone.total.saturating_sub(zone.self_total),
+            mean_ns: zone.mean(),
+            p50_ns: zone.percentile(50f64),
+            p99_ns: zone.percentile(99f64),
+            max_ns: zone.durations.last().copied().unwrap_or(0),
+        }).collect();
+        serde_json::to_writer_pretty(&mut *output, &json).map_err(|e| format!("{e}"))?;
+        return writeln!(output).map_err(|e| format!("{e}"));
+    }
+}
+
+// Merged caller -> callee tree, same as `tree --format json`
+struct TreeJson;
+
+impl Exporter for TreeJson {
+    fn name(&self) -> &'static str {
+        return "tree-json";
+    }
+
+    fn description(&self) -> &'static str {
+        return "caller -> callee tree merged over whole capture";
+    }
+
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, _arguments: &Arguments, output: &mut dyn Write) -> Result<(), String> {
+        let tree = CallTree::build(snapshot, table)?;
+        serde_json::to_writer_pretty(&mut *output, &tree.to_json(table, 0)).map_err(|e| format!("{e}"))?;
+        return writeln!(output).map_err(|e| format!("{e}"));
+    }
+}
+
+// Chrome trace event format, opens in chrome://tracing, Perfetto and speedscope
+struct ChromeTrace;
+
+impl Exporter for ChromeTrace {
+    fn name(&self) -> &'static str {
+        return "chrome";
+    }
+
+    fn description(&self) -> &'static str {
+        return "Chrome trace event JSON for Perfetto or chrome://tracing";
+    }
+
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, _arguments: &Arguments, output: &mut dyn Write) -> Result<(), String> {
+        let micros = |ticks: u64| ticks_to_ns(snapshot, ticks) as f64 / 1000f64;
+        // Trace viewers show otherData as metadata, ts stays relative to capture start
+        write!(output, "{{\"otherData\":{{\"captured\":\"{}\"}},\"traceEvents\":[", format_utc_ns(snapshot.wall_clock_ns(snapshot.header.init_begin)))
+            .map_err(|e| format!("{e}"))?;
+        let mut first = true;
+        let mut result = Ok(());
+        walk_snapshot(snapshot, |walk| {
+            if result.is_err() {
+                return;
+            }
+            let separator = if first { "\n" } else { ",\n" };
+            match walk {
+                Walk::Zone(zone) => {
+                    let name = serde_json::to_string(location_string(table, table.locations[zone.location as usize].name)).unwrap_or_default();
+                    result = write!(output, "{separator}{{\"name\":{name},\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}}}",
+                                    zone.thread_id, micros(zone.start.saturating_sub(snapshot.header.init_begin)), micros(zone.duration()));
+                }
+                Walk::Frame { index, timestamp } => {
+                    result = write!(output, "{separator}{{\"name\":\"Frame {index}\",\"ph\":\"i\",\"s\":\"g\",\"pid\":0,\"tid\":0,\"ts\":{:.3}}}",
+                                    micros(timestamp.saturating_sub(snapshot.header.init_begin)));
+                }
+                Walk::Begin { .. } => return,
+            }
+            first = false;
+        })?;
+        result.map_err(|e| format!("{e}"))?;
+        return writeln!(output, "\n]}}").map_err(|e| format!("{e}"));
+    }
+}
+
+// Collapsed stacks weighted by self time, input of flamegraph.pl, inferno and speedscope
+struct Folded;
+
+impl Exporter for Folded {
+    fn name(&self) -> &'static str {
+        return "folded";
+    }
+
+    fn description(&self) -> &'static str {
+        return "collapsed stacks with self time in nanoseconds for flamegraphs";
+    }
+
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, _arguments: &Arguments, output: &mut dyn Write) -> Result<(), String> {
+        let mut stacks = HashMap::<u32, Vec<u64>>::new();
+        let mut folded = HashMap::<Vec<u64>, u64>::new();
+        walk_snapshot(snapshot, |walk| {
+            match walk {
+                Walk::Begin { thread_id, location, .. } => stacks.entry(thread_id).or_default().push(table.locations[location as usize].name),
+                Walk::Zone(zone) => {
+                    let stack = stacks.entry(zone.thread_id).or_default();
+                    *folded.entry(stack.clone()).or_default() += ticks_to_ns(snapshot, zone.self_time());
+                    stack.pop();
+                }
+                Walk::Frame { .. } => {}
+            }
+        })?;
+        let mut lines: Vec<(String, u64)> = folded.into_iter().filter(|(_, time)| *time > 0).map(|(stack, time)| {
+            // Semicolon separates frames in collapsed format
+            let names: Vec<String> = stack.iter().map(|name| location_string(table, *name).replace(';', ":")).collect();
+            return (names.join(";"), time);
+        }).collect();
+        lines.sort();
+        for (stack, time) in lines {
+            writeln!(output, "{stack} {time}").map_err(|e| format!("{e}"))?;
+        }
+        return Ok(());
+    }
+}
+
+// Decoded model with resolved strings, read back by rtracy::model::SnapshotModel::from_json
+struct ModelJson;
+
+impl Exporter for ModelJson {
+    fn name(&self) -> &'static str {
+        return "model-json";
+    }
+
+    fn description(&self) -> &'static str {
+        return "header, source locations, zones and frames as one JSON document for custom tools";
+    }
+
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, _arguments: &Arguments, output: &mut dyn Write) -> Result<(), String> {
+        let json = SnapshotModel::load(snapshot, table)?.to_json()?;
+        return writeln!(output, "{json}").map_err(|e| format!("{e}"));
+    }
+}
+
+// Zone histogram as from_ns,to_ns,count rows
+struct HistCsv;
+
+impl Exporter for HistCsv {
+    fn name(&self) -> &'static str {
+        return "hist-csv";
+    }
+
+    fn description(&self) -> &'static str {
+        return "duration buckets of one zone, needs --zone [--buckets n] [--scale linear|log]";
+    }
+
+    fn options(&self) -> &'static [&'static str] {
+        return &HISTOGRAM_OPTIONS;
+    }
+
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, arguments: &Arguments, output: &mut dyn Write) -> Result<(), String> {
+        let (_, buckets) = zone_histogram(snapshot, table, arguments)?;
+        writeln!(output, "from_ns,to_ns,count").map_err(|e| format!("{e}"))?;
+        for bucket in &buckets {
+            writeln!(output, "{},{},{}", bucket.from_ns, bucket.to_ns, bucket.count).map_err(|e| format!("{e}"))?;
+        }
+        return Ok(());
+    }
+}
+
+struct HistJson;
+
+impl Exporter for HistJson {
+    fn name(&self) -> &'static str {
+        return "hist-json";
+    }
+
+    fn description(&self) -> &'static str {
+        return "same buckets as hist-csv in JSON array";
+    }
+
+    fn options(&self) -> &'static [&'static str] {
+        return &HISTOGRAM_OPTIONS;
+    }
+
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, arguments: &Arguments, output: &mut dyn Write) -> Result<(), String> {
+        let (_, buckets) = zone_histogram(snapshot, table, arguments)?;
+        serde_json::to_writer_pretty(&mut *output, &buckets).map_err(|e| format!("{e}"))?;
+        return writeln!(output).map_err(|e| format!("{e}"));
+    }
+}
+
+// Markdown summary for pull requests and issues, same as `report`
+struct ReportMarkdown;
+
+impl Exporter for ReportMarkdown {
+    fn name(&self) -> &'static str {
+        return "report-markdown";
+    }
+
+    fn description(&self) -> &'static str {
+        return "capture summary, frame times, top zones and spikes as Markdown [--sigma n] [stats options]";
+    }
+
+    fn options(&self) -> &'static [&'static str] {
+        return &REPORT_OPTIONS;
+    }
+
+    fn export(&self, snapshot: &Snapshot, table: &SourceTable, arguments: &Arguments, output: &mut dyn Write) -> Result<(), String> {
+        return output.write_all(markdown_report(snapshot, table, arguments)?.as_bytes()).map_err(|e| format!("{e}"));
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    let (Some(path), Some(format)) = (arguments.positional.first(), arguments.get("--format")) else {
+        println!("Available formats:");
+        for exporter in EXPORTERS {
+            println!("    {:<16} {}", exporter.name(), exporter.description());
+        }
+        return Err(usage("Use: export <file> --format name [-o output] [--groups groups.toml] [format options]"));
+    };
+    let exporter = find_exporter(format)?;
+    let mut known = vec!["--format", "-o", "--groups"];
+    known.extend_from_slice(exporter.options());
+    arguments.check_known(&known)?;
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    if let Some(groups) = arguments.get("--groups").map(ZoneGroups::load).transpose()? {
+        table.apply_groups(&groups);
+    }
+    let mut output: BufWriter<Box<dyn Write>> = match arguments.get("-o") {
+        Some(output) => BufWriter::new(Box::new(File::create(output).map_err(|e| format!("Error creating file {output}: {e}"))?)),
+        None => BufWriter::new(Box::new(stdout())),
+    };
+    exporter.export(&snapshot, &table, &arguments, &mut output)?;
+    return output.flush().map_err(|e| format!("{e}"));
+}
@@ -0,0 +1,39 @@
+use std::io;
+use std::sync::Mutex;
+
+// What went wrong, reported by --error-format json. Errors stay plain messages, kind is given where the message is made
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    PortUnavailable,
+    BadSignature,
+    UnsupportedVersion,
+    Corrupted,
+    Truncated,
+    FileNotFound,
+    FileUnreadable,
+    Usage,
+}
+
+// Kind and text of the newest error made with fail, callers only put context like file path in front of the text
+static LAST_ERROR: Mutex<Option<(ErrorKind, String)>> = Mutex::new(None);
+
+pub fn fail(kind: ErrorKind, msg: impl Into<String>) -> String {
+    let msg = msg.into();
+    *LAST_ERROR.lock().unwrap() = Some((kind, msg.clone()));
+    return msg;
+}
+
+// Wrong command line, option value or command
+pub fn usage(msg: impl Into<String>) -> String {
+    return fail(ErrorKind::Usage, msg);
+}
+
+pub fn open_failed(path: &str, e: io::Error) -> String {
+    let kind = if e.kind() == io::ErrorKind::NotFound { ErrorKind::FileNotFound } else { ErrorKind::FileUnreadable };
+    return fail(kind, format!("Error opening file {path}: {e}"));
+}
+
+// Kind of error that ended up as msg, None when it was not made with fail or a newer error was made since
+pub fn error_kind(msg: &str) -> Option<ErrorKind> {
+    return LAST_ERROR.lock().unwrap().as_ref().filter(|(_, text)| msg.ends_with(text.as_str())).map(|(kind, _)| *kind);
+}
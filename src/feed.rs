@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{accept_hdr, Message, WebSocket};
+use crate::acl::token_matches;
+use crate::analysis::{walk_snapshot_while, Walk, Zone};
+use crate::failure::{fail, ErrorKind};
+use crate::filter::{FilterScript, ZoneAction, ZoneFilter};
+use crate::health::Activity;
+use crate::intern::SourceTable;
+use crate::pool::WorkerPool;
+use crate::server::{Profile, ServerOptions, SharedServed};
+use crate::snapshot::Snapshot;
+
+// One JSON text message per event, times are nanoseconds since capture start of the snapshot
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum FeedEvent<'l> {
+    Snapshot { index: usize, program: &'l str, epoch: u64 },
+    Zone { thread: u32, frame: u64, depth: u32, name: &'l str, file: &'l str, line: u32, color: u32, start_ns: u64, end_ns: u64 },
+    Frame { frame: u64, time_ns: u64 },
+}
+
+// Open zone of walked snapshot, changes come from filter script
+enum OpenZone {
+    // Opened before the first streamed frame
+    Skipped,
+    Dropped,
+    Sent { frame: u64, name: Option<String>, color: Option<u32> },
+}
+
+struct FeedRequest {
+    // Replay speed against capture time, 0 sends as fast as client reads
+    speed: f64,
+    token: Option<String>,
+}
+
+// Query string encoding of browsers and URLSearchParams, "+" is space and "%XX" is byte
+fn percent_decode(text: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest.get(..2).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                bytes.push(hex.ok_or(format!("Wrong escape in query: {text}"))?);
+                rest = &rest[2..];
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    return String::from_utf8(bytes).map_err(|_| format!("Query is not UTF-8: {text}"));
+}
+
+fn parse_request(query: &str) -> Result<FeedRequest, String> {
+    let mut request = FeedRequest { speed: 1f64, token: None };
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let (key, value) = (percent_decode(key)?, percent_decode(value)?);
+        match key.as_str() {
+            "speed" => request.speed = value.parse().ok().filter(|speed: &f64| *speed >= 0f64).ok_or(format!("Wrong value of speed: {value}"))?,
+            "token" => request.token = Some(value),
+            _ => return Err(format!("Unknown parameter {key}")),
+        }
+    }
+    return Ok(request);
+}
+
+fn refuse(status: StatusCode, msg: String) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(msg));
+    *response.status_mut() = status;
+    return response;
+}
+
+// "?speed=n&token=secret" is read during WebSocket handshake, same --allow and --auth-token rules as Tracy connections.
+// Handshake callback has to return tungstenite error response as is
+#[allow(clippy::result_large_err)]
+fn accept_feed(stream: TcpStream, options: &ServerOptions) -> Result<(WebSocket<TcpStream>, FeedRequest), String> {
+    let peer = stream.peer_addr().map_err(|e| format!("{e}"))?;
+    if options.allow.as_ref().is_some_and(|allow| !allow.allows(peer.ip())) {
+        return Err(format!("Feed connection from {} refused, address is not allowed", peer.ip()));
+    }
+    let mut request = None;
+    let socket = accept_hdr(stream, |http: &Request, response: Response| {
+        let feed = parse_request(http.uri().query().unwrap_or("")).map_err(|msg| refuse(StatusCode::BAD_REQUEST, msg))?;
+        if let Some(expected) = &options.auth_token {
+            if !feed.token.as_ref().is_some_and(|token| token_matches(expected, token.as_bytes())) {
+                return Err(refuse(StatusCode::UNAUTHORIZED, "Wrong auth token".into()));
+            }
+        }
+        request = Some(feed);
+        return Ok(response);
+    }).map_err(|e| format!("Feed handshake with {peer} failed: {e}"))?;
+    return Ok((socket, request.unwrap()));
+}
+
+struct Feed<'l> {
+    socket: WebSocket<TcpStream>,
+    table: &'l SourceTable,
+    filter: ZoneFilter,
+    skip_frames: u64,
+    limit_frames: u64,
+    speed: f64,
+}
+
+impl<'l> Feed<'l> {
+    fn send(&mut self, event: &FeedEvent) -> Result<(), String> {
+        let json = serde_json::to_string(event).map_err(|e| format!("{e}"))?;
+        return self.socket.write(Message::Text(json)).map_err(|e| format!("{e}"));
+    }
+
+    fn begin_zone(&mut self, thread_id: u32, location: u32, timestamp: u64, frame: u64) -> Result<OpenZone, String> {
+        if frame <= self.skip_frames {
+            return Ok(OpenZone::Skipped);
+        }
+        return Ok(match self.filter.zone(self.table, thread_id, location as u64, timestamp, frame)? {
+            ZoneAction::Drop => OpenZone::Dropped,
+            ZoneAction::Keep => OpenZone::Sent { frame, name: None, color: None },
+            ZoneAction::Change { name, color } => OpenZone::Sent { frame, name, color },
+        });
+    }
+
+    fn end_zone(&mut self, snapshot: &Snapshot, zone: &Zone, open: OpenZone) -> Result<(), String> {
+        if matches!(open, OpenZone::Skipped) {
+            return Ok(());
+        }
+        // Filter saw the begin, its stack is popped for dropped zones as well
+        self.filter.end(zone.thread_id);
+        let OpenZone::Sent { frame, name, color } = open else {
+            return Ok(());
+        };
+        let table = self.table;
+        let string = |pointer: u64| table.strings.get(&pointer).map(String::as_str).unwrap_or("");
+        let source = &table.locations[zone.location as usize];
+        let file = table.string(source.file).unwrap_or_default();
+        return self.send(&FeedEvent::Zone {
+            thread: zone.thread_id,
+            frame,
+            depth: zone.depth,
+            name: name.as_deref().unwrap_or(string(source.name)),
+            file: &file,
+            line: source.line,
+            color: color.unwrap_or((source.color_r as u32) << 16 | (source.color_g as u32) << 8 | source.color_b as u32),
+            start_ns: since_start(snapshot, zone.start),
+            end_ns: since_start(snapshot, zone.end),
+        });
+    }
+
+    // Replay start holds wall clock and capture time of the first streamed frame, later frames wait until replay reaches them
+    fn frame(&mut self, snapshot: &Snapshot, frame: u64, timestamp: u64, replay_start: &mut Option<(Instant, u64)>) -> Result<(), String> {
+        if frame <= self.skip_frames || !self.filter.frame(frame, timestamp)? {
+            return Ok(());
+        }
+        let time_ns = since_start(snapshot, timestamp);
+        if self.speed > 0f64 {
+            let (started, first) = *replay_start.get_or_insert((Instant::now(), time_ns));
+            let due = started + Duration::from_secs_f64((time_ns - first) as f64 / 1_000_000_000f64 / self.speed);
+            self.socket.flush().map_err(|e| format!("{e}"))?;
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+        return self.send(&FeedEvent::Frame { frame, time_ns });
+    }
+
+    // Returns false once frame limit is reached
+    fn stream_snapshot(&mut self, index: usize, snapshot: &Snapshot) -> Result<bool, String> {
+        self.send(&FeedEvent::Snapshot { index, program: snapshot.program_name(), epoch: snapshot.header.epoch })?;
+        let mut stacks = HashMap::<u32, Vec<OpenZone>>::new();
+        let mut frame = 0u64;
+        let mut replay_start = None;
+        let mut result = Ok(true);
+        walk_snapshot_while(snapshot, |walk| {
+            let step = match walk {
+                Walk::Begin { thread_id, location, timestamp } => self.begin_zone(thread_id, location, timestamp, frame).map(|open| {
+                    stacks.entry(thread_id).or_default().push(open);
+                    true
+                }),
+                Walk::Zone(zone) => match stacks.entry(zone.thread_id).or_default().pop() {
+                    Some(open) => self.end_zone(snapshot, &zone, open).map(|_| true),
+                    None => Ok(true),
+                },
+                Walk::Frame { timestamp, .. } => {
+                    frame += 1;
+                    self.frame(snapshot, frame, timestamp, &mut replay_start).map(|_| frame <= self.skip_frames + self.limit_frames)
+                }
+            };
+            let proceed = step.as_ref().is_ok_and(|proceed| *proceed);
+            result = step;
+            proceed
+        })?;
+        return result;
+    }
+}
+
+fn since_start(snapshot: &Snapshot, ticks: u64) -> u64 {
+    return (ticks.saturating_sub(snapshot.header.init_begin) as f64 * snapshot.header.multiplier) as u64;
+}
+
+fn stream_feed(stream: TcpStream, snapshots: &[Snapshot], table: &SourceTable, options: &ServerOptions, profile: &Profile) -> Result<(), String> {
+    let (socket, request) = accept_feed(stream, options)?;
+    let skip_frames: u64 = profile.skip_frames.into();
+    // Script is compiled separately for every client, like for Tracy sessions
+    let filter = ZoneFilter::new(profile.filter_script.as_deref().map(FilterScript::load).transpose()?, skip_frames, profile.frame_stride.into(),
+                                 profile.within.clone(), profile.max_depth);
+    let mut feed = Feed { socket, table, filter, skip_frames, limit_frames: profile.limit_frames.into(), speed: request.speed };
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        if !feed.stream_snapshot(index, snapshot)? {
+            break;
+        }
+    }
+    feed.socket.close(None).map_err(|e| format!("{e}"))?;
+    // Close handshake completes once client answers or goes away
+    while feed.socket.read().is_ok() {}
+    return Ok(());
+}
+
+pub fn bind_feed(port: u16) -> Result<TcpListener, String> {
+    return TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).map_err(|e| fail(ErrorKind::PortUnavailable, format!("Error binding WebSocket port {port}: {e}")));
+}
+
+// Feed connections use frame range and filters of the main listener profile, take workers from the same pool
+// as Tracy sessions and count as connected clients
+pub fn spawn_feed(listener: TcpListener, served: &'static SharedServed, profile: &'static RwLock<Arc<Profile>>, activity: &'static Activity,
+                  pool: &'static WorkerPool) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            println!("New feed connection: {}", stream.peer_addr().map(|peer| peer.to_string()).unwrap_or_default());
+            let Some(served) = served.read().unwrap().clone() else {
+                println!("No capture uploaded yet, feed connection closed");
+                continue;
+            };
+            let connected = activity.connect();
+            let profile = profile.read().unwrap().clone();
+            pool.submit(Box::new(move || {
+                if let Err(msg) = stream_feed(stream, &served.snapshots, &served.table, &served.options, &profile) {
+                    println!("Feed client disconnected with error: {msg}");
+                }
+                drop(connected);
+            }));
+        }
+    });
+}
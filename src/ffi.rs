@@ -0,0 +1,206 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::io::{ErrorKind, Read};
+use crate::intern::SourceTable;
+use crate::snapshot::{load_snapshot, EventReader, Snapshot};
+use crate::seal::TRAILER_TAG;
+use crate::structs::{BINCODE_CONFIG, EventType, UTracyEvent};
+use crate::writer::EVENT_SIZE;
+
+// C API declared in include/rtracy.h, every function returning int reports errors with -1 and rtracy_last_error
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error(message: String) {
+    LAST_ERROR.with(|error| *error.borrow_mut() = CString::new(message.replace('\0', " ")).unwrap_or_default());
+}
+
+pub struct RtracyReader {
+    snapshot: Snapshot,
+    // Name, function and file of every location, kept alive while reader is open
+    locations: Vec<[CString; 3]>,
+    lines: Vec<u32>,
+    colors: Vec<u32>,
+    events: EventReader,
+}
+
+#[repr(C)]
+pub struct RtracyHeader {
+    pub multiplier: f64,
+    pub init_begin: u64,
+    pub init_end: u64,
+    pub epoch: u64,
+    pub process_id: u64,
+    pub location_count: u32,
+}
+
+#[repr(C)]
+pub struct RtracyLocation {
+    pub name: *const c_char,
+    pub function: *const c_char,
+    pub file: *const c_char,
+    pub line: u32,
+    pub color: u32,
+}
+
+// kind matches utracy event type: 15 zone begin, 17 zone end, 62 zone color, 64 frame mark
+#[repr(C)]
+pub struct RtracyEvent {
+    pub kind: u8,
+    pub thread_id: u32,
+    pub location: u32,
+    pub timestamp: u64,
+    pub color: u32,
+}
+
+/// Last error of current thread, valid until next rtracy call on this thread
+#[no_mangle]
+pub extern "C" fn rtracy_last_error() -> *const c_char {
+    return LAST_ERROR.with(|error| error.borrow().as_ptr());
+}
+
+/// Opens snapshot, returns null on error
+///
+/// # Safety
+/// path must be a valid NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn rtracy_open(path: *const c_char) -> *mut RtracyReader {
+    if path.is_null() {
+        set_error("path is null".into());
+        return std::ptr::null_mut();
+    }
+    let Ok(path) = CStr::from_ptr(path).to_str() else {
+        set_error("path is not valid UTF-8".into());
+        return std::ptr::null_mut();
+    };
+    let mut table = SourceTable::new();
+    let result = load_snapshot(path, &mut table).and_then(|snapshot| {
+        let events = snapshot.open_events()?;
+        let string = |pointer: u64| CString::new(table.strings.get(&pointer).cloned().unwrap_or_default().replace('\0', " ")).unwrap_or_default();
+        let locations = table.locations.iter().map(|l| [string(l.name), string(l.function), string(l.file)]).collect();
+        let lines = table.locations.iter().map(|l| l.line).collect();
+        let colors = table.locations.iter().map(|l| ((l.color_r as u32) << 16) | ((l.color_g as u32) << 8) | l.color_b as u32).collect();
+        Ok(RtracyReader { snapshot, locations, lines, colors, events })
+    });
+    return match result {
+        Ok(reader) => Box::into_raw(Box::new(reader)),
+        Err(msg) => {
+            set_error(msg);
+            std::ptr::null_mut()
+        }
+    };
+}
+
+/// # Safety
+/// reader must come from rtracy_open and not be used after this call
+#[no_mangle]
+pub unsafe extern "C" fn rtracy_close(reader: *mut RtracyReader) {
+    if !reader.is_null() {
+        drop(Box::from_raw(reader));
+    }
+}
+
+/// # Safety
+/// reader must come from rtracy_open, header must point to writable RtracyHeader
+#[no_mangle]
+pub unsafe extern "C" fn rtracy_get_header(reader: *const RtracyReader, header: *mut RtracyHeader) -> c_int {
+    let (Some(reader), Some(header)) = (reader.as_ref(), header.as_mut()) else {
+        set_error("reader or header is null".into());
+        return -1;
+    };
+    let source = &reader.snapshot.header;
+    *header = RtracyHeader {
+        multiplier: source.multiplier,
+        init_begin: source.init_begin,
+        init_end: source.init_end,
+        epoch: source.epoch,
+        process_id: source.process_id,
+        location_count: reader.snapshot.location_count,
+    };
+    return 0;
+}
+
+/// Strings stay valid until rtracy_close
+///
+/// # Safety
+/// reader must come from rtracy_open, location must point to writable RtracyLocation
+#[no_mangle]
+pub unsafe extern "C" fn rtracy_get_location(reader: *const RtracyReader, index: u32, location: *mut RtracyLocation) -> c_int {
+    let (Some(reader), Some(location)) = (reader.as_ref(), location.as_mut()) else {
+        set_error("reader or location is null".into());
+        return -1;
+    };
+    let Some([name, function, file]) = reader.locations.get(index as usize) else {
+        set_error(format!("Location {index} out of range, snapshot has {}", reader.locations.len()));
+        return -1;
+    };
+    *location = RtracyLocation {
+        name: name.as_ptr(),
+        function: function.as_ptr(),
+        file: file.as_ptr(),
+        line: reader.lines[index as usize],
+        color: reader.colors[index as usize],
+    };
+    return 0;
+}
+
+/// Returns 1 when event was read, 0 at the end of snapshot, -1 when events are truncated or corrupted
+///
+/// # Safety
+/// reader must come from rtracy_open, event must point to writable RtracyEvent
+#[no_mangle]
+pub unsafe extern "C" fn rtracy_next_event(reader: *mut RtracyReader, event: *mut RtracyEvent) -> c_int {
+    let (Some(reader), Some(event)) = (reader.as_mut(), event.as_mut()) else {
+        set_error("reader or event is null".into());
+        return -1;
+    };
+    // End of file and seal trailer end the snapshot, anything else that does not decode is an error
+    let mut bytes = [0u8; EVENT_SIZE as usize];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        match reader.events.read(&mut bytes[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => {
+                set_error(format!("{}: {e}", reader.snapshot.path));
+                return -1;
+            }
+        }
+    }
+    if filled == 0 || bytes[..TRAILER_TAG.len()] == TRAILER_TAG {
+        return 0;
+    }
+    if filled < bytes.len() {
+        set_error(format!("{}: last event is cut off, file is truncated", reader.snapshot.path));
+        return -1;
+    }
+    let decoded: UTracyEvent = match bincode::decode_from_slice(&bytes, BINCODE_CONFIG) {
+        Ok((decoded, _)) => decoded,
+        Err(e) => {
+            set_error(format!("{}: corrupted event: {e}", reader.snapshot.path));
+            return -1;
+        }
+    };
+    *event = match decoded.event_type {
+        EventType::Begin => {
+            let begin = decoded.event.begin;
+            RtracyEvent { kind: EventType::Begin as u8, thread_id: begin.thread_id, location: begin.source_location, timestamp: begin.timestamp, color: 0 }
+        }
+        EventType::End => {
+            let end = decoded.event.end;
+            RtracyEvent { kind: EventType::End as u8, thread_id: end.thread_id, location: 0, timestamp: end.timestamp, color: 0 }
+        }
+        EventType::Color => {
+            let color = decoded.event.color;
+            let rgb = ((color.color[0] as u32) << 16) | ((color.color[1] as u32) << 8) | color.color[2] as u32;
+            RtracyEvent { kind: EventType::Color as u8, thread_id: color.thread_id, location: 0, timestamp: 0, color: rgb }
+        }
+        EventType::Mark => {
+            RtracyEvent { kind: EventType::Mark as u8, thread_id: 0, location: 0, timestamp: decoded.event.mark.timestamp, color: 0 }
+        }
+    };
+    return 1;
+}
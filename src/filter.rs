@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::fs;
+use rhai::{Dynamic, Engine, Map, AST};
+use crate::intern::SourceTable;
+
+// Result of running the filter script for one zone
+pub enum ZoneAction {
+    Keep,
+    Drop,
+    Change { name: Option<String>, color: Option<u32> },
+}
+
+// filter.rhai defines `fn filter(event)`, event is a map with kind ("zone" or "frame") and event fields.
+// Returning false drops the event, returning the event map with changed `name` or `color` (0xRRGGBB)
+// renames or recolors the zone, anything else keeps it untouched
+pub struct FilterScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl FilterScript {
+    pub fn load(path: &str) -> Result<FilterScript, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let engine = Engine::new();
+        let ast = engine.compile(source).map_err(|e| format!("{path}: {e}"))?;
+        if !ast.iter_functions().any(|f| f.name == "filter" && f.params.len() == 1) {
+            return Err(format!("{path}: script must define fn filter(event)"));
+        }
+        return Ok(FilterScript { engine, ast });
+    }
+
+    fn call(&self, event: Map) -> Result<Dynamic, String> {
+        return self.engine.call_fn(&mut rhai::Scope::new(), &self.ast, "filter", (event,)).map_err(|e| format!("Filter script: {e}"));
+    }
+
+    pub fn zone(&self, table: &SourceTable, location: u64, thread_id: u32, timestamp: u64, frame: u64) -> Result<ZoneAction, String> {
+        let Some(source) = table.locations.get(location as usize) else {
+            return Ok(ZoneAction::Keep);
+        };
+        let string = |pointer: u64| Dynamic::from(table.string(pointer).unwrap_or_default().into_owned());
+        let name = string(source.name);
+        let color = ((source.color_r as i64) << 16) | ((source.color_g as i64) << 8) | source.color_b as i64;
+        let mut event = Map::new();
+        event.insert("kind".into(), "zone".into());
+        event.insert("name".into(), name.clone());
+        event.insert("function".into(), string(source.function));
+        event.insert("file".into(), string(source.file));
+        event.insert("line".into(), (source.line as i64).into());
+        event.insert("color".into(), color.into());
+        event.insert("thread".into(), (thread_id as i64).into());
+        event.insert("timestamp".into(), (timestamp as i64).into());
+        event.insert("frame".into(), (frame as i64).into());
+
+        let result = self.call(event)?;
+        if result.as_bool() == Ok(false) {
+            return Ok(ZoneAction::Drop);
+        }
+        let Some(changed) = result.try_cast::<Map>() else {
+            return Ok(ZoneAction::Keep);
+        };
+        let new_name = changed.get("name").and_then(|n| n.clone().into_string().ok()).filter(|n| Some(n) != name.read_lock::<String>().as_deref());
+        let new_color = changed.get("color").and_then(|c| c.as_int().ok()).filter(|c| *c != color).map(|c| c as u32);
+        if new_name.is_none() && new_color.is_none() {
+            return Ok(ZoneAction::Keep);
+        }
+        return Ok(ZoneAction::Change { name: new_name, color: new_color });
+    }
+
+    // Frame marks can only be dropped, they still count for skip and limit options
+    pub fn frame(&self, index: u64, timestamp: u64) -> Result<bool, String> {
+        let mut event = Map::new();
+        event.insert("kind".into(), "frame".into());
+        event.insert("frame".into(), (index as i64).into());
+        event.insert("timestamp".into(), (timestamp as i64).into());
+        return Ok(self.call(event)?.as_bool() != Ok(false));
+    }
+}
+
+struct OpenZone {
+    dropped: bool,
+    // Zone is an occurrence of `within` zone or nested in one
+    within: bool,
+    // 1 for outermost zone of thread, or for occurrence of `within` zone
+    depth: u32,
+}
+
+// Frame stride, ancestry, depth and filter script decisions for streamed zones, shared by Tracy sessions and WebSocket feed.
+// Frames count marks seen so far, zones of frame skip_frames + 1 are the first streamed ones
+pub struct ZoneFilter {
+    script: Option<FilterScript>,
+    skip_frames: u64,
+    frame_stride: u64,
+    // Only zones named so and their descendants are kept
+    within: Option<String>,
+    // Deeper zones are dropped
+    max_depth: Option<u32>,
+    // Per thread stack of open zones
+    stacks: HashMap<u32, Vec<OpenZone>>,
+}
+
+impl ZoneFilter {
+    pub fn new(script: Option<FilterScript>, skip_frames: u64, frame_stride: u64, within: Option<String>, max_depth: Option<u32>) -> ZoneFilter {
+        return ZoneFilter { script, skip_frames, frame_stride, within, max_depth, stacks: HashMap::new() };
+    }
+
+    fn is_active(&self) -> bool {
+        return self.script.is_some() || self.frame_stride > 1 || self.within.is_some() || self.max_depth.is_some();
+    }
+
+    pub fn zone(&mut self, table: &SourceTable, thread_id: u32, location: u64, timestamp: u64, frame: u64) -> Result<ZoneAction, String> {
+        if !self.is_active() {
+            return Ok(ZoneAction::Keep);
+        }
+        let stack = self.stacks.entry(thread_id).or_default();
+        let parent = stack.last();
+        let within = self.within.as_ref().is_some_and(|within| {
+            return parent.is_some_and(|parent| parent.within) || table.locations.get(location as usize)
+                .and_then(|source| table.strings.get(&source.name)).is_some_and(|name| name == within);
+        });
+        // Depth below `within` zone counts from its occurrence
+        let depth = match parent {
+            Some(parent) if parent.within || !within => parent.depth + 1,
+            _ => 1,
+        };
+        // Only every nth streamed frame keeps its zones, frame marks of the others are still sent
+        let action = if !(frame - self.skip_frames - 1).is_multiple_of(self.frame_stride) || (self.within.is_some() && !within)
+            || self.max_depth.is_some_and(|max_depth| depth > max_depth) {
+            ZoneAction::Drop
+        } else if let Some(script) = &self.script {
+            script.zone(table, location, thread_id, timestamp, frame)?
+        } else {
+            ZoneAction::Keep
+        };
+        stack.push(OpenZone { dropped: matches!(action, ZoneAction::Drop), within, depth });
+        return Ok(action);
+    }
+
+    // Returns true when matching begin was dropped
+    pub fn end(&mut self, thread_id: u32) -> bool {
+        if !self.is_active() {
+            return false;
+        }
+        return self.stacks.entry(thread_id).or_default().pop().is_some_and(|open| open.dropped);
+    }
+
+    // Innermost open zone of thread was dropped, its color events are dropped too
+    pub fn dropped(&self, thread_id: u32) -> bool {
+        return self.stacks.get(&thread_id).and_then(|stack| stack.last()).is_some_and(|open| open.dropped);
+    }
+
+    pub fn frame(&self, index: u64, timestamp: u64) -> Result<bool, String> {
+        return self.script.as_ref().map_or(Ok(true), |script| script.frame(index, timestamp));
+    }
+}
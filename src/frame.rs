@@ -0,0 +1,115 @@
+use crate::analysis::{ticks_to_ns, walk_snapshot_while, Walk, Zone};
+use crate::args::Arguments;
+use crate::clock::{format_count, format_duration, format_utc_ns, parse_utc};
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::search::ZoneIndex;
+use crate::snapshot::{load_snapshot, Snapshot};
+
+// Frame which was running at given unix time, frame N ends with mark N
+fn find_frame_at(snapshot: &Snapshot, unix_ns: u64) -> Result<u32, String> {
+    let target = snapshot.ticks_at_wall_clock(unix_ns);
+    let mut found = None;
+    walk_snapshot_while(snapshot, |walk| {
+        if let Walk::Frame { index, timestamp } = walk {
+            if timestamp >= target {
+                found = Some(index.max(1));
+                return false;
+            }
+        }
+        true
+    })?;
+    return found.ok_or(format!("No frame at {} in snapshot", format_utc_ns(unix_ns)));
+}
+
+// Zone stack open on thread at given unix time
+fn print_running(snapshot: &Snapshot, table: &SourceTable, thread_id: u32, unix_ns: u64) -> Result<(), String> {
+    let index = ZoneIndex::from_snapshot(snapshot)?;
+    let timestamp = snapshot.ticks_at_wall_clock(unix_ns);
+    let stack = index.at(thread_id, timestamp);
+    if stack.is_empty() {
+        println!("Nothing was running on thread {thread_id} at {}", format_utc_ns(unix_ns));
+        return Ok(());
+    }
+    println!("Thread {thread_id} at {}, frame {}", format_utc_ns(unix_ns), stack[0].frame);
+    println!("{:>12} {:>12}  Zone", "Running for", "Total");
+    for zone in stack {
+        let location = &table.locations[zone.location as usize];
+        println!("{:>12} {:>12}  {}{} ({}:{})",
+                 format_duration(ticks_to_ns(snapshot, timestamp.saturating_sub(zone.start))),
+                 format_duration(ticks_to_ns(snapshot, zone.duration())),
+                 "  ".repeat(zone.depth as usize),
+                 table.strings.get(&location.name).map(String::as_str).unwrap_or("Unkn"),
+                 table.strings.get(&location.file).map(String::as_str).unwrap_or(""),
+                 location.line);
+    }
+    return Ok(());
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--index", "--at", "--thread"])?;
+    let Some(path) = arguments.positional.first() else {
+        return Err(usage("Use: frame <file> --index n | --at 2024-05-01T20:31:05Z [--thread id]"));
+    };
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    if let Some(thread_id) = arguments.get_parsed::<u32>("--thread")? {
+        let Some(at) = arguments.get("--at") else {
+            return Err("--thread needs --at time".into());
+        };
+        return print_running(&snapshot, &table, thread_id, parse_utc(at)?);
+    }
+
+    let index = match (arguments.get_parsed::<u32>("--index")?, arguments.get("--at")) {
+        (Some(index), _) => index,
+        (None, Some(at)) => find_frame_at(&snapshot, parse_utc(at)?)?,
+        (None, None) => return Err(usage("Use: frame <file> --index n | --at 2024-05-01T20:31:05Z [--thread id]")),
+    };
+    if index == 0 {
+        return Err("Frame 0 has no start mark, first frame index is 1".into());
+    }
+
+    // Frame N spans from mark N - 1 to mark N
+    let mut start = None;
+    let mut end = None;
+    let mut zones = Vec::<Zone>::new();
+    walk_snapshot_while(&snapshot, |walk| {
+        match walk {
+            Walk::Zone(zone) if zone.frame == index => zones.push(zone),
+            Walk::Frame { index: mark, timestamp } if mark == index - 1 => start = Some(timestamp),
+            Walk::Frame { index: mark, timestamp } if mark == index => {
+                end = Some(timestamp);
+                return false;
+            }
+            _ => {}
+        }
+        true
+    })?;
+    let (Some(start), Some(end)) = (start, end) else {
+        return Err(format!("Frame {index} not found in snapshot"));
+    };
+
+    println!("Frame {index} at {}: {} ({} zones)", format_utc_ns(snapshot.wall_clock_ns(start)),
+             format_duration(ticks_to_ns(&snapshot, end.saturating_sub(start))), format_count(zones.len() as u64));
+    zones.sort_by_key(|zone| (zone.thread_id, zone.start, zone.depth));
+    let mut thread = None;
+    for zone in &zones {
+        if thread != Some(zone.thread_id) {
+            thread = Some(zone.thread_id);
+            println!("Thread {}", zone.thread_id);
+            println!("{:>12} {:>12} {:>12}  Zone", "Offset", "Total", "Self");
+        }
+        let location = &table.locations[zone.location as usize];
+        println!("{:>12} {:>12} {:>12}  {}{} ({}:{})",
+                 format_duration(ticks_to_ns(&snapshot, zone.start.saturating_sub(start))),
+                 format_duration(ticks_to_ns(&snapshot, zone.duration())),
+                 format_duration(ticks_to_ns(&snapshot, zone.self_time())),
+                 "  ".repeat(zone.depth as usize),
+                 table.strings.get(&location.name).map(String::as_str).unwrap_or("Unkn"),
+                 table.strings.get(&location.file).map(String::as_str).unwrap_or(""),
+                 location.line);
+    }
+    return Ok(());
+}
@@ -0,0 +1,145 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::args::Arguments;
+use crate::failure::usage;
+use crate::structs::{U32SizeString, UTracyEvent, UTracyHeader, UTracySourceLocation};
+use crate::writer::create_snapshot;
+
+const SUBSYSTEMS: [&str; 14] = ["air", "machines", "mobs", "garbage", "timer", "npcpool", "lighting", "explosions", "atoms", "processing",
+    "fastprocess", "overlays", "tgui", "input"];
+const PROCS: [(&str, &str); 12] = [("process_cell", "turf"), ("update_icon", "atom"), ("Life", "mob/living"), ("handle_environment", "mob/living"),
+    ("process_atmos", "obj/machinery"), ("qdel", "datum"), ("Destroy", "datum"), ("build_network", "datum/pipeline"), ("Move", "atom/movable"),
+    ("get_turf", "atom"), ("Entered", "turf"), ("Exited", "turf")];
+// Shown by Tracy as captured program, fits header field with its NUL terminator
+const PROGRAM_NAME: &[u8] = b"synthetic";
+// Frame length of 20 fps server tick, timestamps are generated in nanoseconds
+const FRAME_NS: u64 = 50_000_000;
+
+// xorshift64*, generated captures only depend on the seed
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        return self.0.wrapping_mul(0x2545F4914F6CDD1D);
+    }
+
+    fn below(&mut self, limit: u64) -> u64 {
+        return self.next() % limit.max(1);
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        return self.below(100) < percent;
+    }
+
+    // Heavy tailed duration, most steps are short and few are orders of magnitude longer
+    fn step(&mut self, mean: u64) -> u64 {
+        let uniform = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        return (-(1f64 - uniform).ln() * mean as f64 * if self.chance(2) { 20f64 } else { 1f64 }) as u64;
+    }
+}
+
+fn location(name: String, function: String, file: String, line: u32) -> UTracySourceLocation {
+    return UTracySourceLocation {
+        name: U32SizeString(name),
+        function: U32SizeString(function),
+        file: U32SizeString(file),
+        line,
+        color: [0u8; 4],
+    };
+}
+
+// Subsystem fires are top level zones of the main thread, procs are nested below them and run on workers
+fn build_locations() -> Vec<UTracySourceLocation> {
+    let mut locations = Vec::new();
+    for (index, subsystem) in SUBSYSTEMS.iter().enumerate() {
+        locations.push(location(format!("SS{subsystem}.fire"), format!("/datum/controller/subsystem/{subsystem}/fire"),
+                                format!("code/controllers/subsystem/{subsystem}.dm"), 10 + index as u32));
+    }
+    for (index, (name, path)) in PROCS.iter().enumerate() {
+        locations.push(location(name.to_string(), format!("/{path}/proc/{name}"), format!("code/{path}.dm"), 100 + index as u32 * 7));
+    }
+    return locations;
+}
+
+// Zones of one thread for one frame as random walk: every step opens child, closes current zone or opens sibling
+// Events are paired with timestamp, color events take time of their zone begin
+fn generate_thread(random: &mut Random, thread_id: u32, start: u64, zones: u64, events: &mut Vec<(u64, UTracyEvent)>) -> u64 {
+    let step_mean = FRAME_NS / (zones.max(1) * 4);
+    let mut time = start;
+    let mut depth = 0;
+    let mut opened = 0;
+    while opened < zones || depth > 0 {
+        time += random.step(step_mean).max(1);
+        let open = opened < zones && (depth == 0 || (depth < 6 && random.chance(45)));
+        if open {
+            let location = if depth == 0 && thread_id == 1 {
+                random.below(SUBSYSTEMS.len() as u64)
+            } else {
+                SUBSYSTEMS.len() as u64 + random.below(PROCS.len() as u64)
+            };
+            events.push((time, UTracyEvent::begin(thread_id, location as u32, time)));
+            if random.chance(1) {
+                events.push((time, UTracyEvent::color(thread_id, [255, 64, 64, 0])));
+            }
+            opened += 1;
+            depth += 1;
+        } else {
+            events.push((time, UTracyEvent::end(thread_id, time)));
+            depth -= 1;
+        }
+    }
+    return time;
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--threads", "--frames", "--zones-per-frame", "--seed", "-o"])?;
+    let Some(output) = arguments.get("-o") else {
+        return Err(usage("Use: gen -o out.utracy [--threads n] [--frames n] [--zones-per-frame n] [--seed n]"));
+    };
+    let threads: u32 = arguments.get_parsed("--threads")?.unwrap_or(1).max(1);
+    let frames: u32 = arguments.get_parsed("--frames")?.unwrap_or(1000);
+    let zones_per_frame: u64 = arguments.get_parsed("--zones-per-frame")?.unwrap_or(100);
+    let seed: u64 = arguments.get_parsed("--seed")?.unwrap_or(1);
+    let mut random = Random(seed.max(1));
+
+    let epoch = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let init_begin = 1_000_000;
+    let mut header = UTracyHeader::default();
+    header.init_begin = init_begin;
+    header.init_end = init_begin;
+    header.resolution = 1;
+    header.epoch = epoch;
+    header.exec_time = epoch;
+    header.program_name[..PROGRAM_NAME.len()].copy_from_slice(PROGRAM_NAME);
+    let locations = build_locations();
+    let mut writer = create_snapshot(output, &header, locations.iter())?;
+
+    // Main thread gets half of zones, the rest is split between workers
+    let main_zones = if threads == 1 { zones_per_frame } else { zones_per_frame / 2 };
+    let worker_zones = (zones_per_frame - main_zones) / (threads as u64 - 1).max(1);
+    let mut frame_start = init_begin;
+    let mut events = Vec::new();
+    for _ in 0..frames {
+        events.clear();
+        let mut frame_end = frame_start;
+        for thread_id in 1..=threads {
+            let zones = if thread_id == 1 { main_zones } else { worker_zones };
+            frame_end = frame_end.max(generate_thread(&mut random, thread_id, frame_start, zones, &mut events));
+        }
+        // Threads are generated one after another, file keeps events in time order
+        events.sort_by_key(|(timestamp, _)| *timestamp);
+        for (_, event) in &events {
+            writer.write_event(event).map_err(|e| format!("{output}: {e}"))?;
+        }
+        frame_end += 1 + random.below(FRAME_NS / 10);
+        writer.frame_mark(frame_end).map_err(|e| format!("{output}: {e}"))?;
+        frame_start = frame_end;
+    }
+    let events = writer.event_count();
+    writer.finish().map_err(|e| format!("{output}: {e}"))?;
+    println!("Generated {frames} frames, {events} events on {threads} threads to {output}");
+    return Ok(());
+}
@@ -0,0 +1,48 @@
+use regex::Regex;
+use crate::analysis::{ticks_to_ns, walk_snapshot, Walk, Zone};
+use crate::args::Arguments;
+use crate::clock::{format_count, format_duration, format_utc_ns, parse_duration};
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::snapshot::load_snapshot;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--min"])?;
+    let (Some(path), Some(pattern)) = (arguments.positional.first(), arguments.positional.get(1)) else {
+        return Err(usage("Use: grep <file> <zone regex> [--min duration]"));
+    };
+    let pattern = Regex::new(pattern).map_err(|e| format!("{e}"))?;
+    let min = arguments.get("--min").map(parse_duration).transpose()?.unwrap_or(0);
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    // Resolve matching locations once instead of matching names for every zone
+    let matching: Vec<bool> = table.locations.iter()
+        .map(|location| table.strings.get(&location.name).is_some_and(|name| pattern.is_match(name)))
+        .collect();
+
+    let mut zones = Vec::<Zone>::new();
+    walk_snapshot(&snapshot, |walk| {
+        if let Walk::Zone(zone) = walk {
+            if matching[zone.location as usize] && ticks_to_ns(&snapshot, zone.duration()) >= min {
+                zones.push(zone);
+            }
+        }
+    })?;
+    if zones.is_empty() {
+        return Err(format!("No zones matching \"{pattern}\" in snapshot"));
+    }
+
+    zones.sort_by_key(|zone| zone.start);
+    println!("{:>8} {:>8} {:>27} {:>12}  Zone", "Thread", "Frame", "Time", "Duration");
+    for zone in &zones {
+        let name = table.locations[zone.location as usize].name;
+        println!("{:>8} {:>8} {:>27} {:>12}  {}", zone.thread_id, zone.frame,
+                 format_utc_ns(snapshot.wall_clock_ns(zone.start)),
+                 format_duration(ticks_to_ns(&snapshot, zone.duration())),
+                 table.strings.get(&name).map(String::as_str).unwrap_or("Unkn"));
+    }
+    println!("{} zones", format_count(zones.len() as u64));
+    return Ok(());
+}
@@ -0,0 +1,48 @@
+use std::fs;
+use regex::Regex;
+use serde::Deserialize;
+
+// groups.toml, first matching rule wins, name may refer to capture groups of zone pattern:
+//   [[group]]
+//   zone = "^/datum/controller/subsystem/(\\w+)/"
+//   name = "SS$1"
+#[derive(Deserialize)]
+struct GroupFile {
+    #[serde(default)]
+    group: Vec<GroupEntry>,
+}
+
+#[derive(Deserialize)]
+struct GroupEntry {
+    zone: String,
+    name: String,
+}
+
+#[derive(Clone)]
+pub struct ZoneGroups {
+    rules: Vec<(Regex, String)>,
+}
+
+impl ZoneGroups {
+    pub fn load(path: &str) -> Result<ZoneGroups, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let file: GroupFile = toml::from_str(&text).map_err(|e| format!("{path}: {e}"))?;
+        let mut rules = Vec::new();
+        for entry in file.group {
+            rules.push((Regex::new(&entry.zone).map_err(|e| format!("{path}: {e}"))?, entry.name));
+        }
+        return Ok(ZoneGroups { rules });
+    }
+
+    // Group of zone name, None keeps zone on its own
+    pub fn group_of(&self, zone: &str) -> Option<String> {
+        for (pattern, name) in &self.rules {
+            if let Some(captures) = pattern.captures(zone) {
+                let mut group = String::new();
+                captures.expand(name, &mut group);
+                return Some(group);
+            }
+        }
+        return None;
+    }
+}
@@ -0,0 +1,280 @@
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::thread;
+use prost::{Message, Oneof};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{empty_body, http, BoxFuture, Context, Poll, Service};
+use tonic::server::{Grpc, NamedService, ServerStreamingService};
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status};
+use crate::acl::token_matches;
+use crate::analysis::{walk_snapshot_while, Walk};
+use crate::failure::{fail, ErrorKind};
+use crate::health::Activity;
+use crate::server::{Served, SharedServed};
+
+// Messages of proto/rtracy.proto, written by hand so build does not need protoc
+#[derive(Clone, PartialEq, Message)]
+pub struct StreamRequest {
+    #[prost(uint32, tag = "1")]
+    pub snapshot: u32,
+    #[prost(uint32, tag = "2")]
+    pub skip_frames: u32,
+    #[prost(uint32, tag = "3")]
+    pub limit_frames: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Header {
+    #[prost(string, tag = "1")]
+    pub program_name: String,
+    #[prost(string, tag = "2")]
+    pub host_info: String,
+    #[prost(uint64, tag = "3")]
+    pub epoch: u64,
+    #[prost(uint64, tag = "4")]
+    pub process_id: u64,
+    #[prost(double, tag = "5")]
+    pub multiplier: f64,
+    #[prost(uint32, tag = "6")]
+    pub location_count: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Location {
+    #[prost(uint32, tag = "1")]
+    pub id: u32,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub function: String,
+    #[prost(string, tag = "4")]
+    pub file: String,
+    #[prost(uint32, tag = "5")]
+    pub line: u32,
+    #[prost(uint32, tag = "6")]
+    pub color: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Zone {
+    #[prost(uint32, tag = "1")]
+    pub thread: u32,
+    #[prost(uint32, tag = "2")]
+    pub location: u32,
+    #[prost(uint32, tag = "3")]
+    pub frame: u32,
+    #[prost(uint32, tag = "4")]
+    pub depth: u32,
+    #[prost(uint64, tag = "5")]
+    pub start_ns: u64,
+    #[prost(uint64, tag = "6")]
+    pub end_ns: u64,
+    #[prost(uint64, tag = "7")]
+    pub self_ns: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Frame {
+    #[prost(uint32, tag = "1")]
+    pub index: u32,
+    #[prost(uint64, tag = "2")]
+    pub time_ns: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Event {
+    #[prost(oneof = "EventKind", tags = "1, 2, 3, 4")]
+    pub event: Option<EventKind>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub enum EventKind {
+    #[prost(message, tag = "1")]
+    Header(Header),
+    #[prost(message, tag = "2")]
+    Location(Location),
+    #[prost(message, tag = "3")]
+    Zone(Zone),
+    #[prost(message, tag = "4")]
+    Frame(Frame),
+}
+
+// Events buffered per stream before decoding waits for slow client
+const STREAM_BUFFER: usize = 1024;
+
+#[derive(Clone)]
+struct Capture {
+    served: &'static SharedServed,
+    activity: &'static Activity,
+}
+
+// Status is the error tonic sends to client, boxing it would only move the conversion
+#[allow(clippy::result_large_err)]
+impl Capture {
+    // Same --allow and --auth-token rules as Tracy connections, token comes in "authorization: Bearer <token>" metadata
+    fn check_access(&self, served: &Served, request: &Request<StreamRequest>) -> Result<(), Status> {
+        if let Some(allow) = &served.options.allow {
+            if !request.remote_addr().is_some_and(|peer| allow.allows(peer.ip())) {
+                return Err(Status::permission_denied("Address is not allowed"));
+            }
+        }
+        if let Some(expected) = &served.options.auth_token {
+            let token = request.metadata().get("authorization").and_then(|value| value.to_str().ok()).and_then(|value| value.strip_prefix("Bearer "));
+            if !token.is_some_and(|token| token_matches(expected, token.as_bytes())) {
+                return Err(Status::unauthenticated("Wrong auth token"));
+            }
+        }
+        return Ok(());
+    }
+
+    fn stream(&self, request: Request<StreamRequest>) -> Result<ReceiverStream<Result<Event, Status>>, Status> {
+        // Stream keeps snapshots it started with when upload replaces them
+        let Some(served) = self.served.read().unwrap().clone() else {
+            return Err(Status::unavailable("No capture uploaded yet"));
+        };
+        self.check_access(&served, &request)?;
+        let request = request.into_inner();
+        if request.snapshot as usize >= served.snapshots.len() {
+            return Err(Status::invalid_argument(format!("Snapshot {} not found, server has {}", request.snapshot, served.snapshots.len())));
+        }
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let connected = self.activity.connect();
+        // Decoding blocks, so every stream gets its own thread like Tracy clients do
+        thread::spawn(move || {
+            send_snapshot(&served, &request, &sender);
+            drop(connected);
+        });
+        return Ok(ReceiverStream::new(receiver));
+    }
+}
+
+// Stops as soon as client goes away
+fn send_snapshot(served: &Arc<Served>, request: &StreamRequest, sender: &mpsc::Sender<Result<Event, Status>>) {
+    let snapshot = &served.snapshots[request.snapshot as usize];
+    let table = &served.table;
+    let send = |event: EventKind| sender.blocking_send(Ok(Event { event: Some(event) })).is_ok();
+    let header = &snapshot.header;
+    let ns = |ticks: u64| (ticks.saturating_sub(header.init_begin) as f64 * header.multiplier) as u64;
+    let string = |pointer: u64| table.string(pointer).unwrap_or_default().into_owned();
+    let sent = send(EventKind::Header(Header {
+        program_name: snapshot.program_name().to_string(),
+        host_info: String::from_utf8_lossy(&header.host_info).trim_end_matches('\0').to_string(),
+        epoch: header.epoch,
+        process_id: header.process_id,
+        multiplier: header.multiplier,
+        location_count: snapshot.location_count,
+    }));
+    if !sent {
+        return;
+    }
+    let first = snapshot.location_offset as usize;
+    for (id, location) in table.locations[first..first + snapshot.location_count as usize].iter().enumerate() {
+        let sent = send(EventKind::Location(Location {
+            id: id as u32,
+            name: string(location.name),
+            function: string(location.function),
+            file: string(location.file),
+            line: location.line,
+            color: (location.color_r as u32) << 16 | (location.color_g as u32) << 8 | location.color_b as u32,
+        }));
+        if !sent {
+            return;
+        }
+    }
+    let end_frame = match request.limit_frames {
+        0 => u32::MAX,
+        limit => request.skip_frames.saturating_add(limit),
+    };
+    let result = walk_snapshot_while(snapshot, |walk| match walk {
+        Walk::Zone(zone) if zone.frame >= request.skip_frames => send(EventKind::Zone(Zone {
+            thread: zone.thread_id,
+            location: zone.location - snapshot.location_offset,
+            frame: zone.frame,
+            depth: zone.depth,
+            start_ns: ns(zone.start),
+            end_ns: ns(zone.end),
+            self_ns: (zone.self_time() as f64 * header.multiplier) as u64,
+        })),
+        Walk::Frame { index, .. } if index >= end_frame => false,
+        Walk::Frame { index, timestamp } if index >= request.skip_frames => send(EventKind::Frame(Frame { index, time_ns: ns(timestamp) })),
+        _ => true,
+    });
+    if let Err(msg) = result {
+        let _ = sender.blocking_send(Err(Status::internal(msg)));
+    }
+}
+
+struct StreamMethod(Capture);
+
+impl ServerStreamingService<StreamRequest> for StreamMethod {
+    type Response = Event;
+    type ResponseStream = ReceiverStream<Result<Event, Status>>;
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: Request<StreamRequest>) -> Self::Future {
+        let result = self.0.stream(request).map(Response::new);
+        return Box::pin(async move { result });
+    }
+}
+
+// Routing done by generated code of tonic-build, service has single method
+impl Service<http::Request<BoxBody>> for Capture {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        return Poll::Ready(Ok(()));
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        if request.uri().path() != "/rtracy.Capture/Stream" {
+            return Box::pin(async move {
+                let mut response = http::Response::new(empty_body());
+                response.headers_mut().insert(Status::GRPC_STATUS, (Code::Unimplemented as i32).into());
+                response.headers_mut().insert(http::header::CONTENT_TYPE, tonic::metadata::GRPC_CONTENT_TYPE);
+                return Ok(response);
+            });
+        }
+        let method = StreamMethod(self.clone());
+        return Box::pin(async move {
+            return Ok(Grpc::new(ProstCodec::default()).server_streaming(method, request).await);
+        });
+    }
+}
+
+impl NamedService for Capture {
+    const NAME: &'static str = "rtracy.Capture";
+}
+
+pub fn bind_grpc(port: u16) -> Result<TcpListener, String> {
+    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).map_err(|e| fail(ErrorKind::PortUnavailable, format!("Error binding gRPC port {port}: {e}")))?;
+    listener.set_nonblocking(true).map_err(|e| format!("{e}"))?;
+    return Ok(listener);
+}
+
+// Async runtime is confined to this thread, the rest of server stays blocking
+pub fn spawn_grpc(listener: TcpListener, served: &'static SharedServed, activity: &'static Activity) {
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_multi_thread().worker_threads(2).enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                println!("Error starting gRPC runtime: {e}");
+                return;
+            }
+        };
+        let result = runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).map_err(|e| format!("{e}"))?;
+            let capture = Capture { served, activity };
+            return Server::builder().add_service(capture).serve_with_incoming(TcpListenerStream::new(listener)).await.map_err(|e| format!("{e}"));
+        });
+        if let Err(msg) = result {
+            println!("gRPC server stopped: {msg}");
+        }
+    });
+}
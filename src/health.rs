@@ -0,0 +1,217 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::acl::{token_matches, AllowList};
+use crate::failure::{fail, ErrorKind};
+use crate::pool::WorkerPool;
+use crate::upload::Uploads;
+
+// Longest request head accepted, uploads send their body after it
+const MAX_HEAD: usize = 8192;
+// Whole head has to arrive within it, however slowly it is sent
+const HEAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Connected<'a>(&'a Activity);
+
+impl Drop for Connected<'_> {
+    fn drop(&mut self) {
+        self.0.disconnect();
+    }
+}
+
+// Connected Tracy clients and time last one left, shared by accept loop, health endpoint and idle watcher
+pub struct Activity {
+    active: AtomicUsize,
+    served: AtomicUsize,
+    idle_since: Mutex<Instant>,
+    started: Instant,
+    // Frame range (first, count) requested over HTTP, streamed in full detail to the next connection
+    region: Mutex<Option<(u32, u32)>>,
+}
+
+impl Activity {
+    pub fn new() -> Activity {
+        return Activity {
+            active: AtomicUsize::new(0),
+            served: AtomicUsize::new(0),
+            idle_since: Mutex::new(Instant::now()),
+            started: Instant::now(),
+            region: Mutex::new(None),
+        };
+    }
+
+    // Client counts as connected until returned guard is dropped, also when its session panics
+    pub fn connect(&self) -> Connected<'_> {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        self.served.fetch_add(1, Ordering::SeqCst);
+        return Connected(self);
+    }
+
+    fn disconnect(&self) {
+        let mut idle_since = self.idle_since.lock().unwrap();
+        if self.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            *idle_since = Instant::now();
+        }
+    }
+
+    pub fn take_region(&self) -> Option<(u32, u32)> {
+        return self.region.lock().unwrap().take();
+    }
+
+    fn idle_for(&self) -> Option<Duration> {
+        let idle_since = self.idle_since.lock().unwrap();
+        if self.active.load(Ordering::SeqCst) != 0 {
+            return None;
+        }
+        return Some(idle_since.elapsed());
+    }
+}
+
+// --allow and --auth-token of requests changing what is served, token is sent as "Authorization: Bearer <token>"
+pub struct HttpAccess {
+    pub allow: Option<AllowList>,
+    pub auth_token: Option<String>,
+}
+
+impl HttpAccess {
+    // Returns HTTP status and reason of refusal
+    pub fn check(&self, stream: &TcpStream, head: &str) -> Result<(), (&'static str, String)> {
+        if let Some(allow) = &self.allow {
+            if !stream.peer_addr().is_ok_and(|peer| allow.allows(peer.ip())) {
+                return Err(("403 Forbidden", "Address is not allowed".into()));
+            }
+        }
+        if let Some(expected) = &self.auth_token {
+            let token = header(head, "authorization").and_then(|value| value.strip_prefix("Bearer "));
+            if !token.is_some_and(|token| token_matches(expected, token.as_bytes())) {
+                return Err(("401 Unauthorized", "Wrong auth token".into()));
+            }
+        }
+        return Ok(());
+    }
+}
+
+pub fn header<'l>(head: &'l str, name: &str) -> Option<&'l str> {
+    return head.lines().skip(1).filter_map(|line| line.split_once(':')).find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim());
+}
+
+pub fn bind_health(port: u16) -> Result<TcpListener, String> {
+    return TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).map_err(|e| fail(ErrorKind::PortUnavailable, format!("Error binding health port {port}: {e}")));
+}
+
+// Reads until the empty line, returns head and bytes of body that came with it
+fn read_head(stream: &mut TcpStream) -> (String, Vec<u8>) {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    let deadline = Instant::now() + HEAD_TIMEOUT;
+    while request.len() < MAX_HEAD {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || stream.set_read_timeout(Some(remaining)).is_err() {
+            break;
+        }
+        let read = stream.read(&mut buffer).unwrap_or(0);
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            let body = request.split_off(end + 4);
+            return (String::from_utf8_lossy(&request).to_string(), body);
+        }
+    }
+    return (String::from_utf8_lossy(&request).to_string(), Vec::new());
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let _ = write!(stream, "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len());
+}
+
+// "/region?start=n&end=m" queues frame range for the next connection, "PUT /upload/<name>.utracy" stores and serves
+// new capture when daemon has --upload-dir, both only for clients passing access check. Any other request gets status.
+// JSON status is enough for docker HEALTHCHECK and load balancer probes
+pub fn spawn_health(listener: TcpListener, activity: &'static Activity, pool: &'static WorkerPool, access: &'static HttpAccess,
+                    uploads: Option<&'static Uploads>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            // Slow clients and uploads, which take a while to receive and decode, don't hold up other requests
+            thread::spawn(move || handle_request(stream, activity, pool, access, uploads));
+        }
+    });
+}
+
+fn handle_request(mut stream: TcpStream, activity: &'static Activity, pool: &'static WorkerPool, access: &HttpAccess, uploads: Option<&'static Uploads>) {
+    let (request, body) = read_head(&mut stream);
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+    let mut words = request.split_whitespace();
+    let method = words.next().unwrap_or("GET");
+    let target = words.next().unwrap_or("/");
+    if let (Some(uploads), Some(name)) = (uploads, target.strip_prefix("/upload/")) {
+        if method != "PUT" {
+            respond(&mut stream, "405 Method Not Allowed", "{\"error\":\"Upload with PUT\"}\n");
+            return;
+        }
+        let (status, response) = uploads.receive(&mut stream, &request, name, &body);
+        respond(&mut stream, status, &response);
+        return;
+    }
+    let (status, body) = match target.split_once('?').unwrap_or((target, "")) {
+        ("/region", query) => match access.check(&stream, &request).and_then(|_| parse_region(query).map_err(|msg| ("400 Bad Request", msg))) {
+            Ok(region) => {
+                *activity.region.lock().unwrap() = region;
+                let body = match region {
+                    Some((first, count)) => format!("{{\"region\":{{\"start\":{first},\"end\":{}}}}}\n", first + count),
+                    None => "{\"region\":null}\n".into(),
+                };
+                ("200 OK", body)
+            }
+            Err((status, msg)) => (status, format!("{{\"error\":{:?}}}\n", msg)),
+        },
+        _ => {
+            let workers = pool.status();
+            ("200 OK", format!("{{\"status\":\"ok\",\"clients\":{},\"served\":{},\"uptime_s\":{},\"workers\":{},\"busy_workers\":{},\"queued\":{},\"saturated\":{}}}\n",
+                               activity.active.load(Ordering::SeqCst), activity.served.load(Ordering::SeqCst), activity.started.elapsed().as_secs(),
+                               workers.size, workers.busy, workers.queued, workers.saturated))
+        }
+    };
+    respond(&mut stream, status, &body);
+}
+
+// Region without parameters clears the queued one
+fn parse_region(query: &str) -> Result<Option<(u32, u32)>, String> {
+    let mut start = None;
+    let mut end = None;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value: u32 = value.parse().map_err(|_| format!("Wrong value of {key}"))?;
+        match key {
+            "start" => start = Some(value),
+            "end" => end = Some(value),
+            _ => return Err(format!("Unknown parameter {key}")),
+        }
+    }
+    return match (start, end) {
+        (None, None) => Ok(None),
+        (Some(start), Some(end)) if end > start => Ok(Some((start, end - start))),
+        _ => Err("Region needs start and end frames, end after start".into()),
+    };
+}
+
+// Exits process once no client was connected for given time, counted from start or last disconnect
+pub fn spawn_idle_exit(timeout: Duration, activity: &'static Activity) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            if activity.idle_for().is_some_and(|idle| idle >= timeout) {
+                println!("No clients for {} s, exiting", timeout.as_secs());
+                std::process::exit(0);
+            }
+        }
+    });
+}
@@ -0,0 +1,114 @@
+use std::io::stdout;
+use serde::Serialize;
+use crate::analysis::{ticks_to_ns, walk_snapshot, Walk};
+use crate::args::Arguments;
+use crate::clock::{format_count, format_duration};
+use crate::export::find_exporter;
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::snapshot::{load_snapshot, Snapshot};
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(Serialize)]
+pub struct Bucket {
+    pub from_ns: u64,
+    pub to_ns: u64,
+    pub count: u64,
+}
+
+pub const HISTOGRAM_OPTIONS: [&str; 3] = ["--zone", "--buckets", "--scale"];
+
+// Bucket edges between min and max, log scale keeps long tails readable
+fn bucket_edges(min: u64, max: u64, count: usize, log: bool) -> Vec<u64> {
+    let min = min.max(1) as f64;
+    let max = (max as f64).max(min + 1f64);
+    return (0..=count).map(|i| {
+        let t = i as f64 / count as f64;
+        if log {
+            (min.ln() + (max.ln() - min.ln()) * t).exp().round() as u64
+        } else {
+            (min + (max - min) * t).round() as u64
+        }
+    }).collect();
+}
+
+// Durations must not be empty
+pub fn histogram(durations: &[u64], bucket_count: usize, log: bool) -> Vec<Bucket> {
+    let min = *durations.iter().min().unwrap();
+    let max = *durations.iter().max().unwrap();
+    let edges = bucket_edges(min, max, bucket_count, log);
+    let mut buckets: Vec<Bucket> = edges.windows(2).map(|w| Bucket { from_ns: w[0], to_ns: w[1], count: 0 }).collect();
+    for duration in durations {
+        let index = edges[1..].iter().position(|edge| duration <= edge).unwrap_or(bucket_count - 1);
+        buckets[index].count += 1;
+    }
+    return buckets;
+}
+
+pub fn print_histogram(zone_name: &str, durations: &[u64], buckets: &[Bucket]) {
+    let min = *durations.iter().min().unwrap_or(&0);
+    let max = *durations.iter().max().unwrap_or(&0);
+    let peak = buckets.iter().map(|b| b.count).max().unwrap_or(1).max(1);
+    let sparkline: String = buckets.iter().map(|b| {
+        if b.count == 0 { ' ' } else { SPARKS[(b.count * (SPARKS.len() as u64 - 1) / peak) as usize] }
+    }).collect();
+    println!("{zone_name}: {} calls, {} .. {}", format_count(durations.len() as u64), format_duration(min), format_duration(max));
+    println!("{sparkline}");
+    for bucket in buckets {
+        let bar = "#".repeat((bucket.count * 40 / peak) as usize);
+        println!("{:>12} .. {:>12} {:>10} {bar}", format_duration(bucket.from_ns), format_duration(bucket.to_ns), format_count(bucket.count));
+    }
+}
+
+// Durations of zone named by --zone and their buckets, shared by console output and hist-csv / hist-json exporters
+pub fn zone_histogram(snapshot: &Snapshot, table: &SourceTable, arguments: &Arguments) -> Result<(Vec<u64>, Vec<Bucket>), String> {
+    let Some(zone_name) = arguments.get("--zone") else {
+        return Err("Histogram needs zone: --zone name".into());
+    };
+    let bucket_count: usize = arguments.get_parsed("--buckets")?.unwrap_or(20).max(1);
+    let log = match arguments.get("--scale").unwrap_or("log") {
+        "log" => true,
+        "linear" => false,
+        scale => return Err(usage(format!("Wrong input: --scale {scale}, expected linear or log"))),
+    };
+    let mut durations = Vec::<u64>::new();
+    walk_snapshot(snapshot, |walk| {
+        if let Walk::Zone(zone) = walk {
+            let name = table.locations[zone.location as usize].name;
+            if table.strings.get(&name).is_some_and(|n| n == zone_name) {
+                durations.push(ticks_to_ns(snapshot, zone.duration()));
+            }
+        }
+    })?;
+    if durations.is_empty() {
+        return Err(format!("Zone \"{zone_name}\" not found in snapshot"));
+    }
+    let buckets = histogram(&durations, bucket_count, log);
+    return Ok((durations, buckets));
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--zone", "--buckets", "--scale", "--format"])?;
+    let (Some(path), Some(zone_name)) = (arguments.positional.first(), arguments.get("--zone")) else {
+        return Err(usage("Use: hist <file> --zone name [--buckets n] [--scale linear|log] [--format text|csv|json]"));
+    };
+    let format = arguments.get("--format").unwrap_or("text");
+    let exporter = match format {
+        "text" => None,
+        "csv" | "json" => Some(find_exporter(&format!("hist-{format}"))?),
+        format => return Err(usage(format!("Wrong input: --format {format}, expected text, csv or json"))),
+    };
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    match exporter {
+        Some(exporter) => exporter.export(&snapshot, &table, &arguments, &mut stdout().lock())?,
+        None => {
+            let (durations, buckets) = zone_histogram(&snapshot, &table, &arguments)?;
+            print_histogram(zone_name, &durations, &buckets);
+        }
+    }
+    return Ok(());
+}
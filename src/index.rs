@@ -0,0 +1,232 @@
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use regex::Regex;
+use rusqlite::{params, Connection};
+use crate::analysis::{collect_frame_marks, collect_zone_stats, ticks_to_ns};
+use crate::args::Arguments;
+use crate::clock::{format_duration, format_utc, parse_duration};
+use crate::color::{progress_bar, yellow};
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::meta::{load_meta, meta_path};
+use crate::snapshot::load_snapshot;
+
+pub const DEFAULT_DB: &str = "index.sqlite";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS captures (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    size INTEGER NOT NULL,
+    modified INTEGER NOT NULL,
+    program TEXT NOT NULL,
+    process_id INTEGER NOT NULL,
+    epoch INTEGER NOT NULL,
+    frames INTEGER NOT NULL,
+    duration_ns INTEGER NOT NULL,
+    mean_frame_ns INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS zones (
+    capture INTEGER NOT NULL REFERENCES captures(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    total_ns INTEGER NOT NULL,
+    self_ns INTEGER NOT NULL,
+    mean_ns INTEGER NOT NULL,
+    p99_ns INTEGER NOT NULL,
+    max_ns INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS zones_name ON zones(name);
+CREATE TABLE IF NOT EXISTS metadata (
+    capture INTEGER NOT NULL REFERENCES captures(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+PRAGMA foreign_keys = ON;
+";
+
+pub fn open_db(path: &str) -> Result<Connection, String> {
+    let db = Connection::open(path).map_err(|e| format!("{path}: {e}"))?;
+    db.execute_batch(SCHEMA).map_err(|e| format!("{path}: {e}"))?;
+    return Ok(db);
+}
+
+// Size and modification time, captures with both unchanged are not indexed again. Edited metadata sidecar counts as change
+pub fn file_version(path: &Path) -> Result<(i64, i64), String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let modified_secs = |metadata: fs::Metadata| metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|time| time.as_secs());
+    let sidecar = fs::metadata(meta_path(&path.to_string_lossy())).ok().and_then(modified_secs).unwrap_or(0);
+    let modified = modified_secs(metadata.clone()).unwrap_or(0).max(sidecar);
+    return Ok((metadata.len() as i64, modified as i64));
+}
+
+// Returns "<path>: <frames> frames, <zones> zones" line for the console
+pub fn index_capture(db: &mut Connection, path: &str, size: i64, modified: i64) -> Result<String, String> {
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    let marks = collect_frame_marks(&snapshot)?;
+    let stats = collect_zone_stats(&snapshot, &table)?;
+    let duration = match (marks.first(), marks.last()) {
+        (Some(first), Some(last)) => ticks_to_ns(&snapshot, last - first),
+        _ => 0,
+    };
+    let frames = marks.len() as i64;
+    let mean_frame = if marks.len() > 1 { duration / (marks.len() as u64 - 1) } else { 0 };
+
+    let transaction = db.transaction().map_err(|e| format!("{e}"))?;
+    transaction.execute("DELETE FROM captures WHERE path = ?1", params![path]).map_err(|e| format!("{e}"))?;
+    transaction.execute("INSERT INTO captures (path, size, modified, program, process_id, epoch, frames, duration_ns, mean_frame_ns)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                        params![path, size, modified, snapshot.program_name(), snapshot.header.process_id as i64, snapshot.header.epoch as i64,
+                                frames, duration as i64, mean_frame as i64]).map_err(|e| format!("{e}"))?;
+    let capture = transaction.last_insert_rowid();
+    if let Some(meta) = load_meta(path)? {
+        for (key, value) in &meta.fields {
+            transaction.execute("INSERT INTO metadata (capture, key, value) VALUES (?1, ?2, ?3)", params![capture, key, value]).map_err(|e| format!("{e}"))?;
+        }
+    }
+    {
+        let mut insert = transaction.prepare("INSERT INTO zones (capture, name, count, total_ns, self_ns, mean_ns, p99_ns, max_ns)
+                                              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)").map_err(|e| format!("{e}"))?;
+        for zone in &stats {
+            insert.execute(params![capture, zone.name, zone.count() as i64, zone.total as i64, zone.self_total as i64, zone.mean() as i64,
+                                   zone.percentile(99f64) as i64, zone.durations.last().copied().unwrap_or(0) as i64])
+                .map_err(|e| format!("{e}"))?;
+        }
+    }
+    transaction.commit().map_err(|e| format!("{e}"))?;
+    return Ok(format!("{path}: {frames} frames, {} zones", stats.len()));
+}
+
+pub fn run_index(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--db"])?;
+    let Some(directory) = arguments.positional.first() else {
+        return Err(usage("Use: index <directory> [--db index.sqlite]"));
+    };
+    let db_path = arguments.get("--db").unwrap_or(DEFAULT_DB);
+    let mut db = open_db(db_path)?;
+
+    let mut paths: Vec<_> = fs::read_dir(directory).map_err(|e| format!("{directory}: {e}"))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "utracy"))
+        .collect();
+    paths.sort();
+
+    let (mut indexed, mut unchanged, mut failed) = (0, 0, 0);
+    let bar = progress_bar(paths.len(), "Indexing");
+    for path in &paths {
+        bar.inc(1);
+        let (size, modified) = file_version(path)?;
+        let path = path.to_string_lossy();
+        bar.set_message(path.to_string());
+        let known: Option<(i64, i64)> = db.query_row("SELECT size, modified FROM captures WHERE path = ?1", params![path], |row| Ok((row.get(0)?, row.get(1)?)))
+            .ok();
+        if known == Some((size, modified)) {
+            unchanged += 1;
+            continue;
+        }
+        // One broken capture should not stop indexing of the whole archive
+        match index_capture(&mut db, &path, size, modified) {
+            Ok(line) => {
+                bar.suspend(|| println!("{line}"));
+                indexed += 1;
+            }
+            Err(msg) => {
+                bar.suspend(|| println!("{}", yellow(&format!("{path}: skipped, {msg}"))));
+                failed += 1;
+            }
+        }
+    }
+    bar.finish_and_clear();
+    // Captures deleted from the directory are dropped from the index
+    let prefix = Path::new(directory).to_string_lossy().to_string();
+    let listed: Vec<String> = paths.iter().map(|path| path.to_string_lossy().to_string()).collect();
+    let mut removed = 0;
+    let stored: Vec<(i64, String)> = db.prepare("SELECT id, path FROM captures").and_then(|mut query| {
+        return query.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect();
+    }).map_err(|e| format!("{e}"))?;
+    for (id, path) in stored {
+        if Path::new(&path).parent() == Some(Path::new(&prefix)) && !listed.contains(&path) {
+            db.execute("DELETE FROM captures WHERE id = ?1", params![id]).map_err(|e| format!("{e}"))?;
+            removed += 1;
+        }
+    }
+    println!("Indexed {indexed}, unchanged {unchanged}, failed {failed}, removed {removed}, database {db_path}");
+    return Ok(());
+}
+
+pub fn run_list(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--db", "--program"])?;
+    let db_path = arguments.get("--db").unwrap_or(DEFAULT_DB);
+    if !Path::new(db_path).exists() {
+        return Err(format!("{db_path} does not exist, create it with: index <directory> --db {db_path}"));
+    }
+    let db = open_db(db_path)?;
+    let program = arguments.get("--program").unwrap_or("");
+
+    let mut query = db.prepare("SELECT path, program, epoch, frames, duration_ns, mean_frame_ns,
+                                       (SELECT value FROM metadata WHERE capture = id AND key = 'round_id'),
+                                       (SELECT value FROM metadata WHERE capture = id AND key = 'map') FROM captures
+                                WHERE ?1 = '' OR program = ?1 ORDER BY epoch").map_err(|e| format!("{e}"))?;
+    let rows = query.query_map(params![program], |row| {
+        return Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?, row.get::<_, i64>(3)?, row.get::<_, i64>(4)?,
+                   row.get::<_, i64>(5)?, row.get::<_, Option<String>>(6)?, row.get::<_, Option<String>>(7)?));
+    }).map_err(|e| format!("{e}"))?;
+
+    println!("{:<24} {:>8} {:>12} {:>12}  {:<16} {:<8} {:<16} Path", "Captured", "Frames", "Duration", "Mean frame", "Program", "Round", "Map");
+    for row in rows {
+        let (path, program, epoch, frames, duration, mean_frame, round, map) = row.map_err(|e| format!("{e}"))?;
+        println!("{:<24} {:>8} {:>12} {:>12}  {:<16} {:<8} {:<16} {path}", format_utc(epoch as u64), frames, format_duration(duration as u64),
+                 format_duration(mean_frame as u64), program, round.unwrap_or_default(), map.unwrap_or_default());
+    }
+    return Ok(());
+}
+
+// Captures where zones matching regex exceed thresholds, in capture order, shows when regression first appeared
+pub fn run_search(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--db", "--zone", "--min-mean", "--min-p99", "--min-max"])?;
+    let Some(zone) = arguments.get("--zone") else {
+        return Err(usage("Use: search --zone regex [--min-mean duration] [--min-p99 duration] [--min-max duration] [--db index.sqlite]"));
+    };
+    let pattern = Regex::new(zone).map_err(|e| format!("{e}"))?;
+    let min_mean = arguments.get("--min-mean").map(parse_duration).transpose()?.unwrap_or(0);
+    let min_p99 = arguments.get("--min-p99").map(parse_duration).transpose()?.unwrap_or(0);
+    let min_max = arguments.get("--min-max").map(parse_duration).transpose()?.unwrap_or(0);
+    let db_path = arguments.get("--db").unwrap_or(DEFAULT_DB);
+    if !Path::new(db_path).exists() {
+        return Err(format!("{db_path} does not exist, create it with: index <directory> --db {db_path}"));
+    }
+    let db = open_db(db_path)?;
+
+    // Thresholds are applied by SQLite, regex on the remaining rows
+    let mut query = db.prepare("SELECT captures.path, captures.epoch, zones.name, zones.count, zones.mean_ns, zones.p99_ns, zones.max_ns
+                                FROM zones JOIN captures ON captures.id = zones.capture
+                                WHERE zones.mean_ns >= ?1 AND zones.p99_ns >= ?2 AND zones.max_ns >= ?3
+                                ORDER BY captures.epoch, captures.path, zones.mean_ns DESC").map_err(|e| format!("{e}"))?;
+    let rows = query.query_map(params![min_mean as i64, min_p99 as i64, min_max as i64], |row| {
+        return Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?, row.get::<_, i64>(3)?, row.get::<_, i64>(4)?,
+                   row.get::<_, i64>(5)?, row.get::<_, i64>(6)?));
+    }).map_err(|e| format!("{e}"))?;
+
+    let mut found = 0;
+    for row in rows {
+        let (path, epoch, name, count, mean, p99, max) = row.map_err(|e| format!("{e}"))?;
+        if !pattern.is_match(&name) {
+            continue;
+        }
+        if found == 0 {
+            println!("{:<24} {:>10} {:>12} {:>12} {:>12}  {:<24} Path", "Captured", "Count", "Mean", "P99", "Max", "Zone");
+        }
+        found += 1;
+        println!("{:<24} {:>10} {:>12} {:>12} {:>12}  {:<24} {path}", format_utc(epoch as u64), count, format_duration(mean as u64),
+                 format_duration(p99 as u64), format_duration(max as u64), name);
+    }
+    if found == 0 {
+        return Err(format!("No indexed captures with zones matching \"{pattern}\" above thresholds"));
+    }
+    return Ok(());
+}
@@ -0,0 +1,39 @@
+use crate::analysis::{collect_frame_marks, ticks_to_ns};
+use crate::args::Arguments;
+use crate::clock::{format_count, format_duration, format_utc};
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::meta::{label, load_meta};
+use crate::snapshot::load_snapshot;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&[])?;
+    let Some(path) = arguments.positional.first() else {
+        return Err(usage("Use: info <file>"));
+    };
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    let meta = load_meta(path)?;
+    let marks = collect_frame_marks(&snapshot)?;
+    let header = &snapshot.header;
+    let host = String::from_utf8_lossy(&header.host_info).trim_end_matches('\0').lines().next().unwrap_or("").to_string();
+
+    println!("{:<12} {}", "Program", snapshot.program_name());
+    println!("{:<12} {}", "Captured", format_utc(header.epoch));
+    println!("{:<12} {}", "Process id", header.process_id);
+    if !host.is_empty() {
+        println!("{:<12} {host}", "Host");
+    }
+    println!("{:<12} {}", "Locations", format_count(snapshot.location_count as u64));
+    println!("{:<12} {}", "Frames", format_count(marks.len().saturating_sub(1) as u64));
+    if let (Some(first), Some(last)) = (marks.first(), marks.last()) {
+        println!("{:<12} {}", "Duration", format_duration(ticks_to_ns(&snapshot, last - first)));
+    }
+    if let Some(meta) = meta {
+        for (key, value) in &meta.fields {
+            println!("{:<12} {value}", label(key));
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,260 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use crate::groups::ZoneGroups;
+use crate::structs::{fnv1a, SourceLocation, U32SizeString, FNV_OFFSET};
+
+// Strings and source locations of all loaded snapshots in one table, so equal strings of different files share pointer and
+// equal locations share id, statistics over several files group the same procs together.
+// String pointers are sent to Tracy as is, locations are sent by id derived from their content, so it is stable between runs
+pub struct SourceTable {
+    pub locations: Vec<SourceLocation>,
+    pub strings: HashMap<u64, String>,
+    pub ids: Vec<u64>,
+    // First index of location with given id
+    pub by_id: HashMap<u64, u32>,
+    // Function and file strings left in capture files, only set up for memory-constrained serving
+    pub lazy: Option<LazyStrings>,
+}
+
+// Place of string bytes in one of loaded captures. Equal strings are found by hash and length, bytes are not compared
+#[derive(Clone, Copy)]
+pub struct LazyString {
+    pub file: u32,
+    pub offset: u64,
+    pub len: u32,
+    pub hash: u64,
+}
+
+pub struct LazyStrings {
+    pub files: Vec<String>,
+    pub strings: HashMap<u64, LazyString>,
+    cache: Mutex<StringCache>,
+}
+
+// Least recently read strings are dropped once their bytes exceed budget
+struct StringCache {
+    budget: usize,
+    bytes: usize,
+    tick: u64,
+    strings: HashMap<u64, (String, u64)>,
+    by_use: BTreeMap<u64, u64>,
+}
+
+impl StringCache {
+    fn get(&mut self, pointer: u64) -> Option<String> {
+        let (string, used) = self.strings.get_mut(&pointer)?;
+        self.tick += 1;
+        self.by_use.remove(used);
+        self.by_use.insert(self.tick, pointer);
+        *used = self.tick;
+        return Some(string.clone());
+    }
+
+    fn insert(&mut self, pointer: u64, string: String) {
+        self.tick += 1;
+        self.bytes += string.len();
+        self.by_use.insert(self.tick, pointer);
+        self.strings.insert(pointer, (string, self.tick));
+        while self.bytes > self.budget {
+            let Some((_, oldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            if let Some((string, _)) = self.strings.remove(&oldest) {
+                self.bytes -= string.len();
+            }
+        }
+    }
+}
+
+impl LazyStrings {
+    fn read(&self, pointer: u64) -> Option<String> {
+        let lazy = self.strings.get(&pointer)?;
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(string) = cache.get(pointer) {
+            return Some(string);
+        }
+        let path = &self.files[lazy.file as usize];
+        let read = || -> Result<String, String> {
+            let mut file = File::open(path).map_err(|e| format!("{e}"))?;
+            file.seek(SeekFrom::Start(lazy.offset)).map_err(|e| format!("{e}"))?;
+            let mut bytes = vec![0u8; lazy.len as usize];
+            file.read_exact(&mut bytes).map_err(|e| format!("{e}"))?;
+            return String::from_utf8(bytes).map_err(|e| format!("{}", e.utf8_error()));
+        };
+        match read() {
+            Ok(string) => {
+                cache.insert(pointer, string.clone());
+                return Some(string);
+            }
+            Err(msg) => {
+                println!("Error reading string from {path}: {msg}");
+                return None;
+            }
+        }
+    }
+}
+
+impl Default for SourceTable {
+    fn default() -> SourceTable {
+        return SourceTable::new();
+    }
+}
+
+impl SourceTable {
+    pub fn new() -> SourceTable {
+        let mut strings = HashMap::<u64, String>::new();
+        strings.insert(0, "".into());
+        return SourceTable {
+            locations: Vec::new(),
+            strings,
+            ids: Vec::new(),
+            by_id: HashMap::new(),
+            lazy: None,
+        };
+    }
+
+    // Table which keeps function and file strings of snapshots loaded later in their files, reading them on request
+    // with up to cache_bytes of them kept in memory. Zone names stay loaded, filters and statistics match them all the time
+    pub fn with_lazy_strings(cache_bytes: usize) -> SourceTable {
+        let mut table = SourceTable::new();
+        table.lazy = Some(LazyStrings {
+            files: Vec::new(),
+            strings: HashMap::new(),
+            cache: Mutex::new(StringCache { budget: cache_bytes, bytes: 0, tick: 0, strings: HashMap::new(), by_use: BTreeMap::new() }),
+        });
+        return table;
+    }
+
+    // String behind pointer, loaded or read from capture file
+    pub fn string(&self, pointer: u64) -> Option<Cow<'_, str>> {
+        if let Some(string) = self.strings.get(&pointer) {
+            return Some(Cow::Borrowed(string));
+        }
+        return self.lazy.as_ref().and_then(|lazy| lazy.read(pointer)).map(Cow::Owned);
+    }
+
+    pub fn intern(&mut self, string: U32SizeString) -> u64 {
+        let hash = string.get_hash();
+        return self.intern_hashed(hash, string.0);
+    }
+
+    pub fn push_location(&mut self, location: SourceLocation) -> u32 {
+        let string = |pointer: u64| self.strings.get(&pointer).map(|string| string.as_str()).unwrap_or("");
+        let id = location_id([string(location.name), string(location.function), string(location.file)], location.line);
+        return self.push_location_with_id(id, location);
+    }
+
+    // Id is a hint, it is moved on collision with different location, 0 is never used
+    pub fn push_location_with_id(&mut self, id: u64, location: SourceLocation) -> u32 {
+        let index = self.locations.len() as u32;
+        let mut id = id.max(1);
+        loop {
+            match self.by_id.entry(id) {
+                Entry::Occupied(entry) if same_location(&self.locations[*entry.get() as usize], &location) => break,
+                Entry::Occupied(_) => id = id.wrapping_add(1).max(1),
+                Entry::Vacant(entry) => {
+                    entry.insert(index);
+                    break;
+                }
+            }
+        }
+        self.locations.push(location);
+        self.ids.push(id);
+        return index;
+    }
+
+    // Index of location Tracy asked for by id
+    pub fn location_by_id(&self, id: u64) -> Option<&SourceLocation> {
+        return self.by_id.get(&id).map(|index| &self.locations[*index as usize]);
+    }
+
+    // Renames zones matched by grouping rules in place, so every user of the table (Tracy queries, exports, filters) sees group names.
+    // Location ids stay derived from original names, returns count of renamed locations
+    pub fn apply_groups(&mut self, groups: &ZoneGroups) -> usize {
+        let mut renamed = 0;
+        for index in 0..self.locations.len() {
+            let name = self.strings.get(&self.locations[index].name).map(String::as_str).unwrap_or("");
+            let Some(group) = groups.group_of(name).filter(|group| group != name) else {
+                continue;
+            };
+            self.locations[index].name = self.intern(U32SizeString(group));
+            renamed += 1;
+        }
+        return renamed;
+    }
+
+    // Hash is computed by caller, so it can be done in parallel while table is filled on one thread
+    pub fn intern_hashed(&mut self, hash: u64, string: String) -> u64 {
+        let mut pointer = hash;
+        loop {
+            // String read lazily before is loaded from now on under the same pointer
+            if let Some(lazy) = self.lazy.as_mut() {
+                match lazy.strings.get(&pointer) {
+                    Some(existing) if existing.hash == hash && existing.len as usize == string.len() => {
+                        lazy.strings.remove(&pointer);
+                    }
+                    Some(_) => {
+                        pointer += 1;
+                        continue;
+                    }
+                    None => {}
+                }
+            }
+            match self.strings.entry(pointer) {
+                Entry::Occupied(entry) if entry.get() == &string => return pointer,
+                Entry::Occupied(_) => pointer += 1,
+                Entry::Vacant(entry) => {
+                    entry.insert(string);
+                    return pointer;
+                }
+            }
+        }
+    }
+
+    // Same as intern_hashed for tables with lazy strings, string is only compared with loaded ones and then dropped
+    pub fn intern_lazy(&mut self, string: &str, source: LazyString) -> u64 {
+        let Some(lazy) = self.lazy.as_mut() else {
+            return self.intern_hashed(source.hash, string.to_string());
+        };
+        if string.is_empty() {
+            return 0;
+        }
+        let mut pointer = source.hash;
+        loop {
+            if let Some(existing) = self.strings.get(&pointer) {
+                if existing == string {
+                    return pointer;
+                }
+                pointer += 1;
+                continue;
+            }
+            match lazy.strings.entry(pointer) {
+                Entry::Occupied(entry) if entry.get().hash == source.hash && entry.get().len == source.len => return pointer,
+                Entry::Occupied(_) => pointer += 1,
+                Entry::Vacant(entry) => {
+                    entry.insert(source);
+                    return pointer;
+                }
+            }
+        }
+    }
+}
+
+// Id of location derived from its content, same for tables with loaded and lazy strings
+pub fn location_id(strings: [&str; 3], line: u32) -> u64 {
+    // Lengths keep ("ab", "c") and ("a", "bc") apart
+    let mut hash = FNV_OFFSET;
+    for string in strings {
+        hash = fnv1a(hash, &(string.len() as u32).to_le_bytes());
+        hash = fnv1a(hash, string.as_bytes());
+    }
+    return fnv1a(hash, &line.to_le_bytes());
+}
+
+fn same_location(a: &SourceLocation, b: &SourceLocation) -> bool {
+    return a.name == b.name && a.function == b.function && a.file == b.file && a.line == b.line;
+}
@@ -0,0 +1,18 @@
+// Snapshot reading and analysis shared by rtracy binary, C bindings and WASM build
+pub mod analysis;
+pub mod args;
+pub mod cache;
+pub mod failure;
+pub mod groups;
+pub mod intern;
+pub mod meta;
+pub mod model;
+pub mod search;
+pub mod seal;
+pub mod snapshot;
+pub mod structs;
+pub mod writer;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
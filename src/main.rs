@@ -1,137 +1,745 @@
-mod structs;
 mod server;
+mod sidecar;
+mod clock;
+mod check;
+mod spikes;
+mod correlate;
+mod report;
+mod top;
+mod tree;
+mod frame;
+mod hist;
+mod anonymize;
+mod split;
+mod grep;
+mod filter;
+mod export;
+mod gen;
+mod bench;
+mod daemon;
+mod health;
+mod index;
+mod trend;
+mod changes;
+mod watch;
+mod align;
+mod strip;
+mod relay;
+mod session;
+mod config;
+mod acl;
+mod audit;
+mod pool;
+mod repl;
+mod sql;
+mod grpc;
+mod feed;
+mod upload;
+mod s3;
+mod remote;
+mod notify;
+mod info;
+mod doctor;
+mod errors;
+mod memory;
+mod broadcast;
+mod color;
+#[cfg(windows)]
+mod service;
 
-use std::{str, thread};
+use rtracy::{analysis, args, cache, failure, groups, intern, meta, model, search, seal, snapshot, structs, writer};
 use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::net::{SocketAddr, TcpListener};
-use std::io::SeekFrom;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::prelude::*;
-use std::env;
-use crate::server::handle_client;
-use crate::structs::{BINCODE_CONFIG, SourceLocation, UTracyHeader, UTracySourceLocation};
+use std::io::ErrorKind;
+use std::{env, process};
+use crate::args::Arguments;
+use crate::analysis::{collect_frame_marks, collect_zone_stats_with, compare_zone_stats, find_idle_gaps, StatsOptions};
+use crate::align::align_frames;
+use crate::clock::{format_duration, format_utc, parse_duration, set_raw_numbers};
+use crate::broadcast::spawn_broadcast;
+use crate::daemon::{daemonize, inherited_listener};
+use crate::health::{bind_health, spawn_health, spawn_idle_exit, Activity, HttpAccess};
+use crate::grpc::{bind_grpc, spawn_grpc};
+use crate::feed::{bind_feed, spawn_feed};
+use crate::upload::{latest_upload, spawn_retention, Retention, Uploads, DEFAULT_MAX_UPLOAD};
+use crate::index::DEFAULT_DB;
+use crate::changes::{capture_commit, Commits};
+use crate::watch::load_watchlist;
+use crate::remote::{resolve_paths, DEFAULT_CACHE};
+use crate::pool::{WorkerPool, DEFAULT_WORKERS};
+use crate::filter::FilterScript;
+use crate::acl::{AllowList, parse_token};
+use crate::config::load_config;
+use crate::server::{clear_block_cache, collapse_table, handle_client, parse_bandwidth, parse_size, Profile, Served, ServerOptions, SharedServed, ZonePlot,
+                    DEFAULT_BATCH_EVENTS, DEFAULT_DURATION_COLORS, MAX_BATCH_BYTES};
+use crate::sidecar::{load_gpu_zones, load_perf_samples, load_scheduling_events, load_sysmon, parse_gpu_api, Sidecar};
+use crate::groups::ZoneGroups;
+use crate::intern::SourceTable;
+use crate::memory::{choose_mode, hold_snapshot, source_table, MemoryMode};
+use crate::errors::{print_error, take_error_format};
+use crate::failure::{fail, usage, ErrorKind as Kind};
+use crate::color::{green, init_color, red, warning};
+use crate::meta::load_meta;
+use crate::snapshot::{load_snapshot, Snapshot};
+use crate::strip::build_frame_strips;
+use crate::structs::U32SizeString;
 
+const SERVE_OPTIONS: [&str; 65] = ["-p", "-s", "-l", "--context-switches", "--samples", "--gpu-zones", "--vsync", "--plot", "--filter-script", "--tcp-nodelay",
+    "--send-buffer", "--recv-buffer", "--batch-events", "--batch-bytes", "--max-bandwidth", "--daemon", "--pidfile", "--health-port",
+    "--exit-after-idle", "--merge-processes", "--merge-threads", "--thread-order", "--gap-threshold", "--collapse-gaps",
+    "--every-nth-frame", "--block-cache", "--baseline", "--baseline-threshold", "--frame-strip", "--sysmon", "--zone-self-time", "--close-open-zones",
+    "--record-session", "--config", "--allow", "--auth-token", "--audit-log", "--workers", "--grpc-port", "--ws-port",
+    "--upload-dir", "--max-upload", "--db", "--keep-size", "--keep-days", "--keep-count",
+    "--s3-endpoint", "--download-cache", "--webhook", "--git-repo", "--changelog", "--within", "--max-depth", "--color-by-duration", "--color-thresholds", "--watchlist", "--baseline-align", "--groups", "--lazy-strings", "--max-memory",
+    "--no-broadcast", "--gpu-api", "--frames", "--trim", "--winsorize"];
 
-const FILE_SIGNATURE: u64 = 0x6D64796361727475;
+fn print_usage() {
+    println!("Use: <file> [-p port] [-s skip_frames] [-l limit_frames] [--context-switches file] [--samples perf.script] [--gpu-zones file] [--gpu-api name] [--vsync rate] [--plot zone]... [--filter-script filter.rhai] [--groups groups.toml] [--lazy-strings] [--max-memory size] [--tcp-nodelay] [--send-buffer bytes] [--recv-buffer bytes] [--batch-events n] [--batch-bytes n] [--max-bandwidth rate] [--daemon] [--pidfile path] [--health-port port] [--exit-after-idle minutes] [--merge-processes] [--merge-threads] [--thread-order id,id...] [--gap-threshold duration] [--collapse-gaps max_ms] [--every-nth-frame n] [--within zone] [--max-depth n] [--block-cache] [--baseline old.utracy] [--baseline-threshold percent] [--baseline-align] [--frames first..end] [--trim percent] [--winsorize percent] [--git-repo dir | --changelog file] [--frame-strip] [--sysmon stats.csv] [--zone-self-time] [--color-by-duration] [--color-thresholds yellow,red] [--watchlist watch.toml] [--close-open-zones] [--record-session out.utracy] [--config rtracy.toml] [--allow address/prefix,...] [--auth-token secret] [--audit-log sessions.jsonl] [--workers n] [--grpc-port port] [--ws-port port] [--upload-dir dir] [--max-upload size] [--db index.sqlite] [--keep-size size] [--keep-days days] [--keep-count n] [--s3-endpoint url] [--download-cache dir] [--webhook url]...");
+    println!("     serve <file>... [options]  Stream several snapshots back-to-back in one session");
+    println!("     serve <file>... --merge-processes [options]  Stream snapshots of processes captured together as one session");
+    println!("     check <file> --budget budgets.toml [stats options]  Verify zone timings against budgets");
+    println!("     spikes <file> [--threshold duration] [--sigma n] [--top zones]  List slow frames and their heaviest zones");
+    println!("     info <file>  Header, frame count and round metadata of snapshot");
+    println!("     doctor [file]... [-p port]  Check that captures are readable, protocol matches Tracy release and port is free");
+    println!("     report <file> [--format markdown] [-o output] [--sigma n] [stats options]  Summary for posting to issues or chat");
+    println!("     correlate <file> [-n count]  Rank zones by their share of frame time variance");
+    println!("     top <file> [-n count] [--by self|total|count] [stats options]  Print hottest zones");
+    println!("     tree <file> [--format text|json] [--depth n] [--min-percent p]  Print merged call tree");
+    println!("     frame <file> --index n | --at time [--thread id]  Print zones of a single frame or zones running on thread at time");
+    println!("     hist <file> --zone name [--buckets n] [--scale linear|log] [--format text|csv|json]  Zone duration histogram");
+    println!("     repl <file>  Interactive top, frame, zone and thread queries over snapshot decoded once");
+    println!("     sql <file> \"SELECT ...\" [--format text|csv]  Run SQLite query over zones and frames tables of snapshot");
+    println!("     anonymize <file> -o out.utracy [--hash]  Strip file paths, host info and program name");
+    println!("     seal <file>  Append checksum, sealed snapshots are verified on every open");
+    println!("     grep <file> <zone regex> [--min duration]  List every occurrence of matching zones");
+    println!("     export <file> --format name [-o output] [--groups groups.toml]  Export snapshot, run without format to list formats");
+    println!("     gen -o out.utracy [--threads n] [--frames n] [--zones-per-frame n] [--seed n]  Generate synthetic snapshot");
+    println!("     bench <file> [--batch-bytes n] [--clients n] [--workers n]  Measure decode, encode and compress throughput of serving pipeline");
+    println!("     index <directory> [--db index.sqlite]  Store metadata and zone stats of snapshots in directory to SQLite");
+    println!("     list [--db index.sqlite] [--program name]  List indexed snapshots");
+    println!("     search --zone regex [--min-mean duration] [--min-p99 duration] [--min-max duration] [--db index.sqlite]  Find indexed snapshots with slow zones");
+    println!("     trend <file>... --zone name [--format text|csv|png] [-o output] [--git-repo dir | --changelog file] [stats options]  Zone mean and p99 per snapshot over time");
+    println!("     relay host:port [-p port] [--allow address/prefix,...] [--auth-token secret] [--upstream-token secret]  Re-serve live session of another rtracy or Tracy client to several Tracy UIs");
+    println!("     s3 s3://bucket/prefix/ [--s3-endpoint url] [--download-cache dir]  List captures in S3 bucket, serve them as s3://bucket/key");
+    println!("     service install <file or directory> [options] | uninstall  Run server as Windows service");
+    println!("     split <file> --frames n -o out_dir  Split snapshot into chunks of n frames");
+    println!("     --raw  Print durations as nanoseconds and counts without thousands separators");
+    println!("     --no-color  Print plain text even on terminal, same as NO_COLOR environment variable");
+    println!("     --error-format json  Print fatal error of any command as {{\"error\": code, \"message\": text}} line on stderr");
+    println!("     stats options: [--frames first..end] [--trim percent] [--winsorize percent] [--groups groups.toml]");
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let json_errors = match take_error_format(&mut args) {
+        Ok(json) => json,
+        Err(msg) => {
+            print_error(&msg, false);
+            process::exit(1);
+        }
+    };
+    // Plain integers for scripts, honored by every command printing durations or counts
+    if let Some(index) = args.iter().position(|arg| arg == "--raw") {
+        args.remove(index);
+        set_raw_numbers(true);
+    }
+    // Colors are also off when output is not a terminal or NO_COLOR is set
+    let no_color = args.iter().position(|arg| arg == "--no-color");
+    if let Some(index) = no_color {
+        args.remove(index);
+    }
+    init_color(no_color.is_some());
     if args.len() < 2 {
         println!("No input file supplied, exiting");
-        println!("Use: <file> [-p port] [-s skip_frames] [-l limit_frames]");
+        print_usage();
         return;
     }
 
-    let mut port = 8086;
-    let mut skip_frames = 0u32;
-    let mut limit_frames = u32::MAX;
+    let result = match args[1].as_str() {
+        "serve" => serve(&args[2..]),
+        "check" => check::run(&args[2..]),
+        "spikes" => spikes::run(&args[2..]),
+        "correlate" => correlate::run(&args[2..]),
+        "report" => report::run(&args[2..]),
+        "info" => info::run(&args[2..]),
+        "doctor" => doctor::run(&args[2..]),
+        "top" => top::run(&args[2..]),
+        "tree" => tree::run(&args[2..]),
+        "frame" => frame::run(&args[2..]),
+        "hist" => hist::run(&args[2..]),
+        "repl" => repl::run(&args[2..]),
+        "sql" => sql::run(&args[2..]),
+        "anonymize" => anonymize::run(&args[2..]),
+        "seal" => seal::run(&args[2..]),
+        "split" => split::run(&args[2..]),
+        "grep" => grep::run(&args[2..]),
+        "export" => export::run(&args[2..]),
+        "gen" => gen::run(&args[2..]),
+        "bench" => bench::run(&args[2..]),
+        "index" => index::run_index(&args[2..]),
+        "list" => index::run_list(&args[2..]),
+        "search" => index::run_search(&args[2..]),
+        "trend" => trend::run(&args[2..]),
+        "relay" => relay::run(&args[2..]),
+        "s3" => s3::run(&args[2..]),
+        #[cfg(windows)]
+        "service" => service::run(&args[2..]),
+        #[cfg(not(windows))]
+        "service" => Err("Windows service is not supported on this platform, use --daemon or systemd".into()),
+        _ => serve(&args[1..]),
+    };
+    if let Err(msg) = result {
+        print_error(&msg, json_errors);
+        process::exit(1);
+    }
+}
 
-    if args.len() > 2 {
-        if args.len() == 3 {
-            println!("Wrong option");
-            println!("Available options: -p port, -s skip_frames, -l limit_frames");
-        } else {
-            for i in 0..(args.len() - 2) / 2 {
-                match args[i * 2 + 2].as_str() {
-                    "-p" => {
-                        port = args[i * 2 + 3].parse().expect("Wrong input: -p");
-                    }
-                    "-s" => {
-                        skip_frames = args[i * 2 + 3].parse().expect("Wrong input: -s");
-                    }
-                    "-l" => {
-                        limit_frames = args[i * 2 + 3].parse().expect("Wrong input: -l");
-                    }
-                    _ => {
-                        println!("Wrong option {}", args[i * 2 + 3].as_str());
-                        println!("Available options: -p port, -s skip_frames, -l limit_frames");
-                    }
-                };
+// Baseline zones shown in Tracy, the rest is only counted in summary line
+const BASELINE_ANNOTATIONS: usize = 20;
+
+// With --baseline-align only frames paired by zone composition are compared
+fn baseline_annotations(snapshot: &Snapshot, table: &SourceTable, current_path: &str, path: &str, groups: Option<&ZoneGroups>, arguments: &Arguments)
+                        -> Result<Vec<String>, String> {
+    let threshold: f64 = arguments.get_parsed("--baseline-threshold")?.unwrap_or(10f64);
+    let commits = Commits::from_arguments(arguments)?;
+    let mut baseline_table = SourceTable::new();
+    let baseline = load_snapshot(path, &mut baseline_table).map_err(|e| format!("Error loading baseline: {e}"))?;
+    if let Some(groups) = groups {
+        baseline_table.apply_groups(groups);
+    }
+    // Tables are grouped already, options only select frames and outliers, the same on both sides
+    let mut current_options = StatsOptions { groups: None, ..StatsOptions::from_arguments(arguments)? };
+    let mut baseline_options = current_options.clone();
+    let mut aligned = None;
+    if arguments.has("--baseline-align") {
+        let pairs = align_frames(snapshot, table, &baseline, &baseline_table)?;
+        current_options.frame_set = Some(pairs.iter().map(|(current, _)| *current).collect());
+        baseline_options.frame_set = Some(pairs.iter().map(|(_, baseline)| *baseline).collect());
+        aligned = Some(format!("Aligned {} of {} frames with baseline by zone composition", pairs.len(), collect_frame_marks(snapshot)?.len()));
+    }
+    let current = collect_zone_stats_with(snapshot, table, &current_options)?;
+    let deltas = compare_zone_stats(&current, &collect_zone_stats_with(&baseline, &baseline_table, &baseline_options)?, threshold);
+    let slower = deltas.iter().filter(|delta| delta.impact > 0).count();
+    let mut lines = vec![format!("Baseline {path}: {slower} zones slower, {} faster by {threshold}% or more", deltas.len() - slower)];
+    lines.extend(aligned);
+    for line in &lines {
+        println!("{line}");
+    }
+    // Annotations go to Tracy as plain text, only console copy is colored
+    for delta in deltas.iter().take(BASELINE_ANNOTATIONS) {
+        let line = format!("{} {:+.0}% vs baseline ({} -> {})", delta.name, delta.percent(), format_duration(delta.baseline_mean), format_duration(delta.mean));
+        println!("{}", if delta.impact > 0 { red(&line) } else { green(&line) });
+        lines.push(line);
+    }
+    if let (Some(old), Some(new)) = (capture_commit(path)?, capture_commit(current_path)?) {
+        let described = commits.describe(&old, &new);
+        for line in &described {
+            println!("{line}");
+        }
+        lines.extend(described);
+    }
+    return Ok(lines);
+}
+
+// Ports tried after requested one when it is already taken
+const PORT_ATTEMPTS: u16 = 20;
+const CONFIG_POLL: Duration = Duration::from_secs(2);
+
+// Profile of listener, replaced when config file changes
+type SharedProfile = RwLock<Arc<Profile>>;
+
+fn bind_listener(port: u16) -> Result<(TcpListener, u16), String> {
+    let mut candidate = port;
+    loop {
+        match TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], candidate))) {
+            Ok(listener) => {
+                if candidate != port {
+                    println!("Port {port} is busy, using port {candidate} instead");
+                }
+                return Ok((listener, candidate));
+            }
+            Err(e) if e.kind() == ErrorKind::AddrInUse && candidate - port < PORT_ATTEMPTS && candidate < u16::MAX => {
+                candidate += 1;
             }
+            Err(e) => return Err(fail(Kind::PortUnavailable, format!("Error binding port {candidate}: {e}"))),
         }
     }
+}
 
-    let mut file_reader = BufReader::new(File::open(&args[1]).expect("Error opening file"));
+fn serve(args: &[String]) -> Result<(), String> {
+    // Kept for the whole run, uploaded captures are loaded with the same options
+    let arguments: &'static Arguments = Box::leak(Box::new(Arguments::parse(args, &["--tcp-nodelay", "--daemon", "--merge-processes", "--merge-threads", "--block-cache", "--frame-strip", "--zone-self-time", "--close-open-zones", "--color-by-duration", "--baseline-align", "--lazy-strings", "--no-broadcast"])?));
+    if let Err(msg) = arguments.check_known(&SERVE_OPTIONS) {
+        print_usage();
+        return Err(msg);
+    }
+    let upload_dir = arguments.get("--upload-dir").map(str::to_string);
+    if arguments.positional.is_empty() && upload_dir.is_none() {
+        print_usage();
+        return Err(usage("No input file supplied, exiting"));
+    }
 
-    let header: UTracyHeader = bincode::decode_from_reader(&mut file_reader, BINCODE_CONFIG).unwrap();
+    let port: u16 = arguments.get_parsed("-p")?.unwrap_or(8086);
+    let skip_frames: u32 = arguments.get_parsed("-s")?.unwrap_or(0);
+    let limit_frames: u32 = arguments.get_parsed("-l")?.unwrap_or(u32::MAX);
+    let frame_stride: u32 = arguments.get_parsed("--every-nth-frame")?.unwrap_or(1).max(1);
+    let health_port: Option<u16> = arguments.get_parsed("--health-port")?;
+    let grpc_port: Option<u16> = arguments.get_parsed("--grpc-port")?;
+    let ws_port: Option<u16> = arguments.get_parsed("--ws-port")?;
+    let exit_after_idle: Option<f64> = arguments.get_parsed("--exit-after-idle")?;
+    if exit_after_idle.is_some_and(|minutes| minutes <= 0f64) {
+        return Err(usage("Wrong input: --exit-after-idle"));
+    }
+    let workers: usize = arguments.get_parsed("--workers")?.unwrap_or(DEFAULT_WORKERS);
+    if workers == 0 {
+        return Err(usage("Wrong input: --workers 0, expected at least one"));
+    }
+    let max_upload = arguments.get("--max-upload").map(parse_size).transpose()?.unwrap_or(DEFAULT_MAX_UPLOAD);
+    if upload_dir.is_some() && health_port.is_none() {
+        return Err("--upload-dir needs --health-port, uploads are received on it".into());
+    }
+    let keep_days: Option<f64> = arguments.get_parsed("--keep-days")?;
+    if keep_days.is_some_and(|days| days <= 0f64) {
+        return Err(usage("Wrong input: --keep-days"));
+    }
+    let retention = Retention {
+        max_size: arguments.get("--keep-size").map(parse_size).transpose()?,
+        max_age: keep_days.map(|days| Duration::from_secs_f64(days * 86400f64)),
+        max_count: arguments.get_parsed("--keep-count")?,
+    };
+    if retention.is_set() && upload_dir.is_none() {
+        return Err("--keep-size, --keep-days and --keep-count need --upload-dir".into());
+    }
 
-    if header.signature != FILE_SIGNATURE {
-        println!("Wrong utracy file signature, expected \"{FILE_SIGNATURE}\" got \"{}\"", header.signature);
-        return;
+    // Forked before captures are loaded, decoding starts threads
+    let daemon = arguments.has("--daemon").then(daemonize).transpose()?;
+    let served = match &upload_dir {
+        Some(dir) if arguments.positional.is_empty() => latest_upload(dir)?.map(|path| load_served(&[path], arguments)).transpose()?,
+        _ => {
+            let paths = resolve_paths(&arguments.positional, arguments.get("--download-cache").unwrap_or(DEFAULT_CACHE), arguments.get("--s3-endpoint"))?;
+            Some(load_served(&paths, arguments)?)
+        }
+    };
+    if served.is_none() {
+        println!("Upload directory is empty, clients are refused until the first upload");
     }
 
-    if header.version != 2 {
-        println!("Wrong utracy file version, expected 2 got {}", header.version);
-        return;
+    let filter_script = arguments.get("--filter-script").map(String::from);
+    if let Some(path) = &filter_script {
+        // Compile once to report script errors before any client connects
+        FilterScript::load(path)?;
+    }
+    let within = arguments.get("--within").map(String::from);
+    if let (Some(zone), Some(served)) = (&within, &served) {
+        if !served.table.locations.iter().any(|source| served.table.strings.get(&source.name) == Some(zone)) {
+            warning(&format!("no zone named \"{zone}\" in snapshots, only frame marks are streamed"));
+        }
+    }
+    let max_depth: Option<u32> = arguments.get_parsed("--max-depth")?;
+    if max_depth == Some(0) {
+        return Err(usage("Wrong input: --max-depth 0, expected at least 1"));
+    }
+    let base = Profile { name: None, skip_frames, limit_frames, filter_script, frame_stride, within, max_depth, thread_names: HashMap::new() };
+    let config_path = arguments.get("--config").map(str::to_string);
+    let (profile, listeners) = match config_path.as_deref().map(|path| load_config(path, &base)).transpose()? {
+        Some(config) => (config.main, config.listeners),
+        None => (base.clone(), Vec::new()),
+    };
+    if listeners.iter().any(|(listener_port, _)| *listener_port == port) {
+        return Err(format!("Port {port} is used by command line and config file listener"));
     }
 
-    let location_count: u32 = bincode::decode_from_reader(&mut file_reader, BINCODE_CONFIG).unwrap();
-    println!("Captured process: {}", str::from_utf8(&header.program_name).unwrap());
+    let served_ref: &'static SharedServed = Box::leak(Box::new(RwLock::new(served.map(Arc::new))));
+    let profile_ref: &'static SharedProfile = Box::leak(Box::new(RwLock::new(Arc::new(profile))));
+    let access: &'static HttpAccess = Box::leak(Box::new(HttpAccess {
+        allow: arguments.get("--allow").map(AllowList::parse).transpose()?,
+        auth_token: arguments.get("--auth-token").map(parse_token).transpose()?,
+    }));
+    let uploads = upload_dir.map(|dir| -> Result<&'static Uploads, String> {
+        Ok(Box::leak(Box::new(Uploads {
+            dir,
+            db: arguments.get("--db").unwrap_or(DEFAULT_DB).to_string(),
+            max_size: max_upload,
+            access,
+            retention,
+            served: served_ref,
+            load: Box::new(|path: &str| load_served(&[path.to_string()], arguments)),
+        })))
+    }).transpose()?;
 
-    println!("Found {} source locations", location_count);
-    let mut locations = Vec::<SourceLocation>::with_capacity(location_count as usize);
-    let mut strings = HashMap::<u64, String>::new();
-    strings.insert(0, "".into());
+    let (listener, port) = match inherited_listener()? {
+        Some(listener) => {
+            let port = listener.local_addr().map(|address| address.port()).unwrap_or(0);
+            println!("Using socket passed by systemd");
+            (listener, port)
+        }
+        None => bind_listener(port)?,
+    };
+    println!("Server listening on port {port}");
+    let mut extra_listeners = Vec::new();
+    for (port, profile) in listeners {
+        let (listener, bound) = bind_listener(port)?;
+        println!("Profile \"{}\" listening on port {bound}", profile.name.as_deref().unwrap_or(""));
+        let shared: &'static SharedProfile = Box::leak(Box::new(RwLock::new(Arc::new(profile))));
+        extra_listeners.push((listener, port, shared));
+    }
+    let health_listener = health_port.map(bind_health).transpose()?;
+    if let Some(port) = health_port {
+        println!("Health endpoint on port {port}");
+    }
+    let grpc_listener = grpc_port.map(bind_grpc).transpose()?;
+    if let Some(port) = grpc_port {
+        println!("gRPC event stream on port {port}");
+    }
+    let ws_listener = ws_port.map(bind_feed).transpose()?;
+    if let Some(port) = ws_port {
+        println!("WebSocket event feed on port {port}");
+    }
+    if let Some(daemon) = daemon {
+        daemon.ready(arguments.get("--pidfile"))?;
+    } else if let Some(path) = arguments.get("--pidfile") {
+        std::fs::write(path, format!("{}\n", process::id())).map_err(|e| format!("Error writing pidfile {path}: {e}"))?;
+    }
+    let activity: &'static Activity = Box::leak(Box::new(Activity::new()));
+    // Shared by all listeners
+    let pool = WorkerPool::start(workers);
+    if let Some(listener) = health_listener {
+        spawn_health(listener, activity, pool, access, uploads);
+    }
+    if let Some(uploads) = uploads {
+        spawn_retention(uploads);
+    }
+    if let Some(listener) = grpc_listener {
+        spawn_grpc(listener, served_ref, activity);
+    }
+    if let Some(listener) = ws_listener {
+        spawn_feed(listener, served_ref, profile_ref, activity, pool);
+    }
+    if !arguments.has("--no-broadcast") {
+        spawn_broadcast(port, served_ref)?;
+    }
+    if let Some(minutes) = exit_after_idle {
+        spawn_idle_exit(Duration::from_secs_f64(minutes * 60f64), activity);
+    }
+    let spawn_listener = move |listener: TcpListener, profile: &'static SharedProfile| {
+        thread::spawn(move || accept_clients(listener, served_ref, profile, activity, pool));
+    };
+    let mut watched = HashMap::new();
+    for (listener, port, profile) in extra_listeners {
+        watched.insert(port, profile);
+        spawn_listener(listener, profile);
+    }
+    if let Some(path) = config_path {
+        thread::spawn(move || watch_config(path, base, profile_ref, watched, spawn_listener));
+    }
+    accept_clients(listener, served_ref, profile_ref, activity, pool);
+    return Ok(());
+}
+
+// Snapshots and everything derived from them for given files, built on start and for every uploaded capture
+fn load_served(paths: &[String], arguments: &Arguments) -> Result<Served, String> {
+    let vsync_rate: Option<f64> = arguments.get_parsed("--vsync")?;
+    if vsync_rate.is_some_and(|rate| rate <= 0f64) {
+        return Err(usage("Wrong input: --vsync"));
+    }
+    let send_buffer: Option<usize> = arguments.get_parsed("--send-buffer")?;
+    let recv_buffer: Option<usize> = arguments.get_parsed("--recv-buffer")?;
+    let batch_events: u32 = arguments.get_parsed("--batch-events")?.unwrap_or(DEFAULT_BATCH_EVENTS).max(1);
+    let batch_bytes: usize = arguments.get_parsed("--batch-bytes")?.unwrap_or(MAX_BATCH_BYTES);
+    if batch_bytes == 0 || batch_bytes > MAX_BATCH_BYTES {
+        return Err(usage(format!("Wrong input: --batch-bytes {batch_bytes}, expected 1..={MAX_BATCH_BYTES}")));
+    }
+    let max_bandwidth = arguments.get("--max-bandwidth").map(parse_bandwidth).transpose()?;
+    let allow = arguments.get("--allow").map(AllowList::parse).transpose()?;
+    let auth_token = arguments.get("--auth-token").map(parse_token).transpose()?;
+    let audit_log = arguments.get("--audit-log").map(str::to_string);
+    let watchlist = arguments.get("--watchlist").map(load_watchlist).transpose()?.unwrap_or_default();
+    let thresholds = arguments.get("--color-thresholds");
+    let duration_colors = if arguments.has("--color-by-duration") {
+        let wrong = || usage(format!("Wrong input: --color-thresholds {}, expected increasing yellow,red durations like {DEFAULT_DURATION_COLORS}", thresholds.unwrap_or("")));
+        let (yellow, red) = thresholds.unwrap_or(DEFAULT_DURATION_COLORS).split_once(',').ok_or_else(wrong)?;
+        let (yellow, red) = (parse_duration(yellow)?, parse_duration(red)?);
+        if yellow >= red {
+            return Err(wrong());
+        }
+        Some((yellow, red))
+    } else if thresholds.is_some() {
+        return Err("--color-thresholds needs --color-by-duration".into());
+    } else {
+        None
+    };
 
-    for i in 0..location_count {
-        let location: UTracySourceLocation = bincode::decode_from_reader(&mut file_reader, BINCODE_CONFIG).unwrap();
+    let memory_mode = arguments.get("--max-memory").map(parse_size).transpose()?.map(|budget| choose_mode(paths, budget)).transpose()?;
+    let mut table = source_table(memory_mode, arguments.has("--lazy-strings"));
+    let mut snapshots = Vec::<Snapshot>::new();
+    for path in paths {
+        let load_start = Instant::now();
+        let mut snapshot = load_snapshot(path, &mut table)?;
+        if let Some(mode) = memory_mode {
+            hold_snapshot(&mut snapshot, mode);
+        }
+        println!("Captured process: {}", snapshot.program_name());
+        println!("Found {} source locations, loaded in {:.2} s", snapshot.location_count, load_start.elapsed().as_secs_f64());
+        if let Some(first) = snapshots.first() {
+            if first.header.multiplier != snapshot.header.multiplier {
+                warning(&format!("{path} uses different timer multiplier, timings will be scaled incorrectly"));
+            }
+        }
+        snapshots.push(snapshot);
+    }
 
-        let mut name_string = location.name.get_hash();
-        while strings.get(&name_string).is_some_and(|t| t != &location.name.0) {
-            name_string += 1;
+    // Gaps are detected in every snapshot on load, 0 disables markers
+    let gap_threshold = parse_duration(arguments.get("--gap-threshold").unwrap_or("10s"))?;
+    let mut gaps = Vec::new();
+    for snapshot in &snapshots {
+        let snapshot_gaps = if gap_threshold == 0 {
+            Vec::new()
+        } else {
+            find_idle_gaps(snapshot, (gap_threshold as f64 / snapshot.header.multiplier) as u64)?
+        };
+        if !snapshot_gaps.is_empty() {
+            println!("Found {} idle gaps longer than {} in {}", snapshot_gaps.len(), format_duration(gap_threshold), snapshot.path);
         }
-        strings.insert(name_string, location.name.0);
+        gaps.push(snapshot_gaps);
+    }
+    // Collapsed gaps are searched separately, threshold may differ from markers one
+    let collapse_gaps = arguments.get("--collapse-gaps").map(parse_duration).transpose()?;
+    let mut collapse = Vec::new();
+    for snapshot in &snapshots {
+        let Some(max_gap) = collapse_gaps else {
+            collapse.push(Vec::new());
+            continue;
+        };
+        let max_gap = (max_gap as f64 / snapshot.header.multiplier) as u64;
+        collapse.push(collapse_table(&find_idle_gaps(snapshot, max_gap)?, max_gap));
+    }
 
-        let mut function_string = location.function.get_hash();
-        while strings.get(&function_string).is_some_and(|t| t != &location.function.0) {
-            function_string += 1;
+    let mut sidecar = Sidecar::default();
+    if let Some(path) = arguments.get("--context-switches") {
+        load_scheduling_events(&mut sidecar, path).map_err(|e| format!("Error loading context switches: {e}"))?;
+    }
+    if let Some(path) = arguments.get("--samples") {
+        load_perf_samples(&mut sidecar, path, &snapshots[0].header).map_err(|e| format!("Error loading samples: {e}"))?;
+        println!("Loaded {} callstacks with {} unique frames", sidecar.callstacks.len(), sidecar.frames.len());
+    }
+    let gpu_api = arguments.get("--gpu-api").map(parse_gpu_api).transpose()?.unwrap_or_default();
+    if arguments.has("--gpu-api") && !arguments.has("--gpu-zones") {
+        return Err("--gpu-api needs --gpu-zones".into());
+    }
+    if let Some(path) = arguments.get("--gpu-zones") {
+        let zones = load_gpu_zones(&mut sidecar, path, &mut table).map_err(|e| format!("Error loading GPU zones: {e}"))?;
+        println!("Loaded {zones} GPU zones");
+    }
+    if let Some(path) = arguments.get("--sysmon") {
+        let records = load_sysmon(&mut sidecar, path, &snapshots[0].header, &mut table).map_err(|e| format!("Error loading host monitoring: {e}"))?;
+        println!("Loaded {records} host monitoring records");
+    }
+    if !sidecar.events.is_empty() {
+        println!("Loaded {} sidecar records", sidecar.events.len());
+    }
+    let groups = arguments.get("--groups").map(ZoneGroups::load).transpose()?;
+    if let Some(groups) = &groups {
+        println!("Renamed {} source locations by grouping rules", table.apply_groups(groups));
+    }
+
+    let mut annotations = Vec::new();
+    if let Some(path) = arguments.get("--baseline") {
+        annotations = baseline_annotations(&snapshots[0], &table, &paths[0], path, groups.as_ref(), arguments)?;
+    }
+
+    let mut frame_strips = Vec::new();
+    if arguments.has("--frame-strip") {
+        for snapshot in &snapshots {
+            frame_strips.push(build_frame_strips(snapshot, &table)?);
         }
-        strings.insert(function_string, location.function.0);
+    }
 
-        let mut file_string = location.file.get_hash();
-        while strings.get(&file_string).is_some_and(|t| t != &location.file.0) {
-            file_string += 1;
+    let mut app_info = vec![format!("rtracy {}", env!("CARGO_PKG_VERSION"))];
+    for snapshot in &snapshots {
+        app_info.push(format!("Snapshot: {}", snapshot.path));
+        app_info.push(format!("Captured: {}", format_utc(snapshot.header.epoch)));
+        if let Some(meta) = load_meta(&snapshot.path)? {
+            app_info.extend(meta.lines());
+        }
+    }
+    if let Some(path) = arguments.get("--context-switches") {
+        app_info.push(format!("Context switches: {path}"));
+    }
+    if let Some(path) = arguments.get("--gpu-zones") {
+        app_info.push(format!("GPU zones: {path}"));
+    }
+    if let Some(path) = arguments.get("--samples") {
+        app_info.push(format!("Samples: {path}"));
+    }
+    let merge_processes = arguments.has("--merge-processes") && snapshots.len() > 1;
+    if merge_processes {
+        let processes: Vec<String> = snapshots.iter().map(|snapshot| format!("{} ({})", snapshot.program_name(), snapshot.header.process_id)).collect();
+        app_info.push(format!("Merged processes: {}", processes.join(", ")));
+    }
+    let mut thread_order = Vec::new();
+    if let Some(order) = arguments.get("--thread-order") {
+        for thread in order.split(',').filter(|thread| !thread.trim().is_empty()) {
+            thread_order.push(thread.trim().parse::<u32>().map_err(|_| usage(format!("Wrong input: --thread-order {order}")))?);
         }
-        strings.insert(file_string, location.file.0);
+        app_info.push(format!("Thread order: {order}"));
+    }
+    if let Some(max_gap) = collapse_gaps {
+        app_info.push(format!("Gaps collapsed to {}", format_duration(max_gap)));
+    }
+    let merge_threads = arguments.has("--merge-threads");
+    if merge_threads {
+        app_info.push("Merged threads".into());
+    }
+    if let Some(rate) = vsync_rate {
+        app_info.push(format!("Vsync: {rate} Hz"));
+    }
+    if let Some(bandwidth) = max_bandwidth {
+        app_info.push(format!("Max bandwidth: {:.2} MB/s", bandwidth as f64 / 1_000_000f64));
+    }
+
+    let mut snapshot_names = Vec::new();
+    for snapshot in &snapshots {
+        let name = snapshot.path.rsplit(['/', '\\']).next().unwrap_or(&snapshot.path).to_string();
+        snapshot_names.push(table.intern(U32SizeString(name)));
+    }
 
-        locations.insert(i as usize, SourceLocation {
-            name: name_string,
-            function: function_string,
-            file: file_string,
-            line: location.line,
-            color_r: location.color[0],
-            color_g: location.color[1],
-            color_b: location.color[2],
+    let mut plots = Vec::new();
+    for zone in arguments.get_all("--plot") {
+        let locations: Vec<u32> = (0..table.locations.len() as u32)
+            .filter(|i| table.strings.get(&table.locations[*i as usize].name).is_some_and(|name| name == zone))
+            .collect();
+        if locations.is_empty() {
+            warning(&format!("zone \"{zone}\" not found, plot skipped"));
+            continue;
+        }
+        app_info.push(format!("Plot: {zone}"));
+        plots.push(ZonePlot {
+            name: table.intern(U32SizeString(format!("{zone} per frame, ms"))),
+            locations,
         });
     }
 
-    let events_position = file_reader.stream_position().unwrap();
+    // Cached stream is kept on top of captures, there is no room for it when they don't fit
+    let block_cache = arguments.has("--block-cache") && memory_mode != Some(MemoryMode::Lazy);
+    if arguments.has("--block-cache") && !block_cache {
+        warning("--block-cache is off, stream cache doesn't fit into --max-memory");
+    }
 
-    let header_ref = Box::leak(Box::new(header));
-    let locations_ref = Box::leak(Box::new(locations));
-    let strings_ref = Box::leak(Box::new(strings));
-    let skip_frames_ref = Box::leak(Box::new(skip_frames));
-    let limit_frames_ref = Box::leak(Box::new(limit_frames));
+    let options = ServerOptions {
+        sidecar,
+        gpu_api,
+        vsync_rate,
+        app_info,
+        snapshot_names,
+        plots,
+        tcp_nodelay: arguments.has("--tcp-nodelay"),
+        send_buffer,
+        recv_buffer,
+        batch_events,
+        batch_bytes,
+        max_bandwidth,
+        merge_processes,
+        merge_threads,
+        thread_order,
+        gaps,
+        collapse,
+        block_cache,
+        annotations,
+        frame_strips,
+        zone_self_time: arguments.has("--zone-self-time"),
+        duration_colors,
+        watchlist,
+        close_open_zones: arguments.has("--close-open-zones"),
+        record_session: arguments.get("--record-session").map(str::to_string),
+        audit_log,
+        allow,
+        auth_token,
+        webhooks: arguments.get_all("--webhook").into_iter().map(str::to_string).collect(),
+    };
+    return Ok(Served { snapshots, table, options });
+}
 
-    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).unwrap();
-    println!("Server listening on port {port}");
+// Changed profiles and thread names apply to connections accepted after reload, listeners added to config are started.
+// Snapshots stay loaded and open connections keep streaming with profile they started with
+fn watch_config(path: String, base: Profile, main: &'static SharedProfile, mut listeners: HashMap<u16, &'static SharedProfile>,
+                spawn_listener: impl Fn(TcpListener, &'static SharedProfile)) {
+    let modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let mut last_modified = modified(&path);
+    loop {
+        thread::sleep(CONFIG_POLL);
+        let current = modified(&path);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        let config = match load_config(&path, &base) {
+            Ok(config) => config,
+            Err(msg) => {
+                println!("Config not reloaded: {msg}");
+                continue;
+            }
+        };
+        clear_block_cache();
+        *main.write().unwrap() = Arc::new(config.main);
+        let mut removed: Vec<u16> = listeners.keys().copied().collect();
+        for (port, profile) in config.listeners {
+            removed.retain(|listener_port| *listener_port != port);
+            if let Some(shared) = listeners.get(&port) {
+                *shared.write().unwrap() = Arc::new(profile);
+                continue;
+            }
+            match bind_listener(port) {
+                Ok((listener, bound)) => {
+                    println!("Profile \"{}\" listening on port {bound}", profile.name.as_deref().unwrap_or(""));
+                    let shared: &'static SharedProfile = Box::leak(Box::new(RwLock::new(Arc::new(profile))));
+                    listeners.insert(port, shared);
+                    spawn_listener(listener, shared);
+                }
+                Err(msg) => println!("{msg}"),
+            }
+        }
+        removed.sort();
+        for port in removed {
+            println!("Listener on port {port} is not in config anymore, it keeps its last profile until restart");
+        }
+        println!("Reloaded config {path}");
+    }
+}
+
+fn accept_clients(listener: TcpListener, served: &'static SharedServed, profile: &'static SharedProfile, activity: &'static Activity,
+                  pool: &'static WorkerPool) {
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
                 println!("New connection: {}", stream.peer_addr().unwrap());
-                let mut file_reader = BufReader::new(File::open(&args[1]).expect("Error opening file"));
-                file_reader.seek(SeekFrom::Start(events_position)).unwrap();
-                thread::spawn(|| {
-                    if let Err(msg) = handle_client(stream, header_ref, locations_ref, strings_ref, file_reader, *skip_frames_ref, *limit_frames_ref) {
+                // Connections keep snapshots they started with when upload replaces them
+                let Some(served) = served.read().unwrap().clone() else {
+                    println!("No capture uploaded yet, connection closed");
+                    continue;
+                };
+                let connected = activity.connect();
+                let region = activity.take_region();
+                if let Some((first, count)) = region {
+                    println!("Streaming frames {first}..{} in full detail", first + count);
+                }
+                let profile = profile.read().unwrap().clone();
+                let waiting = pool.submit(Box::new(move || {
+                    if let Err(msg) = handle_client(stream, &served.snapshots, &served.table, &served.options, &profile, region) {
                         println!("Client disconnected with error: {}", msg)
                     }
-                });
+                    drop(connected);
+                }));
+                if waiting > 0 {
+                    println!("All {} workers busy, {waiting} connections wait for one", pool.status().size);
+                }
             }
             Err(e) => {
                 println!("Network error: {}", e);
             }
         }
     }
-    drop(listener);
-}
\ No newline at end of file
+}
@@ -0,0 +1,62 @@
+use std::fs;
+use crate::color::warning;
+use crate::intern::SourceTable;
+use crate::snapshot::Snapshot;
+
+// Function and file strings kept in memory when they are read on demand
+pub const LAZY_STRING_CACHE: usize = 16 << 20;
+
+// How served captures are held, chosen by --max-memory from their size on disk
+#[derive(Clone, Copy, PartialEq)]
+pub enum MemoryMode {
+    // Whole files read into memory, sessions never touch disk
+    Preload,
+    // Files mapped, system keeps hot pages and drops the rest under pressure
+    Mapped,
+    // Function and file strings read on demand, events streamed from disk
+    Lazy,
+}
+
+fn megabytes(bytes: u64) -> String {
+    return format!("{:.1} MB", bytes as f64 / 1_000_000f64);
+}
+
+// Preloaded files and their source tables take about twice their size, mapped ones need their size for pages in use
+pub fn choose_mode(paths: &[String], budget: u64) -> Result<MemoryMode, String> {
+    let mut total = 0;
+    for path in paths {
+        total += fs::metadata(path).map_err(|e| format!("{path}: {e}"))?.len();
+    }
+    let (mode, description) = if total * 2 <= budget {
+        (MemoryMode::Preload, "preloading them")
+    } else if total <= budget {
+        (MemoryMode::Mapped, "mapping them")
+    } else {
+        (MemoryMode::Lazy, "reading strings and events from disk")
+    };
+    println!("Memory budget {} for {} of captures, {description}", megabytes(budget), megabytes(total));
+    if mode == MemoryMode::Lazy {
+        warning("captures don't fit into --max-memory, sessions will be slower and memory may still go over budget with many locations");
+    }
+    return Ok(mode);
+}
+
+pub fn source_table(mode: Option<MemoryMode>, lazy_strings: bool) -> SourceTable {
+    if lazy_strings || mode == Some(MemoryMode::Lazy) {
+        return SourceTable::with_lazy_strings(LAZY_STRING_CACHE);
+    }
+    return SourceTable::new();
+}
+
+// Failing to preload or map only costs speed, snapshot is read from disk as without budget
+pub fn hold_snapshot(snapshot: &mut Snapshot, mode: MemoryMode) {
+    let result = match mode {
+        MemoryMode::Preload => snapshot.preload(),
+        // Relies on captures never being truncated or rewritten while served, uploads and downloads are renamed into place
+        MemoryMode::Mapped => snapshot.map(),
+        MemoryMode::Lazy => Ok(()),
+    };
+    if let Err(msg) = result {
+        warning(&format!("{msg}, events are read from disk"));
+    }
+}
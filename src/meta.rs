@@ -0,0 +1,60 @@
+use std::fs;
+use serde_json::{Map, Value};
+
+// Keys of round metadata shown first with their labels, other keys follow as they are named
+const LABELS: [(&str, &str); 4] = [("round_id", "Round"), ("map", "Map"), ("players", "Players"), ("commit", "Commit")];
+
+// Round metadata from JSON object stored next to capture as <name>.meta.json,
+// e.g. {"round_id": 1234, "map": "Box Station", "players": 63, "commit": "4f2a9c1"}
+#[derive(Clone, Default)]
+pub struct RoundMeta {
+    // Known keys in LABELS order, then the rest by name, values as text
+    pub fields: Vec<(String, String)>,
+}
+
+impl RoundMeta {
+    pub fn parse(text: &str) -> Result<RoundMeta, String> {
+        let object: Map<String, Value> = serde_json::from_str(text).map_err(|e| format!("{e}"))?;
+        let mut fields: Vec<(String, String)> = object.into_iter().filter_map(|(key, value)| {
+            let text = match value {
+                Value::Null => return None,
+                Value::String(text) => text,
+                value => value.to_string(),
+            };
+            return Some((key, text));
+        }).collect();
+        fields.sort_by_key(|(key, _)| (LABELS.iter().position(|(known, _)| known == key).unwrap_or(LABELS.len()), key.clone()));
+        return Ok(RoundMeta { fields });
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        return self.fields.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str());
+    }
+
+    pub fn commit(&self) -> Option<&str> {
+        return self.get("commit");
+    }
+
+    // "Round: 1234", "Map: Box Station" ...
+    pub fn lines(&self) -> Vec<String> {
+        return self.fields.iter().map(|(key, value)| format!("{}: {value}", label(key))).collect();
+    }
+}
+
+pub fn label(key: &str) -> &str {
+    return LABELS.iter().find(|(known, _)| *known == key).map(|(_, label)| *label).unwrap_or(key);
+}
+
+// round.utracy -> round.meta.json
+pub fn meta_path(capture: &str) -> String {
+    return format!("{}.meta.json", capture.strip_suffix(".utracy").unwrap_or(capture));
+}
+
+// None when capture has no sidecar, broken sidecar is an error
+pub fn load_meta(capture: &str) -> Result<Option<RoundMeta>, String> {
+    let path = meta_path(capture);
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Ok(None);
+    };
+    return RoundMeta::parse(&text).map(Some).map_err(|msg| format!("{path}: {msg}"));
+}
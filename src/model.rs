@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+use crate::analysis::{walk_snapshot, Walk, Zone};
+use crate::intern::SourceTable;
+use crate::snapshot::Snapshot;
+use crate::structs::UTracyHeader;
+
+// Decoded snapshot for tools that should not depend on utracy layout: strings are resolved,
+// zone locations index `locations` of the same model, timestamps stay in snapshot ticks
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotModel {
+    pub header: Header,
+    pub locations: Vec<Location>,
+    pub zones: Vec<Zone>,
+    // Frame mark timestamps
+    pub frames: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Header {
+    pub program_name: String,
+    pub host_info: String,
+    pub cpu_manufacturer: String,
+    pub cpu_arch: u8,
+    pub cpu_id: u32,
+    // Nanoseconds per tick
+    pub multiplier: f64,
+    pub init_begin: u64,
+    pub init_end: u64,
+    pub delay: u64,
+    pub resolution: u64,
+    // Unix seconds of init_begin
+    pub epoch: u64,
+    pub exec_time: u64,
+    pub process_id: u64,
+    pub sampling_period: u64,
+    pub flags: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Location {
+    pub name: String,
+    pub function: String,
+    pub file: String,
+    pub line: u32,
+    pub color: [u8; 3],
+}
+
+fn text(bytes: &[u8]) -> String {
+    return String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string();
+}
+
+// Longer text is cut to fixed header field, last byte stays zero in NUL-terminated fields
+fn fill(field: &mut [u8], text: &str, terminated: bool) {
+    let size = text.len().min(field.len() - terminated as usize);
+    field[..size].copy_from_slice(&text.as_bytes()[..size]);
+}
+
+impl Header {
+    pub fn from_header(header: &UTracyHeader) -> Header {
+        return Header {
+            program_name: text(&header.program_name),
+            host_info: text(&header.host_info),
+            cpu_manufacturer: text(&header.cpu_manufacturer),
+            cpu_arch: header.cpu_arch,
+            cpu_id: header.cpu_id,
+            multiplier: header.multiplier,
+            init_begin: header.init_begin,
+            init_end: header.init_end,
+            delay: header.delay,
+            resolution: header.resolution,
+            epoch: header.epoch,
+            exec_time: header.exec_time,
+            process_id: header.process_id,
+            sampling_period: header.sampling_period,
+            flags: header.flags,
+        };
+    }
+
+    // Header for writer::create_snapshot, e.g. when tool produces new snapshot from edited model
+    pub fn to_header(&self) -> UTracyHeader {
+        let mut header = UTracyHeader::default();
+        header.multiplier = self.multiplier;
+        header.init_begin = self.init_begin;
+        header.init_end = self.init_end;
+        header.delay = self.delay;
+        header.resolution = self.resolution;
+        header.epoch = self.epoch;
+        header.exec_time = self.exec_time;
+        header.process_id = self.process_id;
+        header.sampling_period = self.sampling_period;
+        header.flags = self.flags;
+        header.cpu_arch = self.cpu_arch;
+        header.cpu_id = self.cpu_id;
+        fill(&mut header.program_name, &self.program_name, true);
+        fill(&mut header.host_info, &self.host_info, true);
+        // Vendor id as returned by cpuid, e.g. "GenuineIntel" fills all 12 bytes
+        fill(&mut header.cpu_manufacturer, &self.cpu_manufacturer, false);
+        return header;
+    }
+}
+
+impl SnapshotModel {
+    // Reads all zones into memory, meant for tools over small and medium captures
+    pub fn load(snapshot: &Snapshot, table: &SourceTable) -> Result<SnapshotModel, String> {
+        let string = |pointer: u64| table.strings.get(&pointer).cloned().unwrap_or_default();
+        let first = snapshot.location_offset as usize;
+        let last = first + snapshot.location_count as usize;
+        let locations = table.locations[first..last].iter().map(|location| Location {
+            name: string(location.name),
+            function: string(location.function),
+            file: string(location.file),
+            line: location.line,
+            color: [location.color_r, location.color_g, location.color_b],
+        }).collect();
+        let mut zones = Vec::new();
+        let mut frames = Vec::new();
+        walk_snapshot(snapshot, |walk| match walk {
+            Walk::Zone(zone) => zones.push(Zone { location: zone.location - snapshot.location_offset, ..zone }),
+            Walk::Frame { timestamp, .. } => frames.push(timestamp),
+            Walk::Begin { .. } => {}
+        })?;
+        return Ok(SnapshotModel { header: Header::from_header(&snapshot.header), locations, zones, frames });
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        return serde_json::to_string(self).map_err(|e| format!("{e}"));
+    }
+
+    pub fn from_json(json: &str) -> Result<SnapshotModel, String> {
+        let model: SnapshotModel = serde_json::from_str(json).map_err(|e| format!("{e}"))?;
+        if let Some(zone) = model.zones.iter().find(|zone| zone.location as usize >= model.locations.len()) {
+            return Err(format!("Zone location {} is out of {} locations", zone.location, model.locations.len()));
+        }
+        return Ok(model);
+    }
+}
@@ -0,0 +1,84 @@
+use std::cmp::Reverse;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::thread;
+use crate::analysis::{collect_frame_marks, collect_zone_stats, ticks_to_ns};
+use crate::audit::SessionAudit;
+use crate::clock::format_duration;
+use crate::intern::SourceTable;
+use crate::remote::agent;
+use crate::server::Served;
+use crate::snapshot::Snapshot;
+
+// Zones by self time listed in capture report
+const REPORT_ZONES: usize = 5;
+// Discord refuses longer messages
+const MAX_MESSAGE: usize = 2000;
+
+// Discord reads "content" and Slack reads "text", both ignore the other field. Zone and capture names may hold "@everyone" or role
+// mentions, Discord is told not to ping for any of them. Sent from own thread, a slow hook never holds a session
+fn post(webhooks: &[String], mut text: String) {
+    if webhooks.is_empty() {
+        return;
+    }
+    if text.len() > MAX_MESSAGE {
+        let mut end = MAX_MESSAGE - 3;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("...");
+    }
+    let body = serde_json::json!({ "content": text, "text": text, "allowed_mentions": { "parse": [] } }).to_string();
+    let webhooks = webhooks.to_vec();
+    thread::spawn(move || {
+        let agent = agent();
+        for url in webhooks {
+            if let Err(e) = agent.post(&url).set("Content-Type", "application/json").send_string(&body) {
+                println!("Webhook failed: {e}");
+            }
+        }
+    });
+}
+
+fn capture_report(name: &str, snapshot: &Snapshot, table: &SourceTable) -> Result<String, String> {
+    let marks = collect_frame_marks(snapshot)?;
+    let mut frames: Vec<u64> = marks.windows(2).map(|pair| ticks_to_ns(snapshot, pair[1] - pair[0])).collect();
+    frames.sort();
+    let mut text = format!("New capture **{name}** of {}", snapshot.program_name());
+    if let (Some(first), Some(last)) = (marks.first(), marks.last()) {
+        let mean = frames.iter().sum::<u64>() / frames.len().max(1) as u64;
+        let p99 = frames.get(((frames.len().saturating_sub(1)) as f64 * 0.99).round() as usize).copied().unwrap_or(0);
+        let _ = write!(text, ": {} frames over {}, mean frame {}, p99 {}, max {}", frames.len(), format_duration(ticks_to_ns(snapshot, last - first)),
+                       format_duration(mean), format_duration(p99), format_duration(frames.last().copied().unwrap_or(0)));
+    }
+    let mut stats = collect_zone_stats(snapshot, table)?;
+    stats.sort_by_key(|zone| Reverse(zone.self_total));
+    for zone in stats.iter().take(REPORT_ZONES) {
+        let _ = write!(text, "\n`{}` self {}, {} calls, mean {}, p99 {}", zone.name, format_duration(zone.self_total), zone.count(),
+                       format_duration(zone.mean()), format_duration(zone.percentile(99f64)));
+    }
+    return Ok(text);
+}
+
+// Stats pass over the whole capture runs on its own thread, upload is answered without waiting for it
+pub fn notify_capture(name: &str, served: Arc<Served>) {
+    if served.options.webhooks.is_empty() {
+        return;
+    }
+    let name = name.to_string();
+    thread::spawn(move || match capture_report(&name, &served.snapshots[0], &served.table) {
+        Ok(text) => post(&served.options.webhooks, text),
+        Err(msg) => println!("Webhook report of {name} failed: {msg}"),
+    });
+}
+
+pub fn notify_session(webhooks: &[String], audit: &SessionAudit) {
+    let mut text = format!("Session of {} finished after {}: {} frames, {:.1} MB sent, {} queries answered", audit.peer,
+                           format_duration(audit.duration_ms * 1_000_000), audit.frames, audit.bytes_sent as f64 / 1_000_000f64, audit.queries);
+    if let Some(profile) = &audit.profile {
+        let _ = write!(text, ", profile {profile}");
+    }
+    let _ = write!(text, "\nCaptures: {}", audit.captures.join(", "));
+    post(webhooks, text);
+}
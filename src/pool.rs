@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+pub const DEFAULT_WORKERS: usize = 64;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Fixed number of session threads, connections over it wait in queue holding only their socket instead of opening own snapshot reader
+pub struct WorkerPool {
+    size: usize,
+    state: Mutex<PoolState>,
+    queued: Condvar,
+}
+
+struct PoolState {
+    jobs: VecDeque<Job>,
+    busy: usize,
+    // Jobs that had to wait for a free worker
+    saturated: u64,
+}
+
+pub struct PoolStatus {
+    pub size: usize,
+    pub busy: usize,
+    pub queued: usize,
+    pub saturated: u64,
+}
+
+impl WorkerPool {
+    pub fn start(size: usize) -> &'static WorkerPool {
+        let pool: &'static WorkerPool = Box::leak(Box::new(WorkerPool {
+            size,
+            state: Mutex::new(PoolState { jobs: VecDeque::new(), busy: 0, saturated: 0 }),
+            queued: Condvar::new(),
+        }));
+        for _ in 0..size {
+            thread::spawn(move || pool.work());
+        }
+        return pool;
+    }
+
+    // Returns number of jobs waiting for worker with this one, 0 when it starts right away
+    pub fn submit(&self, job: Job) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.push_back(job);
+        let waiting = (state.busy + state.jobs.len()).saturating_sub(self.size);
+        if waiting > 0 {
+            state.saturated += 1;
+        }
+        self.queued.notify_one();
+        return waiting;
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        let state = self.state.lock().unwrap();
+        return PoolStatus { size: self.size, busy: state.busy, queued: state.jobs.len(), saturated: state.saturated };
+    }
+
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let Some(job) = state.jobs.pop_front() else {
+                state = self.queued.wait(state).unwrap();
+                continue;
+            };
+            state.busy += 1;
+            drop(state);
+            // Panicking session must not take its worker down with it
+            if let Err(payload) = catch_unwind(AssertUnwindSafe(job)) {
+                let cause = payload.downcast_ref::<&str>().copied().or_else(|| payload.downcast_ref::<String>().map(String::as_str));
+                println!("Session thread panicked: {}", cause.unwrap_or("unknown cause"));
+            }
+            state = self.state.lock().unwrap();
+            state.busy -= 1;
+        }
+    }
+}
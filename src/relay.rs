@@ -0,0 +1,639 @@
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::raw::c_int;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use lz4::block::{compress_bound, compress_to_buffer};
+use lz4::liblz4::{LZ4StreamDecode, LZ4_createStreamDecode, LZ4_decompress_safe_continue, LZ4_freeStreamDecode};
+use num_traits::FromPrimitive;
+use crate::acl::{AllowList, auth_prefix, parse_token};
+use crate::args::Arguments;
+use crate::bind_listener;
+use crate::failure::usage;
+use crate::server::{accept_handshake, MAX_BATCH_BYTES};
+use crate::structs::{describe_protocol, HandshakeStatus, QueryResponseType, ServerQueryType, HEADER_SIZE, PROTOCOL_VERSION, QUERY_SIZE};
+
+// Tracy clients compress frames of up to 256KB, decoder keeps three of them as LZ4 dictionary
+const TARGET_FRAME_SIZE: usize = 256 * 1024;
+const HEADER_FLAGS_OFFSET: usize = 72;
+// Client with on demand flag follows header with current frame and time
+const ON_DEMAND_SIZE: usize = 16;
+
+type Query = [u8; QUERY_SIZE];
+
+// Mirrors Tracy worker, frames are decoded into ring buffer so later frames may refer to earlier ones
+struct StreamDecoder {
+    stream: *mut LZ4StreamDecode,
+    ring: Vec<u8>,
+    offset: usize,
+}
+
+impl StreamDecoder {
+    fn new() -> StreamDecoder {
+        let stream = unsafe { LZ4_createStreamDecode() };
+        return StreamDecoder { stream, ring: vec![0u8; TARGET_FRAME_SIZE * 3], offset: 0 };
+    }
+
+    fn decode(&mut self, compressed: &[u8]) -> Result<&[u8], String> {
+        let size = unsafe {
+            LZ4_decompress_safe_continue(self.stream, compressed.as_ptr(), self.ring.as_mut_ptr().add(self.offset),
+                                         compressed.len() as c_int, TARGET_FRAME_SIZE as c_int)
+        };
+        if size < 0 {
+            return Err(format!("Malformed frame of {} bytes from upstream", compressed.len()));
+        }
+        let start = self.offset;
+        self.offset += size as usize;
+        if self.offset > TARGET_FRAME_SIZE * 2 {
+            self.offset = 0;
+        }
+        return Ok(&self.ring[start..start + size as usize]);
+    }
+}
+
+impl Drop for StreamDecoder {
+    fn drop(&mut self) {
+        unsafe { LZ4_freeStreamDecode(self.stream) };
+    }
+}
+
+// Size of message with its type byte and size of string length that follows, protocol 69 layouts
+fn message_layout(kind: u8) -> Option<(usize, usize)> {
+    use QueryResponseType::*;
+    let size = match QueryResponseType::from_u8(kind)? {
+        SingleStringData | SecondStringData => return Some((1, 2)),
+        StringData | ThreadName | PlotName | SourceLocationPayload | CallstackPayload | CallstackAllocPayload | FrameName
+        | ExternalName | ExternalThreadName | FiberName => return Some((9, 2)),
+        FrameImageData | SymbolCode | SourceCode => return Some((9, 4)),
+        ZoneText | ZoneName | CallstackSerial | Callstack | CallstackAlloc | ExternalNameMetadata | SymbolCodeMetadata
+        | SourceCodeMetadata | Terminate | KeepAlive | Crash | AckServerQueryNoop | AckSymbolCodeNotAvailable => 1,
+        GpuContextName => 2,
+        ZoneColor => 4,
+        LockName | ThreadContext | ZoneValidation | AckSourceCodeNotAvailable => 5,
+        Message | MessageCallstack | MessageAppInfo | ZoneBeginAllocSrcLoc | ZoneBeginAllocSrcLocCallstack | ZoneEnd | ZoneValue
+        | MemNamePayload | ThreadGroupHint => 9,
+        FrameImage | CallstackFrameSize => 10,
+        MessageColor | MessageColorCallstack | GpuTime => 12,
+        CallstackSample | CallstackSampleContextSwitch | LockRelease | SymbolInformation | FiberLeave | FrameVsync | LockTerminate
+        | SysTimeReport => 13,
+        ThreadWakeup | GpuZoneBeginAllocSrcLoc | GpuZoneBeginAllocSrcLocCallstack | GpuZoneEnd | GpuZoneBeginAllocSrcLocSerial
+        | GpuZoneBeginAllocSrcLocCallstackSerial | GpuZoneEndSerial | PlotConfig => 16,
+        ZoneBegin | ZoneBeginCallstack | LockWait | LockObtain | LockSharedWait | LockSharedObtain | LockSharedRelease | CrashReport
+        | FrameMarkMsg | FrameMarkMsgStart | FrameMarkMsgEnd | LockMark | MessageLiteral | MessageLiteralCallstack | CallstackFrame
+        | TidToPid | HwSampleCpuCycle | HwSampleInstructionRetired | HwSampleCacheReference | HwSampleCacheMiss | HwSampleBranchRetired
+        | HwSampleBranchMiss | CpuTopology => 17,
+        GpuTimeSync | ParamSetup => 18,
+        MessageLiteralColor | MessageLiteralColorCallstack => 20,
+        MemFree | MemFreeNamed | MemFreeCallstack | MemFreeCallstackNamed | PlotDataFloat => 21,
+        LockAnnounce => 22,
+        ContextSwitch => 23,
+        GpuZoneBegin | GpuZoneBeginCallstack | GpuZoneBeginSerial | GpuZoneBeginCallstackSerial => 24,
+        PlotDataInt | PlotDataDouble | FiberEnter | SysPowerReport => 25,
+        GpuCalibration => 26,
+        MemAlloc | MemAllocNamed | MemAllocCallstack | MemAllocCallstackNamed => 27,
+        GpuNewContext => 28,
+        SourceLocation => 32,
+        NumTypes => return None,
+    };
+    return Some((size, 0));
+}
+
+fn is_prefix(kind: u8) -> bool {
+    return kind == QueryResponseType::SingleStringData as u8 || kind == QueryResponseType::SecondStringData as u8;
+}
+
+fn is_response(kind: u8) -> bool {
+    use QueryResponseType::*;
+    return matches!(QueryResponseType::from_u8(kind), Some(StringData | ThreadName | PlotName | FrameName | FiberName | SourceLocation
+        | ExternalName | ExternalThreadName | CallstackFrameSize | CallstackFrame | SymbolInformation | SymbolCode | SourceCode
+        | AckServerQueryNoop | AckSourceCodeNotAvailable | AckSymbolCodeNotAvailable));
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    return u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+}
+
+// Message together with string payloads sent ahead of it
+struct Unit {
+    kind: u8,
+    bytes: Vec<u8>,
+    message: usize,
+}
+
+impl Unit {
+    // Pointer field right after type byte
+    fn pointer(&self) -> u64 {
+        return read_u64(&self.bytes, self.message + 1);
+    }
+}
+
+// String payloads may end up in previous frame than message consuming them, so they are carried over
+fn split_units(data: &[u8], carry: &mut Vec<u8>) -> Result<Vec<Unit>, String> {
+    let mut units = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let kind = data[offset];
+        let (fixed, length) = message_layout(kind).ok_or_else(|| format!("Unknown message type {kind} from upstream"))?;
+        let mut end = offset + fixed + length;
+        if end > data.len() {
+            return Err(format!("Message type {kind} crosses frame boundary"));
+        }
+        end += match length {
+            2 => u16::from_le_bytes([data[end - 2], data[end - 1]]) as usize,
+            4 => u32::from_le_bytes(data[end - 4..end].try_into().unwrap()) as usize,
+            _ => 0,
+        };
+        if end > data.len() {
+            return Err(format!("Message type {kind} crosses frame boundary"));
+        }
+        let message = carry.len();
+        carry.extend_from_slice(&data[offset..end]);
+        offset = end;
+        if !is_prefix(kind) {
+            units.push(Unit { kind, bytes: std::mem::take(carry), message });
+        }
+    }
+    return Ok(units);
+}
+
+// Whether response unit answers query, pointers are compared where response carries them
+fn answers(query: &Query, unit: &Unit) -> bool {
+    use QueryResponseType as R;
+    use ServerQueryType as Q;
+    let (Some(query_type), Some(kind)) = (ServerQueryType::from_u8(query[0]), QueryResponseType::from_u8(unit.kind)) else {
+        return false;
+    };
+    let pointer = read_u64(query, 1);
+    return match (query_type, kind) {
+        (Q::ServerQueryString, R::StringData) | (Q::ServerQueryThreadString, R::ThreadName) | (Q::ServerQueryPlotName, R::PlotName)
+        | (Q::ServerQueryFrameName, R::FrameName) | (Q::ServerQueryFiberName, R::FiberName) | (Q::ServerQueryExternalName, R::ExternalThreadName)
+        | (Q::ServerQueryCallstackFrame, R::CallstackFrameSize) | (Q::ServerQuerySymbolCode, R::SymbolCode)
+        | (Q::ServerQuerySourceCode, R::SourceCode) => unit.pointer() == pointer,
+        (Q::ServerQuerySymbol, R::SymbolInformation) => read_u64(&unit.bytes, unit.message + 5) == pointer,
+        (Q::ServerQuerySourceCode, R::AckSourceCodeNotAvailable) => unit.pointer() as u32 == pointer as u32,
+        (Q::ServerQuerySourceLocation, R::SourceLocation) | (Q::ServerQuerySymbolCode, R::AckSymbolCodeNotAvailable) => true,
+        (Q::ServerQueryParameter | Q::ServerQueryDataTransfer | Q::ServerQueryDataTransferPart | Q::ServerQuerySymbol, R::AckServerQueryNoop) => true,
+        _ => false,
+    };
+}
+
+struct Pending {
+    query: Query,
+    // Plain queries are answered from cache later, data transfers and parameters have side effects
+    cached: bool,
+    waiters: Vec<u32>,
+}
+
+// Response in progress, external name and callstack frame answers span several messages
+struct Partial {
+    pending: Pending,
+    answer: Vec<u8>,
+    // Remaining messages and their type
+    remaining: usize,
+    kind: u8,
+}
+
+struct State {
+    upstream: TcpStream,
+    // Decoded event messages, one chunk per upstream frame, replayed to every UI from the start
+    chunks: Vec<Arc<Vec<u8>>>,
+    ended: bool,
+    clients: usize,
+    next_client: u32,
+    cache: HashMap<Query, Arc<Vec<u8>>>,
+    pending: Vec<Pending>,
+    partial: Option<Partial>,
+    outbox: HashMap<u32, Vec<Arc<Vec<u8>>>>,
+    dropped: u64,
+}
+
+struct Relay {
+    header: Vec<u8>,
+    allow: Option<AllowList>,
+    auth_token: Option<String>,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl State {
+    fn deliver(&mut self, pending: Pending, answer: Vec<u8>) {
+        let answer = Arc::new(answer);
+        for waiter in &pending.waiters {
+            if let Some(queue) = self.outbox.get_mut(waiter) {
+                queue.push(answer.clone());
+            }
+        }
+        if pending.cached {
+            self.cache.insert(pending.query, answer);
+        }
+    }
+
+    fn route_response(&mut self, unit: Unit) {
+        if let Some(mut partial) = self.partial.take() {
+            if unit.kind == partial.kind {
+                partial.answer.extend_from_slice(&unit.bytes);
+                partial.remaining -= 1;
+                if partial.remaining == 0 {
+                    self.deliver(partial.pending, partial.answer);
+                } else {
+                    self.partial = Some(partial);
+                }
+                return;
+            }
+            println!("Incomplete answer to query type {} from upstream", partial.pending.query[0]);
+            self.deliver(partial.pending, partial.answer);
+        }
+        let Some(index) = self.pending.iter().position(|pending| answers(&pending.query, &unit)) else {
+            self.dropped += 1;
+            return;
+        };
+        let pending = self.pending.remove(index);
+        let (remaining, kind) = match QueryResponseType::from_u8(unit.kind) {
+            Some(QueryResponseType::ExternalThreadName) => (1, QueryResponseType::ExternalName as u8),
+            Some(QueryResponseType::CallstackFrameSize) => (unit.bytes[unit.message + 9] as usize, QueryResponseType::CallstackFrame as u8),
+            _ => (0, 0),
+        };
+        if remaining == 0 {
+            self.deliver(pending, unit.bytes);
+        } else {
+            self.partial = Some(Partial { pending, answer: unit.bytes, remaining, kind });
+        }
+    }
+}
+
+fn read_frame<R: Read>(reader: &mut R, compressed: &mut Vec<u8>) -> Result<bool, String> {
+    let mut size = [0u8; 4];
+    match reader.read_exact(&mut size) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+        Err(e) => return Err(format!("Upstream: {e}")),
+    }
+    compressed.resize(u32::from_le_bytes(size) as usize, 0);
+    reader.read_exact(compressed).map_err(|e| format!("Upstream: {e}"))?;
+    return Ok(true);
+}
+
+fn relay_upstream(relay: &Relay, mut reader: BufReader<TcpStream>) -> Result<(), String> {
+    let mut decoder = StreamDecoder::new();
+    let mut compressed = Vec::new();
+    let mut carry = Vec::new();
+    while read_frame(&mut reader, &mut compressed)? {
+        let units = split_units(decoder.decode(&compressed)?, &mut carry)?;
+        let mut events = Vec::new();
+        let mut state = relay.state.lock().unwrap();
+        for unit in units {
+            if is_response(unit.kind) {
+                state.route_response(unit);
+                continue;
+            }
+            // Client waits for UI to acknowledge termination before it closes connection
+            if unit.kind == QueryResponseType::Terminate as u8 {
+                let mut query = [0u8; QUERY_SIZE];
+                query[0] = ServerQueryType::ServerQueryTerminate as u8;
+                state.upstream.write_all(&query).map_err(|e| format!("Upstream: {e}"))?;
+            }
+            events.extend_from_slice(&unit.bytes);
+        }
+        if !events.is_empty() {
+            state.chunks.push(Arc::new(events));
+        }
+        drop(state);
+        relay.changed.notify_all();
+    }
+    return Ok(());
+}
+
+// Returns false when UI asks to close connection
+fn handle_query(relay: &Relay, client: u32, query: &Query, transfer: &mut Vec<u8>) -> Result<bool, String> {
+    let query_type = ServerQueryType::from_u8(query[0]).ok_or_else(|| format!("Unknown query type {}", query[0]))?;
+    match query_type {
+        ServerQueryType::ServerQueryTerminate | ServerQueryType::ServerQueryDisconnect => return Ok(false),
+        // Data of source code queries, sent upstream together with query so transfers of several UIs do not interleave
+        ServerQueryType::ServerQueryDataTransfer | ServerQueryType::ServerQueryDataTransferPart => {
+            transfer.extend_from_slice(query);
+            return Ok(true);
+        }
+        _ => {}
+    }
+    let cached = transfer.is_empty() && !matches!(query_type, ServerQueryType::ServerQueryParameter);
+    let mut state = relay.state.lock().unwrap();
+    if cached {
+        if let Some(answer) = state.cache.get(query).cloned() {
+            state.outbox.entry(client).or_default().push(answer);
+            return Ok(true);
+        }
+        if let Some(pending) = state.pending.iter_mut().find(|pending| pending.cached && pending.query == *query) {
+            pending.waiters.push(client);
+            return Ok(true);
+        }
+    }
+    // Every transfer part is acknowledged on its own
+    for part in transfer.chunks_exact(QUERY_SIZE) {
+        state.pending.push(Pending { query: part.try_into().unwrap(), cached: false, waiters: vec![client] });
+    }
+    state.pending.push(Pending { query: *query, cached, waiters: vec![client] });
+    transfer.extend_from_slice(query);
+    state.upstream.write_all(transfer).map_err(|e| format!("Upstream: {e}"))?;
+    transfer.clear();
+    return Ok(true);
+}
+
+fn write_frame(writer: &mut BufWriter<&TcpStream>, data: &[u8], compressed: &mut Vec<u8>) -> Result<(), String> {
+    let bound = compress_bound(data.len()).map_err(|e| format!("{}", e))?;
+    if compressed.len() < bound {
+        compressed.resize(bound, 0);
+    }
+    let size = compress_to_buffer(data, None, false, compressed).map_err(|e| format!("{}", e))?;
+    writer.write_all(&u32::to_le_bytes(size as u32)).map_err(|e| format!("{}", e))?;
+    writer.write_all(&compressed[..size]).map_err(|e| format!("{}", e))?;
+    return Ok(());
+}
+
+fn serve_client(relay: &Relay, stream: &TcpStream, client: u32) -> Result<(), String> {
+    let mut reader = BufReader::new(stream);
+    let mut writer = BufWriter::new(stream);
+    accept_handshake(&mut reader, &mut writer, relay.allow.as_ref(), relay.auth_token.as_deref())?;
+    writer.write_all(&relay.header).map_err(|e| format!("{}", e))?;
+    writer.flush().map_err(|e| format!("{}", e))?;
+
+    let mut sent = 0;
+    let mut query = [0u8; QUERY_SIZE];
+    let mut filled = 0;
+    let mut transfer = Vec::new();
+    let mut compressed = Vec::new();
+    loop {
+        stream.set_nonblocking(true).map_err(|e| format!("{}", e))?;
+        loop {
+            match reader.read(&mut query[filled..]) {
+                Ok(0) => return Ok(()),
+                Ok(size) => filled += size,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(format!("{}", e)),
+            }
+            if filled == QUERY_SIZE {
+                filled = 0;
+                if !handle_query(relay, client, &query, &mut transfer)? {
+                    return Ok(());
+                }
+            }
+        }
+        stream.set_nonblocking(false).map_err(|e| format!("{}", e))?;
+
+        let mut state = relay.state.lock().unwrap();
+        if sent == state.chunks.len() && state.outbox.get(&client).is_none_or(Vec::is_empty) && !state.ended {
+            state = relay.changed.wait_timeout(state, Duration::from_millis(10)).unwrap().0;
+        }
+        let chunks = state.chunks[sent..].to_vec();
+        let answers = state.outbox.get_mut(&client).map(std::mem::take).unwrap_or_default();
+        let finished = state.ended && sent + chunks.len() == state.chunks.len();
+        drop(state);
+
+        sent += chunks.len();
+        for chunk in chunks {
+            write_frame(&mut writer, &chunk, &mut compressed)?;
+        }
+        // Answers are batched, each one stays whole within single frame
+        let mut batch = Vec::new();
+        for answer in answers {
+            if !batch.is_empty() && batch.len() + answer.len() > MAX_BATCH_BYTES {
+                write_frame(&mut writer, &batch, &mut compressed)?;
+                batch.clear();
+            }
+            batch.extend_from_slice(&answer);
+        }
+        if !batch.is_empty() {
+            write_frame(&mut writer, &batch, &mut compressed)?;
+        }
+        writer.flush().map_err(|e| format!("{}", e))?;
+        if finished {
+            return Ok(());
+        }
+    }
+}
+
+fn accept_clients(relay: &'static Relay, listener: TcpListener) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("Network error: {}", e);
+                continue;
+            }
+        };
+        println!("New connection: {}", stream.peer_addr().map(|address| address.to_string()).unwrap_or_default());
+        let client = {
+            let mut state = relay.state.lock().unwrap();
+            state.clients += 1;
+            state.next_client += 1;
+            let client = state.next_client;
+            state.outbox.insert(client, Vec::new());
+            client
+        };
+        thread::spawn(move || {
+            if let Err(msg) = serve_client(relay, &stream, client) {
+                println!("Client disconnected with error: {}", msg)
+            }
+            let _ = stream.shutdown(Shutdown::Both);
+            let mut state = relay.state.lock().unwrap();
+            state.clients -= 1;
+            state.outbox.remove(&client);
+            drop(state);
+            relay.changed.notify_all();
+        });
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["-p", "--allow", "--auth-token", "--upstream-token"])?;
+    let Some(address) = arguments.positional.first() else {
+        return Err(usage("Use: relay host:port [-p port] [--allow address/prefix,...] [--auth-token secret] [--upstream-token secret]"));
+    };
+    let port: u16 = arguments.get_parsed("-p")?.unwrap_or(8086);
+    let allow = arguments.get("--allow").map(AllowList::parse).transpose()?;
+    let auth_token = arguments.get("--auth-token").map(parse_token).transpose()?;
+    let upstream_token = arguments.get("--upstream-token").map(parse_token).transpose()?;
+
+    let upstream = TcpStream::connect(address).map_err(|e| format!("Error connecting to {address}: {e}"))?;
+    let mut reader = BufReader::new(upstream.try_clone().map_err(|e| format!("{}", e))?);
+    let mut greeting = upstream_token.as_deref().map(auth_prefix).unwrap_or_default();
+    greeting.extend_from_slice(b"TracyPrf");
+    greeting.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    (&upstream).write_all(&greeting).map_err(|e| format!("Error connecting to {address}: {e}"))?;
+    let mut status = [0u8; 1];
+    reader.read_exact(&mut status).map_err(|e| format!("Error connecting to {address}: {e}"))?;
+    if status[0] == HandshakeStatus::HandshakeProtocolMismatch as u8 {
+        return Err(format!("{address} doesn't speak protocol {PROTOCOL_VERSION} of {}, only clients built with it can be relayed",
+                           describe_protocol(PROTOCOL_VERSION)));
+    }
+    if status[0] != HandshakeStatus::HandshakeWelcome as u8 {
+        return Err(format!("{address} refused connection with handshake status {}", status[0]));
+    }
+    let mut header = vec![0u8; HEADER_SIZE];
+    reader.read_exact(&mut header).map_err(|e| format!("Error reading header from {address}: {e}"))?;
+    if header[HEADER_FLAGS_OFFSET] & 1 != 0 {
+        header.resize(HEADER_SIZE + ON_DEMAND_SIZE, 0);
+        reader.read_exact(&mut header[HEADER_SIZE..]).map_err(|e| format!("Error reading header from {address}: {e}"))?;
+    }
+    println!("Connected to {address}");
+
+    let relay: &'static Relay = Box::leak(Box::new(Relay {
+        header,
+        allow,
+        auth_token,
+        state: Mutex::new(State {
+            upstream,
+            chunks: Vec::new(),
+            ended: false,
+            clients: 0,
+            next_client: 0,
+            cache: HashMap::new(),
+            pending: Vec::new(),
+            partial: None,
+            outbox: HashMap::new(),
+            dropped: 0,
+        }),
+        changed: Condvar::new(),
+    }));
+    let (listener, port) = bind_listener(port)?;
+    println!("Relay listening on port {port}");
+    thread::spawn(move || accept_clients(relay, listener));
+
+    let result = relay_upstream(relay, reader);
+    let mut state = relay.state.lock().unwrap();
+    state.ended = true;
+    relay.changed.notify_all();
+    match &result {
+        Ok(()) => println!("Upstream closed connection, {} event chunks captured", state.chunks.len()),
+        Err(msg) => println!("Upstream disconnected with error: {msg}"),
+    }
+    if state.dropped > 0 {
+        println!("Dropped {} unrequested answers from upstream", state.dropped);
+    }
+    // Connected UIs still get the rest of the stream
+    while state.clients > 0 {
+        state = relay.changed.wait(state).unwrap();
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use lz4::block::compress_bound;
+    use lz4::liblz4::{LZ4StreamEncode, LZ4_compress_continue, LZ4_createStream, LZ4_freeStream};
+    use super::{read_frame, StreamDecoder, TARGET_FRAME_SIZE};
+
+    // Compresses frames into ring buffer the way Tracy client does, so frames refer to earlier ones
+    struct StreamEncoder {
+        stream: *mut LZ4StreamEncode,
+        ring: Vec<u8>,
+        offset: usize,
+    }
+
+    impl StreamEncoder {
+        fn new() -> StreamEncoder {
+            return StreamEncoder { stream: unsafe { LZ4_createStream() }, ring: vec![0u8; TARGET_FRAME_SIZE * 3], offset: 0 };
+        }
+
+        // Frame with its u32 length prefix as sent on the wire
+        fn encode(&mut self, data: &[u8]) -> Vec<u8> {
+            self.ring[self.offset..self.offset + data.len()].copy_from_slice(data);
+            let mut compressed = vec![0u8; compress_bound(data.len()).unwrap()];
+            let size = unsafe {
+                LZ4_compress_continue(self.stream, self.ring.as_ptr().add(self.offset), compressed.as_mut_ptr(), data.len() as i32)
+            };
+            assert!(size > 0);
+            self.offset += data.len();
+            if self.offset > TARGET_FRAME_SIZE * 2 {
+                self.offset = 0;
+            }
+            let mut frame = (size as u32).to_le_bytes().to_vec();
+            frame.extend_from_slice(&compressed[..size as usize]);
+            return frame;
+        }
+    }
+
+    impl Drop for StreamEncoder {
+        fn drop(&mut self) {
+            unsafe { LZ4_freeStream(self.stream) };
+        }
+    }
+
+    // Socket that returns stream in pieces ending at given sorted offsets, the rest in one piece
+    struct Fragments {
+        data: Vec<u8>,
+        position: usize,
+        cuts: Vec<usize>,
+    }
+
+    impl Read for Fragments {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let next = self.cuts.partition_point(|cut| *cut <= self.position);
+            let end = self.cuts.get(next).copied().unwrap_or(self.data.len()).min(self.data.len());
+            let size = buf.len().min(end - self.position);
+            buf[..size].copy_from_slice(&self.data[self.position..self.position + size]);
+            self.position += size;
+            return Ok(size);
+        }
+    }
+
+    // Frames sharing most of their bytes with the previous one, within LZ4 window of 64KB, enough of them to wrap the ring buffer
+    fn frames() -> Vec<Vec<u8>> {
+        let mut seed = 0x2545F4914F6CDD1Du64;
+        let mut noise = |len: usize| (0..len).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            return seed as u8;
+        }).collect::<Vec<u8>>();
+        let base = noise(40_000);
+        return (0..24).map(|index| {
+            let mut frame = base[index * 1000..].to_vec();
+            frame.extend_from_slice(&base[..index * 1000]);
+            frame.extend(noise(8000));
+            return frame;
+        }).collect();
+    }
+
+    fn decode_stream(cuts: impl Fn(&[usize]) -> Vec<usize>) {
+        let frames = frames();
+        let mut encoder = StreamEncoder::new();
+        let encoded: Vec<Vec<u8>> = frames.iter().map(|frame| encoder.encode(frame)).collect();
+        // Decoding later frames needs earlier ones as dictionary
+        assert!(encoded[1].len() < frames[1].len() / 2);
+        // Offsets where each encoded frame starts in the stream
+        let starts: Vec<usize> = encoded.iter().scan(0, |offset, frame| {
+            let start = *offset;
+            *offset += frame.len();
+            return Some(start);
+        }).collect();
+        let mut reader = Fragments { data: encoded.concat(), position: 0, cuts: cuts(&starts) };
+
+        let mut decoder = StreamDecoder::new();
+        let mut compressed = Vec::new();
+        let mut decoded = Vec::new();
+        while read_frame(&mut reader, &mut compressed).unwrap() {
+            decoded.push(decoder.decode(&compressed).unwrap().to_vec());
+        }
+        assert_eq!(decoded, frames);
+    }
+
+    #[test]
+    fn decodes_frames_split_inside_length_prefix() {
+        decode_stream(|starts| starts.iter().flat_map(|start| [start + 1, start + 3]).collect());
+    }
+
+    #[test]
+    fn decodes_frames_split_inside_block_header() {
+        // First bytes of LZ4 block are token and literal length of its first sequence
+        decode_stream(|starts| starts.iter().flat_map(|start| [start + 4, start + 5, start + 6]).collect());
+    }
+
+    #[test]
+    fn decodes_frames_one_byte_at_a_time() {
+        decode_stream(|starts| (1..*starts.last().unwrap() + TARGET_FRAME_SIZE * 2).collect());
+    }
+}
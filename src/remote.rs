@@ -0,0 +1,219 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac_sha256::Hash;
+use crate::s3::fetch_s3;
+
+pub const DEFAULT_CACHE: &str = "download-cache";
+
+// Attempts of one download, each one continues where the previous stopped
+const RETRIES: u32 = 5;
+
+// Object as announced before download. Validator is ETag or Last-Modified, empty when server sends neither
+pub struct Remote {
+    pub size: u64,
+    pub validator: String,
+    pub md5: Option<[u8; 16]>,
+    pub sha256: Option<[u8; 32]>,
+}
+
+pub fn agent() -> ureq::Agent {
+    return ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(10)).timeout_read(Duration::from_secs(60)).build();
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+}
+
+pub fn parse_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let text = text.trim();
+    if text.len() != N * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    return Some(bytes);
+}
+
+pub fn parse_base64<const N: usize>(text: &str) -> Option<[u8; N]> {
+    return BASE64.decode(text.trim()).ok()?.try_into().ok();
+}
+
+fn sidecar(path: &Path, extension: &str) -> PathBuf {
+    return PathBuf::from(format!("{}.{extension}", path.display()));
+}
+
+// Cached file at <cache>/<kind>/<namespace>/<key>, keys can't climb out of it. Backslashes and colons are refused too,
+// on Windows they separate paths and start drive prefixes like "C:"
+pub fn cache_file(cache: &str, kind: &str, namespace: &str, key: &str) -> Result<PathBuf, String> {
+    let unsafe_part = |part: &str| part.is_empty() || part == "." || part == "..";
+    let unsafe_char = |c: char| c == '\\' || c == ':' || c.is_control();
+    if key.split('/').any(unsafe_part) || key.contains(unsafe_char) || unsafe_part(namespace) || namespace.contains(|c| c == '/' || unsafe_char(c)) {
+        return Err(format!("{namespace}/{key} can't be stored in download cache"));
+    }
+    return Ok(Path::new(cache).join(kind).join(namespace).join(key));
+}
+
+// Files get into cache only after verification, so matching size and validator is enough
+pub fn is_cached(path: &Path, remote: &Remote) -> bool {
+    if remote.validator.is_empty() {
+        return false;
+    }
+    return fs::metadata(path).is_ok_and(|metadata| metadata.len() == remote.size)
+        && fs::read_to_string(sidecar(path, "validator")).is_ok_and(|validator| validator == remote.validator);
+}
+
+// Err(true, ..) is worth another attempt, HTTP errors are not
+fn receive(request: ureq::Request, remote: &Remote, partial: &Path, offset: u64) -> Result<(), (bool, String)> {
+    let request = match offset {
+        0 => request,
+        // Server sends whole object again when it changed since partial file was started
+        _ => request.set("Range", &format!("bytes={offset}-")).set("If-Range", &remote.validator),
+    };
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) => return Err((false, format!("HTTP {status}"))),
+        Err(e) => return Err((true, format!("{e}"))),
+    };
+    let resumed = response.status() == 206;
+    let file = if resumed { OpenOptions::new().append(true).open(partial) } else { File::create(partial) };
+    let mut file = file.map_err(|e| (false, format!("{}: {e}", partial.display())))?;
+    io::copy(&mut response.into_reader(), &mut file).map_err(|e| (true, format!("{e}")))?;
+    return Ok(());
+}
+
+fn verify(path: &Path, remote: &Remote) -> Result<&'static str, String> {
+    if remote.md5.is_none() && remote.sha256.is_none() {
+        return Ok("no checksum published");
+    }
+    let mut file = File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut md5 = md5::Context::new();
+    let mut sha256 = Hash::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer).map_err(|e| format!("{}: {e}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        if remote.md5.is_some() {
+            md5.consume(&buffer[..read]);
+        }
+        if remote.sha256.is_some() {
+            sha256.update(&buffer[..read]);
+        }
+    }
+    if let Some(expected) = remote.sha256 {
+        let got = sha256.finalize();
+        if got != expected {
+            return Err(format!("SHA-256 mismatch, expected {} got {}", hex(&expected), hex(&got)));
+        }
+        return Ok("SHA-256 verified");
+    }
+    if let Some(expected) = remote.md5 {
+        let got = md5.finalize().0;
+        if got != expected {
+            return Err(format!("MD5 mismatch, expected {} got {}", hex(&expected), hex(&got)));
+        }
+    }
+    return Ok("MD5 verified");
+}
+
+// Downloads into <path>.part, resuming partial file of the same object version left by interrupted run,
+// verifies checksum and only then moves file into place
+pub fn download(what: &str, remote: &Remote, path: &Path, get: &dyn Fn() -> ureq::Request) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("{}: {e}", parent.display()))?;
+    }
+    let partial = sidecar(path, "part");
+    let partial_validator = sidecar(path, "part.validator");
+    let resumable = !remote.validator.is_empty() && fs::read_to_string(&partial_validator).is_ok_and(|validator| validator == remote.validator);
+    let mut received = if resumable { fs::metadata(&partial).map(|metadata| metadata.len()).unwrap_or(0).min(remote.size) } else { 0 };
+    if received == 0 {
+        File::create(&partial).map_err(|e| format!("{}: {e}", partial.display()))?;
+    } else {
+        println!("Resuming {what} at {received} of {} bytes", remote.size);
+    }
+    fs::write(&partial_validator, &remote.validator).map_err(|e| format!("{}: {e}", partial_validator.display()))?;
+
+    let mut attempt = 0;
+    while received < remote.size {
+        attempt += 1;
+        let result = receive(get(), remote, &partial, received);
+        received = fs::metadata(&partial).map(|metadata| metadata.len()).unwrap_or(0);
+        let msg = match result {
+            Ok(()) if received >= remote.size => break,
+            Ok(()) => "connection closed early".to_string(),
+            Err((false, msg)) => return Err(format!("Downloading {what}: {msg}")),
+            Err((true, msg)) => msg,
+        };
+        if attempt == RETRIES {
+            return Err(format!("Downloading {what}: {msg}, gave up after {RETRIES} attempts at {received} of {} bytes, next run resumes", remote.size));
+        }
+        println!("Download of {what} interrupted at {received} of {} bytes: {msg}, retrying", remote.size);
+        thread::sleep(Duration::from_secs(attempt as u64));
+    }
+
+    if received != remote.size {
+        let _ = fs::remove_file(&partial);
+        return Err(format!("Downloading {what}: got {received} bytes, expected {}", remote.size));
+    }
+    // Corrupted file can't be resumed, next run starts over
+    let verified = verify(&partial, remote).inspect_err(|_| {
+        let _ = fs::remove_file(&partial);
+        let _ = fs::remove_file(&partial_validator);
+    }).map_err(|msg| format!("Downloading {what}: {msg}"))?;
+    fs::rename(&partial, path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let _ = fs::remove_file(&partial_validator);
+    let validator = sidecar(path, "validator");
+    fs::write(&validator, &remote.validator).map_err(|e| format!("{}: {e}", validator.display()))?;
+    println!("Downloaded {what}, {verified}");
+    return Ok(());
+}
+
+// Plain HTTP(S) capture, checksum comes from Content-MD5 header or <url>.sha256 file next to it
+fn fetch_http(url: &str, cache: &str) -> Result<String, String> {
+    let agent = agent();
+    let address = url.split(['?', '#']).next().unwrap_or(url);
+    let Some((host, key)) = address.split_once("://").and_then(|(_, rest)| rest.split_once('/')) else {
+        return Err(format!("Wrong URL {url}, expected http(s)://host/path"));
+    };
+    let response = agent.head(url).call().map_err(|e| format!("{url}: {e}"))?;
+    let Some(size) = response.header("Content-Length").and_then(|size| size.parse().ok()) else {
+        return Err(format!("{url}: server doesn't send Content-Length"));
+    };
+    // Weak ETag can't be used in If-Range
+    let validator = response.header("ETag").filter(|etag| !etag.starts_with("W/")).or(response.header("Last-Modified")).unwrap_or("").to_string();
+    let md5 = response.header("Content-MD5").and_then(parse_base64);
+    let sha256 = agent.get(&format!("{address}.sha256")).call().ok().and_then(|response| response.into_string().ok())
+        .and_then(|text| text.split_whitespace().next().and_then(parse_hex));
+    let remote = Remote { size, validator, md5, sha256 };
+
+    // Port and IPv6 colons can't be in a directory name on Windows, "host:8087" is cached under "host_8087"
+    let host_port = host.trim_start_matches('[').replace([':', ']'], "_");
+    let path = cache_file(cache, "http", &host_port, key)?;
+    if is_cached(&path, &remote) {
+        println!("Using cached {url}");
+    } else {
+        download(url, &remote, &path, &|| agent.get(url))?;
+    }
+    return Ok(path.to_string_lossy().to_string());
+}
+
+// S3 and HTTP URLs among served paths are replaced by cached copies, local paths stay
+pub fn resolve_paths(paths: &[String], cache: &str, s3_endpoint: Option<&str>) -> Result<Vec<String>, String> {
+    return paths.iter().map(|path| {
+        if path.starts_with("s3://") {
+            return fetch_s3(path, cache, s3_endpoint);
+        }
+        if path.starts_with("http://") || path.starts_with("https://") {
+            return fetch_http(path, cache);
+        }
+        return Ok(path.clone());
+    }).collect();
+}
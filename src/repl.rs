@@ -0,0 +1,192 @@
+use std::cmp::Reverse;
+use std::io::{stdin, stdout, BufRead, Write};
+use crate::analysis::Zone;
+use crate::args::Arguments;
+use crate::clock::{format_count, format_duration, format_utc_ns, parse_utc};
+use crate::failure::usage;
+use crate::hist::{histogram, print_histogram};
+use crate::intern::SourceTable;
+use crate::model::SnapshotModel;
+use crate::search::ZoneIndex;
+use crate::snapshot::{load_snapshot, Snapshot};
+
+const HELP: &str = "Commands:
+  threads                         Zone count and busy time of every thread
+  top [n] [total|self|count]      Hottest zones
+  frame <n>                       Zones of frame n
+  zone <name> [hist|list]         Duration stats of zone, histogram or slowest occurrences
+  at <thread> <time>              Zones running on thread at UTC time, e.g. at 1 2024-05-01T20:31:05.120Z
+  help                            This list
+  quit                            Exit";
+
+// Snapshot is decoded once, every command works on model and index in memory
+struct Repl {
+    snapshot: Snapshot,
+    model: SnapshotModel,
+    index: ZoneIndex,
+}
+
+impl Repl {
+    fn ns(&self, ticks: u64) -> u64 {
+        return (ticks as f64 * self.model.header.multiplier) as u64;
+    }
+
+    fn name(&self, zone: &Zone) -> &str {
+        return &self.model.locations[zone.location as usize].name;
+    }
+
+    fn threads(&self) {
+        println!("{:>8} {:>10} {:>12}", "Thread", "Zones", "Busy");
+        for thread_id in self.index.threads() {
+            let mut count = 0;
+            let mut busy = 0;
+            for zone in self.index.zones(thread_id) {
+                count += 1;
+                if zone.depth == 0 {
+                    busy += zone.duration();
+                }
+            }
+            println!("{thread_id:>8} {count:>10} {:>12}", format_duration(self.ns(busy)));
+        }
+    }
+
+    fn top(&self, count: usize, by: &str) -> Result<(), String> {
+        // Count, total and self ticks per location
+        let mut stats = vec![(0u64, 0u64, 0u64); self.model.locations.len()];
+        for zone in &self.model.zones {
+            let entry = &mut stats[zone.location as usize];
+            entry.0 += 1;
+            entry.1 += zone.duration();
+            entry.2 += zone.self_time();
+        }
+        let mut order: Vec<usize> = (0..stats.len()).filter(|location| stats[*location].0 > 0).collect();
+        match by {
+            "total" => order.sort_by_key(|location| Reverse(stats[*location].1)),
+            "self" => order.sort_by_key(|location| Reverse(stats[*location].2)),
+            "count" => order.sort_by_key(|location| Reverse(stats[*location].0)),
+            _ => return Err(usage(format!("Wrong input: top {by}, expected self, total or count"))),
+        }
+        println!("{:>10} {:>12} {:>12} {:>12}  Zone", "Count", "Total", "Self", "Mean");
+        for location in order.into_iter().take(count) {
+            let (calls, total, self_time) = stats[location];
+            println!("{:>10} {:>12} {:>12} {:>12}  {}", format_count(calls), format_duration(self.ns(total)), format_duration(self.ns(self_time)),
+                     format_duration(self.ns(total / calls)), self.model.locations[location].name);
+        }
+        return Ok(());
+    }
+
+    fn frame(&self, index: usize) -> Result<(), String> {
+        // Frame N spans from mark N - 1 to mark N
+        let (Some(start), Some(end)) = (index.checked_sub(1).and_then(|first| self.model.frames.get(first)), self.model.frames.get(index)) else {
+            return Err(format!("Frame {index} not found, snapshot has frames 1..{}", self.model.frames.len().saturating_sub(1)));
+        };
+        println!("Frame {index} at {}: {}", format_utc_ns(self.snapshot.wall_clock_ns(*start)), format_duration(self.ns(end - start)));
+        for thread_id in self.index.threads() {
+            let mut zones = self.index.overlapping(thread_id, *start, *end);
+            if zones.is_empty() {
+                continue;
+            }
+            zones.sort_by_key(|zone| (zone.start, zone.depth));
+            println!("Thread {thread_id}");
+            for zone in zones {
+                println!("{:>12} {:>12}  {}{}", format_duration(self.ns(zone.start.saturating_sub(*start))), format_duration(self.ns(zone.duration())),
+                         "  ".repeat(zone.depth as usize), self.name(zone));
+            }
+        }
+        return Ok(());
+    }
+
+    fn zone(&self, name: &str, mode: Option<&str>) -> Result<(), String> {
+        let mut zones: Vec<&Zone> = self.model.zones.iter().filter(|zone| self.name(zone) == name).collect();
+        if zones.is_empty() {
+            return Err(format!("Zone \"{name}\" not found in snapshot"));
+        }
+        let mut durations: Vec<u64> = zones.iter().map(|zone| self.ns(zone.duration())).collect();
+        match mode {
+            None => {
+                durations.sort();
+                let percentile = |p: f64| durations[((durations.len() - 1) as f64 * p / 100f64).round() as usize];
+                let mean = durations.iter().sum::<u64>() / durations.len() as u64;
+                println!("{name}: {} calls, mean {}, p50 {}, p99 {}, max {}", format_count(durations.len() as u64), format_duration(mean),
+                         format_duration(percentile(50f64)), format_duration(percentile(99f64)), format_duration(percentile(100f64)));
+            }
+            Some("hist") => print_histogram(name, &durations, &histogram(&durations, 20, true)),
+            Some("list") => {
+                zones.sort_by_key(|zone| Reverse(zone.duration()));
+                println!("{:>8} {:>8} {:>27} {:>12}", "Thread", "Frame", "Time", "Duration");
+                for zone in zones.iter().take(10) {
+                    println!("{:>8} {:>8} {:>27} {:>12}", zone.thread_id, zone.frame, format_utc_ns(self.snapshot.wall_clock_ns(zone.start)),
+                             format_duration(self.ns(zone.duration())));
+                }
+            }
+            Some(mode) => return Err(usage(format!("Wrong input: zone {name} {mode}, expected hist or list"))),
+        }
+        return Ok(());
+    }
+
+    fn at(&self, thread_id: u32, unix_ns: u64) {
+        let timestamp = self.snapshot.ticks_at_wall_clock(unix_ns);
+        let stack = self.index.at(thread_id, timestamp);
+        if stack.is_empty() {
+            println!("Nothing was running on thread {thread_id} at {}", format_utc_ns(unix_ns));
+            return;
+        }
+        for zone in stack {
+            println!("{:>12} {:>12}  {}{}", format_duration(self.ns(timestamp.saturating_sub(zone.start))), format_duration(self.ns(zone.duration())),
+                     "  ".repeat(zone.depth as usize), self.name(zone));
+        }
+    }
+
+    // Returns false on quit
+    fn execute(&self, line: &str) -> Result<bool, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let parse_number = |word: &str| word.parse::<usize>().map_err(|_| usage(format!("Wrong input: {word}, expected number")));
+        match words.as_slice() {
+            [] => {}
+            ["quit" | "exit"] => return Ok(false),
+            ["help"] => println!("{HELP}"),
+            ["threads"] => self.threads(),
+            ["top"] => self.top(20, "total")?,
+            ["top", count] => self.top(parse_number(count)?, "total")?,
+            ["top", count, by] => self.top(parse_number(count)?, by)?,
+            ["frame", index] => self.frame(parse_number(index)?)?,
+            ["zone", name @ .., mode @ ("hist" | "list")] if !name.is_empty() => self.zone(&name.join(" "), Some(mode))?,
+            ["zone", name @ ..] if !name.is_empty() => self.zone(&name.join(" "), None)?,
+            ["at", thread, time] => self.at(thread.parse().map_err(|_| usage(format!("Wrong input: {thread}, expected thread id")))?, parse_utc(time)?),
+            _ => return Err(format!("Unknown command \"{line}\", type help for list of commands")),
+        }
+        return Ok(true);
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&[])?;
+    let Some(path) = arguments.positional.first() else {
+        return Err(usage("Use: repl <file>"));
+    };
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    let model = SnapshotModel::load(&snapshot, &table)?;
+    // Model zones use locations of the model, so index is built from them
+    let index = ZoneIndex::build(model.zones.iter().copied());
+    let threads = index.threads().len();
+    let repl = Repl { snapshot, model, index };
+    println!("{}: {} zones on {threads} threads, {} frames. Type help for commands", path, repl.model.zones.len(), repl.model.frames.len().saturating_sub(1));
+
+    let mut lines = stdin().lock().lines();
+    loop {
+        print!("rtracy> ");
+        stdout().flush().map_err(|e| format!("{e}"))?;
+        let Some(line) = lines.next() else {
+            println!();
+            return Ok(());
+        };
+        let line = line.map_err(|e| format!("{e}"))?;
+        match repl.execute(line.trim()) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(msg) => println!("{msg}"),
+        }
+    }
+}
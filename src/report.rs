@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{stdout, Write};
+use crate::analysis::{collect_frame_marks, collect_zone_stats_with, ticks_to_ns, StatsOptions};
+use crate::args::Arguments;
+use crate::clock::{format_count, format_duration, format_utc, format_utc_ns};
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::meta::{label, load_meta};
+use crate::export::find_exporter;
+use crate::snapshot::{load_snapshot, Snapshot};
+use crate::spikes::{spike_limit, spike_zones};
+
+const TOP_ZONES: usize = 15;
+const TOP_SPIKES: usize = 10;
+const SPIKE_ZONES: usize = 3;
+
+// Zone names may contain table separators
+fn cell(text: &str) -> String {
+    return text.replace('|', "\\|");
+}
+
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((p / 100f64) * sorted.len() as f64).ceil() as usize;
+    return sorted[rank.clamp(1, sorted.len()) - 1];
+}
+
+// Read by report-markdown exporter
+pub const REPORT_OPTIONS: [&str; 5] = ["--sigma", "--frames", "--trim", "--winsorize", "--groups"];
+
+pub fn markdown_report(snapshot: &Snapshot, table: &SourceTable, arguments: &Arguments) -> Result<String, String> {
+    let path = &snapshot.path;
+    let sigma: f64 = arguments.get_parsed("--sigma")?.unwrap_or(3f64);
+    let options = StatsOptions::from_arguments(arguments)?;
+    let marks = collect_frame_marks(snapshot)?;
+    let stats = collect_zone_stats_with(snapshot, table, &options)?;
+    let durations: Vec<u64> = marks.windows(2).map(|w| ticks_to_ns(snapshot, w[1].saturating_sub(w[0]))).collect();
+    let mut sorted = durations.clone();
+    sorted.sort_unstable();
+
+    // Writing to String never fails
+    let mut out = String::new();
+    let file_name = std::path::Path::new(path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or(path.clone());
+    let _ = writeln!(out, "## Performance report: {}\n", cell(snapshot.program_name()));
+    let _ = writeln!(out, "| | |\n|---|---|");
+    let _ = writeln!(out, "| Capture | `{}` |", cell(&file_name));
+    let _ = writeln!(out, "| Captured | {} |", format_utc(snapshot.header.epoch));
+    let _ = writeln!(out, "| Process id | {} |", snapshot.header.process_id);
+    let host = String::from_utf8_lossy(&snapshot.header.host_info).trim_end_matches('\0').lines().next().unwrap_or("").to_string();
+    if !host.is_empty() {
+        let _ = writeln!(out, "| Host | {} |", cell(&host));
+    }
+    if let Some(meta) = load_meta(path)? {
+        for (key, value) in &meta.fields {
+            let _ = writeln!(out, "| {} | {} |", cell(label(key)), cell(value));
+        }
+    }
+    let _ = writeln!(out, "| Frames | {} |", format_count(durations.len() as u64));
+    if let (Some(first), Some(last)) = (marks.first(), marks.last()) {
+        let _ = writeln!(out, "| Duration | {} |", format_duration(ticks_to_ns(snapshot, last - first)));
+    }
+    if let Some((first, end)) = options.frames {
+        let end = if end == u32::MAX { "end".to_string() } else { end.to_string() };
+        let _ = writeln!(out, "| Zone stats of frames | {first}..{end} |");
+    }
+
+    if !sorted.is_empty() {
+        let _ = writeln!(out, "\n### Frame time\n");
+        let _ = writeln!(out, "| Mean | P50 | P90 | P99 | Max |\n|---:|---:|---:|---:|---:|");
+        let mean = sorted.iter().sum::<u64>() / sorted.len() as u64;
+        let _ = writeln!(out, "| {} | {} | {} | {} | {} |", format_duration(mean), format_duration(percentile(&sorted, 50f64)),
+                         format_duration(percentile(&sorted, 90f64)), format_duration(percentile(&sorted, 99f64)),
+                         format_duration(*sorted.last().unwrap_or(&0)));
+    }
+
+    let _ = writeln!(out, "\n### Top {TOP_ZONES} zones by total time\n");
+    let _ = writeln!(out, "| Zone | Count | Total | Self | Mean | P99 |\n|---|---:|---:|---:|---:|---:|");
+    for zone in stats.iter().take(TOP_ZONES) {
+        let _ = writeln!(out, "| `{}` | {} | {} | {} | {} | {} |", cell(&zone.name), format_count(zone.count() as u64), format_duration(zone.total),
+                         format_duration(zone.self_total), format_duration(zone.mean()), format_duration(zone.percentile(99f64)));
+    }
+
+    // Frame N ends with mark N, durations[N - 1] is its length
+    let (frame_median, limit) = spike_limit(&durations, sigma);
+    let mut spikes: Vec<(u32, u64)> = durations.iter().enumerate()
+        .filter(|(_, duration)| **duration > limit)
+        .map(|(i, duration)| (i as u32 + 1, *duration))
+        .collect();
+    let spike_count = spikes.len();
+    spikes.sort_by_key(|(_, duration)| std::cmp::Reverse(*duration));
+    spikes.truncate(TOP_SPIKES);
+    let _ = writeln!(out, "\n### Spikes\n");
+    if spikes.is_empty() {
+        let _ = writeln!(out, "No frames above {} ({sigma} deviations over median {}).", format_duration(limit), format_duration(frame_median));
+    } else {
+        let _ = writeln!(out, "{spike_count} frames above {} ({sigma} deviations over median {}), slowest {}:\n", format_duration(limit),
+                         format_duration(frame_median), spikes.len());
+        let contributions = spike_zones(snapshot, table, &spikes.iter().copied().collect::<HashMap<u32, u64>>())?;
+        let _ = writeln!(out, "| Frame | Time | Duration | Heaviest zones |\n|---:|---|---:|---|");
+        for (frame, duration) in &spikes {
+            let zones: Vec<String> = contributions.get(frame).into_iter().flatten().take(SPIKE_ZONES).map(|(name, time)| {
+                return format!("`{}` {}", cell(table.strings.get(name).map(String::as_str).unwrap_or("Unkn")), format_duration(*time));
+            }).collect();
+            let start = marks[*frame as usize - 1];
+            let _ = writeln!(out, "| {frame} | {} | {} | {} |", format_utc_ns(snapshot.wall_clock_ns(start)), format_duration(*duration), zones.join(", "));
+        }
+    }
+
+    return Ok(out);
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--format", "-o", "--sigma", "--frames", "--trim", "--winsorize", "--groups"])?;
+    let Some(path) = arguments.positional.first() else {
+        return Err(usage("Use: report <file> [--format markdown] [-o output] [--sigma n] [stats options]"));
+    };
+    let format = arguments.get("--format").unwrap_or("markdown");
+    if format != "markdown" {
+        return Err(usage(format!("Wrong input: --format {format}, expected markdown")));
+    }
+    let exporter = find_exporter(&format!("report-{format}"))?;
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    let mut out = Vec::new();
+    exporter.export(&snapshot, &table, &arguments, &mut out)?;
+    match arguments.get("-o") {
+        Some(output) => {
+            fs::write(output, &out).map_err(|e| format!("{output}: {e}"))?;
+            println!("Report written to {output}");
+        }
+        None => stdout().write_all(&out).map_err(|e| format!("{e}"))?,
+    }
+    return Ok(());
+}
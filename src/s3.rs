@@ -0,0 +1,239 @@
+use std::env;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+use hmac_sha256::{Hash, HMAC};
+use regex::Regex;
+use crate::args::Arguments;
+use crate::clock::format_amz_date;
+use crate::failure::usage;
+use crate::remote::{agent, cache_file, download, hex, is_cached, parse_base64, parse_hex, Remote, DEFAULT_CACHE};
+
+// Hash of empty body, requests here never send one
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+// "s3://bucket/key", key ending with '/' or empty is a prefix
+pub struct S3Url {
+    pub bucket: String,
+    pub key: String,
+}
+
+impl S3Url {
+    pub fn parse(url: &str) -> Option<S3Url> {
+        let rest = url.strip_prefix("s3://")?;
+        let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return None;
+        }
+        return Some(S3Url { bucket: bucket.to_string(), key: key.to_string() });
+    }
+
+    pub fn is_prefix(&self) -> bool {
+        return self.key.is_empty() || self.key.ends_with('/');
+    }
+}
+
+pub struct S3Object {
+    pub key: String,
+    pub size: u64,
+    // ISO 8601 as sent by S3, compares in time order
+    pub modified: String,
+    pub etag: String,
+}
+
+// Path style requests signed with AWS Signature Version 4, credentials come from the usual AWS_* variables
+pub struct S3Client {
+    endpoint: String,
+    host: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+    agent: ureq::Agent,
+}
+
+// Unreserved characters stay, '/' stays in object paths only
+fn uri_encode(text: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    return encoded;
+}
+
+fn xml_unescape(text: &str) -> String {
+    return text.replace("&quot;", "\"").replace("&apos;", "'").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&");
+}
+
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    return Some(xml_unescape(&xml[start..end]));
+}
+
+fn env_value(names: &[&str]) -> Option<String> {
+    return names.iter().find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()));
+}
+
+impl S3Client {
+    // Endpoint option points to MinIO or other S3 compatible storage
+    pub fn from_env(endpoint: Option<&str>) -> Result<S3Client, String> {
+        let region = env_value(&["AWS_REGION", "AWS_DEFAULT_REGION"]).unwrap_or("us-east-1".into());
+        let (Some(access_key), Some(secret_key)) = (env_value(&["AWS_ACCESS_KEY_ID"]), env_value(&["AWS_SECRET_ACCESS_KEY"])) else {
+            return Err("S3 needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY".into());
+        };
+        let endpoint = endpoint.map(str::to_string).or(env_value(&["AWS_ENDPOINT_URL_S3", "AWS_ENDPOINT_URL"]))
+            .unwrap_or(format!("https://s3.{region}.amazonaws.com")).trim_end_matches('/').to_string();
+        let Some(host) = endpoint.split_once("://").map(|(_, rest)| rest.split('/').next().unwrap_or(rest).to_string()) else {
+            return Err(format!("Wrong S3 endpoint {endpoint}, expected http(s)://host[:port]"));
+        };
+        return Ok(S3Client { endpoint, host, region, access_key, secret_key, session_token: env_value(&["AWS_SESSION_TOKEN"]), agent: agent() });
+    }
+
+    // Extra headers are x-amz-* ones, S3 wants all of them signed
+    fn request(&self, method: &str, bucket: &str, key: &str, query: &[(&str, &str)], extra: &[(&'static str, &str)]) -> ureq::Request {
+        let mut path = format!("/{}", uri_encode(bucket, false));
+        if !key.is_empty() {
+            path = format!("{path}/{}", uri_encode(key, true));
+        }
+        let mut pairs: Vec<(String, String)> = query.iter().map(|(name, value)| (uri_encode(name, false), uri_encode(value, false))).collect();
+        pairs.sort();
+        let query = pairs.iter().map(|(name, value)| format!("{name}={value}")).collect::<Vec<_>>().join("&");
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        let amz_date = format_amz_date(now);
+        let date = &amz_date[..8];
+        let mut headers = vec![("host", self.host.as_str()), ("x-amz-content-sha256", EMPTY_SHA256), ("x-amz-date", amz_date.as_str())];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token));
+        }
+        headers.extend_from_slice(extra);
+        headers.sort();
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{name}:{}\n", value.trim())).collect();
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical = format!("{method}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{EMPTY_SHA256}");
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}", hex(&Hash::hash(canonical.as_bytes())));
+        let mut key = HMAC::mac(date, format!("AWS4{}", self.secret_key));
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = HMAC::mac(part, key);
+        }
+        let signature = hex(&HMAC::mac(string_to_sign, key));
+
+        let url = if query.is_empty() { format!("{}{path}", self.endpoint) } else { format!("{}{path}?{query}", self.endpoint) };
+        let mut request = self.agent.request(method, &url)
+            .set("Authorization", &format!("AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}", self.access_key));
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.set(name, value);
+        }
+        return request;
+    }
+
+    // S3 puts error code and message into XML body
+    fn call(&self, request: ureq::Request, what: &str) -> Result<ureq::Response, String> {
+        return match request.call() {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                match (xml_value(&body, "Code"), xml_value(&body, "Message")) {
+                    (Some(code), Some(message)) => Err(format!("S3 {what}: {status} {code}, {message}")),
+                    // HEAD responses have no body
+                    _ if status == 404 => Err(format!("S3 {what}: not found")),
+                    _ => Err(format!("S3 {what}: HTTP {status}")),
+                }
+            }
+            Err(e) => Err(format!("S3 {what}: {e}")),
+        };
+    }
+
+    pub fn list(&self, bucket: &str, prefix: &str) -> Result<Vec<S3Object>, String> {
+        let contents = Regex::new(r"(?s)<Contents>(.*?)</Contents>").unwrap();
+        let mut objects = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(token) = &continuation {
+                query.push(("continuation-token", token));
+            }
+            let response = self.call(self.request("GET", bucket, "", &query, &[]), &format!("listing s3://{bucket}/{prefix}"))?;
+            let body = response.into_string().map_err(|e| format!("{e}"))?;
+            for item in contents.captures_iter(&body) {
+                let item = &item[1];
+                objects.push(S3Object {
+                    key: xml_value(item, "Key").unwrap_or_default(),
+                    size: xml_value(item, "Size").and_then(|size| size.parse().ok()).unwrap_or(0),
+                    modified: xml_value(item, "LastModified").unwrap_or_default(),
+                    etag: xml_value(item, "ETag").unwrap_or_default(),
+                });
+            }
+            continuation = xml_value(&body, "NextContinuationToken");
+            if xml_value(&body, "IsTruncated").as_deref() != Some("true") || continuation.is_none() {
+                return Ok(objects);
+            }
+        }
+    }
+
+    // Single part uploads have MD5 of content as ETag unless encrypted with KMS, SHA-256 is known for objects uploaded with it
+    pub fn head(&self, bucket: &str, key: &str) -> Result<Remote, String> {
+        let request = self.request("HEAD", bucket, key, &[], &[("x-amz-checksum-mode", "ENABLED")]);
+        let response = self.call(request, &format!("s3://{bucket}/{key}"))?;
+        let etag = response.header("ETag").unwrap_or("").to_string();
+        let kms = response.header("x-amz-server-side-encryption").is_some_and(|encryption| encryption.starts_with("aws:kms"));
+        return Ok(Remote {
+            size: response.header("Content-Length").and_then(|size| size.parse().ok()).unwrap_or(0),
+            md5: if kms { None } else { parse_hex(etag.trim_matches('"')) },
+            sha256: response.header("x-amz-checksum-sha256").and_then(parse_base64),
+            validator: etag,
+        });
+    }
+}
+
+// Local path of capture at S3 URL, downloaded on first use and again only when object changes.
+// Prefix URL resolves to its newest capture
+pub fn fetch_s3(url: &str, cache: &str, endpoint: Option<&str>) -> Result<String, String> {
+    let Some(location) = S3Url::parse(url) else {
+        return Err(format!("Wrong S3 URL {url}, expected s3://bucket/key"));
+    };
+    let client = S3Client::from_env(endpoint)?;
+    let key = if location.is_prefix() {
+        client.list(&location.bucket, &location.key)?.into_iter().filter(|object| object.key.ends_with(".utracy"))
+            .max_by(|a, b| a.modified.cmp(&b.modified))
+            .ok_or(format!("No captures under {url}"))?.key
+    } else {
+        location.key
+    };
+    let remote = client.head(&location.bucket, &key)?;
+    let path = cache_file(cache, "s3", &location.bucket, &key)?;
+    let what = format!("s3://{}/{key}", location.bucket);
+    if is_cached(&path, &remote) {
+        println!("Using cached {what}");
+    } else {
+        download(&what, &remote, &path, &|| client.request("GET", &location.bucket, &key, &[], &[]))?;
+    }
+    return Ok(path.to_string_lossy().to_string());
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--s3-endpoint", "--download-cache"])?;
+    let Some(location) = arguments.positional.first().and_then(|url| S3Url::parse(url)) else {
+        return Err(usage("Use: s3 s3://bucket/prefix/ [--s3-endpoint url] [--download-cache dir]"));
+    };
+    let cache = arguments.get("--download-cache").unwrap_or(DEFAULT_CACHE);
+    let client = S3Client::from_env(arguments.get("--s3-endpoint"))?;
+    let mut objects = client.list(&location.bucket, &location.key)?;
+    objects.retain(|object| object.key.ends_with(".utracy"));
+    objects.sort_by(|a, b| a.modified.cmp(&b.modified));
+    println!("{:>12}  {:<24}  {:<6}  Key", "Size", "Modified", "Cached");
+    for object in &objects {
+        let cached = cache_file(cache, "s3", &location.bucket, &object.key).ok()
+            .and_then(|path| fs::read_to_string(format!("{}.validator", path.display())).ok())
+            .is_some_and(|validator| validator == object.etag);
+        println!("{:>12}  {:<24}  {:<6}  {}", object.size, object.modified, if cached { "yes" } else { "" }, object.key);
+    }
+    println!("{} captures", objects.len());
+    return Ok(());
+}
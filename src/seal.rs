@@ -0,0 +1,123 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use crate::args::Arguments;
+use crate::failure::{fail, open_failed, usage, ErrorKind};
+use crate::snapshot::FILE_SIGNATURE;
+use crate::structs::{BINCODE_CONFIG, UTracyHeader};
+
+pub const SEAL_MARKER: u32 = 0x6C616573;
+// Header offset of UTracyHeader::seal_marker
+const SEAL_MARKER_OFFSET: u64 = 12;
+// Trailer has the size of one event and starts with invalid event type, so readers stop on it
+const TRAILER_SIZE: u64 = 24;
+pub const TRAILER_TAG: [u8; 8] = [0xFF, b'r', b't', b's', b'e', b'a', b'l', 0];
+
+// Checksum covers header with seal marker set, so it can be computed before the marker is written
+fn checksum(file: &mut File, length: u64) -> Result<u32, String> {
+    file.seek(SeekFrom::Start(0)).map_err(|e| format!("{e}"))?;
+    let mut reader = BufReader::with_capacity(1 << 20, file.take(length));
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    let mut position = 0u64;
+    loop {
+        let read = reader.read(&mut buffer).map_err(|e| format!("{e}"))?;
+        if read == 0 {
+            break;
+        }
+        for (index, byte) in SEAL_MARKER.to_le_bytes().into_iter().enumerate() {
+            let offset = SEAL_MARKER_OFFSET + index as u64;
+            if (position..position + read as u64).contains(&offset) {
+                buffer[(offset - position) as usize] = byte;
+            }
+        }
+        hasher.update(&buffer[..read]);
+        position += read as u64;
+    }
+    return Ok(hasher.finalize());
+}
+
+// Length of snapshot data without trailer, None for snapshots which were never sealed
+pub fn sealed_length(path: &str, header: &UTracyHeader) -> Result<Option<u64>, String> {
+    if header.seal_marker != SEAL_MARKER {
+        return Ok(None);
+    }
+    let mut file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+    let (length, _) = read_trailer(&mut file)?.ok_or_else(|| fail(ErrorKind::Truncated, format!("{path} is sealed but checksum trailer is missing, file is truncated")))?;
+    return Ok(Some(length));
+}
+
+fn read_trailer(file: &mut File) -> Result<Option<(u64, u32)>, String> {
+    let file_length = file.seek(SeekFrom::End(0)).map_err(|e| format!("{e}"))?;
+    if file_length < TRAILER_SIZE {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(file_length - TRAILER_SIZE)).map_err(|e| format!("{e}"))?;
+    let mut trailer = [0u8; TRAILER_SIZE as usize];
+    file.read_exact(&mut trailer).map_err(|e| format!("{e}"))?;
+    if trailer[..8] != TRAILER_TAG {
+        return Ok(None);
+    }
+    let length = u64::from_le_bytes(trailer[8..16].try_into().unwrap());
+    let crc = u64::from_le_bytes(trailer[16..24].try_into().unwrap()) as u32;
+    if length != file_length - TRAILER_SIZE {
+        return Ok(None);
+    }
+    return Ok(Some((length, crc)));
+}
+
+// Called on every snapshot load, unsealed snapshots are accepted as is
+pub fn verify(path: &str, header: &UTracyHeader) -> Result<(), String> {
+    if header.seal_marker != SEAL_MARKER {
+        return Ok(());
+    }
+    let mut file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+    let Some((length, expected)) = read_trailer(&mut file).map_err(|e| format!("{path}: {e}"))? else {
+        return Err(fail(ErrorKind::Truncated, format!("{path} is sealed but checksum trailer is missing, file is truncated")));
+    };
+    println!("Verifying snapshot checksum");
+    let actual = checksum(&mut file, length).map_err(|e| format!("{path}: {e}"))?;
+    if actual != expected {
+        return Err(fail(ErrorKind::Corrupted, format!("{path} is corrupted, checksum mismatch (expected {expected:08x} got {actual:08x})")));
+    }
+    return Ok(());
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&[])?;
+    let Some(path) = arguments.positional.first() else {
+        return Err(usage("Use: seal <file>"));
+    };
+    let mut file = OpenOptions::new().read(true).write(true).open(path).map_err(|e| open_failed(path, e))?;
+    let header: UTracyHeader = bincode::decode_from_std_read(&mut file, BINCODE_CONFIG).map_err(|e| format!("{path}: {e}"))?;
+    if header.signature != FILE_SIGNATURE || header.version != 2 {
+        return Err(format!("{path} is not utracy v2 snapshot"));
+    }
+    if header.seal_marker == SEAL_MARKER {
+        verify(path, &header)?;
+        println!("{path} is already sealed, checksum is valid");
+        return Ok(());
+    }
+
+    // Trailer goes to disk before the marker, a crash in between leaves unsealed snapshot whose trailer is reused on next run
+    let crc = match read_trailer(&mut file).map_err(|e| format!("{path}: {e}"))? {
+        Some((length, crc)) if checksum(&mut file, length).map_err(|e| format!("{path}: {e}"))? == crc => crc,
+        _ => {
+            let length = file.seek(SeekFrom::End(0)).map_err(|e| format!("{path}: {e}"))?;
+            let crc = checksum(&mut file, length).map_err(|e| format!("{path}: {e}"))?;
+            let mut trailer = Vec::with_capacity(TRAILER_SIZE as usize);
+            trailer.extend_from_slice(&TRAILER_TAG);
+            trailer.extend_from_slice(&length.to_le_bytes());
+            trailer.extend_from_slice(&(crc as u64).to_le_bytes());
+            file.seek(SeekFrom::End(0)).map_err(|e| format!("{path}: {e}"))?;
+            file.write_all(&trailer).map_err(|e| format!("{path}: {e}"))?;
+            file.sync_data().map_err(|e| format!("{path}: {e}"))?;
+            crc
+        }
+    };
+    file.seek(SeekFrom::Start(SEAL_MARKER_OFFSET)).map_err(|e| format!("{path}: {e}"))?;
+    file.write_all(&SEAL_MARKER.to_le_bytes()).map_err(|e| format!("{path}: {e}"))?;
+    file.sync_data().map_err(|e| format!("{path}: {e}"))?;
+    println!("Sealed {path}, checksum {crc:08x}");
+    return Ok(());
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use crate::analysis::{walk_snapshot, Walk, Zone};
+use crate::snapshot::Snapshot;
+
+// Zones of every thread split by depth and sorted by start. Zones of one depth never overlap, so zones running
+// at given time are found with one binary search per depth instead of walking the whole snapshot
+pub struct ZoneIndex {
+    threads: HashMap<u32, Vec<Vec<Zone>>>,
+}
+
+impl ZoneIndex {
+    pub fn build<I: IntoIterator<Item = Zone>>(zones: I) -> ZoneIndex {
+        let mut threads: HashMap<u32, Vec<Vec<Zone>>> = HashMap::new();
+        for zone in zones {
+            let levels = threads.entry(zone.thread_id).or_default();
+            if levels.len() <= zone.depth as usize {
+                levels.resize(zone.depth as usize + 1, Vec::new());
+            }
+            levels[zone.depth as usize].push(zone);
+        }
+        for level in threads.values_mut().flatten() {
+            level.sort_by_key(|zone| zone.start);
+        }
+        return ZoneIndex { threads };
+    }
+
+    pub fn from_snapshot(snapshot: &Snapshot) -> Result<ZoneIndex, String> {
+        let mut zones = Vec::new();
+        walk_snapshot(snapshot, |walk| {
+            if let Walk::Zone(zone) = walk {
+                zones.push(zone);
+            }
+        })?;
+        return Ok(ZoneIndex::build(zones));
+    }
+
+    pub fn threads(&self) -> Vec<u32> {
+        let mut threads: Vec<u32> = self.threads.keys().copied().collect();
+        threads.sort();
+        return threads;
+    }
+
+    pub fn zones(&self, thread_id: u32) -> impl Iterator<Item = &Zone> {
+        return self.threads.get(&thread_id).into_iter().flatten().flatten();
+    }
+
+    // Zones open at timestamp on thread, outermost first
+    pub fn at(&self, thread_id: u32, timestamp: u64) -> Vec<&Zone> {
+        let mut stack = Vec::new();
+        for level in self.threads.get(&thread_id).into_iter().flatten() {
+            let after = level.partition_point(|zone| zone.start <= timestamp);
+            match after.checked_sub(1).map(|index| &level[index]) {
+                Some(zone) if zone.end >= timestamp => stack.push(zone),
+                // Deeper zones are inside this level ones
+                _ => break,
+            }
+        }
+        return stack;
+    }
+
+    // Zones of thread overlapping start..end, by depth and then by start
+    pub fn overlapping(&self, thread_id: u32, start: u64, end: u64) -> Vec<&Zone> {
+        let mut zones = Vec::new();
+        for level in self.threads.get(&thread_id).into_iter().flatten() {
+            // Ends are sorted as well, zones of one depth do not overlap
+            let first = level.partition_point(|zone| zone.end < start);
+            let last = level.partition_point(|zone| zone.start <= end);
+            zones.extend(level[first..last.max(first)].iter());
+        }
+        return zones;
+    }
+}
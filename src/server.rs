@@ -1,192 +1,1449 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
 use std::net::{Shutdown, TcpStream};
-use std::thread::sleep;
-use std::time::Duration;
-use bincode::de::read::Reader;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, sleep};
+use std::vec;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use bincode::Encode;
-use bincode::error::DecodeError;
-use bincode::error::DecodeError::Io;
-use crate::structs::{BINCODE_CONFIG, WriterBox, EventType, HandshakeStatus, SourceLocation, UTracyEvent, UTracyHeader, NetworkZoneBegin, NetworkZoneEnd, NetworkZoneColor, NetworkFrameMark, NetworkQuery, NetworkThreadContext, NetworkHeader, QueryResponseType, NetworkMessageSourceLocation, NetworkMessageString, U16SizeString, ServerQueryType, NetworkSourceCode};
-use lz4::block::compress;
+use crate::structs::{describe_protocol, release_table, tracy_release, BINCODE_CONFIG, PROTOCOL_VERSION, QUERY_SIZE, TRACY_DOWNLOAD, WriterBox, EventType, HandshakeStatus, UTracyEvent, NetworkZoneBegin, NetworkZoneEnd, NetworkZoneColor, NetworkFrameMark, NetworkQuery, NetworkThreadContext, NetworkHeader, QueryResponseType, NetworkMessageSourceLocation, NetworkMessageString, U16SizeString, ServerQueryType, NetworkSourceCode, NetworkContextSwitch, NetworkThreadWakeup, NetworkCallstackSample, NetworkCallstackPayload, NetworkCallstackFrameSize, NetworkCallstackFrame, NetworkSingleString, NetworkFrameVsync, NetworkMessage, NetworkPlotDataDouble, SourceLocation, NetworkTidToPid, NetworkFiberEnter, NetworkThreadGroupHint, NetworkGpuNewContext, GpuContextType,
+    NetworkGpuZoneBegin, NetworkGpuZoneEnd, NetworkGpuTime, NetworkGpuContextName, NetworkFrameImageData, NetworkFrameImage,
+    NetworkSysTime, NetworkSysPower, NetworkZoneValue};
+use crate::audit::{append_audit, SessionAudit};
+use crate::notify::notify_session;
+use crate::acl::{AUTH_MAGIC, AllowList, token_matches};
+use crate::clock::format_duration;
+use crate::color::warning;
+use crate::filter::{FilterScript, ZoneAction, ZoneFilter};
+use crate::sidecar::{Sidecar, SidecarEvent};
+use crate::strip::{strip_image, STRIP_COLUMNS, STRIP_HEIGHT, STRIP_IMAGE_BYTES, STRIP_WIDTH};
+use crate::intern::SourceTable;
+use crate::session::SessionRecorder;
+use crate::watch::Watch;
+use crate::snapshot::{EventReader, Snapshot};
+use lz4::block::{compress_bound, compress_to_buffer};
+use socket2::SockRef;
+
+// Encoded messages are compressed and sent once buffer grows above batch bytes, Tracy decompresses
+// frames into 256KB buffer so this is also the upper limit
+pub const MAX_BATCH_BYTES: usize = 250 * 1024;
+// Queries are polled after every batch of events
+pub const DEFAULT_BATCH_EVENTS: u32 = 10000;
+const PARTIAL_QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Events decoded ahead of encoder are passed in batches of this size, at most READ_AHEAD_BATCHES wait in channel
+const READ_AHEAD_EVENTS: usize = 4096;
+const READ_AHEAD_BATCHES: usize = 8;
+
+// Zone colors of --color-by-duration, from fast to slow
+const DURATION_GREEN: [u8; 3] = [60, 180, 75];
+const DURATION_YELLOW: [u8; 3] = [240, 200, 0];
+const DURATION_RED: [u8; 3] = [220, 40, 40];
+pub const DEFAULT_DURATION_COLORS: &str = "1ms,10ms";
+
+// Encode and compression buffers of finished connections, so steady streaming does not allocate
+static BUFFER_POOL: Mutex<Vec<(Vec<u8>, Vec<u8>)>> = Mutex::new(Vec::new());
+// Compressed blocks of first completely streamed session of every profile, replayed to later clients with --block-cache
+type CachedBlocks = Arc<Vec<Vec<u8>>>;
+// Served capture as paths and start times of its snapshots, and profile name
+type BlockKey = (Vec<(String, u64)>, Option<String>);
+// Key, blocks and number of frames in them
+type CachedSession = (BlockKey, CachedBlocks, u32);
+// Generation bumped on every clear and cached sessions
+static BLOCK_CACHE: Mutex<(u64, Vec<CachedSession>)> = Mutex::new((0, Vec::new()));
+// Only the first streamed session is recorded with --record-session
+static SESSION_RECORDED: AtomicBool = AtomicBool::new(false);
+
+// Decodes snapshot events on own thread, so disk reads overlap with encoding, compression and sending.
+// Thread ends at end of file or when the session drops its receiver
+struct ReadAhead {
+    receiver: Receiver<Vec<UTracyEvent>>,
+    batch: vec::IntoIter<UTracyEvent>,
+}
+
+impl ReadAhead {
+    fn start(mut reader: EventReader) -> ReadAhead {
+        let (sender, receiver) = sync_channel(READ_AHEAD_BATCHES);
+        thread::spawn(move || loop {
+            let mut batch = Vec::with_capacity(READ_AHEAD_EVENTS);
+            while batch.len() < READ_AHEAD_EVENTS {
+                let Ok(event) = bincode::decode_from_reader(&mut reader, BINCODE_CONFIG) else {
+                    break;
+                };
+                batch.push(event);
+            }
+            let finished = batch.len() < READ_AHEAD_EVENTS;
+            if sender.send(batch).is_err() || finished {
+                return;
+            }
+        });
+        return ReadAhead { receiver, batch: Vec::new().into_iter() };
+    }
+}
+
+impl Iterator for ReadAhead {
+    type Item = UTracyEvent;
+
+    fn next(&mut self) -> Option<UTracyEvent> {
+        loop {
+            if let Some(event) = self.batch.next() {
+                return Some(event);
+            }
+            self.batch = self.receiver.recv().ok()?.into_iter();
+        }
+    }
+}
+
+// Cached sessions were streamed with profiles that changed on config reload or capture that was replaced.
+// Sessions started before the clear don't store their blocks, they may have streamed old capture or profile
+pub fn clear_block_cache() {
+    let mut cache = BLOCK_CACHE.lock().unwrap();
+    cache.0 += 1;
+    cache.1.clear();
+}
+
+// Gaps longer than max_gap are shortened to it, returns gap ends with cumulative removed ticks for ServerContext::shifted
+pub fn collapse_table(gaps: &[(u64, u64)], max_gap: u64) -> Vec<(u64, u64)> {
+    let mut removed = 0;
+    let mut table = Vec::new();
+    for (start, end) in gaps {
+        if end - start > max_gap {
+            removed += end - start - max_gap;
+            table.push((*end, removed));
+        }
+    }
+    return table;
+}
+
+// Accepts "5MB/s", "500KB/s", "1GB/s", "/s" suffix is optional and plain numbers are bytes per second
+pub fn parse_bandwidth(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    return parse_amount(text, text.strip_suffix("/s").unwrap_or(text), "bandwidth");
+}
+
+// Accepts "500KB", "64MB", "2GB", plain numbers are bytes
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    return parse_amount(text, text, "size");
+}
+
+fn parse_amount(text: &str, amount: &str, what: &str) -> Result<u64, String> {
+    let split = amount.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(amount.len());
+    let (value, unit) = amount.split_at(split);
+    let value: f64 = value.parse().map_err(|_| format!("Invalid {what} \"{text}\""))?;
+    let scale = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1f64,
+        "KB" => 1_000f64,
+        "MB" => 1_000_000f64,
+        "GB" => 1_000_000_000f64,
+        _ => return Err(format!("Invalid {what} unit in \"{text}\"")),
+    };
+    let bytes = (value * scale) as u64;
+    if bytes == 0 {
+        return Err(format!("Invalid {what} \"{text}\""));
+    }
+    return Ok(bytes);
+}
+
+// Frame range and filters of one listener, listeners from config file serve the same snapshots with their own profile
+#[derive(Clone)]
+pub struct Profile {
+    // None for profile given on command line
+    pub name: Option<String>,
+    pub skip_frames: u32,
+    pub limit_frames: u32,
+    pub filter_script: Option<String>,
+    pub frame_stride: u32,
+    // Only zones of this name and zones nested in them are streamed
+    pub within: Option<String>,
+    // Zones nested deeper are not streamed, 1 keeps only outermost zones
+    pub max_depth: Option<u32>,
+    // Names answered for thread ids, set in config file
+    pub thread_names: HashMap<u32, String>,
+}
+
+impl Profile {
+    pub fn app_info(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(name) = &self.name {
+            lines.push(format!("Profile: {name}"));
+        }
+        if self.skip_frames != 0 {
+            lines.push(format!("Skip frames: {}", self.skip_frames));
+        }
+        if self.limit_frames != u32::MAX {
+            lines.push(format!("Limit frames: {}", self.limit_frames));
+        }
+        if self.frame_stride > 1 {
+            lines.push(format!("Zones of every {}th frame", self.frame_stride));
+        }
+        if let Some(zone) = &self.within {
+            lines.push(format!("Zones within: {zone}"));
+        }
+        if let Some(depth) = self.max_depth {
+            lines.push(format!("Max zone depth: {depth}"));
+        }
+        if let Some(path) = &self.filter_script {
+            lines.push(format!("Filter script: {path}"));
+        }
+        return lines;
+    }
+}
+
+#[derive(Default)]
+pub struct ServerOptions {
+    pub sidecar: Sidecar,
+    // Graphics API of the context GPU zones are sent in
+    pub gpu_api: GpuContextType,
+    pub vsync_rate: Option<f64>,
+    pub app_info: Vec<String>,
+    // Frame set names labeling each snapshot, used when several are streamed in one session
+    pub snapshot_names: Vec<u64>,
+    pub plots: Vec<ZonePlot>,
+    pub tcp_nodelay: bool,
+    pub send_buffer: Option<usize>,
+    pub recv_buffer: Option<usize>,
+    pub batch_events: u32,
+    pub batch_bytes: usize,
+    pub max_bandwidth: Option<u64>,
+    pub merge_processes: bool,
+    pub merge_threads: bool,
+    pub thread_order: Vec<u32>,
+    // Idle gaps of every snapshot in snapshot ticks, start and end
+    pub gaps: Vec<Vec<(u64, u64)>>,
+    pub collapse: Vec<Vec<(u64, u64)>>,
+    pub block_cache: bool,
+    // Messages sent with the first streamed event, e.g. changes against baseline capture
+    pub annotations: Vec<String>,
+    // Synthetic frame thumbnails of every snapshot, empty when not enabled
+    pub frame_strips: Vec<Vec<[u16; STRIP_COLUMNS]>>,
+    pub zone_self_time: bool,
+    // Zones shorter than the first duration are colored green, shorter than the second yellow, the rest red
+    pub duration_colors: Option<(u64, u64)>,
+    // Zones flagged with message on timeline when they run too long
+    pub watchlist: Vec<Watch>,
+    pub close_open_zones: bool,
+    // Snapshot path for events streamed to the first client
+    pub record_session: Option<String>,
+    // JSON lines file getting record of every client connection
+    pub audit_log: Option<String>,
+    // Peers allowed to complete handshake, everyone when not set
+    pub allow: Option<AllowList>,
+    // Shared secret clients must send before handshake, see acl::AUTH_MAGIC
+    pub auth_token: Option<String>,
+    // Discord or Slack compatible URLs told about uploaded captures and finished sessions
+    pub webhooks: Vec<String>,
+}
+
+// Snapshots served to new connections with everything derived from them, replaced as a whole when capture is uploaded
+pub struct Served {
+    pub snapshots: Vec<Snapshot>,
+    pub table: SourceTable,
+    pub options: ServerOptions,
+}
+
+// None until the first upload when server was started with empty upload directory
+pub type SharedServed = RwLock<Option<Arc<Served>>>;
+
+// Client socket, or memory sink which drops blocks and never gets queries, used by bench
+enum Connection<'l> {
+    Socket {
+        socket: &'l TcpStream,
+        reader: BufReader<&'l TcpStream>,
+        writer: BufWriter<&'l TcpStream>,
+    },
+    Memory,
+}
+
+impl Connection<'_> {
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<(), String> {
+        if let Connection::Socket { socket, .. } = self {
+            socket.set_nonblocking(nonblocking).map_err(|e| format!("{}", e))?;
+        }
+        return Ok(());
+    }
+
+    fn write_block(&mut self, block: &[u8]) -> Result<(), String> {
+        if let Connection::Socket { writer, .. } = self {
+            writer.write(&u32::to_le_bytes(block.len() as u32)).map_err(|e| format!("{}", e))?;
+            writer.write(block).map_err(|e| format!("{}", e))?;
+            writer.flush().map_err(|e| format!("{}", e))?;
+        }
+        return Ok(());
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        return match self {
+            Connection::Socket { reader, .. } => std::io::Read::read(reader, buf),
+            Connection::Memory => Err(ErrorKind::WouldBlock.into()),
+        };
+    }
+}
+
+// Per-frame total time of zones at given locations, streamed as plot
+pub struct ZonePlot {
+    pub name: u64,
+    pub locations: Vec<u32>,
+}
+
+// Zone sent to Tracy and not ended yet, with time of its ended children for self time
+struct OpenZone {
+    start: u64,
+    location: u64,
+    children: u64,
+}
 
 struct ServerContext<'l> {
-    socket: &'l TcpStream,
-    reader: BufReader<&'l TcpStream>,
-    writer: BufWriter<&'l TcpStream>,
+    connection: Connection<'l>,
     encoder: WriterBox<'l, Vec<u8>>,
+    // Scratch buffer for LZ4 output, grows to largest batch once and is reused after
+    compressed: &'l mut Vec<u8>,
+    // Blocks sent so far when session is recorded for block cache, query answers are flushed separately and not recorded
+    record: Option<Vec<Vec<u8>>>,
+    answering: bool,
     last_thread_id: u32,
     timestamp: u64,
-    locations: &'l Vec<SourceLocation>,
-    strings: &'l HashMap<u64, String>,
-    events_data: BufReader<File>,
+    table: &'l SourceTable,
+    snapshots: &'l [Snapshot],
+    snapshot_names: &'l [u64],
+    time_offset: i64,
+    last_timestamp: u64,
+    location_offset: u64,
     skip_frames: u64,
     limit_frames: u64,
+    sidecar: &'l Sidecar,
+    next_sidecar_event: usize,
+    vsync_period: Option<f64>,
+    next_vsync: Option<f64>,
+    plot_names: Vec<u64>,
+    plot_locations: HashMap<u64, usize>,
+    plot_values: Vec<u64>,
+    filter: ZoneFilter,
+    batch_events: u32,
+    batch_bytes: usize,
+    // Bytes per second cap, compressed bytes sent since streaming started are compared against it
+    max_bandwidth: Option<u64>,
+    sent_bytes: u64,
+    // Sent blocks before compression, only for bench
+    encoded_bytes: u64,
+    send_start: Instant,
+    merge_processes: bool,
+    // Thread ids of merged snapshots are remapped to (snapshot index, original id) order of appearance
+    thread_map: HashMap<(usize, u32), u32>,
+    thread_names: HashMap<u32, String>,
+    merge_threads: bool,
+    merged_thread_id: Option<u32>,
+    current_fiber: Option<u32>,
+    // Serial CPU and GPU times are deltas against their own references, which thread context does not reset
+    serial_timestamp: u64,
+    gpu_timestamp: u64,
+    gpu_context: bool,
+    gpu_api: GpuContextType,
+    gaps: &'l [(u64, u64)],
+    next_gap: usize,
+    // Gap end and total ticks removed by collapsed gaps up to it
+    collapse: &'l [(u64, u64)],
+    annotations: &'l [String],
+    annotated: bool,
+    frame_strips: &'l [[u16; STRIP_COLUMNS]],
+    // Unnamed frame marks sent so far, Tracy attaches frame images by this count
+    marks_sent: u32,
+    zone_self_time: bool,
+    // Color thresholds in ticks
+    color_thresholds: Option<(u64, u64)>,
+    watchlist: &'l [Watch],
+    // Watchlist entry matching zone name of location, filled as locations are first seen
+    watched: HashMap<u64, Option<usize>>,
+    // Sent zones per thread, shared by pairing, plots, self time, duration colors and watchlist.
+    // Ends without sent begin would make Tracy stop loading the trace
+    zone_stacks: HashMap<u32, Vec<OpenZone>>,
+    unmatched_ends: u64,
+    close_open_zones: bool,
+    session: Option<SessionRecorder>,
+    queries_answered: u64,
+    query: [u8; QUERY_SIZE],
+    query_filled: usize,
 }
 
-impl ServerContext<'_> {
-    fn process_client(&mut self) -> Result<(), String> {
-        self.socket.set_nonblocking(true).map_err(|e| format!("{}", e))?;
+impl<'l> ServerContext<'l> {
+    fn new(connection: Connection<'l>, snapshots: &'l [Snapshot], table: &'l SourceTable, options: &'l ServerOptions, profile: &Profile,
+           region: Option<(u32, u32)>, (buffer, compressed): (&'l mut Vec<u8>, &'l mut Vec<u8>)) -> Result<ServerContext<'l>, String> {
+        let header = &snapshots[0].header;
+        let skip_frames: u64 = region.map_or(profile.skip_frames, |(first, _)| first).into();
+        let frame_stride: u64 = if region.is_some() { 1 } else { profile.frame_stride.into() };
+        return Ok(ServerContext {
+            connection,
+            encoder: WriterBox(buffer),
+            compressed,
+            record: None,
+            answering: false,
+            last_thread_id: 0,
+            timestamp: 0,
+            table,
+            snapshots,
+            snapshot_names: &options.snapshot_names,
+            time_offset: 0,
+            last_timestamp: 0,
+            location_offset: 0,
+            skip_frames,
+            limit_frames: region.map_or(profile.limit_frames, |(_, count)| count).into(),
+            sidecar: &options.sidecar,
+            next_sidecar_event: 0,
+            vsync_period: options.vsync_rate.map(|rate| 1_000_000_000f64 / rate / header.multiplier),
+            next_vsync: None,
+            plot_names: options.plots.iter().map(|plot| plot.name).collect(),
+            plot_locations: options.plots.iter().enumerate()
+                .flat_map(|(i, plot)| plot.locations.iter().map(move |location| (*location as u64, i)))
+                .collect(),
+            plot_values: vec![0; options.plots.len()],
+            // Script is compiled separately for every client
+            filter: ZoneFilter::new(profile.filter_script.as_deref().map(FilterScript::load).transpose()?, skip_frames, frame_stride, profile.within.clone(),
+                                    profile.max_depth),
+            batch_events: options.batch_events,
+            batch_bytes: options.batch_bytes,
+            max_bandwidth: options.max_bandwidth,
+            sent_bytes: 0,
+            encoded_bytes: 0,
+            send_start: Instant::now(),
+            merge_processes: options.merge_processes,
+            thread_map: HashMap::new(),
+            thread_names: profile.thread_names.clone(),
+            merge_threads: options.merge_threads,
+            merged_thread_id: None,
+            current_fiber: None,
+            serial_timestamp: 0,
+            gpu_timestamp: 0,
+            gpu_context: false,
+            gpu_api: options.gpu_api,
+            gaps: &[],
+            next_gap: 0,
+            collapse: &[],
+            annotations: &options.annotations,
+            annotated: false,
+            frame_strips: &[],
+            marks_sent: 0,
+            zone_self_time: options.zone_self_time,
+            color_thresholds: options.duration_colors.map(|(yellow, red)| {
+                let multiplier = snapshots[0].header.multiplier;
+                return ((yellow as f64 / multiplier) as u64, (red as f64 / multiplier) as u64);
+            }),
+            watchlist: &options.watchlist,
+            watched: HashMap::new(),
+            zone_stacks: HashMap::new(),
+            unmatched_ends: 0,
+            close_open_zones: options.close_open_zones,
+            session: None,
+            queries_answered: 0,
+            query: [0u8; QUERY_SIZE],
+            query_filled: 0,
+        });
+    }
+
+    fn process_client(&mut self, options: &'l ServerOptions) -> Result<(), String> {
+        self.connection.set_nonblocking(true)?;
+        if !self.stream_all(options)? {
+            // Client asked to stop, send what is already encoded and close
+            println!("Client requested disconnect, streaming stopped");
+            self.record = None;
+            self.flush_buffer()?;
+            return Ok(());
+        }
+        self.flush_buffer()?;
+        return self.wait_queries();
+    }
+
+    // Returns false when client disconnected while streaming
+    fn stream_all(&mut self, options: &'l ServerOptions) -> Result<bool, String> {
+        // Tracy lists threads in order they first appear, announce configured ones before any zone
+        for thread in &options.thread_order {
+            self.send_message(NetworkThreadGroupHint {
+                query_type: QueryResponseType::ThreadGroupHint,
+                thread: *thread,
+                group_hint: 0,
+            })?;
+        }
+        if self.merge_processes {
+            self.gaps = &options.gaps[0];
+            self.collapse = &options.collapse[0];
+            self.frame_strips = options.frame_strips.first().map_or(&[], |strips| strips);
+            return self.stream_merged();
+        }
+        for index in 0..self.snapshots.len() {
+            self.gaps = &options.gaps[index];
+            self.collapse = &options.collapse[index];
+            self.next_gap = 0;
+            self.frame_strips = options.frame_strips.get(index).map_or(&[], |strips| strips);
+            if !self.stream_snapshot(index)? {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+
+    // Sends cached blocks as is, queries are answered between them like between event batches
+    fn replay_blocks(&mut self, blocks: &[Vec<u8>]) -> Result<(), String> {
+        self.connection.set_nonblocking(true)?;
+        for block in blocks {
+            self.connection.set_nonblocking(false)?;
+            self.connection.write_block(block)?;
+            self.throttle(block.len() as u64 + 4);
+            self.connection.set_nonblocking(true)?;
+            if !self.process_query()? {
+                println!("Client requested disconnect, streaming stopped");
+                return Ok(());
+            }
+        }
+        return self.wait_queries();
+    }
+
+    fn wait_queries(&mut self) -> Result<(), String> {
+        println!("Sending done, wait 20 seconds to handle queries");
+        for _i in 0..2  {
+            if !self.process_query()? {
+                return Ok(());
+            }
+            sleep(Duration::from_millis(10));
+        }
+        // Rest of query which started arriving is waited for, client that stopped sending mid query is given up on
+        let waiting_start = Instant::now();
+        while self.query_filled != 0 {
+            if waiting_start.elapsed() > PARTIAL_QUERY_TIMEOUT {
+                println!("Client sent only {} of {QUERY_SIZE} query bytes, closing", self.query_filled);
+                return Ok(());
+            }
+            sleep(Duration::from_millis(10));
+            if !self.process_query()? {
+                return Ok(());
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Snapshots after the first one are shifted to start right after the previous one ends,
+    // returns false when client disconnected while streaming
+    fn stream_snapshot(&mut self, index: usize) -> Result<bool, String> {
+        let snapshot = &self.snapshots[index];
+        let mut events = ReadAhead::start(snapshot.open_events()?);
+        let labeled = self.snapshots.len() > 1;
+        if index > 0 {
+            // Refreshes of previous snapshot were counted on its clock
+            self.next_vsync = None;
+            let gap = 1_000_000_000f64 / snapshot.header.multiplier;
+            self.time_offset = (self.last_timestamp + gap as u64) as i64 - snapshot.header.init_begin as i64;
+        }
+        self.location_offset = snapshot.location_offset.into();
+        let snapshot_name = self.snapshot_names[index];
+        if labeled {
+            let timestamp = self.shift(snapshot.header.init_begin);
+            self.send_message(NetworkFrameMark {
+                query_type: QueryResponseType::FrameMarkMsgStart,
+                timestamp,
+                name: snapshot_name,
+            })?;
+        }
+
         let mut read_event = 0;
         let mut frame = 0;
         loop {
-            let e1: Result<UTracyEvent, DecodeError> = bincode::decode_from_reader(&mut self.events_data, BINCODE_CONFIG);
-            if e1.is_err() {
+            let Some(event) = events.next() else {
                 println!("Reached end of file");
                 break;
+            };
+            if !self.send_event(&event, &mut frame)? {
+                break;
+            }
+            read_event += 1;
+            if read_event > self.batch_events {
+                if !self.process_query()? {
+                    return Ok(false);
+                }
+                read_event = 0;
             }
-            let event = e1.unwrap();
-            unsafe {
-                match event.event_type {
-                    EventType::Begin => {
-                        if frame > self.skip_frames {
-                            self.check_thread(event.event.begin.thread_id);
+        }
+        self.finish_zones()?;
+        if labeled {
+            self.send_message(NetworkFrameMark {
+                query_type: QueryResponseType::FrameMarkMsgEnd,
+                timestamp: self.last_timestamp,
+                name: snapshot_name,
+            })?;
+        }
+        return Ok(true);
+    }
+
+    // Snapshots of companion processes are interleaved by timestamp, captures have to come from one machine so timers match.
+    // First snapshot drives frames, marks of others are sent as frame set named after their snapshot
+    fn stream_merged(&mut self) -> Result<bool, String> {
+        let mut readers = Vec::new();
+        for snapshot in self.snapshots {
+            readers.push(ReadAhead::start(snapshot.open_events()?));
+        }
+        let mut pending: Vec<Option<UTracyEvent>> = readers.iter_mut().map(|reader| reader.next()).collect();
+        let mut last_timestamps = vec![0u64; readers.len()];
+        let mut depths = HashMap::<u32, u32>::new();
+        let mut read_event = 0;
+        let mut frame = 0;
+        loop {
+            // Color events have no timestamp and stay right after zone begin of their snapshot
+            let next = pending.iter().enumerate().filter_map(|(index, event)| event.as_ref().map(|event| match event.event_type {
+                EventType::Color => (last_timestamps[index], index),
+                _ => (unsafe { event.event.begin.timestamp }, index),
+            })).min();
+            let Some((timestamp, index)) = next else {
+                println!("Reached end of files");
+                break;
+            };
+            let Some(mut event) = pending[index].take() else {
+                break;
+            };
+            pending[index] = readers[index].next();
+            last_timestamps[index] = timestamp;
+            self.location_offset = self.snapshots[index].location_offset.into();
+            let proceed = match event.event_type {
+                EventType::Mark if index > 0 => {
+                    if frame > self.skip_frames {
+                        let timestamp = self.shift(timestamp);
+                        self.send_message(NetworkFrameMark {
+                            query_type: QueryResponseType::FrameMarkMsg,
+                            timestamp,
+                            name: self.snapshot_names[index],
+                        })?;
+                    }
+                    true
+                }
+                EventType::Mark => self.send_event(&event, &mut frame)?,
+                _ => {
+                    let thread_id = self.merged_thread(index, unsafe { event.event.begin.thread_id })?;
+                    event.event.begin.thread_id = thread_id;
+                    // Zones of other processes may open before first sent frame, their ends are dropped like begins were
+                    let depth = depths.entry(thread_id).or_default();
+                    let skip = match event.event_type {
+                        EventType::Begin if frame > self.skip_frames => {
+                            *depth += 1;
+                            false
+                        }
+                        EventType::End if *depth == 0 => true,
+                        EventType::End => {
+                            *depth -= 1;
+                            false
+                        }
+                        _ => false,
+                    };
+                    skip || self.send_event(&event, &mut frame)?
+                }
+            };
+            if !proceed {
+                break;
+            }
+            read_event += 1;
+            if read_event > self.batch_events {
+                if !self.process_query()? {
+                    return Ok(false);
+                }
+                read_event = 0;
+            }
+        }
+        self.finish_zones()?;
+        return Ok(true);
+    }
+
+    fn merged_thread(&mut self, index: usize, thread_id: u32) -> Result<u32, String> {
+        if let Some(merged) = self.thread_map.get(&(index, thread_id)) {
+            return Ok(*merged);
+        }
+        let merged = self.thread_map.len() as u32 + 1;
+        self.thread_map.insert((index, thread_id), merged);
+        let snapshot = &self.snapshots[index];
+        self.thread_names.insert(merged, format!("{} ({}) thread {thread_id}", snapshot.program_name(), snapshot.header.process_id));
+        self.send_message(NetworkTidToPid {
+            query_type: QueryResponseType::TidToPid,
+            thread_id: merged as u64,
+            process_id: snapshot.header.process_id,
+        })?;
+        return Ok(merged);
+    }
+
+    // Sends one snapshot event, frame counts marks seen so far, returns false once frame limit is reached
+    fn send_event(&mut self, event: &UTracyEvent, frame: &mut u64) -> Result<bool, String> {
+        unsafe {
+            match event.event_type {
+                EventType::Begin => {
+                    if *frame > self.skip_frames {
+                        let timestamp = self.shift(event.event.begin.timestamp);
+                        let source_location = self.location_offset + event.event.begin.source_location as u64;
+                        let action = self.filter.zone(self.table, event.event.begin.thread_id, source_location, timestamp, *frame)?;
+                        if !matches!(action, ZoneAction::Drop) {
+                            self.send_timed_events(timestamp)?;
+                            self.enter_thread(event.event.begin.thread_id, timestamp)?;
                             self.send_message(NetworkZoneBegin {
                                 query_type: QueryResponseType::ZoneBegin,
-                                timestamp: event.event.begin.timestamp - self.timestamp,
-                                source_location: event.event.begin.source_location.into(),
+                                timestamp: timestamp - self.timestamp,
+                                source_location: self.location_id(source_location),
                             })?;
-                            self.timestamp = event.event.begin.timestamp;
+                            self.record_begin(event.event.begin.thread_id, source_location, timestamp, &action)?;
+                            if let ZoneAction::Change { name, color } = action {
+                                self.send_zone_change(name, color)?;
+                            }
+                            let zone = OpenZone { start: timestamp, location: source_location, children: 0 };
+                            self.zone_stacks.entry(event.event.begin.thread_id).or_default().push(zone);
+                            self.timestamp = timestamp;
                         }
                     }
-                    EventType::End => {
-                        if frame > self.skip_frames {
-                            self.check_thread(event.event.begin.thread_id);
-                            self.send_message(NetworkZoneEnd {
-                                query_type: QueryResponseType::ZoneEnd,
-                                timestamp: event.event.end.timestamp - self.timestamp,
-                            })?;
-                            self.timestamp = event.event.end.timestamp;
-                        }
+                }
+                EventType::End => {
+                    let zone = if *frame > self.skip_frames && !self.filter.end(event.event.end.thread_id) {
+                        self.end_zone(event.event.end.thread_id)
+                    } else {
+                        None
+                    };
+                    if let Some(zone) = zone {
+                        let timestamp = self.shift(event.event.end.timestamp);
+                        self.send_timed_events(timestamp)?;
+                        self.enter_thread(event.event.begin.thread_id, timestamp)?;
+                        self.send_zone_end(event.event.end.thread_id, &zone, timestamp)?;
+                        self.end_plot_zone(&zone, timestamp);
                     }
-                    EventType::Color => {
-                        if frame > self.skip_frames {
-                            self.check_thread(event.event.begin.thread_id);
-                            self.send_message(NetworkZoneColor {
-                                query_type: QueryResponseType::ZoneColor,
-                                color_r: event.event.color.color[0],
-                                color_g: event.event.color.color[1],
-                                color_b: event.event.color.color[2],
-                            })?;
-                        }
+                }
+                EventType::Color => {
+                    if *frame > self.skip_frames && !self.filter.dropped(event.event.color.thread_id) {
+                        self.enter_thread(event.event.begin.thread_id, self.timestamp)?;
+                        self.send_message(NetworkZoneColor {
+                            query_type: QueryResponseType::ZoneColor,
+                            color_r: event.event.color.color[0],
+                            color_g: event.event.color.color[1],
+                            color_b: event.event.color.color[2],
+                        })?;
+                        self.record_event(UTracyEvent::color(event.event.color.thread_id, event.event.color.color))?;
                     }
-                    EventType::Mark => {
-                        frame += 1;
-                        if frame > self.skip_frames {
+                }
+                EventType::Mark => {
+                    *frame += 1;
+                    let timestamp = self.shift(event.event.mark.timestamp);
+                    if *frame > self.skip_frames {
+                        self.send_timed_events(timestamp)?;
+                        if self.filter.frame(*frame, timestamp)? {
                             self.send_message(NetworkFrameMark {
                                 query_type: QueryResponseType::FrameMarkMsg,
-                                timestamp: event.event.mark.timestamp,
+                                timestamp,
                                 name: 0,
                             })?;
+                            self.marks_sent += 1;
+                            self.record_event(UTracyEvent::mark(timestamp))?;
+                            self.send_frame_strip(*frame)?;
                         }
-                        if frame > self.skip_frames + self.limit_frames {
-                            break;
-                        }
+                        self.send_plots(timestamp)?;
+                    } else {
+                        self.skip_timed_events(timestamp);
+                    }
+                    if *frame > self.skip_frames + self.limit_frames {
+                        return Ok(false);
                     }
                 }
             }
-            read_event += 1;
-            if read_event > 10000 {
-                self.flush_buffer()?;
-                self.process_query()?;
-                read_event = 0;
+        }
+        return Ok(true);
+    }
+
+    // Index out of range comes from broken snapshot, it is sent as is and Tracy gets empty location for it
+    fn location_id(&self, index: u64) -> u64 {
+        return self.table.ids.get(index as usize).copied().unwrap_or(index);
+    }
+
+    fn shift(&mut self, timestamp: u64) -> u64 {
+        let shifted = self.shifted(timestamp);
+        self.last_timestamp = self.last_timestamp.max(shifted);
+        return shifted;
+    }
+
+    // Answers of all pending queries follow already encoded events and go out with them in one block
+    fn process_query(&mut self) -> Result<bool, String> {
+        // When session is recorded, answers go out in their own block, so recorded blocks hold only streamed events
+        if self.record.is_some() {
+            self.flush_buffer()?;
+        }
+        self.answering = true;
+        let result = self.answer_queries();
+        if self.record.is_some() && result.is_ok() {
+            self.flush_buffer()?;
+        }
+        self.answering = false;
+        return result;
+    }
+
+    // Everything received so far is read first, so high priority queries are answered before others like Tracy client does
+    fn answer_queries(&mut self) -> Result<bool, String> {
+        let mut priority = Vec::new();
+        let mut other = Vec::new();
+        loop {
+            // Query may arrive split over several TCP segments, received part is kept until the rest comes on later poll
+            let result = self.connection.read(&mut self.query[self.query_filled..]);
+            match result {
+                Ok(0) => return Ok(false),
+                Ok(read) => self.query_filled += read,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("{}", e)),
+            }
+            if self.query_filled < QUERY_SIZE {
+                continue;
+            }
+            self.query_filled = 0;
+            let buffer = self.query;
+            let request: NetworkQuery = match bincode::decode_from_slice(&buffer, BINCODE_CONFIG) {
+                Ok((request, _)) => request,
+                Err(e) => {
+                    // Queries have fixed size, dropping the malformed one keeps following queries aligned
+                    let bytes: Vec<String> = buffer.iter().map(|b| format!("{b:02x}")).collect();
+                    println!("Skipping malformed query [{}]: {e}", bytes.join(" "));
+                    continue;
+                }
+            };
+            match request.query_type {
+                ServerQueryType::ServerQueryTerminate | ServerQueryType::ServerQueryDisconnect => {
+                    return Ok(false);
+                }
+                _ if request.query_type.is_priority() => priority.push(request),
+                _ => other.push(request),
             }
         }
+        for request in priority.into_iter().chain(other) {
+            self.answer_query(request)?;
+        }
         self.flush_buffer()?;
-        println!("Sending done, wait 20 seconds to handle queries");
-        for _i in 0..2  {
-            if !self.process_query()? {
+        return Ok(true);
+    }
+
+    fn answer_query(&mut self, request: NetworkQuery) -> Result<(), String> {
+        match request.query_type {
+            ServerQueryType::ServerQueryString => {
+                let string = self.table.string(request.pointer).map(Cow::into_owned).unwrap_or_else(|| "Unkn".into());
+
+                self.send_message(NetworkMessageString {
+                    query_type: QueryResponseType::StringData,
+                    pointer: request.pointer,
+                    string: U16SizeString(&string),
+                })?;
+            }
+            ServerQueryType::ServerQueryThreadString => {
+                let main: String = "Main".into();
+                let name = self.thread_names.get(&(request.pointer as u32)).cloned().unwrap_or(main);
+                self.send_message(NetworkMessageString {
+                    query_type: QueryResponseType::ThreadName,
+                    pointer: request.pointer,
+                    string: U16SizeString(&name),
+                })?;
+            }
+            ServerQueryType::ServerQueryFiberName => {
+                let name = self.thread_names.get(&(request.pointer as u32)).cloned().unwrap_or_else(|| format!("Thread {}", request.pointer));
+                self.send_message(NetworkMessageString {
+                    query_type: QueryResponseType::FiberName,
+                    pointer: request.pointer,
+                    string: U16SizeString(&name),
+                })?;
+            }
+            ServerQueryType::ServerQueryPlotName => {
+                let string = self.table.string(request.pointer).map(Cow::into_owned).unwrap_or_else(|| "Unkn".into());
+                self.send_message(NetworkMessageString {
+                    query_type: QueryResponseType::PlotName,
+                    pointer: request.pointer,
+                    string: U16SizeString(&string),
+                })?;
+            }
+            ServerQueryType::ServerQueryFrameName => {
+                let string = self.table.string(request.pointer).map(Cow::into_owned).unwrap_or_else(|| "Unkn".into());
+                self.send_message(NetworkMessageString {
+                    query_type: QueryResponseType::FrameName,
+                    pointer: request.pointer,
+                    string: U16SizeString(&string),
+                })?;
+            }
+            ServerQueryType::ServerQuerySourceLocation => {
+                // Tracy waits for an answer to every location query, unknown ones get an empty location
+                let source = self.table.location_by_id(request.pointer).copied().unwrap_or_else(|| {
+                    println!("Query for unknown source location {}", request.pointer);
+                    SourceLocation { name: 0, function: 0, file: 0, line: 0, color_r: 0, color_g: 0, color_b: 0 }
+                });
+
+                self.send_message(NetworkMessageSourceLocation {
+                    query_type: QueryResponseType::SourceLocation,
+                    location: source,
+                })?;
+            }
+            ServerQueryType::ServerQueryCallstackFrame => {
+                self.send_callstack_frame(request.pointer)?;
+            }
+            ServerQueryType::ServerQuerySymbolCode => {
+                self.send_message(QueryResponseType::AckSymbolCodeNotAvailable)?;
+            }
+            ServerQueryType::ServerQuerySourceCode => {
+                self.send_message(NetworkSourceCode {
+                    query_type: QueryResponseType::AckSourceCodeNotAvailable,
+                    id: request.pointer as u32,
+                })?;
+            }
+            ServerQueryType::ServerQueryDataTransfer | ServerQueryType::ServerQueryDataTransferPart => {
+                self.send_message(QueryResponseType::AckServerQueryNoop)?;
+            }
+            _ => {
+                println!("Unknown request {:?}", request.query_type);
                 return Ok(());
             }
-            sleep(Duration::from_millis(10));
+        };
+        self.queries_answered += 1;
+        return Ok(());
+    }
+
+    fn send_app_info(&mut self, lines: &[String], timestamp: u64) -> Result<(), String> {
+        for line in lines {
+            self.send_message(NetworkSingleString {
+                query_type: QueryResponseType::SingleStringData,
+                string: U16SizeString(line),
+            })?;
+            self.send_message(NetworkMessage {
+                query_type: QueryResponseType::MessageAppInfo,
+                timestamp,
+            })?;
         }
+        return Ok(());
+    }
 
+    fn send_callstack_frame(&mut self, pointer: u64) -> Result<(), String> {
+        let unkn: String = "[unknown]".into();
+        let empty: String = "".into();
+        let frame = self.sidecar.get_frame(pointer);
+        self.send_message(NetworkSingleString {
+            query_type: QueryResponseType::SingleStringData,
+            string: U16SizeString(frame.map(|f| &f.image).unwrap_or(&empty)),
+        })?;
+        self.send_message(NetworkCallstackFrameSize {
+            query_type: QueryResponseType::CallstackFrameSize,
+            pointer,
+            size: 1,
+        })?;
+        self.send_message(NetworkSingleString {
+            query_type: QueryResponseType::SingleStringData,
+            string: U16SizeString(frame.map(|f| &f.name).unwrap_or(&unkn)),
+        })?;
+        self.send_message(NetworkSingleString {
+            query_type: QueryResponseType::SecondStringData,
+            string: U16SizeString(frame.map(|f| &f.file).unwrap_or(&empty)),
+        })?;
+        self.send_message(NetworkCallstackFrame {
+            query_type: QueryResponseType::CallstackFrame,
+            line: 0,
+            symbol_address: 0,
+            symbol_length: 0,
+        })?;
         return Ok(());
     }
 
-    fn process_query(&mut self) -> Result<bool, String> {
-        loop {
-            let mut buffer = [0u8; 13];
-            let result = self.reader.read(&mut buffer);
-            if let Err(Io { inner, additional }) = &result {
-                if inner.kind() == ErrorKind::WouldBlock && *additional == 13 {
-                    break;
-                }
-                if inner.kind() == ErrorKind::UnexpectedEof {
-                    return Ok(false);
-                }
+    fn send_message<W: Encode>(&mut self, message: W) -> Result<(), String> {
+        // Small batches are for event latency, answers of one poll are flushed together anyway
+        let limit = if self.answering { MAX_BATCH_BYTES } else { self.batch_bytes };
+        if self.encoder.0.len() > limit {
+            self.flush_buffer()?
+        }
+        bincode::encode_into_writer(message, &mut self.encoder, BINCODE_CONFIG).unwrap();
+        return Ok(());
+    }
+
+    fn flush_buffer(&mut self) -> Result<(), String> {
+        if self.encoder.0.is_empty() {
+            return Ok(());
+        }
+        self.connection.set_nonblocking(false)?;
+        let bound = compress_bound(self.encoder.0.len()).map_err(|e| format!("{}", e))?;
+        if self.compressed.len() < bound {
+            self.compressed.resize(bound, 0);
+        }
+        let size = compress_to_buffer(self.encoder.0.as_slice(), None, false, self.compressed).map_err(|e| format!("{}", e))?;
+        self.connection.write_block(&self.compressed[..size])?;
+        if let Some(record) = self.record.as_mut().filter(|_| !self.answering) {
+            record.push(self.compressed[..size].to_vec());
+        }
+        self.encoded_bytes += self.encoder.0.len() as u64;
+        self.encoder.0.clear();
+        self.throttle(size as u64 + 4);
+        self.connection.set_nonblocking(true)?;
+        return Ok(());
+    }
+
+    // Sleeps until average rate since streaming started drops to bandwidth cap
+    fn throttle(&mut self, bytes: u64) {
+        // Counted without cap too, for audit log
+        self.sent_bytes += bytes;
+        let Some(max_bandwidth) = self.max_bandwidth else {
+            return;
+        };
+        let expected = Duration::from_secs_f64(self.sent_bytes as f64 / max_bandwidth as f64);
+        let elapsed = self.send_start.elapsed();
+        if expected > elapsed {
+            sleep(expected - elapsed);
+        }
+    }
+
+    fn skip_timed_events(&mut self, until: u64) {
+        while self.sidecar.events.get(self.next_sidecar_event).is_some_and(|e| self.shifted(e.timestamp()) <= until) {
+            self.next_sidecar_event += 1;
+        }
+        while self.gaps.get(self.next_gap).is_some_and(|(start, _)| self.shifted(*start) <= until) {
+            self.next_gap += 1;
+        }
+    }
+
+    // Same as shift without updating last_timestamp, used for gap starts which are always at already sent event
+    // and for sidecar records, refreshes and GPU zones which use snapshot clock too
+    fn shifted(&self, timestamp: u64) -> u64 {
+        return (self.collapsed(timestamp).0 as i64 + self.time_offset) as u64;
+    }
+
+    // Timestamp without time removed by collapsed gaps which ended before it, and whether it falls into removed part of a gap.
+    // Events never fall inside collapsed gap, sidecar records and refreshes which do are moved to its end
+    fn collapsed(&self, timestamp: u64) -> (u64, bool) {
+        let collapsed = self.collapse.partition_point(|(end, _)| *end <= timestamp);
+        let removed = collapsed.checked_sub(1).map_or(0, |index| self.collapse[index].1);
+        return match self.collapse.get(collapsed) {
+            Some((end, removed_at_end)) if timestamp - removed > end - removed_at_end => (end - removed_at_end, true),
+            _ => (timestamp - removed, false),
+        };
+    }
+
+    // Snapshot timestamp of shifted one outside removed parts of gaps
+    fn unshifted(&self, timestamp: u64) -> u64 {
+        let kept = (timestamp as i64 - self.time_offset) as u64;
+        let collapsed = self.collapse.partition_point(|(end, removed)| end - removed <= kept);
+        return kept + collapsed.checked_sub(1).map_or(0, |index| self.collapse[index].1);
+    }
+
+    // GPU clock in nanoseconds loses the same time as CPU clock of collapsed gaps, so GPU zones stay under their submission
+    fn shifted_gpu(&self, timestamp: u64, gpu_time: u64) -> u64 {
+        let removed = timestamp - self.collapsed(timestamp).0;
+        return gpu_time.wrapping_sub((removed as f64 * self.snapshots[0].header.multiplier) as u64);
+    }
+
+    // Returns None for end without sent begin, such end is dropped and counted
+    fn end_zone(&mut self, thread_id: u32) -> Option<OpenZone> {
+        let zone = self.zone_stacks.entry(thread_id).or_default().pop();
+        if zone.is_none() {
+            self.unmatched_ends += 1;
+        }
+        return zone;
+    }
+
+    // Zone value and duration color are attached to the open zone, so they go right before zone end
+    fn send_zone_end(&mut self, thread_id: u32, zone: &OpenZone, timestamp: u64) -> Result<(), String> {
+        let duration = timestamp.saturating_sub(zone.start);
+        if let Some(parent) = self.zone_stacks.get_mut(&thread_id).and_then(|stack| stack.last_mut()) {
+            parent.children += duration;
+        }
+        if self.zone_self_time {
+            self.send_message(NetworkZoneValue {
+                query_type: QueryResponseType::ZoneValue,
+                value: (duration.saturating_sub(zone.children) as f64 * self.snapshots[0].header.multiplier) as u64,
+            })?;
+        }
+        self.send_duration_marks(thread_id, zone.location, duration, timestamp)?;
+        self.send_message(NetworkZoneEnd {
+            query_type: QueryResponseType::ZoneEnd,
+            timestamp: timestamp - self.timestamp,
+        })?;
+        self.record_event(UTracyEvent::end(thread_id, timestamp))?;
+        self.timestamp = timestamp;
+        return Ok(());
+    }
+
+    // Reports unpaired zones at the end of snapshot, open ones are optionally closed at the last sent timestamp
+    fn finish_zones(&mut self) -> Result<(), String> {
+        if self.unmatched_ends > 0 {
+            warning(&format!("dropped {} zone ends without matching begin", self.unmatched_ends));
+            self.unmatched_ends = 0;
+        }
+        let mut threads: Vec<u32> = self.zone_stacks.iter().filter(|(_, stack)| !stack.is_empty()).map(|(thread, _)| *thread).collect();
+        if threads.is_empty() {
+            return Ok(());
+        }
+        threads.sort();
+        let open: usize = threads.iter().map(|thread| self.zone_stacks[thread].len()).sum();
+        if !self.close_open_zones {
+            warning(&format!("{open} zones left open on {} threads", threads.len()));
+            self.zone_stacks.clear();
+            return Ok(());
+        }
+        println!("Closing {open} zones left open on {} threads", threads.len());
+        let timestamp = self.last_timestamp;
+        for thread in threads {
+            self.enter_thread(thread, timestamp)?;
+            while let Some(zone) = self.end_zone(thread) {
+                self.send_zone_end(thread, &zone, timestamp)?;
             }
-            result.map_err(|e| format!("{}", e))?;
-            let request: NetworkQuery = bincode::decode_from_slice(&buffer, BINCODE_CONFIG).unwrap().0;
-            match request.query_type {
-                ServerQueryType::ServerQueryTerminate => {
-                    return Ok(false);
+        }
+        return Ok(());
+    }
+
+    fn record_event(&mut self, event: UTracyEvent) -> Result<(), String> {
+        if let Some(session) = &mut self.session {
+            session.record(&event)?;
+        }
+        return Ok(());
+    }
+
+    // Zones renamed by filter script get location of their own, recolored ones are followed by color event
+    fn record_begin(&mut self, thread_id: u32, location: u64, timestamp: u64, action: &ZoneAction) -> Result<(), String> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        let (name, color) = match action {
+            ZoneAction::Change { name, color } => (name.as_deref(), *color),
+            _ => (None, None),
+        };
+        let location = session.location(self.table, location, name);
+        session.record(&UTracyEvent::begin(thread_id, location, timestamp))?;
+        if let Some(color) = color {
+            session.record(&UTracyEvent::color(thread_id, [(color >> 16) as u8, (color >> 8) as u8, color as u8, 0]))?;
+        }
+        return Ok(());
+    }
+
+    // Duration color replaces the one of the open zone. Watchlist message is put at zone end, message timestamps can't go back
+    fn send_duration_marks(&mut self, thread_id: u32, location: u64, duration: u64, timestamp: u64) -> Result<(), String> {
+        if let Some((yellow, red)) = self.color_thresholds {
+            let color = if duration < yellow {
+                DURATION_GREEN
+            } else if duration < red {
+                DURATION_YELLOW
+            } else {
+                DURATION_RED
+            };
+            self.send_message(NetworkZoneColor {
+                query_type: QueryResponseType::ZoneColor,
+                color_r: color[0],
+                color_g: color[1],
+                color_b: color[2],
+            })?;
+            self.record_event(UTracyEvent::color(thread_id, [color[0], color[1], color[2], 0]))?;
+        }
+        if self.watchlist.is_empty() {
+            return Ok(());
+        }
+        let table = self.table;
+        let name = || table.locations.get(location as usize).and_then(|source| table.strings.get(&source.name)).map_or("", |name| name.as_str());
+        let watchlist = self.watchlist;
+        let index = *self.watched.entry(location).or_insert_with(|| watchlist.iter().position(|watch| watch.pattern.is_match(name())));
+        let Some(watch) = index.map(|index| &watchlist[index]) else {
+            return Ok(());
+        };
+        let duration = (duration as f64 * self.snapshots[0].header.multiplier) as u64;
+        if duration <= watch.over {
+            return Ok(());
+        }
+        let text = format!("{} took {}: {}", name(), format_duration(duration), watch.message);
+        self.send_message(NetworkSingleString {
+            query_type: QueryResponseType::SingleStringData,
+            string: U16SizeString(&text),
+        })?;
+        self.send_message(NetworkMessage {
+            query_type: QueryResponseType::Message,
+            timestamp: timestamp.wrapping_sub(self.timestamp),
+        })?;
+        self.timestamp = timestamp;
+        return Ok(());
+    }
+
+    // Thumbnail of frame started by the last sent mark, zones after mark n belong to frame n
+    fn send_frame_strip(&mut self, frame: u64) -> Result<(), String> {
+        let Some(strip) = self.frame_strips.get(frame as usize) else {
+            return Ok(());
+        };
+        self.send_message(NetworkFrameImageData {
+            query_type: QueryResponseType::FrameImageData,
+            pointer: frame,
+            size: STRIP_IMAGE_BYTES as u32,
+            data: strip_image(strip),
+        })?;
+        self.send_message(NetworkFrameImage {
+            query_type: QueryResponseType::FrameImage,
+            frame: self.marks_sent - 1,
+            width: STRIP_WIDTH,
+            height: STRIP_HEIGHT,
+            flip: 0,
+        })?;
+        return Ok(());
+    }
+
+    fn send_annotations(&mut self, timestamp: u64) -> Result<(), String> {
+        if self.annotated {
+            return Ok(());
+        }
+        self.annotated = true;
+        for text in self.annotations {
+            self.send_message(NetworkSingleString {
+                query_type: QueryResponseType::SingleStringData,
+                string: U16SizeString(text),
+            })?;
+            self.send_message(NetworkMessage {
+                query_type: QueryResponseType::Message,
+                timestamp: timestamp.wrapping_sub(self.timestamp),
+            })?;
+            self.timestamp = timestamp;
+        }
+        return Ok(());
+    }
+
+    // Message at gap start on current thread, so Tracy timeline shows why it is empty
+    fn send_gap_markers(&mut self, until: u64) -> Result<(), String> {
+        while let Some((start, end)) = self.gaps.get(self.next_gap).copied() {
+            let multiplier = self.snapshots[0].header.multiplier;
+            let duration = ((end - start) as f64 * multiplier) as u64;
+            let (start, end) = (self.shifted(start), self.shifted(end));
+            if start > until {
+                break;
+            }
+            self.next_gap += 1;
+            let shown = ((end - start) as f64 * multiplier) as u64;
+            let text = if shown < duration {
+                format!("Idle for {}, collapsed to {}", format_duration(duration), format_duration(shown))
+            } else {
+                format!("Idle for {}", format_duration(duration))
+            };
+            self.send_message(NetworkSingleString {
+                query_type: QueryResponseType::SingleStringData,
+                string: U16SizeString(&text),
+            })?;
+            self.send_message(NetworkMessage {
+                query_type: QueryResponseType::Message,
+                timestamp: start.wrapping_sub(self.timestamp),
+            })?;
+            self.timestamp = start;
+        }
+        return Ok(());
+    }
+
+    // Renames and recolors the zone which was just opened on current thread
+    fn send_zone_change(&mut self, name: Option<String>, color: Option<u32>) -> Result<(), String> {
+        if let Some(name) = name {
+            self.send_message(NetworkSingleString {
+                query_type: QueryResponseType::SingleStringData,
+                string: U16SizeString(&name),
+            })?;
+            self.send_message(QueryResponseType::ZoneName)?;
+        }
+        if let Some(color) = color {
+            self.send_message(NetworkZoneColor {
+                query_type: QueryResponseType::ZoneColor,
+                color_r: (color >> 16) as u8,
+                color_g: (color >> 8) as u8,
+                color_b: color as u8,
+            })?;
+        }
+        return Ok(());
+    }
+
+    fn end_plot_zone(&mut self, zone: &OpenZone, timestamp: u64) {
+        if let Some(plot) = self.plot_locations.get(&zone.location) {
+            self.plot_values[*plot] += timestamp.saturating_sub(zone.start);
+        }
+    }
+
+    // Plot values are in milliseconds, plot time is relative to thread reference time like zones
+    fn send_plots(&mut self, timestamp: u64) -> Result<(), String> {
+        for plot in 0..self.plot_names.len() {
+            let value = self.plot_values[plot] as f64 * self.snapshots[0].header.multiplier / 1_000_000f64;
+            self.send_message(NetworkPlotDataDouble {
+                query_type: QueryResponseType::PlotDataDouble,
+                name: self.plot_names[plot],
+                timestamp: timestamp.wrapping_sub(self.timestamp),
+                value,
+            })?;
+            self.timestamp = timestamp;
+            self.plot_values[plot] = 0;
+        }
+        return Ok(());
+    }
+
+    fn send_timed_events(&mut self, until: u64) -> Result<(), String> {
+        self.send_annotations(until)?;
+        self.send_gap_markers(until)?;
+        self.send_sidecar_events(until)?;
+        self.send_vsync(until)?;
+        return Ok(());
+    }
+
+    fn send_vsync(&mut self, until: u64) -> Result<(), String> {
+        let Some(period) = self.vsync_period else {
+            return Ok(());
+        };
+        // Refreshes are counted on snapshot clock, so they keep their phase to frames after collapsed gaps
+        let mut next = self.next_vsync.unwrap_or(self.unshifted(until) as f64);
+        while self.shifted(next as u64) <= until {
+            let (_, removed) = self.collapsed(next as u64);
+            if !removed {
+                self.send_message(NetworkFrameVsync {
+                    query_type: QueryResponseType::FrameVsync,
+                    timestamp: self.shifted(next as u64),
+                    id: 0,
+                })?;
+            }
+            next += period;
+        }
+        self.next_vsync = Some(next);
+        return Ok(());
+    }
+
+    fn send_sidecar_events(&mut self, until: u64) -> Result<(), String> {
+        while let Some(event) = self.sidecar.events.get(self.next_sidecar_event) {
+            if self.shifted(event.timestamp()) > until {
+                break;
+            }
+            self.next_sidecar_event += 1;
+            match *event {
+                SidecarEvent::Switch { timestamp, cpu, old_thread, new_thread, wait_reason, old_thread_state } => {
+                    self.send_message(NetworkContextSwitch {
+                        query_type: QueryResponseType::ContextSwitch,
+                        timestamp: self.shifted(timestamp),
+                        old_thread,
+                        new_thread,
+                        cpu,
+                        old_thread_wait_reason: wait_reason,
+                        old_thread_state,
+                        previous_c_state: 0,
+                        new_thread_priority: 0,
+                        old_thread_priority: 0,
+                    })?;
                 }
-                ServerQueryType::ServerQueryString => {
-                    let unkn: String = "Unkn".into();
-                    let string = self.strings.get(&request.pointer).unwrap_or(&unkn);
-
-                    self.send_message(NetworkMessageString {
-                        query_type: QueryResponseType::StringData,
-                        pointer: request.pointer,
-                        string: U16SizeString(string),
+                SidecarEvent::Wakeup { timestamp, cpu, thread } => {
+                    self.send_message(NetworkThreadWakeup {
+                        query_type: QueryResponseType::ThreadWakeup,
+                        timestamp: self.shifted(timestamp),
+                        thread,
+                        cpu,
+                        adjust_reason: 0,
+                        adjust_increment: 0,
                     })?;
                 }
-                ServerQueryType::ServerQueryThreadString => {
-                    let main: String = "Main".into();
-                    self.send_message(NetworkMessageString {
-                        query_type: QueryResponseType::ThreadName,
-                        pointer: request.pointer,
-                        string: U16SizeString(&main),
+                SidecarEvent::GpuZoneBegin { timestamp, thread, query, location, gpu_time } => {
+                    let gpu_time = self.shifted_gpu(timestamp, gpu_time);
+                    let timestamp = self.shifted(timestamp);
+                    self.check_gpu_context(timestamp, thread, gpu_time)?;
+                    self.send_message(NetworkGpuZoneBegin {
+                        query_type: QueryResponseType::GpuZoneBeginSerial,
+                        cpu_time: timestamp.wrapping_sub(self.serial_timestamp),
+                        thread,
+                        query_id: query,
+                        context: 0,
+                        source_location: self.location_id(location),
                     })?;
+                    self.serial_timestamp = timestamp;
+                    self.send_gpu_time(query, gpu_time)?;
                 }
-                ServerQueryType::ServerQuerySourceLocation => {
-                    let source = self.locations.get(request.pointer as usize).unwrap();
-
-                    self.send_message(NetworkMessageSourceLocation {
-                        query_type: QueryResponseType::SourceLocation,
-                        location: *source,
+                SidecarEvent::GpuZoneEnd { timestamp, thread, query, gpu_time } => {
+                    let gpu_time = self.shifted_gpu(timestamp, gpu_time);
+                    let timestamp = self.shifted(timestamp);
+                    self.check_gpu_context(timestamp, thread, gpu_time)?;
+                    self.send_message(NetworkGpuZoneEnd {
+                        query_type: QueryResponseType::GpuZoneEndSerial,
+                        cpu_time: timestamp.wrapping_sub(self.serial_timestamp),
+                        thread,
+                        query_id: query,
+                        context: 0,
                     })?;
+                    self.serial_timestamp = timestamp;
+                    self.send_gpu_time(query, gpu_time)?;
                 }
-                ServerQueryType::ServerQuerySymbolCode => {
-                    self.send_message(QueryResponseType::AckSymbolCodeNotAvailable)?;
+                SidecarEvent::SysTime { timestamp, usage } => {
+                    self.send_message(NetworkSysTime {
+                        query_type: QueryResponseType::SysTimeReport,
+                        timestamp: self.shifted(timestamp),
+                        usage,
+                    })?;
                 }
-                ServerQueryType::ServerQuerySourceCode => {
-                    self.send_message(NetworkSourceCode {
-                        query_type: QueryResponseType::AckSourceCodeNotAvailable,
-                        id: request.pointer as u32,
+                SidecarEvent::SysPower { timestamp, delta, name } => {
+                    self.send_message(NetworkSysPower {
+                        query_type: QueryResponseType::SysPowerReport,
+                        timestamp: self.shifted(timestamp),
+                        delta,
+                        name,
                     })?;
                 }
-                ServerQueryType::ServerQueryDataTransfer | ServerQueryType::ServerQueryDataTransferPart => {
-                    self.send_message(QueryResponseType::AckServerQueryNoop)?;
+                SidecarEvent::Sample { timestamp, thread, callstack } => {
+                    self.send_message(NetworkCallstackPayload {
+                        query_type: QueryResponseType::CallstackPayload,
+                        pointer: callstack as u64 + 1,
+                        frames: &self.sidecar.callstacks[callstack as usize],
+                    })?;
+                    self.send_message(NetworkCallstackSample {
+                        query_type: QueryResponseType::CallstackSample,
+                        timestamp: self.shifted(timestamp),
+                        thread,
+                    })?;
                 }
-                _ => { println!("Unknown request {:?}", request.query_type) }
-            };
+            }
         }
-        self.flush_buffer()?;
-        return Ok(true);
+        return Ok(());
     }
 
-    fn send_message<W: Encode>(&mut self, message: W) -> Result<(), String> {
-        if self.encoder.0.len() > 250 * 1024 {
-            self.flush_buffer()?
+    // Single GPU context created on first GPU zone, its GPU clock ticks in nanoseconds
+    fn check_gpu_context(&mut self, timestamp: u64, thread: u32, gpu_time: u64) -> Result<(), String> {
+        if self.gpu_context {
+            return Ok(());
         }
-        bincode::encode_into_writer(message, &mut self.encoder, BINCODE_CONFIG).unwrap();
+        self.gpu_context = true;
+        // Unlike serial zone times, context CPU and GPU times are absolute and leave both references as they are
+        self.send_message(NetworkGpuNewContext {
+            query_type: QueryResponseType::GpuNewContext,
+            cpu_time: timestamp,
+            gpu_time,
+            thread,
+            period: 1f32,
+            context: 0,
+            flags: 0,
+            context_type: self.gpu_api,
+        })?;
+        let name: String = "GPU".into();
+        self.send_message(NetworkSingleString {
+            query_type: QueryResponseType::SingleStringData,
+            string: U16SizeString(&name),
+        })?;
+        self.send_message(NetworkGpuContextName {
+            query_type: QueryResponseType::GpuContextName,
+            context: 0,
+        })?;
         return Ok(());
     }
 
-    fn flush_buffer(&mut self) -> Result<(), String> {
-        if self.encoder.0.is_empty() {
+    fn send_gpu_time(&mut self, query: u16, gpu_time: u64) -> Result<(), String> {
+        self.send_message(NetworkGpuTime {
+            query_type: QueryResponseType::GpuTime,
+            gpu_time: gpu_time.wrapping_sub(self.gpu_timestamp),
+            query_id: query,
+            context: 0,
+        })?;
+        self.gpu_timestamp = gpu_time;
+        return Ok(());
+    }
+
+    // With merged threads every snapshot thread becomes fiber running on the first thread, zone stacks of fibers are kept apart
+    fn enter_thread(&mut self, thread_id: u32, timestamp: u64) -> Result<(), String> {
+        if !self.merge_threads {
+            self.check_thread(thread_id);
             return Ok(());
         }
-        self.socket.set_nonblocking(false).map_err(|e| format!("{}", e))?;
-        let result = compress(self.encoder.0.as_slice(), None, false).map_err(|e| format!("{}", e))?;
-        self.writer.write(&u32::to_le_bytes(result.len() as u32)).map_err(|e| format!("{}", e))?;
-        self.writer.write(result.as_slice()).map_err(|e| format!("{}", e))?;
-        self.writer.flush().map_err(|e| format!("{}", e))?;
-        self.encoder.0.clear();
-        self.socket.set_nonblocking(true).map_err(|e| format!("{}", e))?;
+        let merged = *self.merged_thread_id.get_or_insert(thread_id);
+        self.check_thread(merged);
+        if self.current_fiber != Some(thread_id) {
+            self.current_fiber = Some(thread_id);
+            self.send_message(NetworkFiberEnter {
+                query_type: QueryResponseType::FiberEnter,
+                timestamp: timestamp.wrapping_sub(self.timestamp),
+                fiber: thread_id as u64,
+                thread: merged,
+                group_hint: 0,
+            })?;
+            self.timestamp = timestamp;
+        }
         return Ok(());
     }
 
@@ -202,23 +1459,108 @@ impl ServerContext<'_> {
     }
 }
 
-pub fn handle_client(stream: TcpStream, header: &UTracyHeader, locations: &Vec<SourceLocation>, strings: &HashMap<u64, String>, events_data: BufReader<File>, skip_frames: u32, limit_frames: u32) -> Result<(), String> {
-    let mut reader = BufReader::new(&stream);
-    let mut writer = BufWriter::new(&stream);
+fn configure_socket(stream: &TcpStream, options: &ServerOptions) -> Result<(), String> {
+    if options.tcp_nodelay {
+        stream.set_nodelay(true).map_err(|e| format!("Error setting TCP_NODELAY: {e}"))?;
+    }
+    let socket = SockRef::from(stream);
+    if let Some(size) = options.send_buffer {
+        socket.set_send_buffer_size(size).map_err(|e| format!("Error setting send buffer size: {e}"))?;
+    }
+    if let Some(size) = options.recv_buffer {
+        socket.set_recv_buffer_size(size).map_err(|e| format!("Error setting receive buffer size: {e}"))?;
+    }
+    if options.send_buffer.is_some() || options.recv_buffer.is_some() {
+        // Kernel may round or cap requested sizes (e.g. Linux doubles them), report what was applied
+        println!("Socket buffers: send {} bytes, receive {} bytes", socket.send_buffer_size().unwrap_or(0), socket.recv_buffer_size().unwrap_or(0));
+    }
+    return Ok(());
+}
 
+// Reads Tracy UI greeting and answers with welcome status, header is sent by caller
+pub fn accept_handshake(reader: &mut BufReader<&TcpStream>, writer: &mut BufWriter<&TcpStream>, allow: Option<&AllowList>,
+                        auth_token: Option<&str>) -> Result<(), String> {
     let mut client_name = [0u8; 8];
-    reader.read(&mut client_name).map_err(|e| format!("{}", e))?;
-    if std::str::from_utf8(&client_name).unwrap() != "TracyPrf" {
-        return Err(format!("Invalid client, expected \"TracyPrf\", got {}", std::str::from_utf8(&client_name).unwrap()));
+    reader.read_exact(&mut client_name).map_err(|e| format!("{}", e))?;
+    let mut token = None;
+    if &client_name == AUTH_MAGIC {
+        let mut size = [0u8; 2];
+        reader.read_exact(&mut size).map_err(|e| format!("{}", e))?;
+        let mut received = vec![0u8; u16::from_le_bytes(size) as usize];
+        reader.read_exact(&mut received).map_err(|e| format!("{}", e))?;
+        token = Some(received);
+        reader.read_exact(&mut client_name).map_err(|e| format!("{}", e))?;
     }
-    let version: u32 = bincode::decode_from_reader(&mut reader, BINCODE_CONFIG).map_err(|e| format!("{}", e))?;
-    if version != 69 {
+    if &client_name != b"TracyPrf" {
+        return Err(format!("Invalid client, expected \"TracyPrf\", got {}", String::from_utf8_lossy(&client_name)));
+    }
+    let version: u32 = bincode::decode_from_reader(&mut *reader, BINCODE_CONFIG).map_err(|e| format!("{}", e))?;
+    if version != PROTOCOL_VERSION {
         writer.write(&[HandshakeStatus::HandshakeProtocolMismatch as u8]).map_err(|e| format!("{}", e))?;
-        return Err(format!("Invalid client version, expected 69, got {}", version));
+        let release = tracy_release(PROTOCOL_VERSION).unwrap_or_default();
+        return Err(format!("{} (protocol {version}) can't open this stream, connect with Tracy {release} (protocol {PROTOCOL_VERSION}) from {TRACY_DOWNLOAD}{release}. Known releases: {}",
+                           describe_protocol(version), release_table()));
+    }
+    // Greeting is read first, so Tracy shows the connection was refused instead of protocol error
+    if let Some(allow) = allow {
+        let peer = writer.get_ref().peer_addr().map_err(|e| format!("{}", e))?;
+        if !allow.allows(peer.ip()) {
+            writer.write(&[HandshakeStatus::HandshakeDropped as u8]).map_err(|e| format!("{}", e))?;
+            return Err(format!("Connection from {} is not allowed", peer.ip()));
+        }
+    }
+    // Token of client is ignored when server doesn't need one
+    if let Some(expected) = auth_token {
+        if !token.as_deref().is_some_and(|token| token_matches(expected, token)) {
+            writer.write(&[HandshakeStatus::HandshakeDropped as u8]).map_err(|e| format!("{}", e))?;
+            let peer = writer.get_ref().peer_addr().map_err(|e| format!("{}", e))?;
+            let reason = if token.is_some() { "wrong auth token" } else { "no auth token" };
+            return Err(format!("Connection from {} refused, {reason}", peer.ip()));
+        }
     }
 
     writer.write(&[HandshakeStatus::HandshakeWelcome as u8]).map_err(|e| format!("{}", e))?;
     writer.flush().map_err(|e| format!("{}", e))?;
+    return Ok(());
+}
+
+// Region (first frame, frame count) overrides skip and limit of options and streams every frame
+pub fn handle_client(stream: TcpStream, snapshots: &[Snapshot], table: &SourceTable, options: &ServerOptions, profile: &Profile,
+                     region: Option<(u32, u32)>) -> Result<(), String> {
+    let start = SystemTime::now();
+    let mut audit = SessionAudit {
+        peer: stream.peer_addr().map(|peer| peer.to_string()).unwrap_or_default(),
+        start: start.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        duration_ms: 0,
+        captures: snapshots.iter().map(|snapshot| snapshot.path.clone()).collect(),
+        profile: profile.name.clone(),
+        frames: 0,
+        queries: 0,
+        bytes_sent: 0,
+        error: None,
+    };
+    let result = stream_session(stream, snapshots, table, options, profile, region, &mut audit);
+    audit.duration_ms = start.elapsed().unwrap_or_default().as_millis() as u64;
+    audit.error = result.as_ref().err().cloned();
+    if let Some(path) = &options.audit_log {
+        if let Err(msg) = append_audit(path, &audit) {
+            println!("{msg}");
+        }
+    }
+    // Refused and broken sessions are only audited
+    if result.is_ok() {
+        notify_session(&options.webhooks, &audit);
+    }
+    return result;
+}
+
+fn stream_session(stream: TcpStream, snapshots: &[Snapshot], table: &SourceTable, options: &ServerOptions, profile: &Profile,
+                  region: Option<(u32, u32)>, audit: &mut SessionAudit) -> Result<(), String> {
+    let header = &snapshots[0].header;
+    configure_socket(&stream, options)?;
+    let mut reader = BufReader::new(&stream);
+    let mut writer = BufWriter::new(&stream);
+    accept_handshake(&mut reader, &mut writer, options.allow.as_ref(), options.auth_token.as_deref())?;
     bincode::encode_into_writer(NetworkHeader {
         multiplier: header.multiplier,
         init_begin: header.init_begin,
@@ -238,22 +1580,224 @@ pub fn handle_client(stream: TcpStream, header: &UTracyHeader, locations: &Vec<S
     }, WriterBox(&mut writer), BINCODE_CONFIG).map_err(|e| format!("{}", e))?;
     writer.flush().map_err(|e| format!("{}", e))?;
 
-    let mut buffer = Vec::new();
-    let mut context = ServerContext {
-        socket: &stream,
-        reader,
-        writer,
-        encoder: WriterBox(&mut buffer),
-        last_thread_id: 0,
-        timestamp: 0,
-        locations,
-        strings,
-        events_data,
-        skip_frames: skip_frames.into(),
-        limit_frames: limit_frames.into(),
+    let (mut buffer, mut compressed) = BUFFER_POOL.lock().unwrap().pop()
+        .unwrap_or_else(|| (Vec::with_capacity(MAX_BATCH_BYTES * 2), Vec::new()));
+    let connection = Connection::Socket { socket: &stream, reader, writer };
+    let mut context = ServerContext::new(connection, snapshots, table, options, profile, region, (&mut buffer, &mut compressed))?;
+    // Stream depends only on options unless region is requested, merged thread names are collected while streaming so merged sessions are not cached
+    let cacheable = options.block_cache && region.is_none() && !options.merge_processes;
+    let key: BlockKey = (snapshots.iter().map(|snapshot| (snapshot.path.clone(), snapshot.header.init_begin)).collect(), profile.name.clone());
+    let (generation, cached) = {
+        let cache = BLOCK_CACHE.lock().unwrap();
+        let cached = cache.1.iter().find(|(cached_key, _, _)| *cached_key == key).map(|(_, blocks, frames)| (blocks.clone(), *frames));
+        (cache.0, cached.filter(|_| cacheable))
     };
-    context.process_client()?;
+    let result = match &cached {
+        Some((blocks, _)) => {
+            println!("Replaying {} cached blocks", blocks.len());
+            context.replay_blocks(blocks)
+        }
+        None => {
+            context.record = cacheable.then(Vec::new);
+            if let Some(path) = options.record_session.as_deref().filter(|_| !SESSION_RECORDED.swap(true, Ordering::Relaxed)) {
+                context.session = Some(SessionRecorder::create(path)?);
+            }
+            context.send_app_info(&options.app_info, header.init_begin)
+                .and_then(|_| context.send_app_info(&profile.app_info(), header.init_begin))
+                .and_then(|_| context.process_client(options))
+        }
+    };
+    if let Some(record) = context.record.take().filter(|_| result.is_ok()) {
+        let mut cache = BLOCK_CACHE.lock().unwrap();
+        if cache.0 == generation && !cache.1.iter().any(|(cached_key, _, _)| *cached_key == key) {
+            println!("Cached {} blocks, {:.1} MB", record.len(), record.iter().map(|block| block.len()).sum::<usize>() as f64 / 1_000_000f64);
+            cache.1.push((key, Arc::new(record), context.marks_sent));
+        }
+    }
+    audit.frames = cached.map_or(context.marks_sent, |(_, frames)| frames);
+    audit.queries = context.queries_answered;
+    audit.bytes_sent = context.sent_bytes;
+    // Written even when client disconnected early, it holds what the client actually got
+    if let Some(session) = context.session.take() {
+        let mut header = header.clone();
+        header.seal_marker = 0;
+        session.finish(&header)?;
+    }
+    drop(context);
+    buffer.clear();
+    BUFFER_POOL.lock().unwrap().push((buffer, compressed));
+    result?;
     stream.shutdown(Shutdown::Both).map_err(|e| format!("{}", e))?;
 
     return Ok(());
 }
+
+// Totals of a stream encoded into memory
+pub struct MemoryStream {
+    pub frames: u32,
+    pub encoded_bytes: u64,
+    pub sent_bytes: u64,
+}
+
+// Runs the session path after handshake with memory in place of the socket, compressed blocks are counted and dropped
+pub fn stream_to_memory(snapshots: &[Snapshot], table: &SourceTable, options: &ServerOptions, profile: &Profile) -> Result<MemoryStream, String> {
+    let header = &snapshots[0].header;
+    let (mut buffer, mut compressed) = BUFFER_POOL.lock().unwrap().pop()
+        .unwrap_or_else(|| (Vec::with_capacity(MAX_BATCH_BYTES * 2), Vec::new()));
+    let mut context = ServerContext::new(Connection::Memory, snapshots, table, options, profile, None, (&mut buffer, &mut compressed))?;
+    let result = context.send_app_info(&options.app_info, header.init_begin)
+        .and_then(|_| context.send_app_info(&profile.app_info(), header.init_begin))
+        .and_then(|_| context.stream_all(options))
+        .and_then(|_| context.flush_buffer());
+    let stream = MemoryStream { frames: context.marks_sent, encoded_bytes: context.encoded_bytes, sent_bytes: context.sent_bytes };
+    drop(context);
+    buffer.clear();
+    BUFFER_POOL.lock().unwrap().push((buffer, compressed));
+    result?;
+    return Ok(stream);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::{BufReader, BufWriter, Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+    use lz4::block::decompress_to_buffer;
+    use crate::intern::SourceTable;
+    use crate::snapshot::{load_snapshot, Snapshot};
+    use crate::structs::{ServerQueryType, U32SizeString, UTracyHeader, UTracySourceLocation, QUERY_SIZE};
+    use crate::writer::create_snapshot;
+    use super::{Connection, Profile, ServerContext, ServerOptions, MAX_BATCH_BYTES, PARTIAL_QUERY_TIMEOUT};
+
+    // Capture with one zone on thread 1, removed again once loaded
+    fn capture(name: &str) -> (Snapshot, SourceTable) {
+        let path = std::env::temp_dir().join(format!("rtracy-{name}-{}.utracy", std::process::id())).to_string_lossy().to_string();
+        let location = UTracySourceLocation {
+            name: U32SizeString("Tick".into()),
+            function: U32SizeString("tick".into()),
+            file: U32SizeString("world.dm".into()),
+            line: 12,
+            color: [0u8; 4],
+        };
+        let mut writer = create_snapshot(&path, &UTracyHeader::default(), [location].iter()).unwrap();
+        writer.zone_begin(1, 0, 100).unwrap();
+        writer.zone_end(1, 200).unwrap();
+        writer.frame_mark(300).unwrap();
+        writer.finish().unwrap();
+        let mut table = SourceTable::new();
+        let snapshot = load_snapshot(&path, &mut table).unwrap();
+        fs::remove_file(&path).unwrap();
+        return (snapshot, table);
+    }
+
+    fn query(query_type: ServerQueryType, pointer: u64) -> [u8; QUERY_SIZE] {
+        let mut query = [0u8; QUERY_SIZE];
+        query[0] = query_type as u8;
+        query[1..9].copy_from_slice(&pointer.to_le_bytes());
+        return query;
+    }
+
+    // High priority queries first, so answers keep arrival order however the queries are split between polls
+    fn queries(table: &SourceTable) -> Vec<u8> {
+        return [
+            query(ServerQueryType::ServerQueryString, table.locations[0].name),
+            query(ServerQueryType::ServerQuerySourceLocation, table.ids[0]),
+            query(ServerQueryType::ServerQueryThreadString, 1),
+            query(ServerQueryType::ServerQuerySourceCode, 7),
+        ].concat();
+    }
+
+    fn connect() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_nodelay(true).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        return (server, client);
+    }
+
+    fn profile() -> Profile {
+        return Profile { name: None, skip_frames: 0, limit_frames: u32::MAX, filter_script: None, frame_stride: 1, within: None, max_depth: None, thread_names: HashMap::new() };
+    }
+
+    // Sends data in pieces ending at cuts with a poll after each, returns answered count and everything written back
+    fn answers(snapshot: &Snapshot, table: &SourceTable, data: &[u8], cuts: &[usize]) -> (u64, Vec<u8>) {
+        let (server, mut client) = connect();
+        let options = ServerOptions::default();
+        let profile = profile();
+        let (mut buffer, mut compressed) = (Vec::new(), Vec::new());
+        let connection = Connection::Socket { socket: &server, reader: BufReader::new(&server), writer: BufWriter::new(&server) };
+        let mut context = ServerContext::new(connection, std::slice::from_ref(snapshot), table, &options, &profile, None, (&mut buffer, &mut compressed)).unwrap();
+        context.connection.set_nonblocking(true).unwrap();
+        let mut start = 0;
+        for end in cuts.iter().copied().chain([data.len()]) {
+            client.write_all(&data[start..end]).unwrap();
+            start = end;
+            sleep(Duration::from_millis(20));
+            assert!(context.process_query().unwrap());
+        }
+        assert_eq!(context.query_filled, 0);
+        let answered = context.queries_answered;
+        drop(context);
+        server.shutdown(Shutdown::Write).unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        // Answers of separate polls go out in separate blocks, so blocks are compared decompressed
+        let mut answers = Vec::new();
+        let mut blocks = received.as_slice();
+        while !blocks.is_empty() {
+            let size = u32::from_le_bytes(blocks[..4].try_into().unwrap()) as usize;
+            let mut answer = vec![0u8; MAX_BATCH_BYTES];
+            let length = decompress_to_buffer(&blocks[4..4 + size], Some(MAX_BATCH_BYTES as i32), &mut answer).unwrap();
+            answers.extend_from_slice(&answer[..length]);
+            blocks = &blocks[4 + size..];
+        }
+        return (answered, answers);
+    }
+
+    fn split_queries(cuts: impl Fn(usize) -> Vec<usize>) {
+        let (snapshot, table) = capture(&format!("query-{:?}", cuts(0)));
+        let data = queries(&table);
+        let (answered, whole) = answers(&snapshot, &table, &data, &[]);
+        assert_eq!(answered, 4);
+        assert!(!whole.is_empty());
+        let cuts: Vec<usize> = (0..data.len() / QUERY_SIZE).flat_map(|index| cuts(index * QUERY_SIZE)).collect();
+        assert_eq!(answers(&snapshot, &table, &data, &cuts), (answered, whole));
+    }
+
+    #[test]
+    fn query_split_after_type() {
+        split_queries(|start| vec![start + 1]);
+    }
+
+    #[test]
+    fn query_split_before_last_byte() {
+        split_queries(|start| vec![start + 12]);
+    }
+
+    #[test]
+    fn query_split_into_single_bytes() {
+        split_queries(|start| (start + 1..start + QUERY_SIZE).collect());
+    }
+
+    #[test]
+    fn stalled_partial_query_ends_session() {
+        let (snapshot, table) = capture("stalled");
+        let (server, mut client) = connect();
+        let options = ServerOptions::default();
+        let profile = profile();
+        let (mut buffer, mut compressed) = (Vec::new(), Vec::new());
+        let connection = Connection::Socket { socket: &server, reader: BufReader::new(&server), writer: BufWriter::new(&server) };
+        let mut context = ServerContext::new(connection, std::slice::from_ref(&snapshot), &table, &options, &profile, None, (&mut buffer, &mut compressed)).unwrap();
+        context.connection.set_nonblocking(true).unwrap();
+        client.write_all(&query(ServerQueryType::ServerQueryString, table.locations[0].name)[..5]).unwrap();
+        sleep(Duration::from_millis(20));
+        let start = Instant::now();
+        context.wait_queries().unwrap();
+        assert!(start.elapsed() >= PARTIAL_QUERY_TIMEOUT);
+        assert!(start.elapsed() < PARTIAL_QUERY_TIMEOUT * 3);
+        assert_eq!((context.query_filled, context.queries_answered), (5, 0));
+    }
+}
@@ -0,0 +1,145 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+use std::time::Duration;
+use windows_service::{define_windows_service, service_control_handler, service_dispatcher};
+use windows_service::service::{ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo, ServiceStartType,
+    ServiceState, ServiceStatus, ServiceType};
+use windows_service::service_control_handler::ServiceControlHandlerResult;
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use crate::failure::usage;
+
+const SERVICE_NAME: &str = "rtracy";
+
+// Serve arguments of "service run", dispatcher calls service main without them
+static SERVE_ARGS: OnceLock<Vec<String>> = OnceLock::new();
+
+// Newest .utracy file when directory is served, so restarting service picks up last round
+fn latest_capture(path: &str) -> Result<String, String> {
+    if !Path::new(path).is_dir() {
+        return Ok(path.to_string());
+    }
+    let mut latest: Option<(std::time::SystemTime, PathBuf)> = None;
+    for entry in std::fs::read_dir(path).map_err(|e| format!("Error reading directory {path}: {e}"))? {
+        let entry = entry.map_err(|e| format!("Error reading directory {path}: {e}"))?;
+        let file = entry.path();
+        if file.extension().is_none_or(|extension| extension != "utracy") {
+            continue;
+        }
+        let modified = entry.metadata().and_then(|metadata| metadata.modified()).map_err(|e| format!("{}: {e}", file.display()))?;
+        if latest.as_ref().is_none_or(|(time, _)| modified > *time) {
+            latest = Some((modified, file));
+        }
+    }
+    let Some((_, file)) = latest else {
+        return Err(format!("No .utracy files in {path}"));
+    };
+    return Ok(file.to_string_lossy().to_string());
+}
+
+fn install(args: &[String]) -> Result<(), String> {
+    let Some(path) = args.first() else {
+        return Err(usage("Use: service install <file or directory> [serve options]"));
+    };
+    // Services start in system directory, snapshot path has to be absolute
+    let path = std::fs::canonicalize(path).map_err(|e| format!("Error opening {path}: {e}"))?;
+    let mut launch_arguments = vec![OsString::from("service"), OsString::from("run"), path.into_os_string()];
+    launch_arguments.extend(args[1..].iter().map(OsString::from));
+
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)
+        .map_err(|e| format!("Error connecting to service manager: {e}"))?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("RTracy server"),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe().map_err(|e| format!("{e}"))?,
+        launch_arguments,
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)
+        .map_err(|e| format!("Error creating service: {e}"))?;
+    service.set_description("Streams utracy snapshots to Tracy profiler").map_err(|e| format!("{e}"))?;
+    service.start::<&str>(&[]).map_err(|e| format!("Error starting service: {e}"))?;
+    println!("Service {SERVICE_NAME} installed and started");
+    return Ok(());
+}
+
+fn uninstall() -> Result<(), String> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(|e| format!("Error connecting to service manager: {e}"))?;
+    let service = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)
+        .map_err(|e| format!("Error opening service: {e}"))?;
+    service.delete().map_err(|e| format!("Error deleting service: {e}"))?;
+    if service.query_status().map_err(|e| format!("{e}"))?.current_state != ServiceState::Stopped {
+        service.stop().map_err(|e| format!("Error stopping service: {e}"))?;
+    }
+    println!("Service {SERVICE_NAME} uninstalled");
+    return Ok(());
+}
+
+fn status(state: ServiceState, controls_accepted: ServiceControlAccept, exit_code: u32) -> ServiceStatus {
+    return ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    };
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+// Server loop never returns, service stops when control handler or failed serve reports it
+fn service_main(_arguments: Vec<OsString>) {
+    let (stop_sender, stop_receiver) = mpsc::channel();
+    let handler_sender = stop_sender.clone();
+    let handler = move |control| match control {
+        ServiceControl::Stop => {
+            let _ = handler_sender.send(0);
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let Ok(status_handle) = service_control_handler::register(SERVICE_NAME, handler) else {
+        return;
+    };
+    let _ = status_handle.set_service_status(status(ServiceState::Running, ServiceControlAccept::STOP, 0));
+
+    let mut args = SERVE_ARGS.get().cloned().unwrap_or_default();
+    thread::spawn(move || {
+        let result = match args.first().map(|path| latest_capture(path)) {
+            Some(Ok(path)) => {
+                args[0] = path;
+                crate::serve(&args)
+            }
+            Some(Err(msg)) => Err(msg),
+            None => Err("No snapshot configured".into()),
+        };
+        if let Err(msg) = result {
+            println!("{msg}");
+        }
+        let _ = stop_sender.send(1);
+    });
+    let exit_code = stop_receiver.recv().unwrap_or(1);
+    let _ = status_handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty(), exit_code));
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    return match args.first().map(String::as_str) {
+        Some("install") => install(&args[1..]),
+        Some("uninstall") => uninstall(),
+        Some("run") => {
+            let _ = SERVE_ARGS.set(args[1..].to_vec());
+            service_dispatcher::start(SERVICE_NAME, ffi_service_main).map_err(|e| format!("Error starting service dispatcher: {e}"))
+        }
+        _ => Err(usage("Use: service install <file or directory> [serve options] | service uninstall | service run")),
+    };
+}
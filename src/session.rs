@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use crate::intern::SourceTable;
+use crate::structs::{BINCODE_CONFIG, U32SizeString, UTracyEvent, UTracyHeader, UTracySourceLocation, WriterBox};
+use crate::writer::create_snapshot;
+
+// Events exactly as streamed to one client, after frame range, filter script, frame stride, merging and gap collapsing.
+// Locations are only known at the end, so events go to temporary file first and are copied after locations
+pub struct SessionRecorder {
+    path: String,
+    events_path: String,
+    events: BufWriter<File>,
+    // Recorded location index by table location and name given by filter script
+    location_map: HashMap<(u64, Option<String>), u32>,
+    locations: Vec<UTracySourceLocation>,
+}
+
+impl SessionRecorder {
+    pub fn create(path: &str) -> Result<SessionRecorder, String> {
+        let events_path = format!("{path}.events");
+        let file = File::create(&events_path).map_err(|e| format!("Error creating file {events_path}: {e}"))?;
+        return Ok(SessionRecorder {
+            path: path.to_string(),
+            events_path,
+            events: BufWriter::new(file),
+            location_map: HashMap::new(),
+            locations: Vec::new(),
+        });
+    }
+
+    pub fn location(&mut self, table: &SourceTable, location: u64, name: Option<&str>) -> u32 {
+        let key = (location, name.map(str::to_string));
+        if let Some(index) = self.location_map.get(&key) {
+            return *index;
+        }
+        let string = |pointer: u64| U32SizeString(table.string(pointer).unwrap_or_default().into_owned());
+        let recorded = match table.locations.get(location as usize) {
+            Some(source) => UTracySourceLocation {
+                name: name.map(|name| U32SizeString(name.to_string())).unwrap_or_else(|| string(source.name)),
+                function: string(source.function),
+                file: string(source.file),
+                line: source.line,
+                color: [source.color_r, source.color_g, source.color_b, 0],
+            },
+            // Broken snapshot location, kept as empty one like Tracy shows it
+            None => UTracySourceLocation {
+                name: U32SizeString(name.unwrap_or("").to_string()),
+                function: U32SizeString(String::new()),
+                file: U32SizeString(String::new()),
+                line: 0,
+                color: [0u8; 4],
+            },
+        };
+        let index = self.locations.len() as u32;
+        self.locations.push(recorded);
+        self.location_map.insert(key, index);
+        return index;
+    }
+
+    pub fn record(&mut self, event: &UTracyEvent) -> Result<(), String> {
+        return bincode::encode_into_writer(event, WriterBox(&mut self.events), BINCODE_CONFIG).map_err(|e| format!("{}: {e}", self.events_path));
+    }
+
+    pub fn finish(self, header: &UTracyHeader) -> Result<(), String> {
+        let SessionRecorder { path, events_path, events, locations, .. } = self;
+        events.into_inner().map_err(|e| format!("{events_path}: {e}"))?;
+        let mut writer = create_snapshot(&path, header, locations.iter())?;
+        let mut reader = BufReader::new(File::open(&events_path).map_err(|e| format!("{events_path}: {e}"))?);
+        let count = writer.copy_events(&mut reader).map_err(|e| format!("{path}: {e}"))?;
+        writer.finish().map_err(|e| format!("{path}: {e}"))?.flush().map_err(|e| format!("{path}: {e}"))?;
+        fs::remove_file(&events_path).map_err(|e| format!("{events_path}: {e}"))?;
+        println!("Recorded session to {path}: {count} events, {} source locations", locations.len());
+        return Ok(());
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use crate::intern::SourceTable;
+use crate::structs::{GpuContextType, SourceLocation, U32SizeString, UTracyHeader, MAX_CALLSTACK_FRAMES};
+
+// Records captured next to the snapshot (ETW/perf exports), merged into the zone stream by timestamp
+#[derive(Copy, Clone, Debug)]
+pub enum SidecarEvent {
+    Switch {
+        timestamp: u64,
+        cpu: u8,
+        old_thread: u32,
+        new_thread: u32,
+        wait_reason: u8,
+        old_thread_state: u8,
+    },
+    Wakeup {
+        timestamp: u64,
+        cpu: u8,
+        thread: u32,
+    },
+    Sample {
+        timestamp: u64,
+        thread: u32,
+        callstack: u32,
+    },
+    // GPU zones are sent with CPU timestamp of submission, GPU time follows under the same query id
+    GpuZoneBegin {
+        timestamp: u64,
+        thread: u32,
+        query: u16,
+        location: u64,
+        gpu_time: u64,
+    },
+    GpuZoneEnd {
+        timestamp: u64,
+        thread: u32,
+        query: u16,
+        gpu_time: u64,
+    },
+    // Host CPU usage in percent
+    SysTime {
+        timestamp: u64,
+        usage: f32,
+    },
+    // Energy in microjoules used since previous report, Tracy plots it as watts
+    SysPower {
+        timestamp: u64,
+        delta: u64,
+        name: u64,
+    },
+}
+
+impl SidecarEvent {
+    pub fn timestamp(&self) -> u64 {
+        return match self {
+            SidecarEvent::Switch { timestamp, .. } => *timestamp,
+            SidecarEvent::Wakeup { timestamp, .. } => *timestamp,
+            SidecarEvent::Sample { timestamp, .. } => *timestamp,
+            SidecarEvent::GpuZoneBegin { timestamp, .. } => *timestamp,
+            SidecarEvent::GpuZoneEnd { timestamp, .. } => *timestamp,
+            SidecarEvent::SysTime { timestamp, .. } => *timestamp,
+            SidecarEvent::SysPower { timestamp, .. } => *timestamp,
+        };
+    }
+}
+
+#[derive(Debug)]
+pub struct CallstackFrame {
+    pub name: String,
+    pub file: String,
+    pub image: String,
+}
+
+#[derive(Default)]
+pub struct Sidecar {
+    pub events: Vec<SidecarEvent>,
+    // Frame pointers are 1-based indices into frames, 0 is never sent to Tracy
+    pub callstacks: Vec<Vec<u64>>,
+    pub frames: Vec<CallstackFrame>,
+}
+
+impl Sidecar {
+    pub fn get_frame(&self, pointer: u64) -> Option<&CallstackFrame> {
+        if pointer == 0 {
+            return None;
+        }
+        return self.frames.get(pointer as usize - 1);
+    }
+
+    fn finish(&mut self) {
+        self.events.sort_by_key(|e| e.timestamp());
+    }
+}
+
+fn parse_field<T: std::str::FromStr>(fields: &[&str], index: usize) -> Result<T, String> {
+    let field = fields.get(index).ok_or(format!("missing field {index}"))?;
+    return field.trim().parse().map_err(|_| format!("invalid field {index} \"{field}\""));
+}
+
+fn parse_optional_field<T: std::str::FromStr + Default>(fields: &[&str], index: usize) -> Result<T, String> {
+    if fields.len() <= index {
+        return Ok(T::default());
+    }
+    return parse_field(fields, index);
+}
+
+// One record per line, timestamps must use the same clock as the snapshot:
+//   switch,<timestamp>,<cpu>,<old_thread>,<new_thread>[,<wait_reason>,<old_thread_state>]
+//   wakeup,<timestamp>,<cpu>,<thread>
+fn parse_scheduling_event(line: &str) -> Result<SidecarEvent, String> {
+    let fields: Vec<&str> = line.split(',').collect();
+    return match fields[0].trim() {
+        "switch" => Ok(SidecarEvent::Switch {
+            timestamp: parse_field(&fields, 1)?,
+            cpu: parse_field(&fields, 2)?,
+            old_thread: parse_field(&fields, 3)?,
+            new_thread: parse_field(&fields, 4)?,
+            wait_reason: parse_optional_field(&fields, 5)?,
+            old_thread_state: parse_optional_field(&fields, 6)?,
+        }),
+        "wakeup" => Ok(SidecarEvent::Wakeup {
+            timestamp: parse_field(&fields, 1)?,
+            cpu: parse_field(&fields, 2)?,
+            thread: parse_field(&fields, 3)?,
+        }),
+        other => Err(format!("unknown record \"{other}\"")),
+    };
+}
+
+pub fn load_scheduling_events(sidecar: &mut Sidecar, path: &str) -> Result<(), String> {
+    let reader = BufReader::new(File::open(path).map_err(|e| format!("{path}: {e}"))?);
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("{path}: {e}"))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        sidecar.events.push(parse_scheduling_event(line).map_err(|e| format!("{path}: line {}: {e}", i + 1))?);
+    }
+    sidecar.finish();
+    return Ok(());
+}
+
+// Names of --gpu-api
+pub fn parse_gpu_api(name: &str) -> Result<GpuContextType, String> {
+    return match name.to_ascii_lowercase().as_str() {
+        "opengl" => Ok(GpuContextType::OpenGl),
+        "vulkan" => Ok(GpuContextType::Vulkan),
+        "opencl" => Ok(GpuContextType::OpenCl),
+        "d3d12" => Ok(GpuContextType::Direct3D12),
+        "d3d11" => Ok(GpuContextType::Direct3D11),
+        "metal" => Ok(GpuContextType::Metal),
+        "custom" => Ok(GpuContextType::Custom),
+        _ => Err(format!("Unknown GPU API \"{name}\", expected opengl, vulkan, opencl, d3d12, d3d11, metal or custom")),
+    };
+}
+
+// One zone per line, CPU timestamps use the same clock as the snapshot, GPU timestamps are nanoseconds of GPU clock:
+//   <thread>,<cpu_begin>,<cpu_end>,<gpu_begin>,<gpu_end>,<zone name>
+// Zone names get their own source locations appended to the table
+pub fn load_gpu_zones(sidecar: &mut Sidecar, path: &str, table: &mut SourceTable) -> Result<usize, String> {
+    let reader = BufReader::new(File::open(path).map_err(|e| format!("{path}: {e}"))?);
+    let file = table.intern(U32SizeString(path.to_string()));
+    let mut locations = HashMap::<String, u64>::new();
+    let mut query: u16 = 0;
+    let mut zones = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("{path}: {e}"))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(6, ',').collect();
+        let parse = |index| parse_field::<u64>(&fields, index).map_err(|e| format!("{path}: line {}: {e}", i + 1));
+        let thread = parse_field::<u32>(&fields, 0).map_err(|e| format!("{path}: line {}: {e}", i + 1))?;
+        let (cpu_begin, cpu_end, gpu_begin, gpu_end) = (parse(1)?, parse(2)?, parse(3)?, parse(4)?);
+        let name = fields.get(5).map(|name| name.trim()).unwrap_or("GPU zone");
+        let location = match locations.get(name) {
+            Some(location) => *location,
+            None => {
+                let name_string = table.intern(U32SizeString(name.to_string()));
+                let location = table.push_location(SourceLocation { name: name_string, function: name_string, file, line: 0, color_r: 0, color_g: 0, color_b: 0 }) as u64;
+                locations.insert(name.to_string(), location);
+                location
+            }
+        };
+        sidecar.events.push(SidecarEvent::GpuZoneBegin { timestamp: cpu_begin, thread, query, location, gpu_time: gpu_begin });
+        sidecar.events.push(SidecarEvent::GpuZoneEnd { timestamp: cpu_end.max(cpu_begin), thread, query: query.wrapping_add(1), gpu_time: gpu_end });
+        query = query.wrapping_add(2);
+        zones += 1;
+    }
+    sidecar.finish();
+    return Ok(zones);
+}
+
+// Monitoring export with header line allowed, timestamps are unix seconds (fractions allowed), power in watts:
+//   <timestamp>,<cpu percent>[,<power>]
+// Values are mapped onto the snapshot clock using the capture epoch, records outside of capture are dropped
+pub fn load_sysmon(sidecar: &mut Sidecar, path: &str, header: &UTracyHeader, table: &mut SourceTable) -> Result<usize, String> {
+    let reader = BufReader::new(File::open(path).map_err(|e| format!("{path}: {e}"))?);
+    let name = table.intern(U32SizeString("Host power".to_string()));
+    let mut previous: Option<u64> = None;
+    let mut records = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("{path}: {e}"))?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        let Ok(seconds) = fields[0].trim().parse::<f64>() else {
+            if i == 0 {
+                continue;
+            }
+            return Err(format!("{path}: line {}: invalid timestamp \"{}\"", i + 1, fields[0]));
+        };
+        let usage: f32 = parse_field(&fields, 1).map_err(|e| format!("{path}: line {}: {e}", i + 1))?;
+        let power: Option<f64> = match fields.get(2).map(|field| field.trim()) {
+            Some(field) if !field.is_empty() => Some(parse_field(&fields, 2).map_err(|e| format!("{path}: line {}: {e}", i + 1))?),
+            _ => None,
+        };
+        let offset_ns = seconds * 1e9 - (header.epoch * 1_000_000_000) as f64;
+        if offset_ns < 0f64 {
+            continue;
+        }
+        let timestamp = header.init_begin + (offset_ns / header.multiplier) as u64;
+        sidecar.events.push(SidecarEvent::SysTime { timestamp, usage });
+        if let Some(watts) = power {
+            // First report only creates the plot in Tracy, energy is counted from the previous sample
+            let elapsed_ns = previous.map_or(0f64, |previous| timestamp.saturating_sub(previous) as f64 * header.multiplier);
+            let delta = (watts * elapsed_ns / 1000f64) as u64;
+            sidecar.events.push(SidecarEvent::SysPower { timestamp, delta, name });
+            previous = Some(timestamp);
+        }
+        records += 1;
+    }
+    sidecar.finish();
+    return Ok(records);
+}
+
+// Perf prints "seconds.micros", parsed as integer nanoseconds to keep precision of wall clock values
+fn parse_perf_time(token: &str) -> Option<u64> {
+    let (seconds, fraction) = token.strip_suffix(':')?.split_once('.')?;
+    let seconds: u64 = seconds.parse().ok()?;
+    let digits = fraction.len().min(9);
+    let fraction: u64 = fraction[..digits].parse().ok()?;
+    return Some(seconds * 1_000_000_000 + fraction * 10u64.pow(9 - digits as u32));
+}
+
+// Sample header of `perf script` output: "comm pid/tid [cpu] seconds: period event:"
+fn parse_perf_sample_header(line: &str) -> Option<(u64, u32, u64)> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let time_index = tokens.iter().position(|t| parse_perf_time(t).is_some())?;
+    let time_ns = parse_perf_time(tokens[time_index])?;
+    let mut id_index = time_index.checked_sub(1)?;
+    if tokens[id_index].starts_with('[') {
+        id_index = id_index.checked_sub(1)?;
+    }
+    let (pid, tid) = match tokens[id_index].split_once('/') {
+        Some((pid, tid)) => (pid.parse().ok()?, tid.parse().ok()?),
+        None => (0, tokens[id_index].parse().ok()?),
+    };
+    return Some((pid, tid, time_ns));
+}
+
+// Callstack line of `perf script` output: "address symbol+offset (image)"
+fn parse_perf_frame(line: &str) -> CallstackFrame {
+    let line = line.trim();
+    let rest = line.split_once(' ').map(|(_, rest)| rest.trim()).unwrap_or("");
+    let (symbol, image) = match rest.rfind(" (") {
+        Some(position) => (&rest[..position], rest[position + 2..].trim_end_matches(')')),
+        None => (rest, ""),
+    };
+    let name = match symbol.rfind("+0x") {
+        Some(position) => &symbol[..position],
+        None => symbol,
+    };
+    return CallstackFrame {
+        name: if name.is_empty() { "[unknown]".into() } else { name.into() },
+        file: image.into(),
+        image: image.rsplit('/').next().unwrap_or("").into(),
+    };
+}
+
+// Perf timestamps are expected in wall clock seconds (perf record -k CLOCK_REALTIME),
+// they are mapped onto the snapshot clock using the capture epoch
+pub fn load_perf_samples(sidecar: &mut Sidecar, path: &str, header: &UTracyHeader) -> Result<(), String> {
+    let reader = BufReader::new(File::open(path).map_err(|e| format!("{path}: {e}"))?);
+    let mut frame_ids = HashMap::<(String, String), u64>::new();
+    let mut callstack_ids = HashMap::<Vec<u64>, u32>::new();
+    let mut current: Option<(u64, u32)> = None;
+    let mut stack = Vec::<u64>::new();
+    for line in reader.lines().chain(std::iter::once(Ok(String::new()))) {
+        let line = line.map_err(|e| format!("{path}: {e}"))?;
+        if line.trim().is_empty() {
+            if let Some((timestamp, thread)) = current.take() {
+                if !stack.is_empty() {
+                    let next_id = callstack_ids.len() as u32;
+                    let callstack = *callstack_ids.entry(stack.clone()).or_insert_with(|| {
+                        sidecar.callstacks.push(stack.clone());
+                        next_id
+                    });
+                    sidecar.events.push(SidecarEvent::Sample { timestamp, thread, callstack });
+                }
+            }
+            stack.clear();
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            // perf prints innermost frame first, outermost frames of very deep stacks are dropped
+            if current.is_some() && stack.len() < MAX_CALLSTACK_FRAMES {
+                let frame = parse_perf_frame(&line);
+                let next_id = sidecar.frames.len() as u64 + 1;
+                let id = *frame_ids.entry((frame.name.clone(), frame.file.clone())).or_insert_with(|| {
+                    sidecar.frames.push(frame);
+                    next_id
+                });
+                stack.push(id);
+            }
+            continue;
+        }
+        current = None;
+        stack.clear();
+        if let Some((pid, tid, time_ns)) = parse_perf_sample_header(&line) {
+            if pid != 0 && pid != header.process_id {
+                continue;
+            }
+            let Some(offset_ns) = time_ns.checked_sub(header.epoch * 1_000_000_000) else {
+                continue;
+            };
+            let timestamp = header.init_begin + (offset_ns as f64 / header.multiplier) as u64;
+            current = Some((timestamp, tid));
+        }
+    }
+    sidecar.finish();
+    return Ok(());
+}
@@ -0,0 +1,340 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::str;
+use std::sync::Arc;
+use bincode::de::read::Reader;
+use bincode::error::DecodeError;
+use std::string::FromUtf8Error;
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+use crate::{cache, seal};
+use crate::failure::{fail, open_failed, ErrorKind as Kind};
+use crate::intern::{location_id, LazyString, SourceTable};
+use crate::structs::{BINCODE_CONFIG, SourceLocation, U32SizeString, UTracyHeader};
+
+pub const FILE_SIGNATURE: u64 = 0x6D64796361727475;
+// Locations decoded at once, bounds memory of raw strings waiting for hashing
+const LOCATION_CHUNK: usize = 65536;
+// Three empty strings with their lengths, line and color
+const MIN_LOCATION_BYTES: u64 = 3 * 4 + 8;
+
+pub struct Snapshot {
+    pub path: String,
+    pub header: UTracyHeader,
+    pub events_position: u64,
+    // Index of first snapshot location in SourceTable::locations
+    pub location_offset: u32,
+    pub location_count: u32,
+    // Whole file when it is preloaded or mapped, events are read from disk otherwise
+    pub bytes: Option<FileBytes>,
+}
+
+// Capture file in memory or mapped, shared by everything reading its events
+#[derive(Clone)]
+pub struct FileBytes(Arc<dyn AsRef<[u8]> + Send + Sync>);
+
+impl AsRef<[u8]> for FileBytes {
+    fn as_ref(&self) -> &[u8] {
+        return (*self.0).as_ref();
+    }
+}
+
+pub enum EventReader {
+    File(BufReader<File>),
+    Memory(Cursor<FileBytes>),
+}
+
+impl Read for EventReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        return match self {
+            EventReader::File(reader) => Read::read(reader, buf),
+            EventReader::Memory(reader) => Read::read(reader, buf),
+        };
+    }
+}
+
+// Events are decoded with bincode reader, so in-memory files are decoded without copying through a buffer
+impl Reader for EventReader {
+    fn read(&mut self, bytes: &mut [u8]) -> Result<(), DecodeError> {
+        return match self {
+            EventReader::File(reader) => Reader::read(reader, bytes),
+            EventReader::Memory(reader) => reader.read_exact(bytes).map_err(|inner| DecodeError::Io { inner, additional: bytes.len() }),
+        };
+    }
+
+    fn peek_read(&mut self, n: usize) -> Option<&[u8]> {
+        return match self {
+            EventReader::File(reader) => reader.peek_read(n),
+            EventReader::Memory(reader) => {
+                let position = reader.position() as usize;
+                reader.get_ref().as_ref().get(position..position + n)
+            }
+        };
+    }
+
+    fn consume(&mut self, n: usize) {
+        match self {
+            EventReader::File(reader) => reader.consume(n),
+            EventReader::Memory(reader) => reader.set_position(reader.position() + n as u64),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn program_name(&self) -> &str {
+        return str::from_utf8(&self.header.program_name).unwrap_or("").trim_end_matches('\0');
+    }
+
+    // Snapshot ticks to unix time in nanoseconds, capture epoch corresponds to init_begin
+    pub fn wall_clock_ns(&self, ticks: u64) -> u64 {
+        let offset = ((ticks as i64 - self.header.init_begin as i64) as f64 * self.header.multiplier) as i64;
+        return (self.header.epoch as i64 * 1_000_000_000 + offset).max(0) as u64;
+    }
+
+    pub fn ticks_at_wall_clock(&self, unix_ns: u64) -> u64 {
+        let offset = ((unix_ns as i64 - self.header.epoch as i64 * 1_000_000_000) as f64 / self.header.multiplier) as i64;
+        return (self.header.init_begin as i64 + offset).max(0) as u64;
+    }
+
+    pub fn open_events(&self) -> Result<EventReader, String> {
+        if let Some(bytes) = &self.bytes {
+            let mut reader = Cursor::new(bytes.clone());
+            reader.set_position(self.events_position);
+            return Ok(EventReader::Memory(reader));
+        }
+        let mut reader = BufReader::new(File::open(&self.path).map_err(|e| format!("{}: {e}", self.path))?);
+        reader.seek(SeekFrom::Start(self.events_position)).map_err(|e| format!("{}: {e}", self.path))?;
+        return Ok(EventReader::File(reader));
+    }
+
+    // Reads whole file into memory, sessions stop touching disk
+    pub fn preload(&mut self) -> Result<(), String> {
+        let bytes = fs::read(&self.path).map_err(|e| format!("{}: {e}", self.path))?;
+        self.bytes = Some(FileBytes(Arc::new(bytes)));
+        return Ok(());
+    }
+
+    // Maps file, its pages are read on first use and dropped by system under memory pressure.
+    // Captures are written to new files and never changed in place, so mapping stays valid. File that is already shorter than
+    // what was loaded from it is refused, reading mapped pages past its end would kill the process instead of failing the read
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn map(&mut self) -> Result<(), String> {
+        let file = File::open(&self.path).map_err(|e| format!("{}: {e}", self.path))?;
+        let length = file.metadata().map_err(|e| format!("{}: {e}", self.path))?.len();
+        if length < self.events_position {
+            return Err(format!("{} is {length} bytes, shorter than its header and source locations of {} bytes", self.path, self.events_position));
+        }
+        seal::sealed_length(&self.path, &self.header)?;
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| format!("{}: {e}", self.path))?;
+        self.bytes = Some(FileBytes(Arc::new(map)));
+        return Ok(());
+    }
+}
+
+// Parsing works on any reader, so snapshots can be read from files as well as from memory buffers
+pub fn read_header<R: Read>(reader: &mut R) -> Result<UTracyHeader, String> {
+    let header: UTracyHeader = bincode::decode_from_std_read(reader, BINCODE_CONFIG).map_err(|e| match e {
+        DecodeError::Io { inner, .. } if inner.kind() == ErrorKind::UnexpectedEof => fail(Kind::Truncated, "File ends inside utracy header, it is truncated or not a utracy capture"),
+        e => format!("{e}"),
+    })?;
+
+    if header.signature != FILE_SIGNATURE {
+        return Err(fail(Kind::BadSignature, format!("Wrong utracy file signature, expected \"{FILE_SIGNATURE}\" got \"{}\"", header.signature)));
+    }
+
+    if header.version != 2 {
+        return Err(fail(Kind::UnsupportedVersion, format!("Wrong utracy file version, expected 2 got {}", header.version)));
+    }
+    return Ok(header);
+}
+
+// Strings of location as read from file, validated and hashed later on worker threads
+struct RawLocation {
+    strings: [Vec<u8>; 3],
+    // Positions of string bytes from start of locations, kept for tables with lazy strings
+    offsets: [u64; 3],
+    line: u32,
+    color: [u8; 4],
+}
+
+struct HashedLocation {
+    strings: [(u64, String); 3],
+    offsets: [u64; 3],
+    line: u32,
+    color: [u8; 4],
+}
+
+fn location_error(e: io::Error) -> String {
+    if e.kind() == ErrorKind::UnexpectedEof {
+        return fail(Kind::Truncated, "File ends inside source locations, it is truncated");
+    }
+    return format!("{e}");
+}
+
+fn read_raw_string<R: Read>(reader: &mut R, position: &mut u64) -> Result<(Vec<u8>, u64), String> {
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length).map_err(location_error)?;
+    // Grows with bytes actually read, so corrupted length fails at end of file instead of allocating it up front
+    let length = u32::from_le_bytes(length) as u64;
+    let mut bytes = Vec::new();
+    reader.take(length).read_to_end(&mut bytes).map_err(location_error)?;
+    if (bytes.len() as u64) < length {
+        return Err(location_error(ErrorKind::UnexpectedEof.into()));
+    }
+    let offset = *position + 4;
+    *position = offset + bytes.len() as u64;
+    return Ok((bytes, offset));
+}
+
+fn read_raw_location<R: Read>(reader: &mut R, position: &mut u64) -> Result<RawLocation, String> {
+    let (name, name_offset) = read_raw_string(reader, position)?;
+    let (function, function_offset) = read_raw_string(reader, position)?;
+    let (file, file_offset) = read_raw_string(reader, position)?;
+    let mut tail = [0u8; 8];
+    reader.read_exact(&mut tail).map_err(location_error)?;
+    *position += 8;
+    return Ok(RawLocation {
+        strings: [name, function, file],
+        offsets: [name_offset, function_offset, file_offset],
+        line: u32::from_le_bytes([tail[0], tail[1], tail[2], tail[3]]),
+        color: [tail[4], tail[5], tail[6], tail[7]],
+    });
+}
+
+fn hash_location(raw: RawLocation) -> Result<HashedLocation, String> {
+    let [name, function, file] = raw.strings.map(|bytes| String::from_utf8(bytes).map(|string| {
+        let string = U32SizeString(string);
+        return (string.get_hash(), string.0);
+    }));
+    let error = |e: FromUtf8Error| format!("{}", e.utf8_error());
+    return Ok(HashedLocation {
+        strings: [name.map_err(error)?, function.map_err(error)?, file.map_err(error)?],
+        offsets: raw.offsets,
+        line: raw.line,
+        color: raw.color,
+    });
+}
+
+// Appends snapshot locations to the table, returns index of the first one and their count. Available is count of file bytes
+// after the header when it is known, impossible location count is refused before anything is read.
+// Locations are read in chunks, strings of each chunk are decoded and hashed in parallel and then interned in file order
+pub fn read_locations<R: Read>(reader: &mut R, table: &mut SourceTable, available: Option<u64>) -> Result<(u32, u32), String> {
+    return read_locations_at(reader, table, None, available);
+}
+
+// Lazy is index of snapshot file among files of table with lazy strings and position of locations in it
+fn read_locations_at<R: Read>(reader: &mut R, table: &mut SourceTable, lazy: Option<(u32, u64)>, available: Option<u64>) -> Result<(u32, u32), String> {
+    let location_count: u32 = bincode::decode_from_std_read(reader, BINCODE_CONFIG).map_err(|e| format!("{e}"))?;
+    if let Some(available) = available {
+        let room = available.saturating_sub(4) / MIN_LOCATION_BYTES;
+        if location_count as u64 > room {
+            return Err(fail(Kind::Truncated, format!("File claims {location_count} source locations but has room for at most {room}, it is truncated or corrupted")));
+        }
+    }
+    let location_offset = table.locations.len() as u32;
+
+    // Count is followed by locations. Memory is reserved for locations already read, count itself is not trusted
+    let mut position = 4;
+    let mut remaining = location_count as usize;
+    let mut chunk = Vec::new();
+    while remaining > 0 {
+        chunk.clear();
+        for _ in 0..remaining.min(LOCATION_CHUNK) {
+            chunk.push(read_raw_location(reader, &mut position)?);
+        }
+        remaining -= chunk.len();
+        table.locations.reserve(chunk.len());
+        table.ids.reserve(chunk.len());
+        // Names and functions are mostly unique, files are shared by many locations
+        table.strings.reserve(chunk.len() * 2);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let hashed: Vec<_> = chunk.par_drain(..).map(hash_location).collect();
+        #[cfg(target_arch = "wasm32")]
+        let hashed: Vec<_> = chunk.drain(..).map(hash_location).collect();
+
+        for location in hashed {
+            let HashedLocation { strings: [(name_hash, name), (function_hash, function), (file_hash, file)], offsets, line, color } = location?;
+            let id = location_id([&name, &function, &file], line);
+            let name_string = table.intern_hashed(name_hash, name);
+            let (function_string, file_string) = match lazy {
+                Some((index, start)) => {
+                    let source = |hash: u64, string: &str, offset: u64| LazyString { file: index, offset: start + offset, len: string.len() as u32, hash };
+                    (table.intern_lazy(&function, source(function_hash, &function, offsets[1])),
+                     table.intern_lazy(&file, source(file_hash, &file, offsets[2])))
+                }
+                None => (table.intern_hashed(function_hash, function), table.intern_hashed(file_hash, file)),
+            };
+
+            table.push_location_with_id(id, SourceLocation {
+                name: name_string,
+                function: function_string,
+                file: file_string,
+                line,
+                color_r: color[0],
+                color_g: color[1],
+                color_b: color[2],
+            });
+        }
+    }
+    return Ok((location_offset, location_count));
+}
+
+// Decodes header and source locations the way load_snapshot does, without keeping them or touching the cache
+pub fn check_snapshot(path: &str) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| open_failed(path, e))?;
+    let available = file.metadata().map_err(|e| format!("{path}: {e}"))?.len();
+    let mut file_reader = BufReader::new(file);
+    let header = read_header(&mut file_reader).map_err(|e| format!("{path}: {e}"))?;
+    seal::verify(path, &header)?;
+    let start = file_reader.stream_position().map_err(|e| format!("{path}: {e}"))?;
+    read_locations_at(&mut file_reader, &mut SourceTable::new(), None, Some(available.saturating_sub(start))).map_err(|e| format!("{path}: {e}"))?;
+    return Ok(());
+}
+
+pub fn load_snapshot(path: &str, table: &mut SourceTable) -> Result<Snapshot, String> {
+    let mut file_reader = BufReader::new(File::open(path).map_err(|e| open_failed(path, e))?);
+
+    let header = read_header(&mut file_reader).map_err(|e| format!("{path}: {e}"))?;
+
+    seal::verify(path, &header)?;
+
+    // Cache holds all strings, tables with lazy strings read locations from capture itself
+    let cached = if table.lazy.is_none() { cache::load(path, &header, table) } else { None };
+    if let Some((location_offset, location_count, events_position)) = cached {
+        return Ok(Snapshot {
+            path: path.into(),
+            header,
+            events_position,
+            location_offset,
+            location_count,
+            bytes: None,
+        });
+    }
+
+    let lazy = match table.lazy.as_mut() {
+        Some(lazy_strings) => {
+            lazy_strings.files.push(path.into());
+            let start = file_reader.stream_position().map_err(|e| format!("{path}: {e}"))?;
+            Some((lazy_strings.files.len() as u32 - 1, start))
+        }
+        None => None,
+    };
+    let start = file_reader.stream_position().map_err(|e| format!("{path}: {e}"))?;
+    let available = file_reader.get_ref().metadata().ok().map(|metadata| metadata.len().saturating_sub(start));
+    let (location_offset, location_count) = read_locations_at(&mut file_reader, table, lazy, available).map_err(|e| format!("{path}: {e}"))?;
+
+    let events_position = file_reader.stream_position().map_err(|e| format!("{path}: {e}"))?;
+    if lazy.is_none() {
+        cache::store(path, &header, table, location_offset, location_count, events_position);
+    }
+
+    return Ok(Snapshot {
+        path: path.into(),
+        header,
+        events_position,
+        location_offset,
+        location_count,
+        bytes: None,
+    });
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use crate::analysis::{collect_frame_marks, median, standard_deviation, ticks_to_ns, walk_snapshot, Walk};
+use crate::args::Arguments;
+use crate::clock::{format_count, format_duration, format_utc_ns, parse_duration};
+use crate::color::{red, yellow};
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::snapshot::{load_snapshot, Snapshot};
+
+// Spikes at least this many medians long are shown as severe
+const SEVERE_SPIKE: f64 = 2f64;
+
+// Median frame time and spike limit sigma deviations above it
+pub fn spike_limit(durations: &[u64], sigma: f64) -> (u64, u64) {
+    let mut sorted = durations.to_vec();
+    sorted.sort_unstable();
+    let frame_median = median(&sorted);
+    return (frame_median, frame_median + (sigma * standard_deviation(durations)) as u64);
+}
+
+// Time of zone names inside given frames, heaviest first
+pub fn spike_zones(snapshot: &Snapshot, table: &SourceTable, spikes: &HashMap<u32, u64>) -> Result<HashMap<u32, Vec<(u64, u64)>>, String> {
+    let mut contributions = HashMap::<u32, HashMap<u64, u64>>::new();
+    walk_snapshot(snapshot, |walk| {
+        if let Walk::Zone(zone) = walk {
+            if spikes.contains_key(&zone.frame) {
+                let name = table.locations[zone.location as usize].name;
+                *contributions.entry(zone.frame).or_default().entry(name).or_default() += ticks_to_ns(snapshot, zone.duration());
+            }
+        }
+    })?;
+    return Ok(contributions.into_iter().map(|(frame, zones)| {
+        let mut zones: Vec<(u64, u64)> = zones.into_iter().collect();
+        zones.sort_by_key(|(_, time)| std::cmp::Reverse(*time));
+        return (frame, zones);
+    }).collect());
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--threshold", "--sigma", "--top"])?;
+    let Some(path) = arguments.positional.first() else {
+        return Err(usage("Use: spikes <file> [--threshold duration] [--sigma n] [--top zones]"));
+    };
+    let threshold = arguments.get("--threshold").map(parse_duration).transpose()?;
+    let sigma: f64 = arguments.get_parsed("--sigma")?.unwrap_or(3f64);
+    let top: usize = arguments.get_parsed("--top")?.unwrap_or(5);
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    let marks = collect_frame_marks(&snapshot)?;
+    let durations: Vec<u64> = marks.windows(2).map(|w| ticks_to_ns(&snapshot, w[1].saturating_sub(w[0]))).collect();
+    let (frame_median, sigma_limit) = spike_limit(&durations, sigma);
+    let limit = threshold.unwrap_or(sigma_limit);
+    println!("{} frames, median {}, spike limit {}", format_count(durations.len() as u64), format_duration(frame_median), format_duration(limit));
+
+    // Frame N ends with mark N, durations[N - 1] is its length
+    let spikes: HashMap<u32, u64> = durations.iter().enumerate()
+        .filter(|(_, duration)| **duration > limit)
+        .map(|(i, duration)| (i as u32 + 1, *duration))
+        .collect();
+    if spikes.is_empty() {
+        println!("No spikes found");
+        return Ok(());
+    }
+
+    let contributions = spike_zones(&snapshot, &table, &spikes)?;
+
+    let mut frames: Vec<(&u32, &u64)> = spikes.iter().collect();
+    frames.sort();
+    for (frame, duration) in frames {
+        let start = marks[*frame as usize - 1];
+        let ratio = *duration as f64 / frame_median.max(1) as f64;
+        let line = format!("Frame {frame} at {}: {} ({ratio:.1}x median)", format_utc_ns(snapshot.wall_clock_ns(start)), format_duration(*duration));
+        println!("{}", if ratio >= SEVERE_SPIKE { red(&line) } else { yellow(&line) });
+        for (name, time) in contributions.get(frame).into_iter().flatten().take(top) {
+            println!("    {:>10}  {}", format_duration(*time), table.strings.get(name).map(String::as_str).unwrap_or("Unkn"));
+        }
+    }
+    println!("{} spikes found", format_count(spikes.len() as u64));
+    return Ok(());
+}
@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use crate::analysis::bad_location;
+use crate::args::Arguments;
+use crate::failure::{open_failed, usage};
+use crate::seal::SEAL_MARKER;
+use crate::snapshot::FILE_SIGNATURE;
+use crate::structs::{BINCODE_CONFIG, EventType, UTracyEvent, UTracyHeader, UTracySourceLocation};
+use crate::writer::create_snapshot;
+
+fn read_event(reader: &mut impl Read) -> Option<UTracyEvent> {
+    return bincode::decode_from_std_read(reader, BINCODE_CONFIG).ok();
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--frames", "-o"])?;
+    let (Some(path), Some(frames), Some(output)) = (arguments.positional.first(), arguments.get_parsed::<u32>("--frames")?, arguments.get("-o")) else {
+        return Err(usage("Use: split <file> --frames n -o out_dir"));
+    };
+    if frames == 0 {
+        return Err(usage("Wrong input: --frames 0"));
+    }
+
+    let mut reader = BufReader::new(File::open(path).map_err(|e| open_failed(path, e))?);
+    let mut header: UTracyHeader = bincode::decode_from_reader(&mut reader, BINCODE_CONFIG).map_err(|e| format!("{path}: {e}"))?;
+    if header.signature != FILE_SIGNATURE || header.version != 2 {
+        return Err(format!("{path} is not utracy v2 snapshot"));
+    }
+    if header.seal_marker == SEAL_MARKER {
+        crate::seal::verify(path, &header)?;
+        header.seal_marker = 0;
+    }
+    let location_count: u32 = bincode::decode_from_reader(&mut reader, BINCODE_CONFIG).map_err(|e| format!("{path}: {e}"))?;
+    let mut locations = Vec::with_capacity(location_count as usize);
+    for _ in 0..location_count {
+        let location: UTracySourceLocation = bincode::decode_from_reader(&mut reader, BINCODE_CONFIG).map_err(|e| format!("{path}: {e}"))?;
+        locations.push(location);
+    }
+    let events_position = reader.stream_position().map_err(|e| format!("{path}: {e}"))?;
+
+    // First pass collects locations used by each chunk, chunk N holds frames [N * frames, (N + 1) * frames).
+    // Zones open at a split are closed at the last frame mark of the chunk and reopened in the next one, so every chunk has balanced zones
+    let mut chunks = vec![BTreeMap::<u32, u32>::new()];
+    let mut open = HashMap::<u32, Vec<u32>>::new();
+    let mut frame = 0u32;
+    while let Some(event) = read_event(&mut reader) {
+        unsafe {
+            match event.event_type {
+                EventType::Begin => {
+                    if event.event.begin.source_location >= location_count {
+                        return Err(format!("{path}: {}", bad_location(event.event.begin.source_location, location_count)));
+                    }
+                    chunks.last_mut().unwrap().insert(event.event.begin.source_location, 0);
+                    open.entry(event.event.begin.thread_id).or_default().push(event.event.begin.source_location);
+                }
+                EventType::End => {
+                    open.entry(event.event.end.thread_id).or_default().pop();
+                }
+                EventType::Mark => {
+                    frame += 1;
+                    if frame.is_multiple_of(frames) {
+                        chunks.push(open.values().flatten().map(|location| (*location, 0)).collect());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    for chunk in &mut chunks {
+        for (index, remapped) in chunk.values_mut().enumerate() {
+            *remapped = index as u32;
+        }
+    }
+
+    fs::create_dir_all(output).map_err(|e| format!("{output}: {e}"))?;
+    let stem = Path::new(path).file_stem().and_then(|s| s.to_str()).unwrap_or("snapshot");
+    reader.seek(SeekFrom::Start(events_position)).map_err(|e| format!("{path}: {e}"))?;
+    let mut pending = read_event(&mut reader);
+    // Stacks by thread in ascending order, so reopened zones come out the same on every run
+    let mut open = BTreeMap::<u32, Vec<u32>>::new();
+    let mut split_at = 0u64;
+    for (index, chunk) in chunks.iter().enumerate() {
+        if pending.is_none() {
+            break;
+        }
+        let chunk_path = Path::new(output).join(format!("{stem}_{index:04}.utracy"));
+        let chunk_name = chunk_path.display().to_string();
+        let mut writer = create_snapshot(&chunk_name, &header, chunk.keys().map(|location| &locations[*location as usize]))?;
+        for (thread_id, stack) in &open {
+            for location in stack {
+                writer.zone_begin(*thread_id, chunk[location], split_at).map_err(|e| format!("{chunk_name}: {e}"))?;
+            }
+        }
+
+        let mut chunk_frames = 0;
+        while let Some(event) = pending.take() {
+            unsafe {
+                match event.event_type {
+                    EventType::Begin => {
+                        let begin = event.event.begin;
+                        open.entry(begin.thread_id).or_default().push(begin.source_location);
+                        writer.zone_begin(begin.thread_id, chunk[&begin.source_location], begin.timestamp)
+                    }
+                    EventType::End => {
+                        open.entry(event.event.end.thread_id).or_default().pop();
+                        writer.write_event(&event)
+                    }
+                    EventType::Mark => {
+                        chunk_frames += 1;
+                        split_at = event.event.mark.timestamp;
+                        writer.write_event(&event)
+                    }
+                    _ => writer.write_event(&event),
+                }.map_err(|e| format!("{chunk_name}: {e}"))?;
+            }
+            pending = read_event(&mut reader);
+            if chunk_frames == frames {
+                break;
+            }
+        }
+        if pending.is_some() {
+            for (thread_id, stack) in &open {
+                for _ in stack {
+                    writer.zone_end(*thread_id, split_at).map_err(|e| format!("{chunk_name}: {e}"))?;
+                }
+            }
+        }
+        writer.finish().map_err(|e| format!("{chunk_name}: {e}"))?;
+        println!("{chunk_name}: {chunk_frames} frames, {} source locations", chunk.len());
+    }
+    return Ok(());
+}
@@ -0,0 +1,114 @@
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection};
+use crate::args::Arguments;
+use crate::export::csv_field;
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::model::SnapshotModel;
+use crate::snapshot::load_snapshot;
+
+// Times are nanoseconds since capture start, zones of frame N end after mark N - 1 and not after mark N
+const SCHEMA: &str = "
+CREATE TABLE zones (
+    thread INTEGER NOT NULL,
+    frame INTEGER NOT NULL,
+    depth INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    function TEXT NOT NULL,
+    file TEXT NOT NULL,
+    line INTEGER NOT NULL,
+    start INTEGER NOT NULL,
+    duration INTEGER NOT NULL,
+    self_time INTEGER NOT NULL
+);
+CREATE TABLE frames (
+    frame INTEGER PRIMARY KEY,
+    start INTEGER NOT NULL,
+    duration INTEGER NOT NULL
+);
+";
+
+// Decoded snapshot in in-memory SQLite database
+fn load_database(model: &SnapshotModel) -> Result<Connection, String> {
+    let mut db = Connection::open_in_memory().map_err(|e| format!("{e}"))?;
+    db.execute_batch(SCHEMA).map_err(|e| format!("{e}"))?;
+    let ns = |ticks: u64| (ticks as f64 * model.header.multiplier) as i64;
+    let since_start = |ticks: u64| ns(ticks.saturating_sub(model.header.init_begin));
+    let transaction = db.transaction().map_err(|e| format!("{e}"))?;
+    {
+        let mut insert = transaction.prepare("INSERT INTO zones VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)").map_err(|e| format!("{e}"))?;
+        for zone in &model.zones {
+            let location = &model.locations[zone.location as usize];
+            insert.execute(params![zone.thread_id, zone.frame, zone.depth, location.name, location.function, location.file, location.line,
+                                   since_start(zone.start), ns(zone.duration()), ns(zone.self_time())])
+                .map_err(|e| format!("{e}"))?;
+        }
+        let mut insert = transaction.prepare("INSERT INTO frames VALUES (?1, ?2, ?3)").map_err(|e| format!("{e}"))?;
+        for (index, pair) in model.frames.windows(2).enumerate() {
+            insert.execute(params![index as i64 + 1, since_start(pair[0]), ns(pair[1] - pair[0])]).map_err(|e| format!("{e}"))?;
+        }
+    }
+    transaction.commit().map_err(|e| format!("{e}"))?;
+    return Ok(db);
+}
+
+fn value_text(value: ValueRef) -> String {
+    return match value {
+        ValueRef::Null => "NULL".into(),
+        ValueRef::Integer(value) => value.to_string(),
+        ValueRef::Real(value) => value.to_string(),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).to_string(),
+        ValueRef::Blob(blob) => format!("<{} bytes>", blob.len()),
+    };
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--format"])?;
+    let (Some(path), Some(query)) = (arguments.positional.first(), arguments.positional.get(1)) else {
+        return Err(usage("Use: sql <file> \"SELECT ... FROM zones\" [--format text|csv]"));
+    };
+    let format = arguments.get("--format").unwrap_or("text");
+    if format != "text" && format != "csv" {
+        return Err(usage(format!("Wrong input: --format {format}, expected text or csv")));
+    }
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    let db = load_database(&SnapshotModel::load(&snapshot, &table)?)?;
+    let mut statement = db.prepare(query).map_err(|e| format!("{e}"))?;
+    let columns: Vec<String> = statement.column_names().iter().map(|name| name.to_string()).collect();
+    let mut rows = Vec::<Vec<String>>::new();
+    let mut result = statement.query([]).map_err(|e| format!("{e}"))?;
+    while let Some(row) = result.next().map_err(|e| format!("{e}"))? {
+        let mut values = Vec::with_capacity(columns.len());
+        for index in 0..columns.len() {
+            values.push(value_text(row.get_ref(index).map_err(|e| format!("{e}"))?));
+        }
+        rows.push(values);
+    }
+
+    if format == "csv" {
+        println!("{}", columns.iter().map(|column| csv_field(column)).collect::<Vec<_>>().join(","));
+        for row in &rows {
+            println!("{}", row.iter().map(|value| csv_field(value)).collect::<Vec<_>>().join(","));
+        }
+        return Ok(());
+    }
+    let mut widths: Vec<usize> = columns.iter().map(|column| column.chars().count()).collect();
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let print_row = |values: &[String]| {
+        let cells: Vec<String> = values.iter().zip(&widths).map(|(value, width)| format!("{value:<width$}")).collect();
+        println!("{}", cells.join("  ").trim_end());
+    };
+    print_row(&columns);
+    for row in &rows {
+        print_row(row);
+    }
+    println!("{} rows", rows.len());
+    return Ok(());
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use crate::analysis::{walk_snapshot, Walk};
+use crate::intern::SourceTable;
+use crate::snapshot::Snapshot;
+
+// Frame thumbnail is a row of columns, each 4 pixels wide, colored by zones in order of their share of frame time
+pub const STRIP_COLUMNS: usize = 16;
+pub const STRIP_WIDTH: u16 = STRIP_COLUMNS as u16 * 4;
+pub const STRIP_HEIGHT: u16 = 16;
+// DXT1 stores 4x4 pixel block in 8 bytes
+pub const STRIP_IMAGE_BYTES: usize = STRIP_WIDTH as usize * STRIP_HEIGHT as usize / 2;
+
+fn rgb565(r: u8, g: u8, b: u8) -> u16 {
+    return ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+}
+
+// Location color from snapshot, zones without one get stable bright color derived from name
+fn location_color(table: &SourceTable, location: u32) -> u16 {
+    let source = &table.locations[location as usize];
+    if source.color_r != 0 || source.color_g != 0 || source.color_b != 0 {
+        return rgb565(source.color_r, source.color_g, source.color_b);
+    }
+    let hash = source.name.wrapping_mul(0x9E3779B97F4A7C15) >> 40;
+    let channel = |shift: u64| 64 + ((hash >> shift) & 0xFF) as u8 % 192;
+    return rgb565(channel(0), channel(8), channel(16));
+}
+
+// Column colors of every frame, index is frame number as counted by walk_snapshot. Zones are weighted by self time,
+// so wrapping zones like world tick do not paint every frame the same
+pub fn build_frame_strips(snapshot: &Snapshot, table: &SourceTable) -> Result<Vec<[u16; STRIP_COLUMNS]>, String> {
+    let mut times = Vec::<HashMap<u32, u64>>::new();
+    walk_snapshot(snapshot, |walk| {
+        if let Walk::Zone(zone) = walk {
+            let frame = zone.frame as usize;
+            if times.len() <= frame {
+                times.resize_with(frame + 1, HashMap::new);
+            }
+            *times[frame].entry(zone.location).or_default() += zone.self_time();
+        }
+    })?;
+
+    let mut strips = Vec::with_capacity(times.len());
+    for frame in times {
+        let mut zones: Vec<(u32, u64)> = frame.into_iter().collect();
+        zones.sort_by_key(|(location, time)| (std::cmp::Reverse(*time), *location));
+        let total = zones.iter().map(|(_, time)| time).sum::<u64>().max(1);
+        let mut strip = [0u16; STRIP_COLUMNS];
+        let mut column = 0;
+        let mut covered = 0;
+        for (location, time) in &zones {
+            covered += time;
+            // Dominant zone always gets the first column even when its share rounds down to zero
+            let end = ((covered * STRIP_COLUMNS as u64).div_ceil(total) as usize).clamp(column.max(1), STRIP_COLUMNS);
+            let color = location_color(table, *location);
+            strip[column..end].fill(color);
+            column = end;
+            if column == STRIP_COLUMNS {
+                break;
+            }
+        }
+        strips.push(strip);
+    }
+    return Ok(strips);
+}
+
+// Solid DXT1 blocks, both endpoint colors equal and all indices pointing to the first one
+pub fn strip_image(strip: &[u16; STRIP_COLUMNS]) -> [u8; STRIP_IMAGE_BYTES] {
+    let mut image = [0u8; STRIP_IMAGE_BYTES];
+    for (index, block) in image.chunks_exact_mut(8).enumerate() {
+        let color = strip[index % STRIP_COLUMNS].to_le_bytes();
+        block[..4].copy_from_slice(&[color[0], color[1], color[0], color[1]]);
+    }
+    return image;
+}
@@ -1,4 +1,3 @@
-use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::Write;
 use bincode::enc::Encoder;
 use bincode::enc::write::Writer;
@@ -10,9 +9,42 @@ use bincode::error::{DecodeError, EncodeError};
 use bincode::error::AllowedEnumVariants::{Allowed, Range};
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
+use crate::snapshot::FILE_SIGNATURE;
 
 pub const BINCODE_CONFIG: Configuration<LittleEndian, Fixint> = bincode::config::standard().with_little_endian().with_fixed_int_encoding();
 
+// Tracy protocol spoken by server and relay
+pub const PROTOCOL_VERSION: u32 = 69;
+// Tracy releases by protocol version, to tell which UI speaks which protocol. Only releases checked against rtracy are listed,
+// protocols of the others are not guessed
+pub const TRACY_RELEASES: [(u32, &str); 3] = [(69, "0.11.1"), (76, "0.13.1"), (82, "0.14.1")];
+// Welcome message (NetworkHeader) and server query sizes of PROTOCOL_VERSION
+pub const HEADER_SIZE: usize = 1178;
+pub const QUERY_SIZE: usize = 13;
+
+// Release page is this followed by release number
+pub const TRACY_DOWNLOAD: &str = "https://github.com/wolfpld/tracy/releases/tag/v";
+// All releases, protocol version of each is in its public/common/TracyProtocol.hpp
+pub const TRACY_RELEASES_PAGE: &str = "https://github.com/wolfpld/tracy/releases";
+
+pub fn tracy_release(protocol: u32) -> Option<&'static str> {
+    return TRACY_RELEASES.iter().find(|(version, _)| *version == protocol).map(|(_, release)| *release);
+}
+
+// "Tracy 0.13.1" for protocols of the table, other releases and development builds are not named
+pub fn describe_protocol(protocol: u32) -> String {
+    return match tracy_release(protocol) {
+        Some(release) => format!("Tracy {release}"),
+        None => "Tracy of unknown release".into(),
+    };
+}
+
+// "0.11.1 (protocol 69), 0.13.1 (protocol 76) ..., other releases at <releases page>"
+pub fn release_table() -> String {
+    let known: Vec<String> = TRACY_RELEASES.iter().map(|(version, release)| format!("{release} (protocol {version})")).collect();
+    return format!("{}, other releases at {TRACY_RELEASES_PAGE}", known.join(", "));
+}
+
 #[derive(Debug)]
 pub struct U16SizeString<'l>(pub &'l String);
 
@@ -24,17 +56,23 @@ impl Encode for U16SizeString<'_> {
     }
 }
 
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// FNV-1a continuing from hash, same on every platform and build unlike DefaultHasher, so ids stay stable in caches and exports
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    return bytes.iter().fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME));
+}
+
 #[derive(Debug)]
 pub struct U32SizeString(pub String);
 
 impl U32SizeString {
     pub fn get_hash(&self) -> u64 {
-        if self.0.len() == 0 {
+        if self.0.is_empty() {
             return 0;
         }
-        let mut hasher = DefaultHasher::new();
-        self.0.hash(&mut hasher);
-        return hasher.finish();
+        return fnv1a(FNV_OFFSET, self.0.as_bytes());
     }
 }
 
@@ -52,8 +90,7 @@ impl Decode for U32SizeString {
     fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
         let len = u32::decode(decoder)?;
         decoder.claim_container_read::<u8>(len as usize)?;
-        let mut vec = Vec::new();
-        vec.resize(len as usize, 0u8);
+        let mut vec = vec![0u8; len as usize];
         decoder.reader().read(&mut vec)?;
         return match String::from_utf8(vec) {
             Ok(result) => Ok(U32SizeString(result)),
@@ -64,11 +101,12 @@ impl Decode for U32SizeString {
     }
 }
 
-#[derive(Decode, Debug)]
+#[derive(Encode, Decode, Clone, Debug)]
 pub struct UTracyHeader {
     pub signature: u64,
     pub version: u32,
-    _padding0: u32,
+    // Padding in utracy, set to SEAL_MARKER by rtracy seal
+    pub seal_marker: u32,
     pub multiplier: f64,
     pub init_begin: u64,
     pub init_end: u64,
@@ -88,7 +126,34 @@ pub struct UTracyHeader {
     _padding2: [u8; 4],
 }
 
-#[derive(Decode, Debug)]
+impl Default for UTracyHeader {
+    fn default() -> UTracyHeader {
+        return UTracyHeader {
+            signature: FILE_SIGNATURE,
+            version: 2,
+            seal_marker: 0,
+            multiplier: 1f64,
+            init_begin: 0,
+            init_end: 0,
+            delay: 0,
+            resolution: 0,
+            epoch: 0,
+            exec_time: 0,
+            process_id: 0,
+            sampling_period: 0,
+            flags: 0,
+            cpu_arch: 0,
+            cpu_manufacturer: [0u8; 12],
+            _padding1: [0u8; 2],
+            cpu_id: 0,
+            program_name: [0u8; 64],
+            host_info: [0u8; 1024],
+            _padding2: [0u8; 4],
+        };
+    }
+}
+
+#[derive(Encode, Decode, Debug)]
 pub struct UTracySourceLocation {
     pub name: U32SizeString,
     pub function: U32SizeString,
@@ -97,7 +162,7 @@ pub struct UTracySourceLocation {
     pub color: [u8; 4],
 }
 
-#[derive(Encode, Copy, Clone, Debug)]
+#[derive(Encode, Decode, Copy, Clone, Debug)]
 pub struct SourceLocation {
     pub name: u64,
     pub function: u64,
@@ -118,6 +183,12 @@ pub enum EventType {
 
 bincode::impl_borrow_decode!(EventType);
 
+impl Encode for EventType {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        return self.to_u8().unwrap().encode(encoder);
+    }
+}
+
 impl Decode for EventType {
     fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
         let value = u8::decode(decoder)?;
@@ -132,7 +203,7 @@ impl Decode for EventType {
     }
 }
 
-#[derive(Decode, Copy, Clone, Debug)]
+#[derive(Encode, Decode, Copy, Clone, Debug)]
 #[allow(dead_code)]
 pub struct EventZoneBegin {
     pub thread_id: u32,
@@ -140,7 +211,7 @@ pub struct EventZoneBegin {
     pub timestamp: u64,
 }
 
-#[derive(Decode, Copy, Clone, Debug)]
+#[derive(Encode, Decode, Copy, Clone, Debug)]
 #[allow(dead_code)]
 pub struct EventZoneEnd {
     pub thread_id: u32,
@@ -148,7 +219,7 @@ pub struct EventZoneEnd {
     pub timestamp: u64,
 }
 
-#[derive(Decode, Copy, Clone, Debug)]
+#[derive(Encode, Decode, Copy, Clone, Debug)]
 #[allow(dead_code)]
 pub struct EventZoneColor {
     pub thread_id: u32,
@@ -156,7 +227,7 @@ pub struct EventZoneColor {
     pub padding: u64,
 }
 
-#[derive(Decode, Copy, Clone, Debug)]
+#[derive(Encode, Decode, Copy, Clone, Debug)]
 #[allow(dead_code)]
 pub struct EventFrameMark {
     pub name: u32,
@@ -179,15 +250,50 @@ impl Decode for Event {
     }
 }
 
-#[derive(Decode)]
+// Mirror of Decode, all variants share begin layout
+impl Encode for Event {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        return unsafe { self.begin }.encode(encoder);
+    }
+}
+
+#[derive(Encode, Decode)]
 pub struct UTracyEvent {
     pub event_type: EventType,
     _padding: [u8; 7],
     pub event: Event,
 }
 
+// Events are built through begin layout like Decode does, so any variant reads back the same fields
+impl UTracyEvent {
+    fn raw(event_type: EventType, first: u32, second: u32, timestamp: u64) -> UTracyEvent {
+        return UTracyEvent {
+            event_type,
+            _padding: [0u8; 7],
+            event: Event { begin: EventZoneBegin { thread_id: first, source_location: second, timestamp } },
+        };
+    }
+
+    pub fn begin(thread_id: u32, source_location: u32, timestamp: u64) -> UTracyEvent {
+        return UTracyEvent::raw(EventType::Begin, thread_id, source_location, timestamp);
+    }
+
+    pub fn end(thread_id: u32, timestamp: u64) -> UTracyEvent {
+        return UTracyEvent::raw(EventType::End, thread_id, 0, timestamp);
+    }
+
+    pub fn color(thread_id: u32, color: [u8; 4]) -> UTracyEvent {
+        return UTracyEvent::raw(EventType::Color, thread_id, u32::from_le_bytes(color), 0);
+    }
+
+    pub fn mark(timestamp: u64) -> UTracyEvent {
+        return UTracyEvent::raw(EventType::Mark, 0, 0, timestamp);
+    }
+}
+
 #[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug)]
 #[repr(u8)]
+#[allow(clippy::enum_variant_names)]
 pub enum HandshakeStatus {
     HandshakePending,
     HandshakeWelcome,
@@ -229,6 +335,17 @@ pub struct NetworkHeader {
     pub host_info: [u8; 1024],
 }
 
+// Packed BroadcastMessage of Tracy without program name, which follows with terminating NUL
+#[derive(Encode, Debug)]
+pub struct NetworkBroadcast {
+    pub broadcast_version: u16,
+    pub listen_port: u16,
+    pub protocol_version: u32,
+    pub process_id: u64,
+    // Seconds since start
+    pub active_time: i32,
+}
+
 #[derive(Decode, Debug)]
 #[allow(dead_code)]
 pub struct NetworkQuery {
@@ -250,6 +367,12 @@ pub struct NetworkZoneEnd {
     pub timestamp: u64,
 }
 
+#[derive(Encode, Debug)]
+pub struct NetworkZoneValue {
+    pub query_type: QueryResponseType,
+    pub value: u64,
+}
+
 #[derive(Encode, Debug)]
 pub struct NetworkZoneColor {
     pub query_type: QueryResponseType,
@@ -265,12 +388,229 @@ pub struct NetworkFrameMark {
     pub name: u64,
 }
 
+#[derive(Encode, Debug)]
+pub struct NetworkFrameVsync {
+    pub query_type: QueryResponseType,
+    pub timestamp: u64,
+    pub id: u32,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkMessage {
+    pub query_type: QueryResponseType,
+    pub timestamp: u64,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkPlotDataDouble {
+    pub query_type: QueryResponseType,
+    pub name: u64,
+    pub timestamp: u64,
+    pub value: f64,
+}
+
 #[derive(Encode, Debug)]
 pub struct NetworkThreadContext {
     pub query_type: QueryResponseType,
     pub thread_id: u32,
 }
 
+#[derive(Encode, Debug)]
+pub struct NetworkFiberEnter {
+    pub query_type: QueryResponseType,
+    pub timestamp: u64,
+    pub fiber: u64,
+    pub thread: u32,
+    pub group_hint: i32,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkThreadGroupHint {
+    pub query_type: QueryResponseType,
+    pub thread: u32,
+    pub group_hint: i32,
+}
+
+// Graphics API of GPU context shown by Tracy, Invalid (0) is never sent
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, Default)]
+#[repr(u8)]
+pub enum GpuContextType {
+    OpenGl = 1,
+    Vulkan,
+    OpenCl,
+    Direct3D12,
+    Direct3D11,
+    Metal,
+    #[default]
+    Custom,
+}
+
+impl Encode for GpuContextType {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        return self.to_u8().unwrap().encode(encoder);
+    }
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkGpuNewContext {
+    pub query_type: QueryResponseType,
+    pub cpu_time: u64,
+    pub gpu_time: u64,
+    pub thread: u32,
+    pub period: f32,
+    pub context: u8,
+    pub flags: u8,
+    pub context_type: GpuContextType,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkGpuZoneBegin {
+    pub query_type: QueryResponseType,
+    pub cpu_time: u64,
+    pub thread: u32,
+    pub query_id: u16,
+    pub context: u8,
+    pub source_location: u64,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkGpuZoneEnd {
+    pub query_type: QueryResponseType,
+    pub cpu_time: u64,
+    pub thread: u32,
+    pub query_id: u16,
+    pub context: u8,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkGpuTime {
+    pub query_type: QueryResponseType,
+    pub gpu_time: u64,
+    pub query_id: u16,
+    pub context: u8,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkGpuContextName {
+    pub query_type: QueryResponseType,
+    pub context: u8,
+}
+
+// Image data is encoded with its header, message must not be split between two compressed frames
+#[derive(Encode, Debug)]
+pub struct NetworkFrameImageData<const N: usize> {
+    pub query_type: QueryResponseType,
+    pub pointer: u64,
+    pub size: u32,
+    pub data: [u8; N],
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkFrameImage {
+    pub query_type: QueryResponseType,
+    pub frame: u32,
+    pub width: u16,
+    pub height: u16,
+    pub flip: u8,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkTidToPid {
+    pub query_type: QueryResponseType,
+    pub thread_id: u64,
+    pub process_id: u64,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkContextSwitch {
+    pub query_type: QueryResponseType,
+    pub timestamp: u64,
+    pub old_thread: u32,
+    pub new_thread: u32,
+    pub cpu: u8,
+    pub old_thread_wait_reason: u8,
+    pub old_thread_state: u8,
+    pub previous_c_state: u8,
+    pub new_thread_priority: i8,
+    pub old_thread_priority: i8,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkThreadWakeup {
+    pub query_type: QueryResponseType,
+    pub timestamp: u64,
+    pub thread: u32,
+    pub cpu: u8,
+    pub adjust_reason: i8,
+    pub adjust_increment: i8,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkSysTime {
+    pub query_type: QueryResponseType,
+    pub timestamp: u64,
+    pub usage: f32,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkSysPower {
+    pub query_type: QueryResponseType,
+    pub timestamp: u64,
+    pub delta: u64,
+    pub name: u64,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkCallstackSample {
+    pub query_type: QueryResponseType,
+    pub timestamp: u64,
+    pub thread: u32,
+}
+
+// Payload size is sent as u16 bytes, deeper stacks have to be cut before they get here
+pub const MAX_CALLSTACK_FRAMES: usize = u16::MAX as usize / 8;
+
+#[derive(Debug)]
+pub struct NetworkCallstackPayload<'l> {
+    pub query_type: QueryResponseType,
+    // Identifies callstack on client side, Tracy only passes it along
+    pub pointer: u64,
+    pub frames: &'l [u64],
+}
+
+impl Encode for NetworkCallstackPayload<'_> {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.query_type.encode(encoder)?;
+        self.pointer.encode(encoder)?;
+        ((self.frames.len() * 8) as u16).encode(encoder)?;
+        for frame in self.frames {
+            frame.encode(encoder)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkCallstackFrameSize {
+    pub query_type: QueryResponseType,
+    pub pointer: u64,
+    pub size: u8,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkCallstackFrame {
+    pub query_type: QueryResponseType,
+    pub line: u32,
+    pub symbol_address: u64,
+    pub symbol_length: u32,
+}
+
+#[derive(Encode, Debug)]
+pub struct NetworkSingleString<'l> {
+    pub query_type: QueryResponseType,
+    pub string: U16SizeString<'l>,
+}
+
 #[derive(Encode, Debug)]
 pub struct NetworkSourceCode {
     pub query_type: QueryResponseType,
@@ -291,6 +631,7 @@ pub struct NetworkMessageString<'l> {
 }
 
 #[derive(FromPrimitive, ToPrimitive, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum ServerQueryType {
     ServerQueryTerminate = 0,
     ServerQueryString,
@@ -311,6 +652,15 @@ pub enum ServerQueryType {
     ServerQueryDataTransferPart,
 }
 
+impl ServerQueryType {
+    // Same split as IsQueryPrio() of Tracy
+    pub fn is_priority(&self) -> bool {
+        return !matches!(self, ServerQueryType::ServerQueryDisconnect | ServerQueryType::ServerQueryCallstackFrame | ServerQueryType::ServerQuerySymbol
+            | ServerQueryType::ServerQuerySymbolCode | ServerQueryType::ServerQuerySourceCode | ServerQueryType::ServerQueryDataTransfer
+            | ServerQueryType::ServerQueryDataTransferPart);
+    }
+}
+
 bincode::impl_borrow_decode!(ServerQueryType);
 impl Decode for ServerQueryType {
     fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
@@ -0,0 +1,35 @@
+use std::cmp::Reverse;
+use crate::analysis::{collect_zone_stats_with, StatsOptions};
+use crate::args::Arguments;
+use crate::clock::{format_count, format_duration};
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::snapshot::load_snapshot;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["-n", "--by", "--frames", "--trim", "--winsorize", "--groups"])?;
+    let options = StatsOptions::from_arguments(&arguments)?;
+    let Some(path) = arguments.positional.first() else {
+        return Err(usage("Use: top <file> [-n count] [--by self|total|count] [stats options]"));
+    };
+    let count: usize = arguments.get_parsed("-n")?.unwrap_or(20);
+    let by = arguments.get("--by").unwrap_or("total");
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    let mut stats = collect_zone_stats_with(&snapshot, &table, &options)?;
+    match by {
+        "total" => stats.sort_by_key(|s| Reverse(s.total)),
+        "self" => stats.sort_by_key(|s| Reverse(s.self_total)),
+        "count" => stats.sort_by_key(|s| Reverse(s.count())),
+        _ => return Err(usage(format!("Wrong input: --by {by}, expected self, total or count"))),
+    }
+
+    println!("{:>10} {:>12} {:>12} {:>12} {:>12}  Zone", "Count", "Total", "Self", "Mean", "P99");
+    for zone in stats.iter().take(count) {
+        println!("{:>10} {:>12} {:>12} {:>12} {:>12}  {}", format_count(zone.count() as u64), format_duration(zone.total), format_duration(zone.self_total),
+                 format_duration(zone.mean()), format_duration(zone.percentile(99f64)), zone.name);
+    }
+    return Ok(());
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::io::stdout;
+use serde::Serialize;
+use crate::analysis::{ticks_to_ns, walk_snapshot, Walk};
+use crate::args::Arguments;
+use crate::clock::{format_count, format_duration};
+use crate::export::find_exporter;
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::snapshot::{load_snapshot, Snapshot};
+
+struct TreeNode {
+    name: u64,
+    count: u64,
+    total: u64,
+    self_total: u64,
+    children: HashMap<u64, usize>,
+}
+
+// Caller -> callee tree merged over all zone occurrences, node 0 is the root
+pub struct CallTree {
+    nodes: Vec<TreeNode>,
+}
+
+#[derive(Serialize)]
+pub struct CallTreeJson {
+    pub name: String,
+    pub count: u64,
+    pub total_ns: u64,
+    pub self_ns: u64,
+    pub children: Vec<CallTreeJson>,
+}
+
+impl CallTree {
+    pub fn build(snapshot: &Snapshot, table: &SourceTable) -> Result<CallTree, String> {
+        let mut nodes = vec![TreeNode { name: 0, count: 0, total: 0, self_total: 0, children: HashMap::new() }];
+        let mut stacks = HashMap::<u32, Vec<usize>>::new();
+        walk_snapshot(snapshot, |walk| {
+            match walk {
+                Walk::Begin { thread_id, location, .. } => {
+                    let stack = stacks.entry(thread_id).or_default();
+                    let parent = *stack.last().unwrap_or(&0);
+                    let name = table.locations[location as usize].name;
+                    let next_index = nodes.len();
+                    let index = *nodes[parent].children.entry(name).or_insert(next_index);
+                    if index == next_index {
+                        nodes.push(TreeNode { name, count: 0, total: 0, self_total: 0, children: HashMap::new() });
+                    }
+                    stack.push(index);
+                }
+                Walk::Zone(zone) => {
+                    let Some(index) = stacks.entry(zone.thread_id).or_default().pop() else {
+                        return;
+                    };
+                    let node = &mut nodes[index];
+                    node.count += 1;
+                    node.total += ticks_to_ns(snapshot, zone.duration());
+                    node.self_total += ticks_to_ns(snapshot, zone.self_time());
+                }
+                Walk::Frame { .. } => {}
+            }
+        })?;
+        let root_total = nodes[0].children.values().map(|child| nodes[*child].total).sum();
+        nodes[0].total = root_total;
+        return Ok(CallTree { nodes });
+    }
+
+    fn sorted_children(&self, index: usize) -> Vec<usize> {
+        let mut children: Vec<usize> = self.nodes[index].children.values().copied().collect();
+        children.sort_by_key(|child| std::cmp::Reverse(self.nodes[*child].total));
+        return children;
+    }
+
+    pub fn to_json(&self, table: &SourceTable, index: usize) -> CallTreeJson {
+        let node = &self.nodes[index];
+        return CallTreeJson {
+            name: if index == 0 { "<root>".into() } else { table.strings.get(&node.name).cloned().unwrap_or_default() },
+            count: node.count,
+            total_ns: node.total,
+            self_ns: node.self_total,
+            children: self.sorted_children(index).into_iter().map(|child| self.to_json(table, child)).collect(),
+        };
+    }
+
+    // Children below min_share of the whole capture time are hidden
+    pub fn print(&self, table: &SourceTable, index: usize, depth: usize, max_depth: usize, min_share: f64) {
+        let root_total = self.nodes[0].total.max(1) as f64;
+        for child in self.sorted_children(index) {
+            let node = &self.nodes[child];
+            let share = node.total as f64 / root_total * 100f64;
+            if share < min_share {
+                continue;
+            }
+            let name = table.strings.get(&node.name).map(String::as_str).unwrap_or("Unkn");
+            println!("{:>6.2}% {:>12} {:>12} {:>10}  {}{}", share, format_duration(node.total), format_duration(node.self_total), format_count(node.count), "  ".repeat(depth), name);
+            if depth + 1 < max_depth {
+                self.print(table, child, depth + 1, max_depth, min_share);
+            }
+        }
+    }
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--format", "--depth", "--min-percent"])?;
+    let Some(path) = arguments.positional.first() else {
+        return Err(usage("Use: tree <file> [--format text|json] [--depth n] [--min-percent p]"));
+    };
+    let max_depth: usize = arguments.get_parsed("--depth")?.unwrap_or(usize::MAX);
+    let min_share: f64 = arguments.get_parsed("--min-percent")?.unwrap_or(0.5f64);
+
+    let mut table = SourceTable::new();
+    let snapshot = load_snapshot(path, &mut table)?;
+    match arguments.get("--format").unwrap_or("text") {
+        "text" => {
+            let tree = CallTree::build(&snapshot, &table)?;
+            println!("{:>7} {:>12} {:>12} {:>10}  Zone", "Share", "Total", "Self", "Count");
+            tree.print(&table, 0, 0, max_depth, min_share);
+        }
+        "json" => {
+            find_exporter("tree-json")?.export(&snapshot, &table, &arguments, &mut stdout().lock())?;
+        }
+        format => return Err(usage(format!("Wrong input: --format {format}, expected text or json"))),
+    }
+    return Ok(());
+}
@@ -0,0 +1,178 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use crate::analysis::{collect_zone_stats_with, StatsOptions};
+use crate::args::Arguments;
+use crate::changes::{capture_commit, Commits};
+use crate::clock::{format_duration, format_utc};
+use crate::color::{progress_bar, yellow};
+use crate::export::csv_field;
+use crate::failure::usage;
+use crate::intern::SourceTable;
+use crate::snapshot::load_snapshot;
+
+const WIDTH: usize = 800;
+const HEIGHT: usize = 400;
+const MARGIN: usize = 20;
+const MEAN_COLOR: [u8; 3] = [40, 110, 220];
+const P99_COLOR: [u8; 3] = [220, 60, 40];
+
+struct Point {
+    path: String,
+    epoch: u64,
+    count: usize,
+    mean: u64,
+    p99: u64,
+    // Game commit from round metadata
+    commit: Option<String>,
+}
+
+// Line chart of mean (blue) and p99 (red) per capture, captures are spaced evenly in time order
+fn draw_chart(points: &[Point]) -> Vec<[u8; 3]> {
+    let mut pixels = vec![[255u8; 3]; WIDTH * HEIGHT];
+    let peak = points.iter().map(|point| point.p99.max(point.mean)).max().unwrap_or(1).max(1) as f64;
+    let x = |index: usize| MARGIN as f64 + (WIDTH - 2 * MARGIN) as f64 * index as f64 / (points.len() - 1).max(1) as f64;
+    let y = |value: u64| (HEIGHT - MARGIN) as f64 - (HEIGHT - 2 * MARGIN) as f64 * value as f64 / peak;
+    let mut plot = |x0: f64, y0: f64, x1: f64, y1: f64, color: [u8; 3]| {
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1f64) as usize;
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            let (px, py) = ((x0 + (x1 - x0) * t).round() as usize, (y0 + (y1 - y0) * t).round() as usize);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                if px + dx < WIDTH && py + dy < HEIGHT {
+                    pixels[(py + dy) * WIDTH + px + dx] = color;
+                }
+            }
+        }
+    };
+    // Axes, zero line at the bottom
+    plot(MARGIN as f64, MARGIN as f64, MARGIN as f64, (HEIGHT - MARGIN) as f64, [0; 3]);
+    plot(MARGIN as f64, (HEIGHT - MARGIN) as f64, (WIDTH - MARGIN) as f64, (HEIGHT - MARGIN) as f64, [0; 3]);
+    for (values, color) in [(points.iter().map(|point| point.p99).collect::<Vec<_>>(), P99_COLOR),
+                            (points.iter().map(|point| point.mean).collect::<Vec<_>>(), MEAN_COLOR)] {
+        for (index, value) in values.iter().enumerate() {
+            let next = values.get(index + 1).unwrap_or(value);
+            plot(x(index), y(*value), x((index + 1).min(values.len() - 1)), y(*next), color);
+        }
+    }
+    return pixels;
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32fast::hash(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// RGB PNG with uncompressed deflate blocks, chart is small so compression is not worth a dependency
+fn encode_png(pixels: &[[u8; 3]]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(HEIGHT * (WIDTH * 3 + 1));
+    for row in pixels.chunks(WIDTH) {
+        raw.push(0);
+        for pixel in row {
+            raw.extend_from_slice(pixel);
+        }
+    }
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = raw.chunks(65535).collect();
+    for (index, block) in blocks.iter().enumerate() {
+        zlib.push((index == blocks.len() - 1) as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in &raw {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    zlib.extend_from_slice(&((b << 16) | a).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(WIDTH as u32).to_be_bytes());
+    header.extend_from_slice(&(HEIGHT as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &zlib);
+    png_chunk(&mut png, b"IEND", &[]);
+    return png;
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[])?;
+    arguments.check_known(&["--zone", "--format", "-o", "--frames", "--trim", "--winsorize", "--groups", "--git-repo", "--changelog"])?;
+    let options = StatsOptions::from_arguments(&arguments)?;
+    let Some(zone_name) = arguments.get("--zone").filter(|_| !arguments.positional.is_empty()) else {
+        return Err(usage("Use: trend <file>... --zone name [--format text|csv|png] [-o output] [--git-repo dir | --changelog file] [stats options]"));
+    };
+    let commits = Commits::from_arguments(&arguments)?;
+    let format = arguments.get("--format").unwrap_or("text");
+    if !["text", "csv", "png"].contains(&format) {
+        return Err(usage(format!("Wrong input: --format {format}, expected text, csv or png")));
+    }
+    let output = arguments.get("-o");
+    if format == "png" && output.is_none() {
+        return Err("PNG chart needs output file: -o trend.png".into());
+    }
+
+    let mut points = Vec::new();
+    let bar = progress_bar(arguments.positional.len(), "Loading");
+    for path in &arguments.positional {
+        bar.inc(1);
+        bar.set_message(path.clone());
+        let mut table = SourceTable::new();
+        let snapshot = load_snapshot(path, &mut table)?;
+        let stats = collect_zone_stats_with(&snapshot, &table, &options)?;
+        let Some(zone) = stats.iter().find(|zone| zone.name == zone_name) else {
+            bar.suspend(|| println!("{}", yellow(&format!("{path}: zone \"{zone_name}\" not found, skipped"))));
+            continue;
+        };
+        points.push(Point { path: path.clone(), epoch: snapshot.header.epoch, count: zone.count(), mean: zone.mean(), p99: zone.percentile(99f64),
+                            commit: capture_commit(path)? });
+    }
+    bar.finish_and_clear();
+    if points.is_empty() {
+        return Err(format!("Zone \"{zone_name}\" not found in any snapshot"));
+    }
+    points.sort_by_key(|point| point.epoch);
+
+    let mut writer: Box<dyn Write> = match output {
+        Some(output) => Box::new(BufWriter::new(File::create(output).map_err(|e| format!("{output}: {e}"))?)),
+        None => Box::new(std::io::stdout()),
+    };
+    let result = match format {
+        "png" => writer.write_all(&encode_png(&draw_chart(&points))),
+        "csv" => {
+            let mut result = writeln!(writer, "captured,path,count,mean_ns,p99_ns,commit");
+            for point in &points {
+                result = result.and_then(|_| writeln!(writer, "{},{},{},{},{},{}", point.epoch, csv_field(&point.path), point.count, point.mean, point.p99,
+                                                      csv_field(point.commit.as_deref().unwrap_or(""))));
+            }
+            result
+        }
+        _ => {
+            let mut result = writeln!(writer, "{:<24} {:>10} {:>12} {:>12}  Path", "Captured", "Count", "Mean", "P99");
+            let mut previous: Option<&str> = None;
+            for point in &points {
+                // Commits that landed between two rounds are listed above the later one
+                if let (Some(old), Some(new)) = (previous, point.commit.as_deref()) {
+                    for line in commits.describe(old, new) {
+                        result = result.and_then(|_| writeln!(writer, "    {line}"));
+                    }
+                }
+                previous = point.commit.as_deref().or(previous);
+                result = result.and_then(|_| writeln!(writer, "{:<24} {:>10} {:>12} {:>12}  {}", format_utc(point.epoch), point.count,
+                                                      format_duration(point.mean), format_duration(point.p99), point.path));
+            }
+            result
+        }
+    };
+    result.and_then(|_| writer.flush()).map_err(|e| format!("{e}"))?;
+    if let Some(output) = output {
+        println!("Trend of {zone_name} over {} snapshots written to {output}", points.len());
+    }
+    return Ok(());
+}
@@ -0,0 +1,340 @@
+use std::cmp::Reverse;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use rusqlite::params;
+use crate::cache::cache_path;
+use crate::color::warning;
+use crate::health::{header, HttpAccess};
+use crate::index::{file_version, index_capture, open_db};
+use crate::notify::notify_capture;
+use crate::server::{clear_block_cache, Served, SharedServed};
+use crate::snapshot::check_snapshot;
+
+pub const DEFAULT_MAX_UPLOAD: u64 = 4 << 30;
+
+// Slow uploads are fine, stalled ones give up the connection
+const BODY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Age limit is checked this often besides after every upload
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Limits of upload directory, oldest captures beyond any of them are deleted. The newest one is always kept, it is the served one
+#[derive(Default)]
+pub struct Retention {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub max_count: Option<usize>,
+}
+
+impl Retention {
+    pub fn is_set(&self) -> bool {
+        return self.max_size.is_some() || self.max_age.is_some() || self.max_count.is_some();
+    }
+
+    // Captures of directory to delete, newest first walk keeps files while they fit
+    fn expired(&self, dir: &str) -> Result<Vec<(String, &'static str)>, String> {
+        let mut captures = Vec::new();
+        for entry in fs::read_dir(dir).map_err(|e| format!("{dir}: {e}"))? {
+            let path = entry.map_err(|e| format!("{dir}: {e}"))?.path();
+            if path.extension().is_none_or(|extension| extension != "utracy") {
+                continue;
+            }
+            let Ok(metadata) = path.metadata() else {
+                continue;
+            };
+            captures.push((metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), metadata.len(), path.to_string_lossy().to_string()));
+        }
+        captures.sort_by_key(|capture| Reverse(capture.0));
+
+        let now = SystemTime::now();
+        let mut kept = 0;
+        let mut kept_size = 0;
+        let mut expired = Vec::new();
+        for (index, (modified, size, path)) in captures.into_iter().enumerate() {
+            let reason = if index == 0 {
+                None
+            } else if self.max_count.is_some_and(|max_count| kept >= max_count) {
+                Some("count")
+            } else if self.max_size.is_some_and(|max_size| kept_size + size > max_size) {
+                Some("size")
+            } else if self.max_age.is_some_and(|max_age| now.duration_since(modified).unwrap_or_default() > max_age) {
+                Some("age")
+            } else {
+                None
+            };
+            match reason {
+                Some(reason) => expired.push((path, reason)),
+                None => {
+                    kept += 1;
+                    kept_size += size;
+                }
+            }
+        }
+        return Ok(expired);
+    }
+}
+
+pub type LoadServed = Box<dyn Fn(&str) -> Result<Served, String> + Send + Sync>;
+
+// Daemon state needed to take "PUT /upload/<name>.utracy" requests on the health port
+pub struct Uploads {
+    pub dir: String,
+    pub db: String,
+    pub max_size: u64,
+    pub access: &'static HttpAccess,
+    pub retention: Retention,
+    pub served: &'static SharedServed,
+    // Decodes capture with sidecars and options of the command line
+    pub load: LoadServed,
+}
+
+// Newest capture of the directory by modification time, served on start when no files are given
+pub fn latest_upload(dir: &str) -> Result<Option<String>, String> {
+    let mut latest = None;
+    for entry in fs::read_dir(dir).map_err(|e| format!("{dir}: {e}"))? {
+        let path = entry.map_err(|e| format!("{dir}: {e}"))?.path();
+        if path.extension().is_none_or(|extension| extension != "utracy") {
+            continue;
+        }
+        let Ok(modified) = path.metadata().and_then(|metadata| metadata.modified()) else {
+            continue;
+        };
+        if latest.as_ref().is_none_or(|(newest, _)| modified > *newest) {
+            latest = Some((modified, path));
+        }
+    }
+    return Ok(latest.map(|(_, path)| path.to_string_lossy().to_string()));
+}
+
+// Plain names only, so upload can not leave the directory or replace the partial file of another one
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = name.strip_suffix(".utracy").is_some_and(|stem| !stem.is_empty())
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(format!("Wrong capture name {name:?}, expected <name>.utracy of letters, digits, '-', '_' and '.'"));
+    }
+    return Ok(());
+}
+
+// Body may have started in the same read as request head
+fn receive_body(stream: &mut TcpStream, prefix: &[u8], mut file: File, size: u64) -> Result<(), String> {
+    let _ = stream.set_read_timeout(Some(BODY_TIMEOUT));
+    let mut body = prefix.chain(stream).take(size);
+    let received = io::copy(&mut body, &mut file).map_err(|e| format!("Upload failed: {e}"))?;
+    if received != size {
+        return Err(format!("Upload ended after {received} of {size} bytes"));
+    }
+    return file.sync_all().map_err(|e| format!("{e}"));
+}
+
+impl Uploads {
+    // Stores capture, registers it in index database and serves it to the next connections.
+    // Returns HTTP status and JSON body
+    pub fn receive(&self, stream: &mut TcpStream, head: &str, name: &str, prefix: &[u8]) -> (&'static str, String) {
+        return match self.store(stream, head, name, prefix) {
+            Ok(body) => ("201 Created", body),
+            Err((status, msg)) => {
+                println!("Upload of {name} refused: {msg}");
+                (status, format!("{{\"error\":{msg:?}}}\n"))
+            }
+        };
+    }
+
+    fn store(&self, stream: &mut TcpStream, head: &str, name: &str, prefix: &[u8]) -> Result<String, (&'static str, String)> {
+        self.access.check(stream, head)?;
+        check_name(name).map_err(|msg| ("400 Bad Request", msg))?;
+        let Some(length) = header(head, "content-length") else {
+            return Err(("411 Length Required", "Upload needs Content-Length".into()));
+        };
+        let size: u64 = length.parse().map_err(|_| ("400 Bad Request", format!("Wrong Content-Length {length}")))?;
+        if size > self.max_size {
+            return Err(("413 Content Too Large", format!("Capture of {size} bytes is over --max-upload {}", self.max_size)));
+        }
+        let path = Path::new(&self.dir).join(name);
+        if path.exists() {
+            return Err(("409 Conflict", format!("{name} was already uploaded")));
+        }
+        if header(head, "expect").is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue")) {
+            let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+        }
+
+        let partial = Path::new(&self.dir).join(format!(".{name}.part"));
+        let file = match OpenOptions::new().write(true).create_new(true).open(&partial) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(("409 Conflict", format!("{name} is being uploaded"))),
+            Err(e) => return Err(("500 Internal Server Error", format!("{}: {e}", partial.display()))),
+        };
+        if let Err(msg) = receive_body(stream, prefix, file, size) {
+            let _ = fs::remove_file(&partial);
+            return Err(("400 Bad Request", msg));
+        }
+        // Checked before it gets into the directory, where a broken capture would be served again on next start
+        if let Err(msg) = check_snapshot(&partial.to_string_lossy()) {
+            let _ = fs::remove_file(&partial);
+            return Err(("422 Unprocessable Content", format!("Not a valid capture: {msg}")));
+        }
+        fs::rename(&partial, &path).map_err(|e| ("500 Internal Server Error", format!("{}: {e}", path.display())))?;
+        let path = path.to_string_lossy().to_string();
+        let served = match (self.load)(&path) {
+            Ok(served) => served,
+            Err(msg) => {
+                let _ = fs::remove_file(&path);
+                return Err(("422 Unprocessable Content", format!("Not a valid capture: {msg}")));
+            }
+        };
+        let snapshots = served.snapshots.len();
+        let served = Arc::new(served);
+        {
+            let mut current = self.served.write().unwrap();
+            clear_block_cache();
+            *current = Some(served.clone());
+        }
+        println!("Now serving uploaded {path}, {size} bytes");
+
+        // Capture is served already, index database is only for searching the archive
+        let indexed = file_version(Path::new(&path)).and_then(|(size, modified)| {
+            let mut db = open_db(&self.db)?;
+            return index_capture(&mut db, &path, size, modified);
+        });
+        match &indexed {
+            Ok(line) => println!("{line}"),
+            Err(msg) => warning(&format!("{path} is not indexed in {}: {msg}", self.db)),
+        }
+        notify_capture(name, served.clone());
+        self.prune();
+        return Ok(format!("{{\"path\":{path:?},\"size\":{size},\"snapshots\":{snapshots},\"indexed\":{}}}\n", indexed.is_ok()));
+    }
+
+    // Deletes captures beyond retention limits with their location caches and index rows
+    pub fn prune(&self) {
+        if !self.retention.is_set() {
+            return;
+        }
+        let expired = match self.retention.expired(&self.dir) {
+            Ok(expired) => expired,
+            Err(msg) => {
+                warning(&format!("retention skipped, {msg}"));
+                return;
+            }
+        };
+        if expired.is_empty() {
+            return;
+        }
+        let db = open_db(&self.db);
+        for (path, reason) in expired {
+            // Cache is found by absolute path of capture, which can't be resolved once capture is gone
+            let cache = cache_path(&path);
+            if let Err(e) = fs::remove_file(&path) {
+                warning(&format!("{path} is over retention {reason} limit but can't be deleted: {e}"));
+                continue;
+            }
+            if let Some(cache) = cache {
+                let _ = fs::remove_file(cache);
+            }
+            if let Ok(db) = &db {
+                let _ = db.execute("DELETE FROM captures WHERE path = ?1", params![path]);
+            }
+            println!("Deleted {path}, over retention {reason} limit");
+        }
+    }
+}
+
+pub fn spawn_retention(uploads: &'static Uploads) {
+    if !uploads.retention.is_set() {
+        return;
+    }
+    thread::spawn(move || {
+        loop {
+            uploads.prune();
+            thread::sleep(PRUNE_INTERVAL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+    use super::{check_name, Retention};
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    // Captures of (name, size, age in hours) in a fresh directory
+    fn directory(test: &str, captures: &[(&str, u64, u32)]) -> String {
+        let dir = std::env::temp_dir().join(format!("rtracy-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, size, age) in captures {
+            let file = File::create(dir.join(name)).unwrap();
+            file.set_len(*size).unwrap();
+            file.set_modified(SystemTime::now() - HOUR * *age).unwrap();
+        }
+        return dir.to_string_lossy().to_string();
+    }
+
+    // Expired file names with reasons, newest first
+    fn expired(retention: Retention, dir: &str) -> Vec<(String, &'static str)> {
+        let expired = retention.expired(dir).unwrap().into_iter()
+            .map(|(path, reason)| (path.rsplit(['/', '\\']).next().unwrap().to_string(), reason)).collect();
+        fs::remove_dir_all(dir).unwrap();
+        return expired;
+    }
+
+    #[test]
+    fn plain_names_are_accepted() {
+        assert!(check_name("round-1234.utracy").is_ok());
+        assert!(check_name("2024.05.01_round.utracy").is_ok());
+    }
+
+    #[test]
+    fn names_leaving_directory_are_refused() {
+        for name in ["../x.utracy", "..utracy", ".x.utracy", ".utracy", "a/b.utracy", "a\\b.utracy", "/x.utracy", "C:x.utracy", "x.tracy", "x.utracy.part",
+                     "x y.utracy", "x\0.utracy"] {
+            assert!(check_name(name).is_err(), "{name:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn count_keeps_newest() {
+        let dir = directory("count", &[("a.utracy", 1, 0), ("b.utracy", 1, 1), ("c.utracy", 1, 2), ("d.utracy", 1, 3), ("notes.txt", 1, 9)]);
+        let retention = Retention { max_count: Some(2), ..Retention::default() };
+        assert_eq!(expired(retention, &dir), [("c.utracy".to_string(), "count"), ("d.utracy".to_string(), "count")]);
+    }
+
+    #[test]
+    fn size_keeps_newest_that_fit() {
+        let dir = directory("size", &[("a.utracy", 100, 0), ("b.utracy", 50, 1), ("c.utracy", 60, 2), ("d.utracy", 10, 3)]);
+        let retention = Retention { max_size: Some(160), ..Retention::default() };
+        assert_eq!(expired(retention, &dir), [("c.utracy".to_string(), "size")]);
+    }
+
+    #[test]
+    fn age_expires_old_captures() {
+        let dir = directory("age", &[("a.utracy", 1, 0), ("b.utracy", 1, 12), ("c.utracy", 1, 48)]);
+        let retention = Retention { max_age: Some(HOUR * 24), ..Retention::default() };
+        assert_eq!(expired(retention, &dir), [("c.utracy".to_string(), "age")]);
+    }
+
+    #[test]
+    fn count_is_checked_before_size_and_age() {
+        let dir = directory("order", &[("a.utracy", 1, 0), ("b.utracy", 100, 48), ("c.utracy", 1, 72)]);
+        let retention = Retention { max_count: Some(1), max_size: Some(50), max_age: Some(HOUR * 24) };
+        assert_eq!(expired(retention, &dir), [("b.utracy".to_string(), "count"), ("c.utracy".to_string(), "count")]);
+        let dir = directory("order", &[("a.utracy", 1, 0), ("b.utracy", 100, 48), ("c.utracy", 1, 72)]);
+        let retention = Retention { max_count: Some(5), max_size: Some(50), max_age: Some(HOUR * 24) };
+        assert_eq!(expired(retention, &dir), [("b.utracy".to_string(), "size"), ("c.utracy".to_string(), "age")]);
+    }
+
+    #[test]
+    fn newest_is_always_kept() {
+        let dir = directory("newest", &[("a.utracy", 1000, 100), ("b.utracy", 1, 200)]);
+        let retention = Retention { max_count: Some(0), max_size: Some(10), max_age: Some(HOUR) };
+        assert_eq!(expired(retention, &dir), [("b.utracy".to_string(), "count")]);
+    }
+}
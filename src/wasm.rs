@@ -0,0 +1,106 @@
+use std::cell::RefCell;
+use serde::Serialize;
+use crate::analysis::{collect_zone_stats_from, walk_events, StatsOptions, Walk};
+use crate::intern::SourceTable;
+use crate::snapshot::{read_header, read_locations};
+
+// Plain exports without wasm-bindgen, web/index.html copies file into memory from rtracy_alloc,
+// calls rtracy_summarize and reads JSON from rtracy_result_ptr / rtracy_result_len
+
+thread_local! {
+    static RESULT: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+#[derive(Serialize)]
+struct ZoneSummary {
+    name: String,
+    count: usize,
+    total_ns: u64,
+    self_ns: u64,
+    mean_ns: u64,
+    p99_ns: u64,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    program: String,
+    epoch: u64,
+    multiplier: f64,
+    process_id: u64,
+    locations: u32,
+    frames: u32,
+    zones: Vec<ZoneSummary>,
+}
+
+fn summarize(mut data: &[u8], top: usize) -> Result<Summary, String> {
+    let header = read_header(&mut data)?;
+    let mut table = SourceTable::new();
+    let available = data.len() as u64;
+    let (_, location_count) = read_locations(&mut data, &mut table, Some(available))?;
+    let events = data;
+
+    let mut frames = 0;
+    walk_events(&mut data, 0, location_count, |walk| {
+        if let Walk::Frame { .. } = walk {
+            frames += 1;
+        }
+        true
+    })?;
+    let stats = collect_zone_stats_from(&mut &events[..], 0, location_count, header.multiplier, &table, &StatsOptions::default())?;
+    return Ok(Summary {
+        program: String::from_utf8_lossy(&header.program_name).trim_end_matches('\0').to_string(),
+        epoch: header.epoch,
+        multiplier: header.multiplier,
+        process_id: header.process_id,
+        locations: location_count,
+        frames,
+        zones: stats.iter().take(top).map(|zone| ZoneSummary {
+            name: zone.name.clone(),
+            count: zone.count(),
+            total_ns: zone.total,
+            self_ns: zone.self_total,
+            mean_ns: zone.mean(),
+            p99_ns: zone.percentile(99f64),
+        }).collect(),
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn rtracy_alloc(length: usize) -> *mut u8 {
+    let mut buffer = vec![0u8; length];
+    let pointer = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    return pointer;
+}
+
+/// # Safety
+/// pointer and length must come from the same rtracy_alloc call
+#[no_mangle]
+pub unsafe extern "C" fn rtracy_free(pointer: *mut u8, length: usize) {
+    drop(Vec::from_raw_parts(pointer, length, length));
+}
+
+/// Summarizes snapshot loaded into memory, returns 0 on success and 1 when result holds an error message
+///
+/// # Safety
+/// pointer and length must describe memory returned by rtracy_alloc
+#[no_mangle]
+pub unsafe extern "C" fn rtracy_summarize(pointer: *const u8, length: usize, top: u32) -> u32 {
+    let data = std::slice::from_raw_parts(pointer, length);
+    let (result, status) = match summarize(data, top as usize).and_then(|summary| serde_json::to_string(&summary).map_err(|e| format!("{e}"))) {
+        Ok(json) => (json, 0),
+        Err(msg) => (msg, 1),
+    };
+    RESULT.with(|cell| *cell.borrow_mut() = result);
+    return status;
+}
+
+#[no_mangle]
+pub extern "C" fn rtracy_result_ptr() -> *const u8 {
+    return RESULT.with(|cell| cell.borrow().as_ptr());
+}
+
+#[no_mangle]
+pub extern "C" fn rtracy_result_len() -> usize {
+    return RESULT.with(|cell| cell.borrow().len());
+}
@@ -0,0 +1,45 @@
+use std::fs;
+use regex::Regex;
+use serde::Deserialize;
+use crate::check::BudgetDuration;
+
+// watchlist.toml, message goes to timeline at the end of every matching zone running longer than `over`:
+//   [[watch]]
+//   zone = "^SSmachines\\.fire$"
+//   over = "50ms"
+//   message = "Machines overran, look for runaway process()"
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WatchFile {
+    #[serde(default)]
+    watch: Vec<WatchEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WatchEntry {
+    zone: String,
+    over: BudgetDuration,
+    message: String,
+}
+
+pub struct Watch {
+    pub pattern: Regex,
+    // Nanoseconds
+    pub over: u64,
+    pub message: String,
+}
+
+pub fn load_watchlist(path: &str) -> Result<Vec<Watch>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let file: WatchFile = toml::from_str(&text).map_err(|e| format!("{path}: {e}"))?;
+    let mut watchlist = Vec::new();
+    for entry in file.watch {
+        watchlist.push(Watch {
+            pattern: Regex::new(&entry.zone).map_err(|e| format!("{path}: {e}"))?,
+            over: entry.over.to_ns()?,
+            message: entry.message,
+        });
+    }
+    return Ok(watchlist);
+}
@@ -0,0 +1,76 @@
+use std::fs::File;
+use std::io::{copy, BufWriter, Read, Write};
+use crate::structs::{BINCODE_CONFIG, EventType, UTracyEvent, UTracyHeader, UTracySourceLocation, WriterBox};
+
+// Size of one encoded UTracyEvent
+pub const EVENT_SIZE: u64 = 24;
+
+// Writes utracy v2 snapshot, file stores all source locations before events so they are passed on creation
+pub struct SnapshotWriter<W: Write> {
+    writer: W,
+    location_count: u32,
+    events: u64,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    pub fn new<'l, I>(mut writer: W, header: &UTracyHeader, locations: I) -> Result<SnapshotWriter<W>, String>
+    where I: ExactSizeIterator<Item = &'l UTracySourceLocation> {
+        let location_count = locations.len() as u32;
+        bincode::encode_into_writer(header, WriterBox(&mut writer), BINCODE_CONFIG).map_err(|e| format!("{e}"))?;
+        bincode::encode_into_writer(location_count, WriterBox(&mut writer), BINCODE_CONFIG).map_err(|e| format!("{e}"))?;
+        for location in locations {
+            bincode::encode_into_writer(location, WriterBox(&mut writer), BINCODE_CONFIG).map_err(|e| format!("{e}"))?;
+        }
+        return Ok(SnapshotWriter { writer, location_count, events: 0 });
+    }
+
+    pub fn write_event(&mut self, event: &UTracyEvent) -> Result<(), String> {
+        if let EventType::Begin = event.event_type {
+            let location = unsafe { event.event.begin.source_location };
+            if location >= self.location_count {
+                return Err(format!("Zone begin references location {location}, snapshot has {}", self.location_count));
+            }
+        }
+        bincode::encode_into_writer(event, WriterBox(&mut self.writer), BINCODE_CONFIG).map_err(|e| format!("{e}"))?;
+        self.events += 1;
+        return Ok(());
+    }
+
+    pub fn zone_begin(&mut self, thread_id: u32, location: u32, timestamp: u64) -> Result<(), String> {
+        return self.write_event(&UTracyEvent::begin(thread_id, location, timestamp));
+    }
+
+    pub fn zone_end(&mut self, thread_id: u32, timestamp: u64) -> Result<(), String> {
+        return self.write_event(&UTracyEvent::end(thread_id, timestamp));
+    }
+
+    pub fn zone_color(&mut self, thread_id: u32, color: [u8; 4]) -> Result<(), String> {
+        return self.write_event(&UTracyEvent::color(thread_id, color));
+    }
+
+    pub fn frame_mark(&mut self, timestamp: u64) -> Result<(), String> {
+        return self.write_event(&UTracyEvent::mark(timestamp));
+    }
+
+    // Copies already encoded events, locations are not checked
+    pub fn copy_events<R: Read>(&mut self, events: &mut R) -> Result<u64, String> {
+        let copied = copy(events, &mut self.writer).map_err(|e| format!("{e}"))?;
+        self.events += copied / EVENT_SIZE;
+        return Ok(copied / EVENT_SIZE);
+    }
+
+    pub fn event_count(&self) -> u64 {
+        return self.events;
+    }
+
+    pub fn finish(mut self) -> Result<W, String> {
+        self.writer.flush().map_err(|e| format!("{e}"))?;
+        return Ok(self.writer);
+    }
+}
+
+pub fn create_snapshot<'l, I>(path: &str, header: &UTracyHeader, locations: I) -> Result<SnapshotWriter<BufWriter<File>>, String>
+where I: ExactSizeIterator<Item = &'l UTracySourceLocation> {
+    let file = File::create(path).map_err(|e| format!("Error creating file {path}: {e}"))?;
+    return SnapshotWriter::new(BufWriter::new(file), header, locations).map_err(|e| format!("{path}: {e}"));
+}
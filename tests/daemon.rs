@@ -0,0 +1,94 @@
+// Serves under --daemon with worker pool and upload directory, loading happens in the forked process
+#![cfg(unix)]
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+const RTRACY: &str = env!("CARGO_BIN_EXE_rtracy");
+
+fn free_port() -> u16 {
+    return TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+}
+
+fn generate(path: &Path, frames: u32) {
+    let status = Command::new(RTRACY)
+        .args(["gen", "--threads", "2", "--frames", &frames.to_string(), "--seed", "3", "-o"])
+        .arg(path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+// Handshake as Tracy UI and read stream until server closes it, returns welcome status and byte count after it
+fn stream(port: u16) -> (u8, usize) {
+    let mut socket = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    socket.write_all(b"TracyPrf").unwrap();
+    socket.write_all(&69u32.to_le_bytes()).unwrap();
+    let mut received = Vec::new();
+    socket.read_to_end(&mut received).unwrap();
+    return (received[0], received.len() - 1);
+}
+
+fn upload(port: u16, name: &str, path: &Path) -> String {
+    let body = fs::read(path).unwrap();
+    let mut socket = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    write!(socket, "PUT /upload/{name} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", body.len()).unwrap();
+    socket.write_all(&body).unwrap();
+    let mut response = String::new();
+    socket.read_to_string(&mut response).unwrap();
+    return response;
+}
+
+struct Stop(PathBuf);
+
+// Daemon is stopped and files are removed even when assertion fails
+impl Drop for Stop {
+    fn drop(&mut self) {
+        if let Ok(pid) = fs::read_to_string(self.0.join("rtracy.pid")) {
+            let _ = Command::new("kill").arg(pid.trim()).status();
+        }
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn serves_uploads_under_daemon() {
+    let dir = std::env::temp_dir().join(format!("rtracy-daemon-{}", std::process::id()));
+    let stop = Stop(dir.clone());
+    fs::create_dir_all(dir.join("uploads")).unwrap();
+    generate(&dir.join("uploads").join("first.utracy"), 10);
+    generate(&dir.join("second.utracy"), 30);
+
+    let port = free_port();
+    let health_port = free_port();
+    let output = Command::new(RTRACY)
+        .args(["serve", "--daemon", "--workers", "2", "--no-broadcast", "-p", &port.to_string(), "--health-port", &health_port.to_string()])
+        .arg("--upload-dir").arg(dir.join("uploads"))
+        .arg("--db").arg(dir.join("index.sqlite"))
+        .arg("--pidfile").arg(stop.0.join("rtracy.pid"))
+        .output()
+        .unwrap();
+    let printed = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{printed}");
+    assert!(printed.contains("Running in background"), "{printed}");
+
+    let (status, first) = stream(port);
+    assert_eq!(status, 1);
+    assert!(first > 0);
+
+    let response = upload(health_port, "second.utracy", &dir.join("second.utracy"));
+    assert!(response.starts_with("HTTP/1.1 201"), "{response}");
+    // Upload is served before response is sent, both workers stream it
+    let sessions: Vec<_> = (0..2).map(|_| std::thread::spawn(move || stream(port))).collect();
+    for session in sessions {
+        let (status, second) = session.join().unwrap();
+        assert_eq!(status, 1);
+        assert!(second > first);
+    }
+}